@@ -1,6 +1,6 @@
 use aide::axum::ApiRouter;
 
-use super::{config, ddns, dnsmasq, healthz, hello, mdns, qos, services, status, technitium, updates, whoami};
+use super::{config, ddns, dnsmasq, healthz, hello, mdns, qos, services, status, technitium, updates, upnp, whoami};
 use crate::prelude::*;
 
 pub fn router(state: AppState) -> ApiRouter<AppState> {
@@ -16,5 +16,6 @@ pub fn router(state: AppState) -> ApiRouter<AppState> {
         .nest("/status", status::router())
         .nest("/technitium", technitium::router())
         .nest("/updates", updates::router())
+        .nest("/upnp", upnp::router())
         .nest("/whoami", whoami::router())
 }
@@ -36,6 +36,7 @@ pub mod services_config;
 pub mod status;
 pub mod technitium;
 pub mod updates;
+pub mod upnp;
 pub mod whoami;
 
 pub fn router(
@@ -676,90 +676,38 @@ fn extract_tin_values(line: &str) -> Vec<String> {
     }
 }
 
-/// Parse nftables mangle rules containing `dscp set` (priority marking).
+/// Mangle table rules that set DSCP (priority marking).
 async fn read_dscp_rules() -> Vec<DscpRule> {
-    let stdout = read_mangle_table().await;
-    stdout
-        .lines()
-        .map(|l| l.trim())
-        .filter(|l| l.contains("dscp set"))
-        .map(|l| parse_nft_rule(l))
-        .collect()
+    mangle_rules_setting(&read_mangle_rules().await, "dscp")
 }
 
-/// Parse nftables mangle rules containing `meta mark set` (bandwidth marking).
+/// Mangle table rules that set `meta mark` (bandwidth marking).
 async fn read_bandwidth_rules() -> Vec<DscpRule> {
-    let stdout = read_mangle_table().await;
-    stdout
-        .lines()
-        .map(|l| l.trim())
-        .filter(|l| l.contains("meta mark set"))
-        .map(|l| parse_nft_rule(l))
-        .collect()
+    mangle_rules_setting(&read_mangle_rules().await, "mark")
 }
 
-async fn read_mangle_table() -> String {
-    let ruleset = match read_state_file("nft-ruleset.txt").await {
-        Some(c) => c,
-        None => return String::new(),
+/// The rules of the inet mangle table in the `nft -j list ruleset` dump.
+async fn read_mangle_rules() -> Vec<nifty_nft::listing::ListedRule> {
+    let Some(contents) = read_state_file("nft-ruleset.json").await else {
+        return vec![];
     };
-    // Extract the inet mangle table section from the full ruleset
-    extract_table_section(&ruleset, "inet", "mangle")
-}
-
-/// Extract a full table block from the nft ruleset text dump.
-fn extract_table_section(ruleset: &str, family: &str, table: &str) -> String {
-    let mut result = String::new();
-    let mut in_table = false;
-    let mut brace_depth = 0;
-
-    for line in ruleset.lines() {
-        let trimmed = line.trim();
-        if !in_table {
-            if trimmed.starts_with(&format!("table {} {}", family, table)) && trimmed.ends_with('{') {
-                in_table = true;
-                brace_depth = 1;
-                result.push_str(line);
-                result.push('\n');
-            }
-            continue;
-        }
-
-        result.push_str(line);
-        result.push('\n');
-
-        for ch in trimmed.chars() {
-            match ch {
-                '{' => brace_depth += 1,
-                '}' => {
-                    brace_depth -= 1;
-                    if brace_depth == 0 {
-                        return result;
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-    result
+    let Ok(parsed) = serde_json::from_str::<Value>(&contents) else {
+        return vec![];
+    };
+    nifty_nft::listing::parse(&parsed)
+        .rules
+        .into_iter()
+        .filter(|r| r.family == "inet" && r.table == nifty_nft::tables::MANGLE)
+        .collect()
 }
 
-fn parse_nft_rule(l: &str) -> DscpRule {
-    let (text, description) = if let Some(comment_pos) = l.rfind(" comment \"") {
-        let after = &l[comment_pos + 10..];
-        if let Some(end_quote) = after.rfind('"') {
-            let comment = &after[..end_quote];
-            let rule_text = l[..comment_pos].to_string();
-            let desc = comment
-                .strip_prefix("nf:")
-                .map(|d| if d.is_empty() { None } else { Some(d.to_string()) })
-                .unwrap_or(None);
-            (rule_text, desc)
-        } else {
-            (l.to_string(), None)
-        }
-    } else {
-        (l.to_string(), None)
-    };
-    DscpRule { text, description }
+fn mangle_rules_setting(rules: &[nifty_nft::listing::ListedRule], key: &str) -> Vec<DscpRule> {
+    rules
+        .iter()
+        .filter(|r| r.sets(key))
+        .map(|r| DscpRule {
+            text: r.text(),
+            description: r.description().filter(|d| !d.is_empty()).map(|d| d.to_string()),
+        })
+        .collect()
 }
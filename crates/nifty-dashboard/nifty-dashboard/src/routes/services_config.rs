@@ -6,6 +6,7 @@ use axum::http::StatusCode;
 use axum::response::sse::{Event, Sse};
 use axum::routing::get;
 use futures_util::stream::{Stream, StreamExt};
+use nifty_nft::objects::{AddressBook, Addresses};
 use schemars::JsonSchema;
 use serde::Serialize;
 use serde_json::Value;
@@ -60,13 +61,67 @@ async fn get_services_config(_state: State<AppState>) -> ApiJson<ServicesConfigR
     };
 
     match config.get("services") {
-        Some(services) => json_ok(ServicesConfigResponse {
-            services: services.clone(),
-        }),
+        Some(services) => {
+            let mut services = services.clone();
+            expand_allow_from(&config, &mut services);
+            json_ok(ServicesConfigResponse { services })
+        }
         None => json_error(StatusCode::NOT_FOUND, "no services block in config"),
     }
 }
 
+/// Expand `@host`/`@group` references in Traefik route `allow_from` lists
+/// into the addresses of the named objects from the top-level config.
+/// References that don't resolve expand to nothing, so a bad reference
+/// never widens access.
+fn expand_allow_from(config: &Value, services: &mut Value) {
+    let Some(routes) = services
+        .pointer_mut("/traefik/route")
+        .and_then(Value::as_object_mut)
+    else {
+        return;
+    };
+    let book = address_book(config);
+    for route in routes.values_mut() {
+        let Some(list) = route.get_mut("allow_from").and_then(Value::as_array_mut) else {
+            continue;
+        };
+        let mut expanded = Vec::new();
+        for entry in list.iter() {
+            match entry.as_str().filter(|s| s.starts_with('@')) {
+                Some(reference) => {
+                    if let Ok(addresses) = book.resolve(reference) {
+                        expanded.extend(addresses.iter().cloned().map(Value::String));
+                    }
+                }
+                None => expanded.push(entry.clone()),
+            }
+        }
+        *list = expanded;
+    }
+}
+
+/// The host and group objects of the config, resolved the same way the
+/// router resolves them for its rules.
+fn address_book(config: &Value) -> AddressBook {
+    let mut book = AddressBook::default();
+    let blocks = |kind: &str| config.get(kind).and_then(Value::as_object).cloned().unwrap_or_default();
+    for (name, host) in blocks("host") {
+        let mut addresses = Addresses::default();
+        for address in ["ipv4", "ipv6"].iter().filter_map(|k| host.get(*k)?.as_str()) {
+            if let Ok(net) = address.parse() {
+                addresses.push(net);
+            }
+        }
+        book.add_host(&name, addresses);
+    }
+    for (name, group) in blocks("group") {
+        let members = group.get("members").and_then(Value::as_array).cloned().unwrap_or_default();
+        book.add_group(&name, members.iter().filter_map(Value::as_str).map(String::from).collect());
+    }
+    book
+}
+
 /// SSE endpoint for services-config change notifications.
 ///
 /// Emits a `config-changed` event whenever the HCL config file is modified.
@@ -90,3 +145,36 @@ pub async fn sse_handler(
             .text(""),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn expands_object_references() {
+        let config = json!({
+            "host": {
+                "nas": { "ipv4": "10.99.10.5", "ipv6": "fd00:10::5" },
+                "laptop": { "ipv4": "10.99.10.50" }
+            },
+            "group": {
+                "admins": { "members": ["laptop", "10.99.10.64/26"] },
+                "looped": { "members": ["laptop", "looped"] },
+                "typo": { "members": ["laptop", "0.0.0.0/0x"] }
+            }
+        });
+        let mut services = json!({
+            "traefik": { "route": {
+                "nas": { "backend": "http://10.99.10.5", "allow_from": ["@nas", "@admins", "10.99.40.0/24"] },
+                "tv": { "backend": "http://10.99.20.5", "allow_from": ["@missing", "@looped", "@typo"] }
+            }}
+        });
+        expand_allow_from(&config, &mut services);
+        assert_eq!(
+            services["traefik"]["route"]["nas"]["allow_from"],
+            json!(["10.99.10.5", "fd00:10::5", "10.99.10.50", "10.99.10.64/26", "10.99.40.0/24"])
+        );
+        assert_eq!(services["traefik"]["route"]["tv"]["allow_from"], json!([]));
+    }
+}
@@ -6,9 +6,11 @@ use axum::Json;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::util::state_files::{is_state_fresh, read_state_file};
+use crate::util::state_files::{is_state_fresh, read_state_file, read_state_file_since};
 
 use crate::{
     errors::ErrorBody,
@@ -22,6 +24,7 @@ pub fn router() -> ApiRouter<AppState> {
         .api_route("/about", get_with_docs!(get_about))
         .api_route("/config", get_with_docs!(get_config))
         .api_route("/nft-rules", get_with_docs!(get_nft_rules))
+        .api_route("/nft-counters", get_with_docs!(get_nft_counters))
 }
 
 fn state_file_path() -> PathBuf {
@@ -362,8 +365,16 @@ async fn get_nft_rules(
         return json_error(StatusCode::BAD_REQUEST, "invalid parameter characters");
     }
 
-    let ruleset = match read_state_file("nft-ruleset.txt").await {
-        Some(c) => c,
+    let listing = match read_state_file("nft-ruleset.json").await {
+        Some(c) => match serde_json::from_str::<Value>(&c) {
+            Ok(parsed) => nifty_nft::listing::parse(&parsed),
+            Err(_) => {
+                return json_error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "network state data is unavailable or stale",
+                );
+            }
+        },
         None => {
             return json_error(
                 StatusCode::SERVICE_UNAVAILABLE,
@@ -372,11 +383,7 @@ async fn get_nft_rules(
         }
     };
 
-    // Extract rules for the requested chain from the full ruleset dump.
-    // Format: `table <family> <table> { ... chain <chain> { ... } ... }`
-    let rules = extract_chain_rules(&ruleset, &q.family, &q.table, &q.chain);
-
-    match rules {
+    match chain_rules(&listing, &q.family, &q.table, &q.chain) {
         Some(rules) => json_ok(NftRulesResponse {
             family: q.family,
             table: q.table,
@@ -387,94 +394,292 @@ async fn get_nft_rules(
     }
 }
 
-/// Parse a rule line, extracting `comment "nf:..."` if present.
-/// Returns the rule text without the comment annotation, the description, and in_source flag.
-fn parse_nft_rule(rule: &str) -> NftRule {
-    // nft outputs: ... comment "nf:Some description"
-    // or: ... comment "nf:"
-    // Find the last `comment "` in the rule
-    if let Some(comment_pos) = rule.rfind(" comment \"") {
-        let after = &rule[comment_pos + 10..]; // skip ` comment "`
-        if let Some(end_quote) = after.rfind('"') {
-            let comment_value = &after[..end_quote];
-            let rule_text = rule[..comment_pos].to_string();
-
-            if let Some(desc) = comment_value.strip_prefix("nf:") {
-                let description = if desc.is_empty() {
-                    None
-                } else {
-                    Some(desc.to_string())
-                };
-                return NftRule {
-                    text: rule_text,
-                    description,
-                    in_source: true,
-                };
-            }
-        }
+/// A listed rule with the description from its `nf:` comment, if present.
+/// Rules without an `nf:` comment were added outside the generated ruleset.
+fn nft_rule(rule: &nifty_nft::listing::ListedRule) -> NftRule {
+    match rule.description() {
+        Some(description) => NftRule {
+            text: rule.text(),
+            description: Some(description.to_string()).filter(|d| !d.is_empty()),
+            in_source: true,
+        },
+        None => NftRule {
+            text: match &rule.comment {
+                Some(comment) => format!("{} comment \"{}\"", rule.text(), comment),
+                None => rule.text(),
+            },
+            description: None,
+            in_source: false,
+        },
     }
+}
 
-    // No nf: comment found — this rule was added outside the template
-    NftRule {
-        text: rule.to_string(),
-        description: None,
-        in_source: false,
-    }
+/// The rules of one chain from the `nft -j list ruleset` listing, or None
+/// when the chain does not exist.
+fn chain_rules(
+    listing: &nifty_nft::listing::Listing,
+    family: &str,
+    table: &str,
+    chain: &str,
+) -> Option<Vec<NftRule>> {
+    listing
+        .chains
+        .iter()
+        .any(|c| c.family == family && c.table == table && c.name == chain)
+        .then(|| {
+            listing
+                .rules
+                .iter()
+                .filter(|r| r.family == family && r.table == table && r.chain == chain)
+                .map(nft_rule)
+                .collect()
+        })
 }
 
-/// Extract rules for a specific chain from the full `nft list ruleset` text output.
-fn extract_chain_rules(ruleset: &str, family: &str, table: &str, chain: &str) -> Option<Vec<NftRule>> {
-    let chain_header = format!("chain {} {{", chain);
+// --- Rule counters ---
+
+/// How often the counter sampler reads the ruleset snapshot.
+const COUNTER_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// Samples kept per rule (one hour at the sample interval).
+const COUNTER_HISTORY_LEN: usize = 720;
+
+#[derive(Serialize, JsonSchema)]
+struct NftCountersResponse {
+    /// Whether any rule carries a counter (`logging { counters = true }`)
+    counters_enabled: bool,
+    sample_interval_seconds: u64,
+    rules: Vec<NftRuleCounter>,
+}
 
-    let mut in_table = false;
-    let mut in_chain = false;
-    let mut brace_depth = 0;
-    let mut rules = Vec::new();
+#[derive(Serialize, JsonSchema)]
+struct NftRuleCounter {
+    family: String,
+    table: String,
+    chain: String,
+    handle: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    in_source: bool,
+    packets: u64,
+    bytes: u64,
+    /// Rate over the most recent sample interval
+    packets_per_second: f64,
+    bytes_per_second: f64,
+    /// Rates over the last hour, oldest first
+    history: Vec<CounterRate>,
+}
 
-    for line in ruleset.lines() {
-        let trimmed = line.trim();
+#[derive(Clone, Serialize, JsonSchema)]
+struct CounterRate {
+    /// Unix time of the sample, in seconds
+    timestamp: u64,
+    packets_per_second: f64,
+    bytes_per_second: f64,
+}
 
-        if !in_table {
-            if trimmed.starts_with(&format!("table {} {}", family, table)) && trimmed.ends_with('{') {
-                in_table = true;
-                brace_depth = 1;
+/// A rule's counter as read from `nft -j list ruleset`.
+#[derive(Clone, Debug, PartialEq)]
+struct RuleCount {
+    family: String,
+    table: String,
+    chain: String,
+    handle: u64,
+    comment: Option<String>,
+    packets: u64,
+    bytes: u64,
+}
+
+/// Identifies a rule between samples: its chain, its comment, and how many
+/// earlier rules in the chain share that comment. Unlike handles this
+/// survives a ruleset reload, so history carries over.
+type RuleKey = (String, String, String, Option<String>, usize);
+
+/// Give each rule of a snapshot its key, in ruleset order.
+fn rule_keys(rules: &[RuleCount]) -> Vec<RuleKey> {
+    let mut seen: HashMap<(&str, &str, &str, Option<&str>), usize> = HashMap::new();
+    rules
+        .iter()
+        .map(|r| {
+            let nth = seen
+                .entry((r.family.as_str(), r.table.as_str(), r.chain.as_str(), r.comment.as_deref()))
+                .or_default();
+            let key = (r.family.clone(), r.table.clone(), r.chain.clone(), r.comment.clone(), *nth);
+            *nth += 1;
+            key
+        })
+        .collect()
+}
+
+struct CounterSample {
+    timestamp: u64,
+    /// Counts indexed by rule id; None for rules absent from this snapshot
+    counts: Vec<Option<(u64, u64)>>,
+}
+
+/// Counter samples shared across requests, filled by the sampler task
+/// started with the server.
+static COUNTER_HISTORY: std::sync::LazyLock<tokio::sync::Mutex<CounterHistory>> =
+    std::sync::LazyLock::new(|| tokio::sync::Mutex::new(CounterHistory::default()));
+
+#[derive(Default)]
+struct CounterHistory {
+    /// Rule ids, so samples store counts by index rather than by key
+    ids: HashMap<RuleKey, usize>,
+    samples: VecDeque<CounterSample>,
+    /// Rules in the latest snapshot, in ruleset order, with their ids
+    latest: Vec<(usize, RuleCount)>,
+    /// Modification time of the last dump sampled
+    modified: Option<std::time::SystemTime>,
+}
+
+impl CounterHistory {
+    fn record(&mut self, timestamp: u64, rules: Vec<RuleCount>) {
+        let keys = rule_keys(&rules);
+        let mut counts = vec![None; self.ids.len()];
+        let mut latest = Vec::with_capacity(rules.len());
+        for (key, rule) in keys.into_iter().zip(rules) {
+            let next = self.ids.len();
+            let id = *self.ids.entry(key).or_insert(next);
+            if id >= counts.len() {
+                counts.resize(id + 1, None);
             }
-            continue;
+            counts[id] = Some((rule.packets, rule.bytes));
+            latest.push((id, rule));
+        }
+        if self.samples.len() == COUNTER_HISTORY_LEN {
+            self.samples.pop_front();
         }
+        self.samples.push_back(CounterSample { timestamp, counts });
+        self.latest = latest;
+    }
 
-        // Track braces within the table
-        if trimmed.contains('{') {
-            if !in_chain && trimmed.starts_with("chain ") && trimmed.contains(&chain_header) {
-                in_chain = true;
-                continue;
-            }
-            if !in_chain {
-                brace_depth += 1;
+    /// Per-interval rates for one rule, oldest first. Intervals where the
+    /// counter went backwards (the ruleset was reloaded) are skipped.
+    fn rates(&self, id: usize) -> Vec<CounterRate> {
+        let count = |sample: &CounterSample| sample.counts.get(id).copied().flatten();
+        let mut rates = Vec::new();
+        let mut samples = self.samples.iter();
+        let Some(mut prev) = samples.next() else {
+            return rates;
+        };
+        for cur in samples {
+            if let (Some((p0, b0)), Some((p1, b1))) = (count(prev), count(cur)) {
+                let secs = cur.timestamp.saturating_sub(prev.timestamp).max(1) as f64;
+                if p1 >= p0 && b1 >= b0 {
+                    rates.push(CounterRate {
+                        timestamp: cur.timestamp,
+                        packets_per_second: (p1 - p0) as f64 / secs,
+                        bytes_per_second: (b1 - b0) as f64 / secs,
+                    });
+                }
             }
+            prev = cur;
         }
+        rates
+    }
+}
 
-        if in_chain {
-            if trimmed == "}" {
-                // End of chain block
-                return Some(rules);
-            }
-            // Skip type/policy header line
-            if !trimmed.is_empty()
-                && !trimmed.starts_with("type ")
-                && trimmed != "}"
-            {
-                rules.push(parse_nft_rule(trimmed));
-            }
-        } else if trimmed == "}" {
-            brace_depth -= 1;
-            if brace_depth == 0 {
-                // End of table without finding the chain
-                return None;
-            }
+/// Pull every rule that has a counter out of `nft -j list ruleset` output.
+fn parse_rule_counters(ruleset: &Value) -> Vec<RuleCount> {
+    nifty_nft::listing::parse(ruleset)
+        .rules
+        .into_iter()
+        .filter_map(|rule| {
+            let (packets, bytes) = rule.counter?;
+            Some(RuleCount {
+                family: rule.family,
+                table: rule.table,
+                chain: rule.chain,
+                handle: rule.handle?,
+                comment: rule.comment,
+                packets,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+/// Record the counters of a new ruleset dump. A dump not rewritten since
+/// the last sample is skipped, and samples are stamped with the dump's
+/// modification time, so rates cover the interval the dumps actually span.
+async fn sample_counters() {
+    let seen = COUNTER_HISTORY.lock().await.modified;
+    let Some((contents, modified)) = read_state_file_since("nft-ruleset.json", seen).await else {
+        return;
+    };
+    let Ok(parsed) = serde_json::from_str::<Value>(&contents) else {
+        return;
+    };
+    let timestamp = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut history = COUNTER_HISTORY.lock().await;
+    history.modified = Some(modified);
+    history.record(timestamp, parse_rule_counters(&parsed));
+}
+
+/// Start sampling rule counters in the background, so history builds up
+/// from startup whether or not anyone has the counters page open.
+pub fn spawn_counter_sampler() {
+    tokio::spawn(async {
+        loop {
+            sample_counters().await;
+            tokio::time::sleep(COUNTER_SAMPLE_INTERVAL).await;
         }
+    });
+}
+
+#[api_doc(
+    id = "get_nft_counters",
+    tag = "status",
+    ok = "Json<ApiResponse<NftCountersResponse>>",
+    err = "Json<ErrorBody>"
+)]
+/// nftables rule counters
+///
+/// Returns packet and byte counts for every rule with a counter, with
+/// per-second rates over the last hour, so you can see which `nf:` rules fire.
+async fn get_nft_counters(_state: State<AppState>) -> ApiJson<NftCountersResponse> {
+    if !is_state_fresh("nft-ruleset.json").await {
+        return json_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "network state data is unavailable or stale",
+        );
     }
 
-    None
+    let history = COUNTER_HISTORY.lock().await;
+    let rules: Vec<NftRuleCounter> = history
+        .latest
+        .iter()
+        .map(|(id, rule)| {
+            let rates = history.rates(*id);
+            let (packets_per_second, bytes_per_second) = rates
+                .last()
+                .map(|r| (r.packets_per_second, r.bytes_per_second))
+                .unwrap_or_default();
+            let description = rule.comment.as_deref().and_then(nifty_nft::description);
+            NftRuleCounter {
+                family: rule.family.clone(),
+                table: rule.table.clone(),
+                chain: rule.chain.clone(),
+                handle: rule.handle,
+                description: description.filter(|d| !d.is_empty()).map(|d| d.to_string()),
+                in_source: description.is_some(),
+                packets: rule.packets,
+                bytes: rule.bytes,
+                packets_per_second,
+                bytes_per_second,
+                history: rates,
+            }
+        })
+        .collect();
+
+    json_ok(NftCountersResponse {
+        counters_enabled: !rules.is_empty(),
+        sample_interval_seconds: COUNTER_SAMPLE_INTERVAL.as_secs(),
+        rules,
+    })
 }
 
 // --- Data collectors ---
@@ -551,24 +756,17 @@ async fn read_nft_chains() -> Vec<NftChain> {
         Err(_) => return vec![],
     };
 
-    let nftables = match parsed["nftables"].as_array() {
-        Some(arr) => arr,
-        None => return vec![],
-    };
-
-    nftables
-        .iter()
-        .filter_map(|item| {
-            let chain = item.get("chain")?;
-            Some(NftChain {
-                family: chain["family"].as_str()?.to_string(),
-                table: chain["table"].as_str()?.to_string(),
-                name: chain["name"].as_str()?.to_string(),
-                chain_type: chain["type"].as_str().map(|s| s.to_string()),
-                hook: chain["hook"].as_str().map(|s| s.to_string()),
-                priority: chain["prio"].as_i64(),
-                policy: chain["policy"].as_str().map(|s| s.to_string()),
-            })
+    nifty_nft::listing::parse(&parsed)
+        .chains
+        .into_iter()
+        .map(|chain| NftChain {
+            family: chain.family,
+            table: chain.table,
+            name: chain.name,
+            chain_type: chain.kind,
+            hook: chain.hook,
+            priority: chain.priority,
+            policy: chain.policy,
         })
         .collect()
 }
@@ -587,3 +785,105 @@ async fn read_switch_state() -> Option<SwitchState> {
     }
     Some(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ruleset(input_packets: u64, input_bytes: u64) -> Value {
+        serde_json::json!({
+            "nftables": [
+                {"metainfo": {"version": "1.0.9", "json_schema_version": 1}},
+                {"table": {"family": "inet", "name": "filter", "handle": 1}},
+                {"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+                           "type": "filter", "hook": "input", "prio": 0, "policy": "drop"}},
+                {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4,
+                          "comment": "nf:Drop invalid conntrack state",
+                          "expr": [
+                              {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}},
+                              {"counter": {"packets": input_packets, "bytes": input_bytes}},
+                              {"drop": null}
+                          ]}},
+                {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 5,
+                          "comment": "nf:Allow localhost loopback",
+                          "expr": [{"accept": null}]}},
+                {"rule": {"family": "inet", "table": "nat", "chain": "postrouting", "handle": 9,
+                          "expr": [{"counter": {"packets": 3, "bytes": 180}}, {"masquerade": null}]}}
+            ]
+        })
+    }
+
+    #[test]
+    fn parse_counters_from_json() {
+        let rules = parse_rule_counters(&ruleset(12, 840));
+        // The loopback rule has no counter
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].chain, "input");
+        assert_eq!(rules[0].handle, 4);
+        assert_eq!(rules[0].comment.as_deref(), Some("nf:Drop invalid conntrack state"));
+        assert_eq!((rules[0].packets, rules[0].bytes), (12, 840));
+        assert_eq!(rules[1].comment, None);
+        assert_eq!((rules[1].table.as_str(), rules[1].packets), ("nat", 3));
+        assert!(parse_rule_counters(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn chain_rules_from_json() {
+        let listing = nifty_nft::listing::parse(&ruleset(12, 840));
+        let rules = chain_rules(&listing, "inet", "filter", "input").unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].text, "ct state invalid counter packets 12 bytes 840 drop");
+        assert_eq!(rules[0].description.as_deref(), Some("Drop invalid conntrack state"));
+        assert!(rules[0].in_source);
+        assert_eq!(rules[1].text, "accept");
+        assert!(chain_rules(&listing, "inet", "filter", "forward").is_none());
+    }
+
+    #[test]
+    fn counter_rates_skip_reloads() {
+        let mut history = CounterHistory::default();
+        history.record(100, parse_rule_counters(&ruleset(10, 1000)));
+        history.record(105, parse_rule_counters(&ruleset(60, 6000)));
+        // Reloading the ruleset resets the counters
+        history.record(110, parse_rule_counters(&ruleset(5, 500)));
+        history.record(120, parse_rule_counters(&ruleset(25, 2500)));
+
+        let rates = history.rates(history.latest[0].0);
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0].timestamp, 105);
+        assert_eq!((rates[0].packets_per_second, rates[0].bytes_per_second), (10.0, 1000.0));
+        assert_eq!((rates[1].packets_per_second, rates[1].bytes_per_second), (2.0, 200.0));
+    }
+
+    #[test]
+    fn counter_history_survives_new_handles() {
+        let mut history = CounterHistory::default();
+        history.record(100, parse_rule_counters(&ruleset(10, 1000)));
+        // A reload renumbers handles but keeps the rule's comment
+        let mut reloaded = ruleset(30, 3000);
+        reloaded["nftables"][3]["rule"]["handle"] = 40.into();
+        history.record(110, parse_rule_counters(&reloaded));
+
+        let (id, rule) = &history.latest[0];
+        assert_eq!(rule.handle, 40);
+        let rates = history.rates(*id);
+        assert_eq!(rates.len(), 1);
+        assert_eq!(rates[0].packets_per_second, 2.0);
+        assert_eq!(history.ids.len(), 2);
+    }
+
+    #[test]
+    fn rule_keys_number_repeated_comments() {
+        let rule = |comment: Option<&str>| RuleCount {
+            family: "inet".into(),
+            table: "nifty_filter".into(),
+            chain: "forward".into(),
+            handle: 0,
+            comment: comment.map(String::from),
+            packets: 0,
+            bytes: 0,
+        };
+        let keys = rule_keys(&[rule(Some("nf:a")), rule(None), rule(Some("nf:a")), rule(None)]);
+        assert_eq!(keys.iter().map(|k| k.4).collect::<Vec<_>>(), vec![0, 0, 1, 1]);
+    }
+}
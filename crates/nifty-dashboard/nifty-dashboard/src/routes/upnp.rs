@@ -0,0 +1,107 @@
+use aide::axum::ApiRouter;
+use api_doc_macros::{api_doc, get_with_docs};
+use axum::Json;
+use axum::extract::State;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::process::Command;
+
+use crate::{
+    errors::ErrorBody,
+    response::{ApiJson, ApiResponse, json_ok},
+    AppState,
+};
+
+pub fn router() -> ApiRouter<AppState> {
+    ApiRouter::<AppState>::new().api_route("/", get_with_docs!(get_upnp))
+}
+
+// --- Response types ---
+
+#[derive(Serialize, JsonSchema)]
+struct UpnpResponse {
+    /// Interfaces whose clients may map ports
+    interfaces: Vec<String>,
+    /// Active port mappings
+    leases: Vec<UpnpLease>,
+    /// Whether the miniupnpd service is active
+    active: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct UpnpLease {
+    protocol: String,
+    external_port: u16,
+    internal_ip: String,
+    internal_port: u16,
+    /// Unix time the mapping expires, or 0 if it does not
+    expires: u64,
+    description: String,
+}
+
+// --- Handler ---
+
+#[api_doc(
+    id = "get_upnp",
+    tag = "upnp",
+    ok = "Json<ApiResponse<UpnpResponse>>",
+    err = "Json<ErrorBody>"
+)]
+/// UPnP / NAT-PMP / PCP status
+///
+/// Returns the miniupnpd listening interfaces (parsed from
+/// /run/miniupnpd/miniupnpd.conf) and the port mappings clients hold.
+async fn get_upnp(_state: State<AppState>) -> ApiJson<UpnpResponse> {
+    let (interfaces, leases, status) = tokio::join!(
+        read_interfaces(),
+        read_leases(),
+        Command::new("systemctl")
+            .args(["is-active", "--quiet", "nifty-miniupnpd"])
+            .status()
+    );
+
+    json_ok(UpnpResponse {
+        interfaces,
+        leases,
+        active: status.map(|s| s.success()).unwrap_or(false),
+    })
+}
+
+// --- Data collectors ---
+
+async fn read_interfaces() -> Vec<String> {
+    match tokio::fs::read_to_string("/run/miniupnpd/miniupnpd.conf").await {
+        Ok(contents) => contents
+            .lines()
+            .filter_map(|line| line.strip_prefix("listening_ip="))
+            .map(|iface| iface.trim().to_string())
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// miniupnpd lease lines look like `TCP:3074:10.20.0.50:3074:1760000000:Xbox`.
+async fn read_leases() -> Vec<UpnpLease> {
+    let contents = match tokio::fs::read_to_string("/var/lib/miniupnpd/upnp.leases").await {
+        Ok(c) => c,
+        Err(_) => return vec![],
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.splitn(6, ':').collect();
+            if parts.len() < 5 {
+                return None;
+            }
+            Some(UpnpLease {
+                protocol: parts[0].to_string(),
+                external_port: parts[1].parse().ok()?,
+                internal_ip: parts[2].to_string(),
+                internal_port: parts[3].parse().ok()?,
+                expires: parts[4].parse().ok()?,
+                description: parts.get(5).unwrap_or(&"").to_string(),
+            })
+        })
+        .collect()
+}
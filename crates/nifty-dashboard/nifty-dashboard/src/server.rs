@@ -141,6 +141,7 @@ pub async fn run(
         crate::routes::status::parse_hcl_to_json(&contents).ok()
     };
     crate::config_watcher::spawn_config_watcher(config_changed_tx.clone());
+    crate::routes::status::spawn_counter_sampler();
 
     // Shutdown broadcast channel — SSE clients receive "shutdown" before the server stops
     let (shutdown_tx, _) = tokio::sync::broadcast::channel::<()>(1);
@@ -16,6 +16,19 @@ pub async fn read_state_file(filename: &str) -> Option<String> {
     tokio::fs::read_to_string(&path).await.ok()
 }
 
+/// Read a fresh state file along with its modification time, returning None
+/// if it is missing, stale, or unchanged since `seen`.
+pub async fn read_state_file_since(filename: &str, seen: Option<SystemTime>) -> Option<(String, SystemTime)> {
+    let path = state_dir().join(filename);
+    check_freshness(&path).await?;
+    let modified = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+    if seen == Some(modified) {
+        return None;
+    }
+    let contents = tokio::fs::read_to_string(&path).await.ok()?;
+    Some((contents, modified))
+}
+
 /// Check if a state file exists and is fresh (modified within MAX_AGE).
 pub async fn is_state_fresh(filename: &str) -> bool {
     let path = state_dir().join(filename);
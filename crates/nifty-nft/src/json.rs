@@ -0,0 +1,318 @@
+//! libnftables JSON encoding (see libnftables-json(5)).
+
+use std::net::IpAddr;
+
+use serde_json::{json, Map, Value as Json};
+
+use crate::{Chain, Expr, Field, Op, RejectType, Rule, Ruleset, Set, Table, Value, Verdict};
+
+pub(crate) fn ruleset(ruleset: &Ruleset) -> Json {
+    let mut commands = vec![json!({"metainfo": {"json_schema_version": 1}})];
+    for (family, name) in &ruleset.replace {
+        let table = json!({"table": {"family": family.as_str(), "name": name}});
+        commands.push(json!({"add": table.clone()}));
+        commands.push(json!({"delete": table}));
+    }
+    for table in &ruleset.tables {
+        commands.push(json!({"add": {"table": {"family": table.family.as_str(), "name": table.name}}}));
+        for set in &table.sets {
+            commands.push(json!({"add": {"set": set_object(table, set)}}));
+        }
+        // Every chain must exist before a rule can jump to it
+        for chain in &table.chains {
+            commands.push(json!({"add": {"chain": chain_object(table, chain)}}));
+        }
+        for chain in &table.chains {
+            for rule in &chain.rules {
+                commands.push(json!({"add": {"rule": rule_object(table, chain, rule)}}));
+            }
+        }
+    }
+    json!({"nftables": commands})
+}
+
+fn set_object(table: &Table, set: &Set) -> Json {
+    let mut obj = Map::new();
+    obj.insert("family".into(), json!(table.family.as_str()));
+    obj.insert("table".into(), json!(table.name));
+    obj.insert("name".into(), json!(set.name));
+    obj.insert("type".into(), json!(set.kind));
+    if !set.flags.is_empty() {
+        obj.insert("flags".into(), json!(set.flags));
+    }
+    if set.auto_merge {
+        obj.insert("auto-merge".into(), json!(true));
+    }
+    if let Some(size) = set.size {
+        obj.insert("size".into(), json!(size));
+    }
+    if let Some(timeout) = set.timeout {
+        obj.insert("timeout".into(), json!(timeout));
+    }
+    if !set.elements.is_empty() {
+        obj.insert("elem".into(), set.elements.iter().map(|e| literal(e)).collect());
+    }
+    Json::Object(obj)
+}
+
+fn chain_object(table: &Table, chain: &Chain) -> Json {
+    let mut obj = json!({"family": table.family.as_str(), "table": table.name, "name": chain.name});
+    if let Some(hook) = &chain.hook {
+        obj["type"] = json!(hook.kind.as_str());
+        obj["hook"] = json!(hook.hook);
+        obj["prio"] = json!(hook.priority.value());
+        obj["policy"] = json!(hook.policy.as_str());
+    }
+    obj
+}
+
+fn rule_object(table: &Table, chain: &Chain, rule: &Rule) -> Json {
+    let mut obj = json!({
+        "family": table.family.as_str(),
+        "table": table.name,
+        "chain": chain.name,
+        "expr": rule.exprs.iter().map(expr).collect::<Vec<_>>(),
+    });
+    if let Some(comment) = &rule.comment {
+        obj["comment"] = json!(comment);
+    }
+    obj
+}
+
+pub(crate) fn field(field: &Field) -> Json {
+    match field {
+        Field::Meta(key) => json!({"meta": {"key": key}}),
+        Field::Payload(protocol, name) => json!({"payload": {"protocol": protocol, "field": name}}),
+        Field::Ct(key) => json!({"ct": {"key": key}}),
+        Field::FibType(flag) => json!({"fib": {"result": "type", "flags": [flag]}}),
+        Field::TcpOption(name, f) => json!({"tcp option": {"name": name, "field": f}}),
+        Field::Rt(key) => json!({"rt": {"key": key}}),
+    }
+}
+
+/// A bare nft literal as JSON: numbers, prefixes and ranges are typed,
+/// anything else (addresses, keywords) stays a string.
+fn literal(text: &str) -> Json {
+    if let Ok(n) = text.parse::<u64>() {
+        return json!(n);
+    }
+    if let Some(hex) = text.strip_prefix("0x") {
+        if let Ok(n) = u64::from_str_radix(hex, 16) {
+            return json!(n);
+        }
+    }
+    if let Some((addr, len)) = text.split_once('/') {
+        if let Ok(len) = len.parse::<u8>() {
+            return json!({"prefix": {"addr": addr, "len": len}});
+        }
+    }
+    if let Some((from, to)) = text.split_once('-') {
+        if let (Ok(from), Ok(to)) = (from.parse::<u64>(), to.parse::<u64>()) {
+            return json!({"range": [from, to]});
+        }
+        if from.parse::<IpAddr>().is_ok() && to.parse::<IpAddr>().is_ok() {
+            return json!({"range": [from, to]});
+        }
+    }
+    json!(text)
+}
+
+pub(crate) fn value(value: &Value) -> Json {
+    match value {
+        Value::Str(s) => json!(s),
+        Value::Literal(s) => literal(s),
+        Value::Set(items) => json!({"set": items.iter().map(self::value).collect::<Vec<_>>()}),
+        Value::SetRef(name) => json!(format!("@{}", name)),
+        Value::Flags(flags) if flags.len() == 1 => json!(flags[0]),
+        Value::Flags(flags) => json!(flags),
+        Value::Range(from, to) => json!({"range": [self::value(from), self::value(to)]}),
+        Value::Field(f) => field(f),
+        Value::NumgenMap { modulus, map } => json!({
+            "map": {
+                "key": {"numgen": {"mode": "random", "mod": modulus, "offset": 0}},
+                "data": {"set": map.iter().map(|(k, v)| json!([self::value(k), self::value(v)])).collect::<Vec<_>>()},
+            }
+        }),
+    }
+}
+
+pub(crate) fn expr(expr: &Expr) -> Json {
+    match expr {
+        Expr::Match { left, op, right } => {
+            let op = match (op, right) {
+                (Op::Ne, _) => "!=",
+                // Flag matches test bits rather than equality
+                (Op::Eq, Value::Flags(_)) => "in",
+                (Op::Eq, _) => "==",
+            };
+            json!({"match": {"op": op, "left": field(left), "right": value(right)}})
+        }
+        Expr::MaskedMatch { left, mask, right } => json!({
+            "match": {"op": "==", "left": {"&": [field(left), value(mask)]}, "right": value(right)}
+        }),
+        Expr::Counter => json!({"counter": {"packets": 0, "bytes": 0}}),
+        Expr::Limit { rate, per, burst, over } => {
+            let mut limit = json!({"rate": rate, "per": per});
+            if let Some(burst) = burst {
+                limit["burst"] = json!(burst);
+            }
+            if *over {
+                limit["inv"] = json!(true);
+            }
+            json!({"limit": limit})
+        }
+        Expr::CtCount { over } => json!({"ct count": {"val": over, "inv": true}}),
+        Expr::AddToSet { set, key, stmt } => {
+            let mut update = json!({"op": "add", "elem": field(key), "set": format!("@{}", set)});
+            if let Some(stmt) = stmt {
+                update["stmt"] = json!([self::expr(stmt)]);
+            }
+            json!({"set": update})
+        }
+        Expr::UpdateSet { set, key } => {
+            json!({"set": {"op": "update", "elem": field(key), "set": format!("@{}", set)}})
+        }
+        Expr::DeleteFromSet { set, key } => {
+            json!({"set": {"op": "delete", "elem": field(key), "set": format!("@{}", set)}})
+        }
+        Expr::Log { prefix, group: None } => json!({"log": {"prefix": prefix}}),
+        Expr::Log { prefix, group: Some(group) } => json!({"log": {"prefix": prefix, "group": group}}),
+        Expr::Reject { kind, code } => {
+            let kind = match kind {
+                RejectType::Icmp => "icmp",
+                RejectType::Icmpx => "icmpx",
+            };
+            json!({"reject": {"type": kind, "expr": code}})
+        }
+        Expr::Masquerade => json!({"masquerade": null}),
+        Expr::Redirect { port } => json!({"redirect": {"port": port}}),
+        Expr::Dnat { ipv6, addr, port } => {
+            let mut dnat = json!({"family": if *ipv6 { "ip6" } else { "ip" }, "addr": addr});
+            if let Some(port) = port {
+                dnat["port"] = json!(port);
+            }
+            json!({"dnat": dnat})
+        }
+        Expr::Mangle { field: f, value: v } => json!({"mangle": {"key": field(f), "value": value(v)}}),
+        Expr::Verdict(Verdict::Accept) => json!({"accept": null}),
+        Expr::Verdict(Verdict::Drop) => json!({"drop": null}),
+        Expr::Verdict(Verdict::Return) => json!({"return": null}),
+        Expr::Verdict(Verdict::Jump(target)) => json!({"jump": {"target": target}}),
+        Expr::Verdict(Verdict::Goto(target)) => json!({"goto": {"target": target}}),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChainType, Family, Policy, Priority};
+
+    #[test]
+    fn test_ruleset_commands() {
+        let mut table = Table::new(Family::Inet, "filter");
+        let mut set = Set::new("domains_v4", "ipv4_addr").flag("timeout");
+        set.timeout = Some(3600);
+        table.sets.push(set);
+        let mut input = Chain::base("input", ChainType::Filter, "input", Priority::Value(0), Policy::Drop);
+        input.push(Rule::new().jump("input_lan").describe("LAN input rules"));
+        table.chains.push(input);
+        table.chains.push(Chain::new("input_lan"));
+        let json = Ruleset::replacing(vec![table]).to_json();
+
+        let commands = json["nftables"].as_array().unwrap();
+        assert_eq!(commands[1], json!({"add": {"table": {"family": "inet", "name": "filter"}}}));
+        assert_eq!(commands[2], json!({"delete": {"table": {"family": "inet", "name": "filter"}}}));
+        assert!(!commands.iter().any(|c| c.get("flush").is_some()));
+        assert_eq!(commands[3], json!({"add": {"table": {"family": "inet", "name": "filter"}}}));
+        assert_eq!(
+            commands[4]["add"]["set"],
+            json!({"family": "inet", "table": "filter", "name": "domains_v4", "type": "ipv4_addr",
+                   "flags": ["timeout"], "timeout": 3600})
+        );
+        assert_eq!(
+            commands[5]["add"]["chain"],
+            json!({"family": "inet", "table": "filter", "name": "input",
+                   "type": "filter", "hook": "input", "prio": 0, "policy": "drop"})
+        );
+        // The jump target is declared before the rule that uses it
+        assert_eq!(commands[6]["add"]["chain"]["name"], "input_lan");
+        assert_eq!(
+            commands[7]["add"]["rule"],
+            json!({"family": "inet", "table": "filter", "chain": "input",
+                   "expr": [{"jump": {"target": "input_lan"}}], "comment": "nf:LAN input rules"})
+        );
+    }
+
+    #[test]
+    fn test_match_values() {
+        let rule = Rule::new()
+            .matching(Field::CT_STATE, Value::flags(&["established", "related"]))
+            .matching(Field::IP_SADDR, Value::set(&["10.0.0.0/8", "192.0.2.1"]))
+            .not_matching(Field::IIFNAME, Value::str("mgmt"))
+            .matching(Field::TCP_DPORT, Value::literal("1000-2000"))
+            .matching(Field::HOUR, Value::Range(Box::new(Value::str("22:00")), Box::new(Value::str("23:59:59"))))
+            .matching(Field::FIB_DADDR_TYPE, Value::literal("local"));
+        let exprs: Vec<Json> = rule.exprs.iter().map(expr).collect();
+        assert_eq!(
+            exprs[0],
+            json!({"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}})
+        );
+        assert_eq!(
+            exprs[1]["match"]["right"],
+            json!({"set": [{"prefix": {"addr": "10.0.0.0", "len": 8}}, "192.0.2.1"]})
+        );
+        assert_eq!(exprs[2]["match"]["op"], "!=");
+        assert_eq!(exprs[3]["match"]["right"], json!({"range": [1000, 2000]}));
+        assert_eq!(
+            value(&Value::set(&["10.0.0.5-10.0.0.9", "2001:db8::1-2001:db8::ff"])),
+            json!({"set": [{"range": ["10.0.0.5", "10.0.0.9"]}, {"range": ["2001:db8::1", "2001:db8::ff"]}]})
+        );
+        assert_eq!(exprs[4]["match"]["right"], json!({"range": ["22:00", "23:59:59"]}));
+        assert_eq!(exprs[5]["match"]["left"], json!({"fib": {"result": "type", "flags": ["daddr"]}}));
+
+        let masked = Expr::MaskedMatch {
+            left: Field::IP6_DADDR,
+            mask: Value::literal("::ffff:ffff:ffff:ffff"),
+            right: Value::literal("::abcd:50"),
+        };
+        assert_eq!(
+            expr(&masked),
+            json!({"match": {"op": "==", "left": {"&": [{"payload": {"protocol": "ip6", "field": "daddr"}}, "::ffff:ffff:ffff:ffff"]}, "right": "::abcd:50"}})
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(
+            expr(&Expr::Dnat { ipv6: false, addr: "10.0.0.5".into(), port: Some(80) }),
+            json!({"dnat": {"family": "ip", "addr": "10.0.0.5", "port": 80}})
+        );
+        assert_eq!(
+            expr(&Expr::Reject { kind: RejectType::Icmpx, code: "admin-prohibited".into() }),
+            json!({"reject": {"type": "icmpx", "expr": "admin-prohibited"}})
+        );
+        assert_eq!(
+            expr(&Expr::Mangle { field: Field::MARK, value: Value::literal("0x10000") }),
+            json!({"mangle": {"key": {"meta": {"key": "mark"}}, "value": 65536}})
+        );
+        assert_eq!(
+            expr(&Expr::Log { prefix: "Dropped input".into(), group: Some(2) }),
+            json!({"log": {"prefix": "Dropped input", "group": 2}})
+        );
+        let limit = Expr::Limit { rate: 10, per: "second".into(), burst: Some(20), over: true };
+        assert_eq!(
+            expr(&Expr::AddToSet { set: "meter".into(), key: Field::IP_SADDR, stmt: Some(Box::new(limit)) }),
+            json!({"set": {"op": "add", "elem": {"payload": {"protocol": "ip", "field": "saddr"}}, "set": "@meter",
+                           "stmt": [{"limit": {"rate": 10, "per": "second", "burst": 20, "inv": true}}]}})
+        );
+        assert_eq!(expr(&Expr::CtCount { over: 50 }), json!({"ct count": {"val": 50, "inv": true}}));
+        assert_eq!(
+            expr(&Expr::UpdateSet { set: "open".into(), key: Field::IP6_SADDR }),
+            json!({"set": {"op": "update", "elem": {"payload": {"protocol": "ip6", "field": "saddr"}}, "set": "@open"}})
+        );
+        assert_eq!(
+            expr(&Expr::DeleteFromSet { set: "stage".into(), key: Field::IP_SADDR }),
+            json!({"set": {"op": "delete", "elem": {"payload": {"protocol": "ip", "field": "saddr"}}, "set": "@stage"}})
+        );
+    }
+}
@@ -0,0 +1,933 @@
+//! A typed model of an nftables ruleset: tables holding sets and chains,
+//! chains holding rules, rules made of match expressions and statements.
+//!
+//! A [`Ruleset`] prints as `nft -f` syntax through `Display` and converts
+//! to libnftables JSON with [`Ruleset::to_json`]. The [`listing`] module
+//! reads `nft -j list ruleset` output back, and [`split_comment`] /
+//! [`description`] implement the `nf:` comment convention that marks rules
+//! generated from the config. [`objects`] resolves the host and group
+//! objects rules refer to.
+
+use std::fmt;
+
+mod json;
+pub mod listing;
+pub mod objects;
+
+/// Prefix of the comment on every rule generated from the config.
+pub const DESCRIPTION_PREFIX: &str = "nf:";
+
+/// Names of the `inet` tables nifty-filter generates and owns. They carry a
+/// prefix so tables of other software on the box are never touched.
+pub mod tables {
+    pub const FILTER: &str = "nifty_filter";
+    pub const NAT: &str = "nifty_nat";
+    pub const WAN_ROUTING: &str = "nifty_wan_routing";
+    pub const MANGLE: &str = "nifty_mangle";
+    /// Every owned table, in the order they are created.
+    pub const OWNED: [&str; 4] = [FILTER, NAT, WAN_ROUTING, MANGLE];
+    /// Tables generated before the prefix, under a `flush ruleset`. A router
+    /// upgraded in place still has them loaded until they are deleted.
+    pub const LEGACY: [&str; 3] = ["filter", "nat", "mangle"];
+}
+
+/// Longest comment nft accepts, in bytes.
+pub const COMMENT_MAX_LEN: usize = 128;
+
+/// Address family of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Inet,
+    Ip,
+    Ip6,
+    Bridge,
+    Netdev,
+}
+
+impl Family {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Inet => "inet",
+            Family::Ip => "ip",
+            Family::Ip6 => "ip6",
+            Family::Bridge => "bridge",
+            Family::Netdev => "netdev",
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A complete ruleset, loaded in one transaction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Ruleset {
+    /// Tables deleted at the start of the transaction, whether or not they
+    /// exist. Tables not listed here are left alone.
+    pub replace: Vec<(Family, String)>,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub family: Family,
+    pub name: String,
+    pub sets: Vec<Set>,
+    pub chains: Vec<Chain>,
+}
+
+impl Table {
+    pub fn new(family: Family, name: &str) -> Self {
+        Table { family, name: name.to_string(), sets: Vec::new(), chains: Vec::new() }
+    }
+
+    pub fn chain(&self, name: &str) -> Option<&Chain> {
+        self.chains.iter().find(|c| c.name == name)
+    }
+
+    pub fn set(&self, name: &str) -> Option<&Set> {
+        self.sets.iter().find(|s| s.name == name)
+    }
+
+    /// Whether any rule in the table uses the named set.
+    pub fn references(&self, set: &str) -> bool {
+        self.chains.iter().flat_map(|c| &c.rules).flat_map(|r| &r.exprs).any(|e| e.references(set))
+    }
+}
+
+/// A named set. Elements are nft literals, e.g. `10.0.0.0/8` or `22`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub name: String,
+    /// Element type, e.g. `ipv4_addr` or `inet_service`
+    pub kind: String,
+    pub flags: Vec<String>,
+    pub auto_merge: bool,
+    /// Maximum number of elements, for sets filled from the packet path
+    pub size: Option<u32>,
+    /// Default element timeout in seconds
+    pub timeout: Option<u32>,
+    pub elements: Vec<String>,
+}
+
+impl Set {
+    pub fn new(name: &str, kind: &str) -> Self {
+        Set {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            flags: Vec::new(),
+            auto_merge: false,
+            size: None,
+            timeout: None,
+            elements: Vec::new(),
+        }
+    }
+
+    pub fn flag(mut self, flag: &str) -> Self {
+        self.flags.push(flag.to_string());
+        self
+    }
+}
+
+/// Chain type of a base chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainType {
+    Filter,
+    Nat,
+    Route,
+}
+
+impl ChainType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChainType::Filter => "filter",
+            ChainType::Nat => "nat",
+            ChainType::Route => "route",
+        }
+    }
+}
+
+/// Base chain priority, by number or by its standard name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Value(i32),
+    Mangle,
+}
+
+impl Priority {
+    pub fn value(&self) -> i32 {
+        match self {
+            Priority::Value(v) => *v,
+            Priority::Mangle => -150,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Value(v) => write!(f, "{}", v),
+            Priority::Mangle => f.write_str("mangle"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Accept,
+    Drop,
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Accept => "accept",
+            Policy::Drop => "drop",
+        }
+    }
+}
+
+/// Hook attachment of a base chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub kind: ChainType,
+    pub hook: String,
+    pub priority: Priority,
+    pub policy: Policy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub name: String,
+    /// `None` for a regular chain reached by jump
+    pub hook: Option<Hook>,
+    pub rules: Vec<Rule>,
+}
+
+impl Chain {
+    /// A regular chain.
+    pub fn new(name: &str) -> Self {
+        Chain { name: name.to_string(), hook: None, rules: Vec::new() }
+    }
+
+    /// A base chain attached to `hook`.
+    pub fn base(name: &str, kind: ChainType, hook: &str, priority: Priority, policy: Policy) -> Self {
+        Chain {
+            name: name.to_string(),
+            hook: Some(Hook { kind, hook: hook.to_string(), priority, policy }),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+}
+
+/// The left-hand side of a match, or the target of a `set` statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// `meta <key>`; iifname and oifname print without the `meta` keyword
+    Meta(&'static str),
+    /// `<protocol> <field>`, e.g. `ip saddr`
+    Payload(&'static str, &'static str),
+    /// `ct <key>`
+    Ct(&'static str),
+    /// `fib <flag> type`
+    FibType(&'static str),
+    /// `tcp option <name> <field>`
+    TcpOption(&'static str, &'static str),
+    /// `rt <key>`
+    Rt(&'static str),
+}
+
+impl Field {
+    pub const IIFNAME: Field = Field::Meta("iifname");
+    pub const OIFNAME: Field = Field::Meta("oifname");
+    pub const NFPROTO: Field = Field::Meta("nfproto");
+    pub const MARK: Field = Field::Meta("mark");
+    pub const DAY: Field = Field::Meta("day");
+    pub const HOUR: Field = Field::Meta("hour");
+    pub const ETHER_SADDR: Field = Field::Payload("ether", "saddr");
+    pub const IP_SADDR: Field = Field::Payload("ip", "saddr");
+    pub const IP_DADDR: Field = Field::Payload("ip", "daddr");
+    pub const IP_DSCP: Field = Field::Payload("ip", "dscp");
+    pub const IP6_SADDR: Field = Field::Payload("ip6", "saddr");
+    pub const IP6_DADDR: Field = Field::Payload("ip6", "daddr");
+    pub const IP6_DSCP: Field = Field::Payload("ip6", "dscp");
+    pub const TCP_SPORT: Field = Field::Payload("tcp", "sport");
+    pub const TCP_DPORT: Field = Field::Payload("tcp", "dport");
+    pub const TCP_FLAGS: Field = Field::Payload("tcp", "flags");
+    pub const UDP_SPORT: Field = Field::Payload("udp", "sport");
+    pub const UDP_DPORT: Field = Field::Payload("udp", "dport");
+    pub const ICMP_TYPE: Field = Field::Payload("icmp", "type");
+    pub const ICMPV6_TYPE: Field = Field::Payload("icmpv6", "type");
+    pub const CT_STATE: Field = Field::Ct("state");
+    pub const CT_STATUS: Field = Field::Ct("status");
+    pub const CT_MARK: Field = Field::Ct("mark");
+    pub const CT_DIRECTION: Field = Field::Ct("direction");
+    pub const FIB_DADDR_TYPE: Field = Field::FibType("daddr");
+    pub const TCP_MAXSEG_SIZE: Field = Field::TcpOption("maxseg", "size");
+    pub const RT_MTU: Field = Field::Rt("mtu");
+
+    /// `ip saddr` or `ip6 saddr`.
+    pub fn saddr(ipv6: bool) -> Field {
+        if ipv6 { Field::IP6_SADDR } else { Field::IP_SADDR }
+    }
+
+    /// `ip daddr` or `ip6 daddr`.
+    pub fn daddr(ipv6: bool) -> Field {
+        if ipv6 { Field::IP6_DADDR } else { Field::IP_DADDR }
+    }
+
+    /// `tcp dport` or `udp dport`.
+    pub fn dport(protocol: &str) -> Field {
+        if protocol == "udp" { Field::UDP_DPORT } else { Field::TCP_DPORT }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Meta(key @ ("iifname" | "oifname")) => f.write_str(key),
+            Field::Meta(key) => write!(f, "meta {}", key),
+            Field::Payload(proto, field) => write!(f, "{} {}", proto, field),
+            Field::Ct(key) => write!(f, "ct {}", key),
+            Field::FibType(flag) => write!(f, "fib {} type", flag),
+            Field::TcpOption(name, field) => write!(f, "tcp option {} {}", name, field),
+            Field::Rt(key) => write!(f, "rt {}", key),
+        }
+    }
+}
+
+/// The right-hand side of a match or `set` statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Quoted string, e.g. an interface name
+    Str(String),
+    /// Bare literal: address, prefix, number, range or keyword
+    Literal(String),
+    /// Anonymous set `{ a, b }`
+    Set(Vec<Value>),
+    /// Named set reference `@name`
+    SetRef(String),
+    /// Comma-separated flags, e.g. `established,related`
+    Flags(Vec<String>),
+    /// `a-b` between two values
+    Range(Box<Value>, Box<Value>),
+    /// The value of another field, e.g. `ct mark`
+    Field(Field),
+    /// `numgen random mod N map { range : value, ... }`
+    NumgenMap { modulus: u32, map: Vec<(Value, Value)> },
+}
+
+impl Value {
+    /// Whether the value is, or contains, a reference to the named set.
+    pub fn references(&self, set: &str) -> bool {
+        match self {
+            Value::SetRef(name) => name == set,
+            Value::Set(items) => items.iter().any(|v| v.references(set)),
+            Value::Range(from, to) => from.references(set) || to.references(set),
+            Value::NumgenMap { map, .. } => map.iter().any(|(k, v)| k.references(set) || v.references(set)),
+            Value::Str(_) | Value::Literal(_) | Value::Flags(_) | Value::Field(_) => false,
+        }
+    }
+
+    pub fn str(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    pub fn literal(s: impl ToString) -> Value {
+        Value::Literal(s.to_string())
+    }
+
+    /// A set of bare literals.
+    pub fn set<T: ToString>(items: &[T]) -> Value {
+        Value::Set(items.iter().map(|item| Value::Literal(item.to_string())).collect())
+    }
+
+    pub fn flags(flags: &[&str]) -> Value {
+        Value::Flags(flags.iter().map(|f| f.to_string()).collect())
+    }
+
+    /// Read a value written in nft syntax: `"name"`, `@set`, `{ a, b }` or
+    /// a bare literal.
+    pub fn parse(text: &str) -> Value {
+        let text = text.trim();
+        if let Some(inner) = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+            Value::Set(inner.split(',').map(str::trim).filter(|e| !e.is_empty()).map(Value::parse).collect())
+        } else if let Some(name) = text.strip_prefix('@') {
+            Value::SetRef(name.to_string())
+        } else if let Some(s) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            Value::Str(s.to_string())
+        } else {
+            Value::Literal(text.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "\"{}\"", s),
+            Value::Literal(s) => f.write_str(s),
+            Value::Set(items) => {
+                let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+                write!(f, "{{ {} }}", items.join(", "))
+            }
+            Value::SetRef(name) => write!(f, "@{}", name),
+            Value::Flags(flags) => f.write_str(&flags.join(",")),
+            Value::Range(from, to) => write!(f, "{}-{}", from, to),
+            Value::Field(field) => write!(f, "{}", field),
+            Value::NumgenMap { modulus, map } => {
+                let entries: Vec<String> = map.iter().map(|(k, v)| format!("{} : {}", k, v)).collect();
+                write!(f, "numgen random mod {} map {{ {} }}", modulus, entries.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Return,
+    Jump(String),
+    Goto(String),
+}
+
+/// ICMP flavour of a reject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectType {
+    /// `icmp type`, IPv4 only
+    Icmp,
+    /// `icmpx type`, either family
+    Icmpx,
+}
+
+/// One element of a rule: a match or a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Match { left: Field, op: Op, right: Value },
+    /// `<field> & <mask> == <value>`, matching only the masked bits
+    MaskedMatch { left: Field, mask: Value, right: Value },
+    Counter,
+    /// `limit rate [over] <rate>/<per> [burst <n> packets]`; with `over`
+    /// it matches packets exceeding the rate instead
+    Limit { rate: u32, per: String, burst: Option<u32>, over: bool },
+    /// `ct count over <n>`, inside a set update keyed by source
+    CtCount { over: u32 },
+    /// `add @<set> { <key> [<stmt>] }`, adding the packet's key to a
+    /// dynamic set and evaluating `stmt` per element
+    AddToSet { set: String, key: Field, stmt: Option<Box<Expr>> },
+    /// `update @<set> { <key> }`, adding the packet's key to a dynamic set
+    /// or restarting its timeout if already there
+    UpdateSet { set: String, key: Field },
+    /// `delete @<set> { <key> }`, removing the packet's key from a dynamic set
+    DeleteFromSet { set: String, key: Field },
+    Log { prefix: String, group: Option<u16> },
+    Reject { kind: RejectType, code: String },
+    Masquerade,
+    /// `redirect to :port`
+    Redirect { port: u16 },
+    /// `dnat to addr[:port]`, with IPv6 addresses bracketed
+    Dnat { ipv6: bool, addr: String, port: Option<u16> },
+    /// `<field> set <value>`
+    Mangle { field: Field, value: Value },
+    Verdict(Verdict),
+}
+
+impl Expr {
+    /// Whether the expression matches against or updates the named set.
+    pub fn references(&self, set: &str) -> bool {
+        match self {
+            Expr::Match { right, .. } => right.references(set),
+            Expr::MaskedMatch { mask, right, .. } => mask.references(set) || right.references(set),
+            Expr::AddToSet { set: name, .. } | Expr::UpdateSet { set: name, .. } | Expr::DeleteFromSet { set: name, .. } => {
+                name == set
+            }
+            Expr::Mangle { value, .. } => value.references(set),
+            _ => false,
+        }
+    }
+
+    pub fn eq(left: Field, right: Value) -> Expr {
+        Expr::Match { left, op: Op::Eq, right }
+    }
+
+    pub fn ne(left: Field, right: Value) -> Expr {
+        Expr::Match { left, op: Op::Ne, right }
+    }
+
+    /// Whether the expression only selects packets. A set update with a
+    /// limit or count selects the sources that exceed it.
+    pub fn is_match(&self) -> bool {
+        matches!(self, Expr::Match { .. } | Expr::MaskedMatch { .. } | Expr::AddToSet { stmt: Some(_), .. })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Match { left, op: Op::Eq, right } => write!(f, "{} {}", left, right),
+            Expr::Match { left, op: Op::Ne, right } => write!(f, "{} != {}", left, right),
+            Expr::MaskedMatch { left, mask, right } => write!(f, "{} & {} == {}", left, mask, right),
+            Expr::Counter => f.write_str("counter"),
+            Expr::Limit { rate, per, burst, over } => {
+                write!(f, "limit rate {}{}/{}", if *over { "over " } else { "" }, rate, per)?;
+                match burst {
+                    Some(burst) => write!(f, " burst {} packets", burst),
+                    None => Ok(()),
+                }
+            }
+            Expr::CtCount { over } => write!(f, "ct count over {}", over),
+            Expr::AddToSet { set, key, stmt: None } => write!(f, "add @{} {{ {} }}", set, key),
+            Expr::AddToSet { set, key, stmt: Some(stmt) } => write!(f, "add @{} {{ {} {} }}", set, key, stmt),
+            Expr::UpdateSet { set, key } => write!(f, "update @{} {{ {} }}", set, key),
+            Expr::DeleteFromSet { set, key } => write!(f, "delete @{} {{ {} }}", set, key),
+            Expr::Log { prefix, group: None } => write!(f, "log prefix \"{}\"", prefix),
+            Expr::Log { prefix, group: Some(group) } => write!(f, "log prefix \"{}\" group {}", prefix, group),
+            Expr::Reject { kind: RejectType::Icmp, code } => write!(f, "reject with icmp type {}", code),
+            Expr::Reject { kind: RejectType::Icmpx, code } => write!(f, "reject with icmpx type {}", code),
+            Expr::Masquerade => f.write_str("masquerade"),
+            Expr::Redirect { port } => write!(f, "redirect to :{}", port),
+            Expr::Dnat { ipv6, addr, port } => {
+                let addr = if *ipv6 && port.is_some() { format!("[{}]", addr) } else { addr.clone() };
+                match port {
+                    Some(port) => write!(f, "dnat to {}:{}", addr, port),
+                    None => write!(f, "dnat to {}", addr),
+                }
+            }
+            Expr::Mangle { field, value } => write!(f, "{} set {}", field, value),
+            Expr::Verdict(Verdict::Accept) => f.write_str("accept"),
+            Expr::Verdict(Verdict::Drop) => f.write_str("drop"),
+            Expr::Verdict(Verdict::Return) => f.write_str("return"),
+            Expr::Verdict(Verdict::Jump(chain)) => write!(f, "jump {}", chain),
+            Expr::Verdict(Verdict::Goto(chain)) => write!(f, "goto {}", chain),
+        }
+    }
+}
+
+/// A rule: expressions evaluated left to right, and an optional comment.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Rule {
+    pub exprs: Vec<Expr>,
+    pub comment: Option<String>,
+}
+
+impl Rule {
+    pub fn new() -> Self {
+        Rule::default()
+    }
+
+    /// Append a match `left right`.
+    pub fn matching(mut self, left: Field, right: Value) -> Self {
+        self.exprs.push(Expr::eq(left, right));
+        self
+    }
+
+    /// Append a match `left != right`.
+    pub fn not_matching(mut self, left: Field, right: Value) -> Self {
+        self.exprs.push(Expr::ne(left, right));
+        self
+    }
+
+    /// Append any expression.
+    pub fn then(mut self, expr: Expr) -> Self {
+        self.exprs.push(expr);
+        self
+    }
+
+    pub fn extend(mut self, exprs: impl IntoIterator<Item = Expr>) -> Self {
+        self.exprs.extend(exprs);
+        self
+    }
+
+    pub fn verdict(self, verdict: Verdict) -> Self {
+        self.then(Expr::Verdict(verdict))
+    }
+
+    pub fn accept(self) -> Self {
+        self.verdict(Verdict::Accept)
+    }
+
+    pub fn drop(self) -> Self {
+        self.verdict(Verdict::Drop)
+    }
+
+    pub fn jump(self, chain: &str) -> Self {
+        self.verdict(Verdict::Jump(chain.to_string()))
+    }
+
+    /// Mark the rule as generated, with a human-readable description.
+    /// nft comments cannot hold double quotes, so they become single ones;
+    /// control characters become spaces and long descriptions are cut to
+    /// fit [`COMMENT_MAX_LEN`].
+    pub fn describe(mut self, description: &str) -> Self {
+        let mut comment = DESCRIPTION_PREFIX.to_string();
+        for c in description.chars() {
+            let c = match c {
+                '"' => '\'',
+                c if c.is_control() => ' ',
+                c => c,
+            };
+            if comment.len() + c.len_utf8() > COMMENT_MAX_LEN {
+                break;
+            }
+            comment.push(c);
+        }
+        self.comment = Some(comment);
+        self
+    }
+
+    /// The description of a generated rule.
+    pub fn description(&self) -> Option<&str> {
+        self.comment.as_deref().and_then(description)
+    }
+
+    /// Insert a counter ahead of the rule's first statement, so it counts
+    /// every packet the matches select.
+    pub fn add_counter(&mut self) {
+        if self.exprs.contains(&Expr::Counter) {
+            return;
+        }
+        let at = self.exprs.iter().position(|e| !e.is_match()).unwrap_or(self.exprs.len());
+        self.exprs.insert(at, Expr::Counter);
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exprs: Vec<String> = self.exprs.iter().map(|e| e.to_string()).collect();
+        f.write_str(&exprs.join(" "))?;
+        if let Some(comment) = &self.comment {
+            write!(f, " comment \"{}\"", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a timeout in seconds the way nft does: `1h`, `30m` or `45s`.
+fn format_timeout(secs: u32) -> String {
+    if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+impl fmt::Display for Set {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    set {} {{", self.name)?;
+        writeln!(f, "        type {}", self.kind)?;
+        if let Some(size) = self.size {
+            writeln!(f, "        size {}", size)?;
+        }
+        if !self.flags.is_empty() {
+            writeln!(f, "        flags {}", self.flags.join(", "))?;
+        }
+        if self.auto_merge {
+            writeln!(f, "        auto-merge")?;
+        }
+        if let Some(timeout) = self.timeout {
+            writeln!(f, "        timeout {}", format_timeout(timeout))?;
+        }
+        if !self.elements.is_empty() {
+            writeln!(f, "        elements = {{ {} }}", self.elements.join(", "))?;
+        }
+        writeln!(f, "    }}")
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "    chain {} {{", self.name)?;
+        if let Some(hook) = &self.hook {
+            writeln!(
+                f,
+                "        type {} hook {} priority {}; policy {};",
+                hook.kind.as_str(),
+                hook.hook,
+                hook.priority,
+                hook.policy.as_str()
+            )?;
+        }
+        for rule in &self.rules {
+            writeln!(f, "        {}", rule)?;
+        }
+        writeln!(f, "    }}")
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "table {} {} {{", self.family, self.name)?;
+        let mut first = true;
+        for set in &self.sets {
+            if !first {
+                writeln!(f)?;
+            }
+            write!(f, "{}", set)?;
+            first = false;
+        }
+        for chain in &self.chains {
+            if !first {
+                writeln!(f)?;
+            }
+            write!(f, "{}", chain)?;
+            first = false;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "#!/usr/sbin/nft -f")?;
+        if !self.replace.is_empty() {
+            writeln!(f)?;
+        }
+        // Declaring the table first makes the delete succeed when it is missing
+        for (family, name) in &self.replace {
+            writeln!(f, "table {} {}", family, name)?;
+            writeln!(f, "delete table {} {}", family, name)?;
+        }
+        for table in &self.tables {
+            writeln!(f)?;
+            write!(f, "{}", table)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ruleset {
+    /// A ruleset that replaces exactly the tables it contains.
+    pub fn replacing(tables: Vec<Table>) -> Self {
+        let replace = tables.iter().map(|t| (t.family, t.name.clone())).collect();
+        Ruleset { replace, tables }
+    }
+
+    pub fn table(&self, family: Family, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.family == family && t.name == name)
+    }
+
+    /// Add a counter to every rule.
+    pub fn add_counters(&mut self) {
+        for table in &mut self.tables {
+            for chain in &mut table.chains {
+                chain.rules.iter_mut().for_each(Rule::add_counter);
+            }
+        }
+    }
+
+    /// The ruleset as a libnftables JSON command list (`nft -j -f`).
+    pub fn to_json(&self) -> serde_json::Value {
+        json::ruleset(self)
+    }
+}
+
+/// Split a rule line from `nft list ruleset` into the rule text and the
+/// contents of its trailing `comment "..."`, if any.
+pub fn split_comment(line: &str) -> (&str, Option<&str>) {
+    if let Some(pos) = line.rfind(" comment \"") {
+        let after = &line[pos + " comment \"".len()..];
+        if let Some(end) = after.rfind('"') {
+            return (&line[..pos], Some(&after[..end]));
+        }
+    }
+    (line, None)
+}
+
+/// The description in an `nf:` comment, or `None` for a comment that was
+/// not generated from the config. An empty description is `Some("")`.
+pub fn description(comment: &str) -> Option<&str> {
+    comment.strip_prefix(DESCRIPTION_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Ruleset {
+        let mut table = Table::new(Family::Inet, "filter");
+        let mut blocked = Set::new("blocked_v4", "ipv4_addr").flag("interval");
+        blocked.auto_merge = true;
+        blocked.elements = vec!["192.0.2.0/24".into()];
+        table.sets.push(blocked);
+        let mut input = Chain::base("input", ChainType::Filter, "input", Priority::Value(0), Policy::Drop);
+        input.push(
+            Rule::new()
+                .matching(Field::CT_STATE, Value::flags(&["established", "related"]))
+                .accept()
+                .describe("Allow established/related connections"),
+        );
+        input.push(
+            Rule::new()
+                .matching(Field::IIFNAME, Value::parse(r#"{ "wan", "wan2" }"#))
+                .matching(Field::TCP_DPORT, Value::set(&[22, 443]))
+                .accept()
+                .describe("Allow TCP ports on WAN"),
+        );
+        input.push(Rule::new().matching(Field::IP_SADDR, Value::SetRef("blocked_v4".into())).drop());
+        table.chains.push(input);
+        Ruleset::replacing(vec![table])
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            sample().to_string(),
+            r#"#!/usr/sbin/nft -f
+
+table inet filter
+delete table inet filter
+
+table inet filter {
+    set blocked_v4 {
+        type ipv4_addr
+        flags interval
+        auto-merge
+        elements = { 192.0.2.0/24 }
+    }
+
+    chain input {
+        type filter hook input priority 0; policy drop;
+        ct state established,related accept comment "nf:Allow established/related connections"
+        iifname { "wan", "wan2" } tcp dport { 22, 443 } accept comment "nf:Allow TCP ports on WAN"
+        ip saddr @blocked_v4 drop
+    }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let rule = Rule::new()
+            .matching(Field::IIFNAME, Value::str("lan"))
+            .matching(Field::IP6_SADDR, Value::literal("fd00::/64"))
+            .matching(Field::TCP_DPORT, Value::literal(8080))
+            .then(Expr::Dnat { ipv6: true, addr: "fd00::5".into(), port: Some(80) });
+        assert_eq!(rule.to_string(), r#"iifname "lan" ip6 saddr fd00::/64 tcp dport 8080 dnat to [fd00::5]:80"#);
+
+        let rule = Rule::new()
+            .then(Expr::MaskedMatch {
+                left: Field::IP6_DADDR,
+                mask: Value::literal("::ffff:ffff:ffff:ffff"),
+                right: Value::literal("::abcd:50"),
+            })
+            .accept();
+        assert_eq!(rule.to_string(), "ip6 daddr & ::ffff:ffff:ffff:ffff == ::abcd:50 accept");
+
+        let rule = Rule::new()
+            .matching(Field::CT_STATE, Value::literal("new"))
+            .matching(Field::CT_MARK, Value::literal(0))
+            .then(Expr::Mangle {
+                field: Field::CT_MARK,
+                value: Value::NumgenMap {
+                    modulus: 3,
+                    map: vec![
+                        (Value::Range(Box::new(Value::literal(0)), Box::new(Value::literal(1))), Value::literal(101)),
+                        (Value::literal(2), Value::literal(102)),
+                    ],
+                },
+            });
+        assert_eq!(
+            rule.to_string(),
+            "ct state new ct mark 0 ct mark set numgen random mod 3 map { 0-1 : 101, 2 : 102 }"
+        );
+
+        let rule = Rule::new()
+            .not_matching(Field::MARK, Value::literal(0))
+            .then(Expr::Mangle { field: Field::MARK, value: Value::Field(Field::CT_MARK) });
+        assert_eq!(rule.to_string(), "meta mark != 0 meta mark set ct mark");
+
+        let rule = Rule::new()
+            .matching(Field::TCP_FLAGS, Value::flags(&["syn"]))
+            .then(Expr::Mangle { field: Field::TCP_MAXSEG_SIZE, value: Value::Field(Field::RT_MTU) });
+        assert_eq!(rule.to_string(), "tcp flags syn tcp option maxseg size set rt mtu");
+
+        let rule = Rule::new()
+            .matching(Field::TCP_DPORT, Value::literal(22))
+            .then(Expr::AddToSet {
+                set: "meter".into(),
+                key: Field::IP_SADDR,
+                stmt: Some(Box::new(Expr::Limit { rate: 10, per: "second".into(), burst: Some(20), over: true })),
+            })
+            .then(Expr::AddToSet { set: "banned".into(), key: Field::IP_SADDR, stmt: None })
+            .drop();
+        assert_eq!(
+            rule.to_string(),
+            "tcp dport 22 add @meter { ip saddr limit rate over 10/second burst 20 packets } add @banned { ip saddr } drop"
+        );
+        let rule = Rule::new()
+            .then(Expr::AddToSet { set: "conns".into(), key: Field::IP6_SADDR, stmt: Some(Box::new(Expr::CtCount { over: 50 })) })
+            .drop();
+        assert_eq!(rule.to_string(), "add @conns { ip6 saddr ct count over 50 } drop");
+        let rule = Rule::new().then(Expr::UpdateSet { set: "open".into(), key: Field::IP_DADDR }).accept();
+        assert_eq!(rule.to_string(), "update @open { ip daddr } accept");
+        let rule = Rule::new().then(Expr::DeleteFromSet { set: "stage".into(), key: Field::IP6_SADDR });
+        assert_eq!(rule.to_string(), "delete @stage { ip6 saddr }");
+    }
+
+    #[test]
+    fn test_add_counter() {
+        let mut rule = Rule::new()
+            .matching(Field::IIFNAME, Value::str("wan"))
+            .then(Expr::Limit { rate: 5, per: "minute".into(), burst: None, over: false })
+            .then(Expr::Log { prefix: "Dropped: ".into(), group: None })
+            .describe("Log");
+        rule.add_counter();
+        rule.add_counter();
+        assert_eq!(
+            rule.to_string(),
+            r#"iifname "wan" counter limit rate 5/minute log prefix "Dropped: " comment "nf:Log""#
+        );
+
+        let mut rule = Rule::new().drop();
+        rule.add_counter();
+        assert_eq!(rule.to_string(), "counter drop");
+
+        // Only sources over the limit are counted
+        let mut rule = Rule::new()
+            .then(Expr::AddToSet { set: "conns".into(), key: Field::IP_SADDR, stmt: Some(Box::new(Expr::CtCount { over: 5 })) })
+            .drop();
+        rule.add_counter();
+        assert_eq!(rule.to_string(), "add @conns { ip saddr ct count over 5 } counter drop");
+    }
+
+    #[test]
+    fn test_value_parse() {
+        assert_eq!(Value::parse("@hosts_nas_v4"), Value::SetRef("hosts_nas_v4".into()));
+        assert_eq!(Value::parse("\"wan\""), Value::str("wan"));
+        assert_eq!(Value::parse("{ 10.0.0.0/8, 22 }"), Value::set(&["10.0.0.0/8", "22"]));
+        assert_eq!(Value::parse("1000-2000"), Value::literal("1000-2000"));
+    }
+
+    #[test]
+    fn test_comments() {
+        let line = r#"iifname "lo" accept comment "nf:Allow localhost loopback""#;
+        assert_eq!(split_comment(line), (r#"iifname "lo" accept"#, Some("nf:Allow localhost loopback")));
+        assert_eq!(split_comment("ip saddr 10.0.0.1 drop"), ("ip saddr 10.0.0.1 drop", None));
+        assert_eq!(description("nf:Default drop"), Some("Default drop"));
+        assert_eq!(description("nf:"), Some(""));
+        assert_eq!(description("added by hand"), None);
+        assert_eq!(sample().tables[0].chains[0].rules[0].description(), Some("Allow established/related connections"));
+
+        let rule = Rule::new().accept().describe("Forward \"web\"\nserver");
+        assert_eq!(rule.to_string(), r#"accept comment "nf:Forward 'web' server""#);
+        let rule = Rule::new().describe(&"é".repeat(100));
+        assert_eq!(rule.comment.as_ref().map(|c| c.len()), Some(127));
+    }
+}
@@ -0,0 +1,391 @@
+//! Reading the live ruleset from `nft -j list ruleset`.
+
+use serde_json::Value as Json;
+
+use crate::description;
+
+/// A chain as listed by nft.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedChain {
+    pub family: String,
+    pub table: String,
+    pub name: String,
+    pub handle: Option<u64>,
+    pub kind: Option<String>,
+    pub hook: Option<String>,
+    pub priority: Option<i64>,
+    pub policy: Option<String>,
+}
+
+/// A rule as listed by nft. Its expressions are kept as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedRule {
+    pub family: String,
+    pub table: String,
+    pub chain: String,
+    pub handle: Option<u64>,
+    pub comment: Option<String>,
+    /// Packets and bytes, when the rule has a counter
+    pub counter: Option<(u64, u64)>,
+    pub expr: Vec<Json>,
+}
+
+impl ListedRule {
+    /// The description of a rule generated from the config.
+    pub fn description(&self) -> Option<&str> {
+        self.comment.as_deref().and_then(description)
+    }
+
+    /// The rule's statements in nft syntax, without its comment.
+    pub fn text(&self) -> String {
+        self.expr.iter().map(statement).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Whether the rule sets `key` with a mangle statement, e.g. "mark" or
+    /// "dscp".
+    pub fn sets(&self, key: &str) -> bool {
+        self.expr.iter().any(|e| {
+            let target = &e["mangle"]["key"];
+            target["meta"]["key"] == key || target["payload"]["field"] == key || target["ct"]["key"] == key
+        })
+    }
+}
+
+/// The chains and rules of a ruleset listing, in listing order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Listing {
+    pub chains: Vec<ListedChain>,
+    pub rules: Vec<ListedRule>,
+}
+
+fn string(obj: &Json, key: &str) -> Option<String> {
+    obj[key].as_str().map(|s| s.to_string())
+}
+
+/// Meta keys nft writes without the `meta` keyword.
+const UNQUALIFIED_META: &[&str] = &["iifname", "oifname", "iif", "oif", "iiftype", "oiftype"];
+
+/// An nft expression (the operand of a match or statement) as text.
+fn expression(e: &Json) -> String {
+    match e {
+        Json::String(s) => s.clone(),
+        Json::Number(n) => n.to_string(),
+        Json::Bool(b) => b.to_string(),
+        Json::Null => String::new(),
+        Json::Array(items) => items.iter().map(expression).collect::<Vec<_>>().join(","),
+        Json::Object(obj) => {
+            let Some((kind, v)) = obj.iter().next() else {
+                return String::new();
+            };
+            match kind.as_str() {
+                "meta" => {
+                    let key = v["key"].as_str().unwrap_or_default();
+                    if UNQUALIFIED_META.contains(&key) {
+                        key.to_string()
+                    } else {
+                        format!("meta {}", key)
+                    }
+                }
+                "payload" => format!("{} {}", expression(&v["protocol"]), expression(&v["field"])),
+                "ct" => format!("ct {}", expression(&v["key"])),
+                "rt" => format!("rt {}", expression(&v["key"])),
+                "fib" => format!("fib {} {}", expression(&v["flags"]).replace(',', " . "), expression(&v["result"])),
+                "tcp option" => format!("tcp option {} {}", expression(&v["name"]), expression(&v["field"])),
+                "prefix" => format!("{}/{}", expression(&v["addr"]), expression(&v["len"])),
+                "range" => format!("{}-{}", expression(&v[0]), expression(&v[1])),
+                "set" => match v {
+                    Json::Array(items) => {
+                        format!("{{ {} }}", items.iter().map(element).collect::<Vec<_>>().join(", "))
+                    }
+                    other => expression(other),
+                },
+                "&" | "|" | "^" => format!("{} {} {}", expression(&v[0]), kind, expression(&v[1])),
+                "numgen" => format!("numgen {} mod {}", expression(&v["mode"]), expression(&v["mod"])),
+                "map" => format!("{} map {}", expression(&v["key"]), expression(&v["data"])),
+                _ => e.to_string(),
+            }
+        }
+    }
+}
+
+/// A set or map element: a value, or a `[key, data]` pair.
+fn element(e: &Json) -> String {
+    match e {
+        Json::Array(pair) if pair.len() == 2 => format!("{} : {}", expression(&pair[0]), expression(&pair[1])),
+        other => expression(other),
+    }
+}
+
+/// A quoted string, as nft writes interface names and log prefixes.
+fn quoted(e: &Json) -> String {
+    format!("\"{}\"", expression(e))
+}
+
+/// One statement of a listed rule as nft syntax.
+fn statement(e: &Json) -> String {
+    let Some((kind, v)) = e.as_object().and_then(|obj| obj.iter().next()) else {
+        return e.to_string();
+    };
+    match kind.as_str() {
+        "match" => {
+            let left = expression(&v["left"]);
+            let is_name = matches!(left.as_str(), "iifname" | "oifname");
+            let right = if is_name && v["right"].is_string() { quoted(&v["right"]) } else { expression(&v["right"]) };
+            match v["op"].as_str().unwrap_or("==") {
+                "==" | "in" => format!("{} {}", left, right),
+                op => format!("{} {} {}", left, op, right),
+            }
+        }
+        "counter" => format!("counter packets {} bytes {}", expression(&v["packets"]), expression(&v["bytes"])),
+        "limit" => {
+            let over = if v["inv"] == true { "over " } else { "" };
+            let mut text = format!("limit rate {}{}/{}", over, expression(&v["rate"]), expression(&v["per"]));
+            if !v["burst"].is_null() && v["burst"] != 0 {
+                text.push_str(&format!(" burst {} packets", expression(&v["burst"])));
+            }
+            text
+        }
+        "ct count" => {
+            let over = if v["inv"] == true { "over " } else { "" };
+            format!("ct count {}{}", over, expression(&v["val"]))
+        }
+        "set" => {
+            let stmts = v["stmt"].as_array().map(|s| s.iter().map(statement).collect::<Vec<_>>()).unwrap_or_default();
+            let mut elem = expression(&v["elem"]);
+            if !stmts.is_empty() {
+                elem = format!("{} {}", elem, stmts.join(" "));
+            }
+            format!("{} {} {{ {} }}", expression(&v["op"]), expression(&v["set"]), elem)
+        }
+        "log" => {
+            let mut text = "log".to_string();
+            if !v["prefix"].is_null() {
+                text.push_str(&format!(" prefix {}", quoted(&v["prefix"])));
+            }
+            if !v["group"].is_null() {
+                text.push_str(&format!(" group {}", expression(&v["group"])));
+            }
+            text
+        }
+        "reject" if v.is_null() => "reject".to_string(),
+        "reject" => format!("reject with {} {}", expression(&v["type"]), expression(&v["expr"])),
+        "dnat" | "snat" => {
+            let family = if v["family"].is_null() { String::new() } else { format!("{} ", expression(&v["family"])) };
+            let mut text = format!("{} {}to {}", kind, family, expression(&v["addr"]));
+            if !v["port"].is_null() {
+                text.push_str(&format!(":{}", expression(&v["port"])));
+            }
+            text
+        }
+        "redirect" if v["port"].is_null() => "redirect".to_string(),
+        "redirect" => format!("redirect to :{}", expression(&v["port"])),
+        "mangle" => format!("{} set {}", expression(&v["key"]), expression(&v["value"])),
+        "jump" | "goto" => format!("{} {}", kind, expression(&v["target"])),
+        "accept" | "drop" | "return" | "continue" | "masquerade" | "notrack" => kind.clone(),
+        _ => e.to_string(),
+    }
+}
+
+/// An element of a set as listed by nft.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedElement {
+    pub value: String,
+    /// Seconds until the element times out, for sets with timeouts
+    pub expires: Option<u64>,
+}
+
+/// The elements of every set in `nft -j list set` (or `list ruleset`)
+/// output, in listing order.
+pub fn set_elements(listing: &Json) -> Vec<ListedElement> {
+    let Some(items) = listing["nftables"].as_array() else {
+        return Vec::new();
+    };
+    items
+        .iter()
+        .filter_map(|item| item["set"]["elem"].as_array())
+        .flatten()
+        .map(|e| match e.get("elem") {
+            Some(elem) => ListedElement { value: expression(&elem["val"]), expires: elem["expires"].as_u64() },
+            None => ListedElement { value: expression(e), expires: None },
+        })
+        .collect()
+}
+
+/// Parse `nft -j list ruleset` output. Objects wrapped in `add` commands,
+/// as [`crate::Ruleset::to_json`] writes them, are read the same way.
+/// Objects missing their family, table or name are skipped.
+pub fn parse(listing: &Json) -> Listing {
+    let mut result = Listing::default();
+    let Some(items) = listing["nftables"].as_array() else {
+        return result;
+    };
+    for item in items {
+        let item = item.get("add").unwrap_or(item);
+        if let Some(chain) = item.get("chain") {
+            let (Some(family), Some(table), Some(name)) =
+                (string(chain, "family"), string(chain, "table"), string(chain, "name"))
+            else {
+                continue;
+            };
+            result.chains.push(ListedChain {
+                family,
+                table,
+                name,
+                handle: chain["handle"].as_u64(),
+                kind: string(chain, "type"),
+                hook: string(chain, "hook"),
+                priority: chain["prio"].as_i64(),
+                policy: string(chain, "policy"),
+            });
+        } else if let Some(rule) = item.get("rule") {
+            let (Some(family), Some(table), Some(chain)) =
+                (string(rule, "family"), string(rule, "table"), string(rule, "chain"))
+            else {
+                continue;
+            };
+            let expr = rule["expr"].as_array().cloned().unwrap_or_default();
+            let counter = expr.iter().find_map(|e| e.get("counter")).map(|c| {
+                (c["packets"].as_u64().unwrap_or(0), c["bytes"].as_u64().unwrap_or(0))
+            });
+            result.rules.push(ListedRule {
+                family,
+                table,
+                chain,
+                handle: rule["handle"].as_u64(),
+                comment: string(rule, "comment"),
+                counter,
+                expr,
+            });
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChainType, Family, Field, Policy, Priority, Rule, Ruleset, Table, Value};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_listing() {
+        let listing = json!({
+            "nftables": [
+                {"metainfo": {"version": "1.0.9", "json_schema_version": 1}},
+                {"table": {"family": "inet", "name": "filter", "handle": 1}},
+                {"chain": {"family": "inet", "table": "filter", "name": "input", "handle": 1,
+                           "type": "filter", "hook": "input", "prio": 0, "policy": "drop"}},
+                {"chain": {"family": "inet", "table": "filter", "name": "input_lan", "handle": 2}},
+                {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 4,
+                          "comment": "nf:Drop invalid conntrack state",
+                          "expr": [
+                              {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}},
+                              {"counter": {"packets": 12, "bytes": 840}},
+                              {"drop": null}
+                          ]}},
+                {"rule": {"family": "inet", "table": "filter", "chain": "input", "handle": 5,
+                          "expr": [{"accept": null}]}}
+            ]
+        });
+        let listing = parse(&listing);
+        assert_eq!(listing.chains.len(), 2);
+        assert_eq!(listing.chains[0].hook.as_deref(), Some("input"));
+        assert_eq!(listing.chains[0].priority, Some(0));
+        assert_eq!(listing.chains[1].kind, None);
+        assert_eq!(listing.rules[0].handle, Some(4));
+        assert_eq!(listing.rules[0].description(), Some("Drop invalid conntrack state"));
+        assert_eq!(listing.rules[0].counter, Some((12, 840)));
+        assert_eq!(listing.rules[0].expr.len(), 3);
+        assert_eq!(listing.rules[1].description(), None);
+        assert_eq!(listing.rules[1].counter, None);
+        assert_eq!(parse(&json!({})), Listing::default());
+    }
+
+    #[test]
+    fn test_parse_generated() {
+        let mut table = Table::new(Family::Inet, "filter");
+        let mut input = crate::Chain::base("input", ChainType::Filter, "input", Priority::Value(0), Policy::Drop);
+        input.push(Rule::new().matching(Field::IIFNAME, Value::str("lo")).accept().describe("Allow loopback"));
+        table.chains.push(input);
+        let mut ruleset = Ruleset::replacing(vec![table]);
+        ruleset.add_counters();
+
+        let listing = parse(&ruleset.to_json());
+        assert_eq!(listing.chains[0].policy.as_deref(), Some("drop"));
+        assert_eq!(listing.rules[0].chain, "input");
+        assert_eq!(listing.rules[0].description(), Some("Allow loopback"));
+        assert_eq!(listing.rules[0].counter, Some((0, 0)));
+        assert_eq!(listing.rules[0].text(), "iifname \"lo\" counter packets 0 bytes 0 accept");
+    }
+
+    #[test]
+    fn test_rule_text() {
+        let rule = |expr: Json| ListedRule {
+            family: "inet".into(),
+            table: "nifty_filter".into(),
+            chain: "input".into(),
+            handle: None,
+            comment: None,
+            counter: None,
+            expr: expr.as_array().cloned().unwrap(),
+        };
+        let listed = rule(json!([
+            {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}},
+            {"match": {"op": "!=", "left": {"meta": {"key": "iifname"}}, "right": "wan"}},
+            {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}},
+                       "right": {"set": [{"prefix": {"addr": "10.0.0.0", "len": 8}}, {"range": ["192.0.2.5", "192.0.2.9"]}]}}},
+            {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"range": [1000, 2000]}}},
+            {"limit": {"rate": 10, "per": "second", "burst": 20, "inv": true}},
+            {"log": {"prefix": "Dropped input", "group": 2}},
+            {"drop": null}
+        ]));
+        assert_eq!(
+            listed.text(),
+            "ct state established,related iifname != \"wan\" ip saddr { 10.0.0.0/8, 192.0.2.5-192.0.2.9 } \
+             tcp dport 1000-2000 limit rate over 10/second burst 20 packets log prefix \"Dropped input\" group 2 drop"
+        );
+
+        let marked = rule(json!([
+            {"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "trusted"}},
+            {"mangle": {"key": {"meta": {"key": "mark"}}, "value": 65536}},
+            {"set": {"op": "update", "elem": {"payload": {"protocol": "ip", "field": "saddr"}}, "set": "@open"}},
+            {"dnat": {"family": "ip", "addr": "10.0.0.5", "port": 80}}
+        ]));
+        assert_eq!(
+            marked.text(),
+            "iifname \"trusted\" meta mark set 65536 update @open { ip saddr } dnat ip to 10.0.0.5:80"
+        );
+        assert!(marked.sets("mark"));
+        assert!(!marked.sets("dscp"));
+    }
+
+    #[test]
+    fn test_set_elements() {
+        let listing = json!({
+            "nftables": [
+                {"metainfo": {"version": "1.0.9", "json_schema_version": 1}},
+                {"set": {"family": "inet", "name": "domains_vlan_20_v4", "table": "nifty_filter",
+                         "type": "ipv4_addr", "flags": ["timeout", "dynamic"], "timeout": 3600,
+                         "elem": [
+                             {"elem": {"val": "192.0.2.7", "timeout": 3600, "expires": 3412}},
+                             {"elem": {"val": "198.51.100.1", "timeout": 3600}}
+                         ]}},
+                {"set": {"family": "inet", "name": "blocked_v4", "table": "nifty_filter",
+                         "type": "ipv4_addr", "flags": ["interval"],
+                         "elem": ["203.0.113.9", {"prefix": {"addr": "10.0.0.0", "len": 8}}]}}
+            ]
+        });
+        let values: Vec<(String, Option<u64>)> =
+            set_elements(&listing).into_iter().map(|e| (e.value, e.expires)).collect();
+        assert_eq!(
+            values,
+            [
+                ("192.0.2.7".to_string(), Some(3412)),
+                ("198.51.100.1".to_string(), None),
+                ("203.0.113.9".to_string(), None),
+                ("10.0.0.0/8".to_string(), None),
+            ]
+        );
+        assert!(set_elements(&json!({})).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+//! Host and group objects: named addresses that rules refer to as `@name`.
+//!
+//! The router resolves them into named sets and the dashboard into Traefik
+//! allow lists; both go through [`AddressBook`] so a group means the same
+//! addresses in each.
+
+use std::collections::BTreeMap;
+
+use ipnetwork::IpNetwork;
+
+/// Addresses and CIDRs of a host or group, split by family.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Addresses {
+    pub ipv4: Vec<String>,
+    pub ipv6: Vec<String>,
+}
+
+impl Addresses {
+    /// Add an address or CIDR, once.
+    pub fn push(&mut self, net: IpNetwork) {
+        // Single addresses are written without a prefix length
+        let max_prefix = if net.is_ipv4() { 32 } else { 128 };
+        let entry = if net.prefix() == max_prefix { net.ip().to_string() } else { net.to_string() };
+        let list = if net.is_ipv4() { &mut self.ipv4 } else { &mut self.ipv6 };
+        if !list.contains(&entry) {
+            list.push(entry);
+        }
+    }
+
+    pub fn extend(&mut self, other: &Addresses) {
+        for entry in other.ipv4.iter().chain(&other.ipv6) {
+            self.push(entry.parse().unwrap());
+        }
+    }
+
+    /// IPv4 entries, then IPv6.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.ipv4.iter().chain(&self.ipv6)
+    }
+}
+
+/// Hosts with their addresses and groups with their members, as declared.
+#[derive(Debug, Default, Clone)]
+pub struct AddressBook {
+    hosts: BTreeMap<String, Addresses>,
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl AddressBook {
+    pub fn add_host(&mut self, name: &str, addresses: Addresses) {
+        self.hosts.insert(name.to_string(), addresses);
+    }
+
+    /// Add a group. Members are addresses, CIDRs, or host or group names
+    /// with or without a leading '@'.
+    pub fn add_group(&mut self, name: &str, members: Vec<String>) {
+        self.groups.insert(name.to_string(), members);
+    }
+
+    pub fn host(&self, name: &str) -> Option<&Addresses> {
+        self.hosts.get(name)
+    }
+
+    pub fn is_group(&self, name: &str) -> bool {
+        self.groups.contains_key(name)
+    }
+
+    /// The addresses of a host or group. A group fails if its members form
+    /// a cycle or one of them is not a host, group, address or CIDR.
+    pub fn resolve(&self, name: &str) -> Result<Addresses, String> {
+        let name = name.trim_start_matches('@');
+        match self.hosts.get(name) {
+            Some(addresses) => Ok(addresses.clone()),
+            None if self.is_group(name) => self.resolve_group(name, &mut Vec::new()),
+            None => Err(format!("unknown host or group '@{}'", name)),
+        }
+    }
+
+    fn resolve_group(&self, name: &str, stack: &mut Vec<String>) -> Result<Addresses, String> {
+        if stack.iter().any(|n| n == name) {
+            stack.push(name.to_string());
+            return Err(format!("group \"{}\": members form a cycle ({}).", stack[0], stack.join(" -> ")));
+        }
+        stack.push(name.to_string());
+        let mut addrs = Addresses::default();
+        for member in &self.groups[name] {
+            let member = member.trim().trim_start_matches('@');
+            if let Ok(net) = member.parse::<IpNetwork>() {
+                addrs.push(net);
+            } else if let Some(host) = self.hosts.get(member) {
+                addrs.extend(host);
+            } else if self.is_group(member) {
+                addrs.extend(&self.resolve_group(member, stack)?);
+            } else {
+                return Err(format!(
+                    "group \"{}\".members: '{}' is not a host, group, address or CIDR.",
+                    stack[0], member
+                ));
+            }
+        }
+        stack.pop();
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(ipv4: &str) -> Addresses {
+        let mut addrs = Addresses::default();
+        addrs.push(ipv4.parse().unwrap());
+        addrs
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut book = AddressBook::default();
+        book.add_host("nas", host("10.99.10.5"));
+        book.add_host("laptop", host("10.99.10.50"));
+        book.add_group("admins", vec!["@laptop".into(), "10.99.10.64/26".into(), "fd00:10::/64".into()]);
+        book.add_group("all", vec!["admins".into(), "nas".into(), "10.99.10.50/32".into()]);
+        book.add_group("loop_a", vec!["loop_b".into()]);
+        book.add_group("loop_b", vec!["@loop_a".into()]);
+        book.add_group("typo", vec!["nas".into(), "nsa".into()]);
+
+        let all = book.resolve("@all").unwrap();
+        assert_eq!(all.ipv4, ["10.99.10.50", "10.99.10.64/26", "10.99.10.5"]);
+        assert_eq!(all.ipv6, ["fd00:10::/64"]);
+        assert_eq!(book.resolve("nas").unwrap(), host("10.99.10.5"));
+        assert_eq!(book.resolve("loop_a").unwrap_err(), "group \"loop_a\": members form a cycle (loop_a -> loop_b -> loop_a).");
+        assert_eq!(book.resolve("typo").unwrap_err(), "group \"typo\".members: 'nsa' is not a host, group, address or CIDR.");
+        assert_eq!(book.resolve("@missing").unwrap_err(), "unknown host or group '@missing'");
+    }
+}
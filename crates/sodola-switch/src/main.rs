@@ -316,7 +316,7 @@ fn parse_hcl_config(path: &std::path::Path) -> Result<(HclSwitchAuth, Option<Des
     let mut managed_vids: HashSet<u16> = HashSet::new();
     // Always include VLAN 1
     managed_vids.insert(1);
-    for (_, vlan) in &root.vlan {
+    for vlan in root.vlan.values() {
         managed_vids.insert(vlan.id);
     }
 
@@ -393,12 +393,12 @@ fn vlan_entry_to_port_modes(entry: &VlanEntry) -> [VlanPortMode; 9] {
     let tagged = parse_port_range(&entry.tagged_ports);
     let untagged = parse_port_range(&entry.untagged_ports);
     let mut modes = [VlanPortMode::NotMember; 9];
-    for i in 0..9 {
+    for (i, mode) in modes.iter_mut().enumerate() {
         let port = (i + 1) as u8;
         if tagged.contains(&port) {
-            modes[i] = VlanPortMode::Tagged;
+            *mode = VlanPortMode::Tagged;
         } else if untagged.contains(&port) {
-            modes[i] = VlanPortMode::Untagged;
+            *mode = VlanPortMode::Untagged;
         }
     }
     modes
@@ -584,10 +584,10 @@ fn run_supervise(client: &mut SodolaClient, desired: DesiredState, state_file: O
                     dv.vid, cur.name, dv.name);
                 needs_update = true;
             }
-            for i in 0..9 {
-                if cur_modes[i] != dv.ports[i] {
+            for (i, (cur_mode, mode)) in cur_modes.iter().zip(&dv.ports).enumerate() {
+                if cur_mode != mode {
                     eprintln!("supervise: VLAN {} port {} mismatch: switch={} config={} — updating",
-                        dv.vid, i + 1, port_mode_label(cur_modes[i]), port_mode_label(dv.ports[i]));
+                        dv.vid, i + 1, port_mode_label(*cur_mode), port_mode_label(*mode));
                     needs_update = true;
                 }
             }
@@ -682,6 +682,9 @@ fn route_up(iface: &str, ip: &str) {
     }
 }
 
+/// One supervise pass against the switch.
+type SuperviseFn<'a> = Box<dyn Fn(&mut SodolaClient) + 'a>;
+
 fn main() {
     let cli = Cli::parse();
     let mut client = SodolaClient::new(&cli.url);
@@ -728,7 +731,7 @@ fn main() {
     // Supervise handles its own auth
     if let Commands::Supervise { ref env_file, ref config, ref state_file, dry_run, save, interval, iface: _, ip: _ } = cli.command {
         // Determine auth and desired-state source
-        let (url, user, pass, run_fn): (String, String, String, Box<dyn Fn(&mut SodolaClient)>) = if let Some(ref hcl_path) = config {
+        let (url, user, pass, run_fn): (String, String, String, SuperviseFn<'_>) = if let Some(ref hcl_path) = config {
             // HCL mode: read switch block from HCL config
             let (auth, desired) = match parse_hcl_config(hcl_path) {
                 Ok(v) => v,
@@ -40,6 +40,14 @@ vlan "lan" {
     subnet = "fd00:10::1/64"
     egress = ["::/0"]
   }
+  # Or take a /64 from the ISP's delegated prefix (DHCPv6-PD) instead of a
+  # fixed subnet. subnet_id picks which /64 of the /60 requested from the
+  # ISP (0x0-0xf) and defaults to the VLAN ID.
+  # ipv6 {
+  #   delegated = true
+  #   subnet_id = "0x1"
+  #   egress    = ["::/0"]
+  # }
 
   firewall {
     # Defaults shown — customize as needed
@@ -17,6 +17,19 @@ wan {
   udp_forward = []
   # Example: forward WAN port 8080 to internal host
   # tcp_forward = ["8080:192.168.10.50:80", "2222:192.168.10.10:22"]
+
+  # Addressing defaults to DHCP. For a static block:
+  # mode    = "static"
+  # address = ["203.0.113.10/29"]
+  # gateway = "203.0.113.9"
+  # dns     = ["1.1.1.1"]
+  #
+  # For DSL (PPPoE runs on the wan interface and creates ppp0):
+  # mode = "pppoe"
+  # pppoe {
+  #   username = "user@isp.example"
+  #   password = "secret"
+  # }
 }
 
 services {
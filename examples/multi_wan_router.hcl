@@ -0,0 +1,66 @@
+# Dual-WAN home router — fiber primary with LTE failover.
+# Load via: nifty-filter nftables --config multi_wan_router.hcl
+#
+# Each `wan "name"` block declares an uplink with its own routing table; the
+# unlabeled `wan {}` block holds the firewall settings they share.
+# With the "failover" policy traffic uses
+# the lowest-priority healthy uplink; with "balance" new connections
+# are spread across uplinks by weight. Run `nifty-filter wan-monitor` to
+# take uplinks out of rotation when their health checks fail.
+
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+  wan2  { name = "wan2" }
+}
+
+wan {
+  enable_ipv4 = true
+
+  icmp_accept = []
+  tcp_accept  = []
+  udp_accept  = []
+
+  policy          = "failover"
+  health_interval = 10
+}
+
+wan "fiber" {
+  interface    = "wan"
+  priority     = 1
+  health_check = ["1.1.1.1", "8.8.8.8"]
+}
+
+wan "lte" {
+  interface    = "wan2"
+  priority     = 2
+  health_check = ["1.1.1.1", "8.8.8.8"]
+}
+
+services {
+  dns {
+    upstream = ["1.1.1.1", "1.0.0.1"]
+  }
+}
+
+vlan "lan" {
+  id = 1
+
+  ipv4 {
+    subnet = "192.168.10.1/24"
+    egress = ["0.0.0.0/0"]
+  }
+
+  firewall {
+    icmp_accept = ["echo-request", "echo-reply", "destination-unreachable", "time-exceeded"]
+    tcp_accept  = [22, 80, 443]
+    udp_accept  = [53, 67, 68]
+  }
+
+  dhcp {
+    pool_start = "192.168.10.100"
+    pool_end   = "192.168.10.250"
+    router     = "192.168.10.1"
+    dns        = "192.168.10.1"
+  }
+}
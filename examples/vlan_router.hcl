@@ -63,9 +63,10 @@ wan {
 
   icmp_accept = []  # e.g. ["echo-request"] to allow ping
   tcp_accept  = []  # e.g. [22] to allow SSH
-  udp_accept  = []  # e.g. [51820, 1194] to allow WireGuard and OpenVPN
+  udp_accept  = []  # e.g. [1194] to allow OpenVPN (wireguard blocks open their own port)
 
-  # Port forwarding (DNAT) to internal hosts: "wan_port:dest_ip:dest_port"
+  # Port forwarding (DNAT) to internal hosts: "wan_port:dest_ip:dest_port",
+  # or "first-last:dest_ip" for a range forwarded to the same ports
   #tcp_forward = [
   #  "443:10.99.40.50:443",
   #  "22:10.99.40.10:22",
@@ -73,8 +74,144 @@ wan {
   #udp_forward = [
   #  "51820:10.99.40.50:51820",
   #]
+  # Forwards for both protocols, several ports or ranges, or only some
+  # sources, labeled with a description of up to 64 characters (shown in
+  # rule comments):
+  #forward "Game server" {
+  #  protocol = "tcp+udp"                  # "tcp", "udp" or "tcp+udp"
+  #  ports    = ["27015-27030", "27036"]
+  #  to       = "10.99.40.50"              # Address or @host
+  #  #to_port = 27015                      # Remap a single port
+  #  #from    = ["203.0.113.0/24"]         # Addresses, CIDRs or @host/@group
+  #  #during  = "weekends"                 # Only during a schedule
+  #  #log     = "sampled"                  # Log connections (true, "sampled" or false)
+  #}
+
+  # NAT reflection: LAN clients reach the IPv4 forwards above through the
+  # public address too, from the destination's VLAN and from VLANs whose
+  # allow_from rules already let them reach it. A forward with from only
+  # reflects for the LAN clients it lists.
+  #hairpin = true
+
+  # Per-source limits on an accepted or forwarded port, by WAN-side port:
+  ## limit caps the rate of new connections, ct_limit the concurrent ones.
+  #port_limit "tcp/443" {
+  #  limit    = "10/second burst 20"
+  #  ct_limit = 50
+  #}
+
+  # Ban sources that open SSH connections too fast (port must be accepted
+  # or forwarded; defaults shown):
+  #ssh_guard {
+  #  port      = 22
+  #  threshold = "5/minute"
+  #  ban       = "1h"
+  #}
+
+  # Port knocking: keep router ports closed until a source knocks on the
+  # sequence, each knock within timeout of the last. Any other port between
+  # the lowest and highest knock starts the sequence over. The ports then
+  # open to that source only, until a timeout passes without a new knock:
+  #knock {
+  #  sequence = ["tcp/7000", "udp/8000", "tcp/9000"]
+  #  timeout  = "10s"
+  #  opens    = ["tcp/22"]
+  #}
 }
 
+# --- Named objects ---
+## Rules may refer to these as `@name` instead of repeating addresses and
+## ports: allow_from and allow_inbound_tcp/udp addresses and ports,
+## tcp_forward/udp_forward destinations (hosts only), qos overrides and
+## Traefik route allow_from. Each becomes an nftables named set, so
+## renumbering a host is a one-line change.
+host "services" {
+  ipv4 = "10.99.2.2"
+}
+service "web" {
+  tcp = [80, 443]
+}
+## Groups collect hosts, other groups, addresses and CIDRs:
+#group "media" {
+#  members = ["services", "10.99.20.16/28"]
+#}
+## Schedules are time windows in the router's local time. A VLAN's
+## egress_blocked cuts its internet access (open connections included)
+## during them, and any allow_from, allow_inbound or forward rule can be
+## limited to one with a "during <name>" suffix, e.g.
+## "25565:10.99.30.5:25565 during weekends". A window whose `to` is
+## earlier than `from` runs past midnight into the next day.
+#schedule "school-nights" {
+#  days = ["sun", "mon", "tue", "wed", "thu"]
+#  from = "22:00"
+#  to   = "06:30"
+#}
+
+# --- Blocklists ---
+## Address lists (one address or CIDR per line, '#' or ';' comments) loaded
+## into nftables sets and refreshed in place by nifty-blocklist-sync.
+## "inbound" drops WAN traffic from listed sources, "outbound" rejects
+## forwarded traffic to listed destinations, "both" does both. Use `file`
+## instead of `url` for a list kept on the router.
+#blocklist "spamhaus-drop" {
+#  url       = "https://www.spamhaus.org/drop/drop.txt"
+#  refresh   = "6h"
+#  direction = "both"
+#}
+
+# --- Static routes ---
+## Networks behind another router on one of the VLANs. The gateway must be
+## in a VLAN's subnet. Routes go in the main table unless `table` is set;
+## a VLAN with a matching routing_table sends its traffic by that table
+## (e.g. out a VPN box instead of the WAN), subject to its egress list.
+#route "lab-behind-r2" {
+#  destination = "192.168.50.0/24"
+#  gateway     = "10.99.40.2"
+#  metric      = 100
+#}
+#route "guest-via-vpn" {
+#  destination = "0.0.0.0/0"
+#  gateway     = "10.99.40.254"
+#  table       = 200
+#}
+
+# --- VPN egress ---
+## A WireGuard tunnel to a VPN provider. A VLAN with egress_via set to its
+## name reaches the internet only through the tunnel: its connections use
+## the tunnel's routing table (default 200 + position), are masqueraded to
+## the tunnel address, and are dropped rather than sent out the WAN if the
+## tunnel is down. DNS still goes through the router's resolver.
+#wireguard_client "vpn-provider" {
+#  private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+#  address          = ["10.64.0.2/32"]
+#  table            = 210
+#  peer {
+#    public_key = "<provider public key>"
+#    endpoint   = "vpn.example.net:51820"
+#  }
+#}
+
+# --- Drop logging ---
+## Each default-drop rule logs a rate-limited sample ("sampled") unless set
+## to true (every drop) or false. With nflog_group set, entries go to that
+## NFLOG group instead of the kernel log and nifty-nflog keeps the most
+## recent drops in /run/nifty-filter/drops.json. A VLAN's `log` setting
+## overrides input and forward for traffic arriving on it. Accepted traffic
+## is only logged by `wan.forward` and `allow_from` blocks that set `log`;
+## the string-list rules (tcp_forward, allow_inbound_*) have no such option.
+## `counters` adds
+## a packet/byte counter to every rule; the dashboard's
+## /api/status/nft-counters endpoint reports their hits and rates.
+#logging {
+#  nflog_group = 1
+#  rate        = "5/minute"
+#  input       = "sampled"
+#  forward     = "sampled"
+#  trunk       = false
+#  mgmt        = true
+#  counters    = true
+#}
+
 # --- QoS: Bufferbloat mitigation (CAKE) ---
 ## You must run a speed test (speedtest.net) and record your peak upload/download rate:
 ## QoS will be disabled if these rates are not set:
@@ -111,7 +248,7 @@ services {
     route "dns" {
       ## Route to local technitium DNS web admin service:
       backend              = "http://127.0.0.1:5380"
-      ## Allowed VLANs for this route:
+      ## Allowed VLANs for this route (CIDRs, or @host/@group names):
       allow_from           = ["10.99.2.0/24", "10.99.10.0/24"]
       ## Require client cert with matching SAN (optional):
       authorized_clients   = ["dashboard.nifty.internal"]
@@ -229,20 +366,20 @@ vlan "infra" {
 
   # Allow NTP (chrony) and Traefik (HTTP/HTTPS) access from all VLANs
   allow_from "trusted" {
-    tcp = ["10.99.2.2:80", "10.99.2.2:443"]
-    udp = ["10.99.2.2:123"]
+    tcp = ["@services:@web"]
+    udp = ["@services:123"]
   }
   allow_from "iot" {
-    tcp = ["10.99.2.2:80", "10.99.2.2:443"]
-    udp = ["10.99.2.2:123"]
+    tcp = ["@services:@web"]
+    udp = ["@services:123"]
   }
   allow_from "guest" {
-    tcp = ["10.99.2.2:80", "10.99.2.2:443"]
-    udp = ["10.99.2.2:123"]
+    tcp = ["@services:@web"]
+    udp = ["@services:123"]
   }
   allow_from "lab" {
-    tcp = ["10.99.2.2:80", "10.99.2.2:443"]
-    udp = ["10.99.2.2:123"]
+    tcp = ["@services:@web"]
+    udp = ["@services:123"]
   }
 }
 
@@ -270,6 +407,14 @@ vlan "trusted" {
     udp_accept  = [53, 67, 68]
   }
 
+  # Let clients (game consoles, VoIP) open WAN ports to themselves via
+  # UPnP IGD, NAT-PMP and PCP (miniupnpd). IPv4 only. Each client can only
+  # map ports to its own address, within allowed_ports (default 1024-65535).
+  # upnp {
+  #   enabled       = true
+  #   allowed_ports = "1024-65535"
+  # }
+
   # DHCP config for dnsmasq - clients receive IP addresses from the range defined.
   dhcp {
     pool_start = "10.99.10.100"
@@ -299,6 +444,15 @@ vlan "iot" {
     egress = []
   }
 
+  # Allow egress only to these domains and their subdomains. dnsmasq adds
+  # the addresses it resolves to a set, where they stay until an hour
+  # passes without a new connection to them. Answers are handed out with
+  # a TTL of at most an hour, and devices must use the router for DNS:
+  # egress_domains = ["*.vendor-cloud.com"]
+
+  # Log every drop from this VLAN rather than a sample:
+  # log = true
+
   firewall {
     icmp_accept = ["destination-unreachable"]
     tcp_accept  = []
@@ -326,6 +480,14 @@ vlan "iot" {
   # allow_from "trusted" {
   #   tcp = ["10.99.20.200:8008", "10.99.20.200:8009"]
   #   udp = ["10.99.20.200:32768-61000"]
+  #   # log = true   # Log the allowed connections (true, "sampled" or false)
+  # }
+
+  # Per-device egress, keyed by a MAC address or the hostname of a DHCP
+  # reservation above. The list replaces this VLAN's egress for that device
+  # only; an empty list blocks it from the internet.
+  # client "chromecast-living-room" {
+  #   egress = ["0.0.0.0/0"]
   # }
 }
 
@@ -339,6 +501,10 @@ vlan "guest" {
     subnet = "10.99.30.1/24"
     egress = ["0.0.0.0/0"]
   }
+  #egress_blocked = ["school-nights"]
+  #routing_table  = 200  # Route via the table of route "guest-via-vpn"
+  #fwmark         = 200  # Connection mark selecting it (default: the table)
+  #egress_via     = "vpn-provider"  # Or: internet only through this tunnel
 
   firewall {
     icmp_accept = ["echo-request", "echo-reply", "destination-unreachable", "time-exceeded"]
@@ -390,12 +556,56 @@ vlan "lab" {
     pool_end   = "fd00:40::1ff"
   }
 
+  # Inbound IPv6 from the WAN to hosts in this VLAN. A full address breaks
+  # when the ISP renumbers the prefix; match only the host part instead:
+  #   "443:[::abcd:50]/-64"            last 64 bits of the destination
+  #   "443:[eui64:52:54:00:12:34:56]"  SLAAC address derived from a MAC
+  #                                    (not temporary/privacy addresses)
   allow_inbound_tcp = [
     "443:[2001:db8:abcd:40::50]",
     "22:[2001:db8:abcd:40::10]",
   ]
 }
 
+###
+# --- WireGuard: Road warrior VPN ---
+## A WireGuard interface is a firewall zone like a VLAN: it gets its own
+## input/forward chains with the same ipv4/ipv6 egress, firewall and
+## allow_from semantics. The listen port is opened on the WAN automatically.
+## Generate the server key (readable by systemd-networkd):
+##   wg genkey > /var/nifty-filter/wg0.key
+##   chown root:systemd-network /var/nifty-filter/wg0.key && chmod 0640 /var/nifty-filter/wg0.key
+## Export a client config (or a QR code for the mobile app):
+##   nifty-filter wireguard peer-config -c nifty-filter.hcl --zone wg0 --peer phone --qr
+#wireguard "wg0" {
+#  listen_port      = 51820
+#  private_key_file = "/var/nifty-filter/wg0.key"
+#  endpoint         = "vpn.example.com"   # Public name peers connect to
+#
+#  ipv4 {
+#    subnet = "10.99.100.1/24"
+#    egress = ["0.0.0.0/0"]
+#  }
+#
+#  firewall {
+#    icmp_accept = ["echo-request"]
+#    tcp_accept  = []
+#    udp_accept  = [53]
+#  }
+#
+#  # Reach VPN clients from a VLAN. Rules for VPN clients reaching a VLAN
+#  # go in that VLAN instead: allow_from "wg0" { tcp = [...] }
+#  #allow_from "trusted" {
+#  #  tcp = ["10.99.100.10:22"]
+#  #}
+#
+#  peer "phone" {
+#    public_key  = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+#    allowed_ips = ["10.99.100.10/32"]
+#    #preshared_key_file = "/var/nifty-filter/wg0-phone.psk"
+#  }
+#}
+
 # --- Managed switch (Sodola) ---
 ## nifty-filter has OPTIONAL support to manage your Sodola switch for
 ## you, uncomment the config and set the port assignments to the VLANs you desire.
@@ -63,8 +63,11 @@ in
       # Disable NixOS's built-in firewall (we replace it entirely)
       networking.firewall.enable = false;
 
-      # Enable nftables (we manage the ruleset ourselves via the boot service)
+      # Enable nftables (we manage the ruleset ourselves via the boot service).
+      # Don't let nftables.service flush the ruleset on reload: it would wipe
+      # our tables, and those of other software are not ours to remove.
       networking.nftables.enable = true;
+      networking.nftables.flushRuleset = false;
 
       # IP forwarding (it's a router)
       # IPv6 forwarding is set per-interface (not all.forwarding) so that
@@ -90,5 +93,6 @@ in
     (import ./services/dashboard.nix serviceArgs)
     (import ./services/sodola-switch.nix serviceArgs)
     (import ./services/avahi.nix serviceArgs)
+    (import ./services/miniupnpd.nix serviceArgs)
   ]);
 }
@@ -30,7 +30,10 @@ in
 rustPlatform.buildRustPackage {
   pname = "nifty-dashboard";
   version = "0.1.0";
-  src = ../../crates/nifty-dashboard;
+  # The whole repo, for the shared crates/nifty-nft path dependency
+  src = ../../.;
+  cargoRoot = "crates/nifty-dashboard";
+  buildAndTestSubdir = "crates/nifty-dashboard";
   cargoLock = {
     lockFile = ../../crates/nifty-dashboard/Cargo.lock;
     outputHashes = {
@@ -42,9 +45,9 @@ rustPlatform.buildRustPackage {
   nativeBuildInputs = [ pkg-config ];
   buildInputs = [ openssl ];
   preBuild = ''
-    rm -rf frontend/build
-    ln -s ${frontend} frontend/build
-    cp ${../../LICENSE.md} LICENSE.md
+    rm -rf crates/nifty-dashboard/frontend/build
+    ln -s ${frontend} crates/nifty-dashboard/frontend/build
+    cp ${../../LICENSE.md} crates/nifty-dashboard/LICENSE.md
   '';
   meta = {
     description = "Web dashboard for nifty-filter";
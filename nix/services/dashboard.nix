@@ -68,12 +68,9 @@ in
           DIR=/run/nifty-state
 
           while true; do
-            # Full nft ruleset as JSON (covers list chains + list chain + list table)
+            # Full nft ruleset as JSON (chains, rules, counters and the mangle table)
             nft -j list ruleset > "$DIR/nft-ruleset.json.tmp" && mv "$DIR/nft-ruleset.json.tmp" "$DIR/nft-ruleset.json"
 
-            # Full nft ruleset as plain text (for per-chain rule listing)
-            nft list ruleset > "$DIR/nft-ruleset.txt.tmp" && mv "$DIR/nft-ruleset.txt.tmp" "$DIR/nft-ruleset.txt"
-
             # ip addr as JSON
             ip -j addr show > "$DIR/ip-addr.json.tmp" && mv "$DIR/ip-addr.json.tmp" "$DIR/ip-addr.json"
 
@@ -155,6 +152,8 @@ in
         "/run/dbus/system_bus_socket"
         "/run/dnsmasq"
         "/var/lib/dnsmasq"
+        "-/run/miniupnpd"
+        "-/var/lib/miniupnpd"
       ];
 
       # Kernel hardening
@@ -1,11 +1,20 @@
 # Firewall and QoS services.
 #
 #   - nifty-filter: generates nftables ruleset from HCL config and applies it.
-#     Falls back to a lockdown ruleset if no config exists.
+#     Falls back to a lockdown ruleset if no config exists. Only the tables
+#     nifty-filter owns (inet nifty_filter, nifty_nat, nifty_wan_routing,
+#     nifty_mangle) are replaced or removed, along with the unprefixed
+#     inet filter/nat/mangle tables older versions generated; tables of
+#     other software are left alone.
 #   - nifty-qos: applies CAKE traffic shaping on the WAN interface with
 #     per-VLAN HTB classes and IFB-based download shaping.
+#   - nifty-pd-sync: keeps the nftables sets for VLANs on a DHCPv6-delegated
+#     prefix up to date when the ISP changes the prefix.
+#   - nifty-blocklist-sync: refreshes blocklist sets from their URLs or files.
+#   - nifty-nflog: collects dropped packets logged to NFLOG into
+#     /run/nifty-filter/drops.json (exits when logging uses the kernel log).
 #
-# Both are oneshot services that run as root (nft and tc require it).
+# All run as root (nft and tc require it).
 
 { pkgs, nifty-filter, hclFile, ... }:
 
@@ -21,30 +30,27 @@
     serviceConfig = {
       Type = "oneshot";
       RemainAfterExit = true;
-      ExecStart = "${pkgs.bash}/bin/bash -c '${nifty-filter}/bin/nifty-filter nftables --config ${hclFile} | ${pkgs.nftables}/bin/nft -f -'";
-      ExecStop = "${pkgs.nftables}/bin/nft flush ruleset";
+      # Elements of the egress domain sets are carried into the new ruleset:
+      # clients keep their cached DNS answers, so dnsmasq would not refill
+      # the sets until the names are looked up again.
+      ExecStart = "${pkgs.bash}/bin/bash -c '${nifty-filter}/bin/nifty-filter nftables --config ${hclFile} --keep-domain-sets | ${pkgs.nftables}/bin/nft -f -'";
+      # Reloading the ruleset empties the delegated-prefix and blocklist sets;
+      # refill them. Blocklists come only from their cached copies so start
+      # never waits on downloads; nifty-blocklist-sync fetches stale ones.
+      ExecStartPost = [
+        "${nifty-filter}/bin/nifty-filter pd-sync --config ${hclFile}"
+        "${nifty-filter}/bin/nifty-filter blocklist-sync --config ${hclFile} --cached"
+      ];
+      # Remove only our own tables
+      ExecStop = "${pkgs.bash}/bin/bash -c '${nifty-filter}/bin/nifty-filter nftables-teardown | ${pkgs.nftables}/bin/nft -f -'";
     };
 
+    path = [ pkgs.nftables pkgs.iproute2 pkgs.curl ];
+
     preStart = ''
       if [ ! -f ${hclFile} ]; then
         echo "ERROR: ${hclFile} not found. Applying emergency lockdown rules."
-        ${pkgs.nftables}/bin/nft -f - <<'LOCKDOWN'
-      flush ruleset
-      table inet filter {
-        chain input {
-          type filter hook input priority 0; policy drop;
-          ct state established,related accept
-          iif "lo" accept
-        }
-        chain forward {
-          type filter hook forward priority 0; policy drop;
-        }
-        chain output {
-          type filter hook output priority 0; policy drop;
-          oif "lo" accept
-        }
-      }
-      LOCKDOWN
+        ${nifty-filter}/bin/nifty-filter nftables-teardown --lockdown | ${pkgs.nftables}/bin/nft -f -
         exit 1
       fi
     '';
@@ -65,4 +71,54 @@
       ExecStop = "${pkgs.bash}/bin/bash -c 'WAN_IFACE=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} wan-name 2>/dev/null); ${pkgs.iproute2}/bin/tc qdisc del dev \"$WAN_IFACE\" root 2>/dev/null; ${pkgs.iproute2}/bin/tc qdisc del dev \"$WAN_IFACE\" ingress 2>/dev/null; ${pkgs.iproute2}/bin/tc qdisc del dev ifb0 root 2>/dev/null; ${pkgs.iproute2}/bin/ip link set ifb0 down 2>/dev/null; true'";
     };
   };
+
+  # Follow delegated IPv6 prefix changes (exits when no VLAN is delegated)
+  systemd.services.nifty-pd-sync = {
+    description = "Sync delegated IPv6 prefixes into nftables sets";
+    wantedBy = [ "multi-user.target" ];
+    after = [ "nifty-filter.service" ];
+    unitConfig.ConditionPathExists = hclFile;
+
+    path = [ pkgs.nftables pkgs.iproute2 ];
+
+    serviceConfig = {
+      ExecStart = "${nifty-filter}/bin/nifty-filter pd-sync --config ${hclFile} --watch";
+      Restart = "on-failure";
+      RestartSec = 5;
+    };
+  };
+
+  # Refresh blocklist sets on their schedules (exits when none are configured)
+  systemd.services.nifty-blocklist-sync = {
+    description = "Refresh blocklist nftables sets";
+    wantedBy = [ "multi-user.target" ];
+    after = [ "nifty-filter.service" "network-online.target" ];
+    wants = [ "network-online.target" ];
+    unitConfig.ConditionPathExists = hclFile;
+
+    path = [ pkgs.nftables pkgs.curl ];
+
+    serviceConfig = {
+      ExecStart = "${nifty-filter}/bin/nifty-filter blocklist-sync --config ${hclFile} --watch";
+      StateDirectory = "nifty-filter/blocklists";
+      Restart = "on-failure";
+      RestartSec = 30;
+    };
+  };
+
+  # Collect drops logged to an NFLOG group (exits when none is configured)
+  systemd.services.nifty-nflog = {
+    description = "Collect nifty-filter drop logs";
+    wantedBy = [ "multi-user.target" ];
+    after = [ "nifty-filter.service" ];
+    unitConfig.ConditionPathExists = hclFile;
+
+    serviceConfig = {
+      ExecStart = "${nifty-filter}/bin/nifty-filter nflog --config ${hclFile}";
+      RuntimeDirectory = "nifty-filter";
+      RuntimeDirectoryPreserve = "yes";
+      Restart = "on-failure";
+      RestartSec = 5;
+    };
+  };
 }
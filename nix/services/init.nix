@@ -1,6 +1,7 @@
 # Boot-time initialization services:
 #   - nifty-filter-init: seeds default HCL config on first boot
-#   - nifty-config-sha: snapshots config hash for drift detection
+#   - nifty-config-sha: snapshots config hash for drift detection (and
+#     reverts an `apply --confirm-timeout` left unconfirmed by a reboot)
 #   - nifty-hostname: sets hostname from HCL config
 #   - nifty-link: generates .link files for interface renaming
 #
@@ -44,6 +45,12 @@
       RuntimeDirectoryPreserve = "yes";
     };
     script = ''
+      # A reboot while a change awaits confirmation rolls it back
+      if [ -f ${configDir}/rollback/pending ]; then
+        echo "Unconfirmed config change found, restoring previous ${hclFile}"
+        ${pkgs.coreutils}/bin/cp ${configDir}/rollback/nifty-filter.hcl ${hclFile}
+        ${pkgs.coreutils}/bin/rm -rf ${configDir}/rollback
+      fi
       if [ -f ${hclFile} ]; then
         ${pkgs.coreutils}/bin/sha256sum ${hclFile} \
           | ${pkgs.coreutils}/bin/cut -d' ' -f1 \
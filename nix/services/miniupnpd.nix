@@ -0,0 +1,56 @@
+# miniupnpd UPnP IGD / NAT-PMP / PCP service.
+#
+# Lets clients on VLANs with an enabled upnp block in the HCL config map
+# ports on the WAN. Mappings are added to the miniupnpd chains of the
+# nifty-filter ruleset; reloading the ruleset empties those chains, so the
+# service restarts with nifty-filter and restores mappings from its lease file.
+#
+# If no VLANs have upnp enabled, the service skips startup gracefully.
+
+{ pkgs, nifty-filter, hclFile, ... }:
+
+{
+  systemd.services.nifty-miniupnpd = {
+    description = "miniupnpd UPnP IGD / NAT-PMP / PCP";
+    wantedBy = [ "multi-user.target" ];
+    after = [ "nifty-network.service" "nifty-filter.service" ];
+    partOf = [ "nifty-filter.service" ];
+
+    serviceConfig = {
+      Type = "forking";
+      ExecStart = "${pkgs.miniupnpd-nftables}/bin/miniupnpd -f /run/miniupnpd/miniupnpd.conf -P /run/miniupnpd/miniupnpd.pid";
+      PIDFile = "/run/miniupnpd/miniupnpd.pid";
+      Restart = "on-failure";
+      RestartSec = "5s";
+
+      RuntimeDirectory = "miniupnpd";
+      StateDirectory = "miniupnpd";
+      ProtectSystem = "strict";
+      ProtectHome = true;
+      PrivateTmp = true;
+      NoNewPrivileges = true;
+      ProtectKernelModules = true;
+      ProtectKernelLogs = true;
+      ProtectControlGroups = true;
+      RestrictSUIDSGID = true;
+      RestrictRealtime = true;
+      LockPersonality = true;
+
+      ExecStartPre = let
+        preStartScript = pkgs.writeShellScript "nifty-miniupnpd-pre" ''
+          if [ ! -f ${hclFile} ]; then
+            echo "No HCL config found, skipping miniupnpd."
+            exit 1
+          fi
+          # An invalid config fails the start with its errors in the journal
+          IFACES=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} upnp-interfaces) || exit 1
+          if [ -z "$IFACES" ]; then
+            echo "No UPnP interfaces configured, skipping miniupnpd."
+            exit 1
+          fi
+          ${nifty-filter}/bin/nifty-filter generate miniupnpd --config ${hclFile} --output /run/miniupnpd/miniupnpd.conf
+        '';
+      in "+${preStartScript}";
+    };
+  };
+}
@@ -3,7 +3,9 @@
 # Reads interface definitions from HCL config, brings up WAN/trunk/mgmt
 # interfaces, generates systemd-networkd .netdev and .network files for
 # VLANs, and restarts networkd to apply. Also configures IPv6 RA acceptance
-# on the WAN interface when IPv6 is enabled.
+# on the WAN interface when IPv6 is enabled. PPPoE uplinks run pppd via
+# nifty-pppoe@<uplink>. With multiple WAN uplinks, nifty-wan-monitor
+# health-checks each uplink and updates policy routing.
 #
 # Runs as root (requires interface manipulation).
 
@@ -54,6 +56,7 @@
       WAN_INTERFACE=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} wan-name)
       TRUNK_INTERFACE=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} trunk-name)
       MGMT_INTERFACE=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} mgmt-name 2>/dev/null || true)
+      WAN_INTERFACES=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} wan-interfaces)
       ENABLE_IPV6=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} enable-ipv6)
 
       # Rename interfaces by MAC if .link files haven't taken effect yet
@@ -76,7 +79,9 @@
       VLAN_INTERFACES=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} vlan-interfaces 2>/dev/null || true)
 
       # Bring up interfaces
-      [ -n "$WAN_INTERFACE" ] && ip link set "$WAN_INTERFACE" up
+      for iface in $WAN_INTERFACES; do
+        ip link set "$iface" up 2>/dev/null || echo "WARNING: WAN interface $iface not found, skipping"
+      done
       [ -n "$TRUNK_INTERFACE" ] && ip link set "$TRUNK_INTERFACE" up
       [ -n "$MGMT_INTERFACE" ] && ip link set "$MGMT_INTERFACE" up 2>/dev/null || true
       for iface in $VLAN_INTERFACES; do
@@ -87,12 +92,23 @@
       mkdir -p /run/systemd/network
       ${nifty-filter}/bin/nifty-filter generate networkd --config ${hclFile} --output-dir /run/systemd/network
 
-      # Ensure WAN accepts RAs despite forwarding (must override after networkd)
-      if [ "$ENABLE_IPV6" = "true" ] && [ -n "$WAN_INTERFACE" ]; then
+      # Generate pppd options for PPPoE uplinks (contain credentials)
+      PPPOE_UPLINKS=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} pppoe-uplinks 2>/dev/null || true)
+      if [ -n "$PPPOE_UPLINKS" ]; then
+        mkdir -p -m 0700 /run/nifty-filter/ppp
+        ${nifty-filter}/bin/nifty-filter generate ppp --config ${hclFile} --output-dir /run/nifty-filter/ppp
+      fi
+
+      # Ensure WAN uplinks accept RAs despite forwarding (must override after networkd)
+      if [ "$ENABLE_IPV6" = "true" ] && [ -n "$WAN_INTERFACES" ]; then
+        RA_SYSCTLS=""
+        for iface in $WAN_INTERFACES; do
+          RA_SYSCTLS="$RA_SYSCTLS net.ipv6.conf.$iface.accept_ra=2 net.ipv6.conf.$iface.forwarding=0"
+        done
         mkdir -p /run/systemd/system/systemd-networkd.service.d
         cat > /run/systemd/system/systemd-networkd.service.d/accept-ra.conf <<RAEOF
       [Service]
-      ExecStartPost=/bin/sh -c 'sleep 1 && /run/current-system/sw/bin/sysctl -w net.ipv6.conf.$WAN_INTERFACE.accept_ra=2 net.ipv6.conf.$WAN_INTERFACE.forwarding=0'
+      ExecStartPost=/bin/sh -c 'sleep 1 && /run/current-system/sw/bin/sysctl -w$RA_SYSCTLS'
       RAEOF
         systemctl daemon-reload
       fi
@@ -127,6 +143,39 @@
           ip link show | grep -E '^[0-9]+:' || true
         fi
       fi
+
+      # Start PPPoE sessions (networkd configures the PPP interfaces once up)
+      for uplink in $PPPOE_UPLINKS; do
+        systemctl restart --no-block "nifty-pppoe@$uplink.service"
+      done
     '';
   };
+
+  # PPPoE session for one uplink; options generated by nifty-network
+  systemd.services."nifty-pppoe@" = {
+    description = "PPPoE session for WAN uplink %i";
+    after = [ "nifty-network.service" ];
+    unitConfig.ConditionPathExists = "/run/nifty-filter/ppp/%i.conf";
+    serviceConfig = {
+      ExecStart = "${pkgs.ppp}/bin/pppd file /run/nifty-filter/ppp/%i.conf nodetach";
+      Restart = "always";
+      RestartSec = 5;
+    };
+  };
+
+  # Health-check WAN uplinks and fail over policy routing (multi-WAN only;
+  # exits immediately when a single uplink is configured)
+  systemd.services.nifty-wan-monitor = {
+    description = "Monitor WAN uplink health";
+    wantedBy = [ "multi-user.target" ];
+    after = [ "nifty-network.service" "systemd-networkd.service" ];
+    requires = [ "nifty-network.service" ];
+    unitConfig.ConditionPathExists = hclFile;
+    serviceConfig = {
+      ExecStart = "${nifty-filter}/bin/nifty-filter wan-monitor --config ${hclFile}";
+      Restart = "on-failure";
+      RestartSec = 5;
+    };
+    path = [ pkgs.iproute2 pkgs.iputils ];
+  };
 }
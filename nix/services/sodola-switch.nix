@@ -36,7 +36,7 @@ in
       ExecStartPre = [
         # Block forwarded traffic to the switch management subnet —
         # only the router itself (output chain) may reach the switch.
-        "+${pkgs.bash}/bin/bash -c 'ROUTER_IP=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} switch-router-ip 2>/dev/null); if [ -n \"$ROUTER_IP\" ]; then NETWORK=$(echo $ROUTER_IP | ${pkgs.gnused}/bin/sed \"s|\\.[0-9]*/|.0/|\"); nft insert rule inet filter forward ip daddr $NETWORK drop comment \"block switch mgmt\" 2>/dev/null || true; fi'"
+        "+${pkgs.bash}/bin/bash -c 'ROUTER_IP=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} switch-router-ip 2>/dev/null); if [ -n \"$ROUTER_IP\" ]; then NETWORK=$(echo $ROUTER_IP | ${pkgs.gnused}/bin/sed \"s|\\.[0-9]*/|.0/|\"); nft insert rule inet nifty_filter forward ip daddr $NETWORK drop comment \"block switch mgmt\" 2>/dev/null || true; fi'"
         # Assign the router IP to the management interface (runs as root)
         "+${pkgs.bash}/bin/bash -c 'IFACE=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} switch-mgmt-iface 2>/dev/null); IP=$(${nifty-filter}/bin/nifty-filter get -c ${hclFile} switch-router-ip 2>/dev/null); if [ -n \"$IFACE\" ] && [ -n \"$IP\" ]; then ${pkgs.iproute2}/bin/ip addr add $IP dev $IFACE 2>/dev/null || true; fi'"
       ];
@@ -61,9 +61,12 @@
   # Reverse-path filtering (BCP 38 / RFC 3704)
   # Strict mode (1): drop packets whose source address would not be routed
   # back out the same interface they arrived on.
+  # The check uses the packet's firewall mark (src_valid_mark), so replies on
+  # a VPN tunnel or a secondary uplink match the table that routed them out.
   boot.kernel.sysctl = {
     "net.ipv4.conf.default.rp_filter" = 1;
     "net.ipv4.conf.all.rp_filter" = 1;
+    "net.ipv4.conf.all.src_valid_mark" = 1;
   };
 
   # Remount root read-only after NixOS activation completes
@@ -170,6 +173,8 @@
     iproute2
     dig
     openssl
+    wireguard-tools
+    qrencode
   ];
 
   # Maintenance mode indicator — modify PS1 and show warning
@@ -0,0 +1,433 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hcl_config::parse_hcl;
+use crate::ruleset::{teardown, OWNED_TABLES};
+
+/// Services that regenerate their configuration from the HCL file.
+const SERVICES: [(&str, &str); 3] = [
+    ("nifty-filter", "Firewall rules"),
+    ("nifty-network", "Network"),
+    ("nifty-dnsmasq", "DHCP/DNS"),
+];
+
+/// Transient systemd unit that fires the automatic rollback.
+const ROLLBACK_UNIT: &str = "nifty-rollback";
+
+/// Config of the last confirmed apply, falling back to the boot snapshot.
+const APPLIED_HCL: &str = "/run/nifty-filter/config-applied";
+const BOOT_HCL: &str = "/run/nifty-filter/config-boot-snapshot";
+
+/// Mode of saved and restored HCL files, which may hold PPPoE secrets.
+const HCL_MODE: u32 = 0o600;
+/// Mode of restored networkd and dnsmasq files, read by unprivileged daemons.
+const GENERATED_MODE: u32 = 0o644;
+
+/// Where the generated runtime configuration lives and where snapshots go.
+pub struct Paths {
+    pub config: PathBuf,
+    /// Snapshot directory. Kept next to the config (persistent) so a reboot
+    /// while a change is unconfirmed also rolls back.
+    pub state_dir: PathBuf,
+    pub networkd_dir: PathBuf,
+    pub dnsmasq_conf: PathBuf,
+}
+
+impl Paths {
+    pub fn for_config(config: &str) -> Self {
+        let config = PathBuf::from(config);
+        let state_dir = config
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("rollback");
+        Paths {
+            config,
+            state_dir,
+            networkd_dir: PathBuf::from("/run/systemd/network"),
+            dnsmasq_conf: PathBuf::from("/run/dnsmasq/dnsmasq.conf"),
+        }
+    }
+
+    fn pending(&self) -> PathBuf {
+        self.state_dir.join("pending")
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending().exists()
+    }
+}
+
+/// Write the contents of `from` into `to`. An existing file keeps its
+/// permissions; a new one gets `mode`. Unlike `fs::copy`, this never
+/// carries the read-only mode of the boot snapshot over to the config.
+fn copy(from: &Path, to: &Path, mode: u32) -> Result<(), String> {
+    let contents = fs::read(from).map_err(|e| format!("Cannot read {}: {}", from.display(), e))?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(to)
+        .and_then(|mut file| file.write_all(&contents))
+        .map_err(|e| format!("Cannot copy {} to {}: {}", from.display(), to.display(), e))
+}
+
+fn is_networkd_unit(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("network" | "netdev" | "link")
+    )
+}
+
+/// The .network/.netdev/.link files of a directory.
+fn networkd_units(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).filter(|p| is_networkd_unit(p)).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Copy the .network/.netdev/.link files of one directory into another.
+fn copy_networkd(from: &Path, to: &Path) -> Result<(), String> {
+    fs::create_dir_all(to).map_err(|e| format!("Cannot create {}: {}", to.display(), e))?;
+    for path in networkd_units(from) {
+        if let Some(name) = path.file_name() {
+            copy(&path, &to.join(name), GENERATED_MODE)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether an HCL file exists and has content. The boot snapshot of a
+/// router that booted without a config is empty.
+fn has_config(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|c| !c.trim().is_empty())
+}
+
+/// Save the last-known-good HCL plus the live networkd/dnsmasq output.
+fn snapshot_files(paths: &Paths, baseline: &Path, deadline: u64) -> Result<(), String> {
+    let dir = &paths.state_dir;
+    if dir.exists() {
+        fs::remove_dir_all(dir).map_err(|e| format!("Cannot clear {}: {}", dir.display(), e))?;
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    copy(baseline, &dir.join("nifty-filter.hcl"), HCL_MODE)?;
+    copy_networkd(&paths.networkd_dir, &dir.join("networkd"))?;
+    if paths.dnsmasq_conf.exists() {
+        copy(&paths.dnsmasq_conf, &dir.join("dnsmasq.conf"), GENERATED_MODE)?;
+    }
+    fs::write(paths.pending(), format!("{}\n", deadline))
+        .map_err(|e| format!("Cannot write {}: {}", paths.pending().display(), e))
+}
+
+/// Put the snapshotted HCL and generated files back in place, removing
+/// networkd files that only the new generation had.
+fn restore_files(paths: &Paths) -> Result<(), String> {
+    let dir = &paths.state_dir;
+    let hcl = dir.join("nifty-filter.hcl");
+    if !has_config(&hcl) {
+        return Err(format!("{} is empty; refusing to restore it.", hcl.display()));
+    }
+    copy(&hcl, &paths.config, HCL_MODE)?;
+    let saved = dir.join("networkd");
+    for path in networkd_units(&paths.networkd_dir) {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if !saved.join(name).exists() {
+            fs::remove_file(&path).map_err(|e| format!("Cannot remove {}: {}", path.display(), e))?;
+        }
+    }
+    copy_networkd(&saved, &paths.networkd_dir)?;
+    let dnsmasq = dir.join("dnsmasq.conf");
+    if dnsmasq.exists() {
+        copy(&dnsmasq, &paths.dnsmasq_conf, GENERATED_MODE)?;
+    }
+    Ok(())
+}
+
+/// The owned tables as currently loaded. Missing tables are skipped.
+fn list_owned_tables() -> Result<String, String> {
+    let mut ruleset = String::new();
+    for name in OWNED_TABLES {
+        let output = Command::new("nft")
+            .args(["list", "table", "inet", name])
+            .output()
+            .map_err(|e| format!("Cannot run nft: {}", e))?;
+        if output.status.success() {
+            ruleset.push_str(&String::from_utf8_lossy(&output.stdout));
+        }
+    }
+    Ok(ruleset)
+}
+
+/// Script that atomically replaces the owned tables with saved ones,
+/// leaving tables of other software alone.
+fn restore_script(ruleset: &str) -> String {
+    format!("{}\n{}", teardown(), ruleset)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn run(cmd: &str, args: &[&str]) -> Result<(), String> {
+    let status = Command::new(cmd)
+        .args(args)
+        .status()
+        .map_err(|e| format!("Cannot run {}: {}", cmd, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} {} failed", cmd, args.join(" ")))
+    }
+}
+
+/// Restart the services that consume the HCL config. Returns false if any
+/// of them failed.
+pub fn restart_services() -> bool {
+    let mut ok = true;
+    for (service, label) in SERVICES {
+        println!("  Restarting {service}...");
+        match Command::new("systemctl").args(["restart", service]).status() {
+            Ok(s) if s.success() => println!("  {label} applied."),
+            _ => {
+                println!("  Failed! Check: journalctl -u {service}");
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Record the current config as the rollback target for the next apply.
+fn mark_applied(paths: &Paths) {
+    let _ = fs::create_dir_all(Path::new(APPLIED_HCL).parent().unwrap());
+    let _ = fs::copy(&paths.config, APPLIED_HCL);
+}
+
+/// Validate and apply the config. With `confirm_timeout`, snapshot the
+/// running configuration first and schedule a rollback that fires unless
+/// `confirm` is run within that many seconds.
+pub fn apply(config_path: &str, confirm_timeout: Option<u64>) -> Result<(), String> {
+    let paths = Paths::for_config(config_path);
+    if paths.is_pending() {
+        return Err(
+            "A previous apply is awaiting confirmation. Run `nifty-filter confirm` or `nifty-filter rollback` first."
+                .to_string(),
+        );
+    }
+
+    let contents = fs::read_to_string(&paths.config)
+        .map_err(|e| format!("Failed to read {}: {}", config_path, e))?;
+    let config = parse_hcl(&contents)?;
+    crate::Router::from_hcl(&config).map_err(|errors| errors.join("\n"))?;
+
+    if let Some(timeout) = confirm_timeout {
+        if timeout == 0 {
+            return Err("--confirm-timeout must be greater than 0.".to_string());
+        }
+        let baseline = [APPLIED_HCL, BOOT_HCL]
+            .into_iter()
+            .map(Path::new)
+            .find(|p| has_config(p))
+            .ok_or("No previously applied config to roll back to.")?;
+
+        let ruleset = list_owned_tables()?;
+        snapshot_files(&paths, baseline, now() + timeout)?;
+        fs::write(paths.state_dir.join("ruleset.nft"), ruleset)
+            .map_err(|e| format!("Cannot save ruleset: {}", e))?;
+
+        let exe = std::env::current_exe().map_err(|e| format!("Cannot locate executable: {}", e))?;
+        let exe = exe.to_string_lossy();
+        let on_active = format!("--on-active={}", timeout);
+        let unit = format!("--unit={}", ROLLBACK_UNIT);
+        // A failed rollback unit from an earlier apply would block the name
+        for suffix in ["service", "timer"] {
+            let _ = Command::new("systemctl")
+                .args(["reset-failed", &format!("{}.{}", ROLLBACK_UNIT, suffix)])
+                .stderr(Stdio::null())
+                .status();
+        }
+        if let Err(e) = run(
+            "systemd-run",
+            &[&unit, &on_active, "--timer-property=AccuracySec=1s", &exe, "rollback", "--config", config_path],
+        ) {
+            let _ = fs::remove_dir_all(&paths.state_dir);
+            return Err(e);
+        }
+    }
+
+    let ok = restart_services();
+    if let Some(hostname) = &config.hostname {
+        let _ = Command::new("hostname").arg(hostname).status();
+    }
+
+    match confirm_timeout {
+        Some(timeout) => {
+            println!();
+            println!("  Run `nifty-filter confirm` within {timeout} seconds to keep these changes,");
+            println!("  otherwise the previous configuration will be restored.");
+        }
+        None if ok => mark_applied(&paths),
+        None => {}
+    }
+    if ok { Ok(()) } else { Err("Some services failed to restart.".to_string()) }
+}
+
+/// Keep the pending changes and cancel the scheduled rollback.
+pub fn confirm(config_path: &str) -> Result<(), String> {
+    let paths = Paths::for_config(config_path);
+    if !paths.is_pending() {
+        return Err("No apply is awaiting confirmation.".to_string());
+    }
+    let _ = Command::new("systemctl")
+        .args(["stop", &format!("{}.timer", ROLLBACK_UNIT)])
+        .status();
+    mark_applied(&paths);
+    fs::remove_dir_all(&paths.state_dir)
+        .map_err(|e| format!("Cannot remove {}: {}", paths.state_dir.display(), e))?;
+    println!("  Changes confirmed.");
+    Ok(())
+}
+
+/// Restore the snapshot taken by `apply --confirm-timeout`. The saved
+/// ruleset is loaded first so filtering recovers even if a service fails
+/// to restart.
+pub fn rollback(config_path: &str) -> Result<(), String> {
+    let paths = Paths::for_config(config_path);
+    if !paths.is_pending() {
+        return Err("No apply is awaiting confirmation.".to_string());
+    }
+    let _ = Command::new("systemctl")
+        .args(["stop", &format!("{}.timer", ROLLBACK_UNIT)])
+        .status();
+
+    println!("  Restoring previous configuration...");
+    let ruleset = fs::read_to_string(paths.state_dir.join("ruleset.nft"))
+        .map_err(|e| format!("Cannot read saved ruleset: {}", e))?;
+    let child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(restore_script(&ruleset).as_bytes());
+            }
+            match child.wait() {
+                Ok(s) if s.success() => println!("  Firewall ruleset restored."),
+                _ => eprintln!("  Warning: failed to load the saved ruleset."),
+            }
+        }
+        Err(e) => eprintln!("  Warning: cannot run nft: {}", e),
+    }
+
+    restore_files(&paths)?;
+    fs::remove_dir_all(&paths.state_dir)
+        .map_err(|e| format!("Cannot remove {}: {}", paths.state_dir.display(), e))?;
+    if restart_services() {
+        mark_applied(&paths);
+        Ok(())
+    } else {
+        Err("Some services failed to restart.".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn test_paths(root: &Path) -> Paths {
+        Paths {
+            config: root.join("etc/nifty-filter.hcl"),
+            state_dir: root.join("etc/rollback"),
+            networkd_dir: root.join("run/network"),
+            dnsmasq_conf: root.join("run/dnsmasq.conf"),
+        }
+    }
+
+    #[test]
+    fn test_paths_for_config() {
+        let paths = Paths::for_config("/var/nifty-filter/nifty-filter.hcl");
+        assert_eq!(paths.state_dir, PathBuf::from("/var/nifty-filter/rollback"));
+        assert_eq!(paths.pending(), PathBuf::from("/var/nifty-filter/rollback/pending"));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let tmp = TempDir::new().unwrap();
+        let paths = test_paths(tmp.path());
+        fs::create_dir_all(tmp.path().join("etc")).unwrap();
+        fs::create_dir_all(&paths.networkd_dir).unwrap();
+        let baseline = tmp.path().join("applied.hcl");
+        fs::write(&baseline, "old config").unwrap();
+        fs::write(&paths.config, "new config").unwrap();
+        fs::write(paths.networkd_dir.join("10-wan.network"), "old wan").unwrap();
+        fs::write(paths.networkd_dir.join("README"), "ignored").unwrap();
+        fs::write(&paths.dnsmasq_conf, "old dnsmasq").unwrap();
+
+        snapshot_files(&paths, &baseline, 1234).unwrap();
+        assert!(paths.is_pending());
+        assert_eq!(fs::read_to_string(paths.pending()).unwrap(), "1234\n");
+        assert!(!paths.state_dir.join("networkd/README").exists());
+
+        // The new generation overwrites the live files
+        fs::write(paths.networkd_dir.join("10-wan.network"), "new wan").unwrap();
+        fs::write(paths.networkd_dir.join("20-iot.netdev"), "new vlan").unwrap();
+        fs::write(&paths.dnsmasq_conf, "new dnsmasq").unwrap();
+
+        restore_files(&paths).unwrap();
+        assert_eq!(fs::read_to_string(&paths.config).unwrap(), "old config");
+        assert_eq!(
+            fs::read_to_string(paths.networkd_dir.join("10-wan.network")).unwrap(),
+            "old wan"
+        );
+        assert!(!paths.networkd_dir.join("20-iot.netdev").exists());
+        assert!(paths.networkd_dir.join("README").exists());
+        assert_eq!(fs::read_to_string(&paths.dnsmasq_conf).unwrap(), "old dnsmasq");
+    }
+
+    #[test]
+    fn test_restore_keeps_config_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = TempDir::new().unwrap();
+        let paths = test_paths(tmp.path());
+        fs::create_dir_all(tmp.path().join("etc")).unwrap();
+        let baseline = tmp.path().join("config-boot-snapshot");
+        fs::write(&baseline, "old config").unwrap();
+        fs::set_permissions(&baseline, fs::Permissions::from_mode(0o444)).unwrap();
+
+        snapshot_files(&paths, &baseline, 1234).unwrap();
+        restore_files(&paths).unwrap();
+        let mode = fs::metadata(&paths.config).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+
+        // The snapshot of a router that booted without a config
+        let empty = tmp.path().join("empty-snapshot");
+        fs::write(&empty, "\n").unwrap();
+        assert!(!has_config(&empty));
+        snapshot_files(&paths, &empty, 1234).unwrap();
+        assert!(restore_files(&paths).unwrap_err().contains("refusing to restore"));
+        assert_eq!(fs::read_to_string(&paths.config).unwrap(), "old config");
+    }
+
+    #[test]
+    fn test_restore_script() {
+        let script = restore_script("table inet nifty_filter {\n}\n");
+        assert!(!script.contains("flush ruleset"));
+        assert!(script.starts_with("#!/usr/sbin/nft -f\n\ntable inet nifty_filter\ndelete table inet nifty_filter\n"));
+        assert!(script.contains("delete table inet nifty_wan_routing\n"));
+        assert!(script.contains("delete table inet nifty_mangle\n"));
+        assert!(script.ends_with("delete table inet mangle\n\ntable inet nifty_filter {\n}\n"));
+    }
+}
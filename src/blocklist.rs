@@ -0,0 +1,532 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use ipnetwork::IpNetwork;
+use nifty_nft::tables;
+
+use crate::hcl_config::HclConfig;
+use crate::objects;
+use crate::parsers::parse_duration;
+
+/// Where blocklists cache their last good download, so a ruleset reload
+/// can refill the sets without fetching again.
+pub const CACHE_DIR: &str = "/var/lib/nifty-filter/blocklists";
+
+const DEFAULT_REFRESH: Duration = Duration::from_secs(24 * 3600);
+const MIN_REFRESH: Duration = Duration::from_secs(5 * 60);
+
+/// Where a blocklist's entries come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Url(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Retrieves the raw text of a blocklist source.
+pub trait Fetcher {
+    fn fetch(&self, source: &Source) -> Result<String, String>;
+}
+
+/// Reads files directly and downloads URLs with curl.
+pub struct SystemFetcher;
+
+impl Fetcher for SystemFetcher {
+    fn fetch(&self, source: &Source) -> Result<String, String> {
+        match source {
+            Source::File(path) => fs::read_to_string(path)
+                .map_err(|e| format!("Cannot read {}: {}", path.display(), e)),
+            Source::Url(url) => {
+                let output = Command::new("curl")
+                    .args(["--fail", "--silent", "--show-error", "--location", "--max-time", "120", url])
+                    .output()
+                    .map_err(|e| format!("Cannot run curl: {}", e))?;
+                if !output.status.success() {
+                    return Err(format!(
+                        "Cannot fetch {}: {}",
+                        url,
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+        }
+    }
+}
+
+/// Loads an nft script into the kernel.
+pub trait Applier {
+    fn apply(&self, script: &str) -> Result<(), String>;
+}
+
+/// Pipes scripts to `nft -f -`.
+pub struct SystemApplier;
+
+impl Applier for SystemApplier {
+    fn apply(&self, script: &str) -> Result<(), String> {
+        let mut child = Command::new("nft")
+            .args(["-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Cannot run nft: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(script.as_bytes()).map_err(|e| format!("Cannot write to nft: {}", e))?;
+        }
+        let output = child.wait_with_output().map_err(|e| format!("nft failed: {}", e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(format!("nft failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+}
+
+/// Traffic a blocklist drops: from listed sources arriving on the WAN,
+/// to listed destinations leaving through it, or both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    Both,
+}
+
+/// A configured blocklist, backed by `blocklist_<name>_v4`/`_v6` sets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blocklist {
+    pub name: String,
+    pub source: Source,
+    pub refresh: Duration,
+    pub direction: Direction,
+}
+
+impl Blocklist {
+    pub fn set_name(&self, family: &str) -> String {
+        format!("blocklist_{}_{}", self.name, family)
+    }
+
+    pub fn inbound(&self) -> bool {
+        self.direction != Direction::Outbound
+    }
+
+    pub fn outbound(&self) -> bool {
+        self.direction != Direction::Inbound
+    }
+
+    fn cache_file(&self, cache_dir: &Path) -> PathBuf {
+        cache_dir.join(format!("{}.txt", self.name))
+    }
+}
+
+/// Resolve the `blocklist` blocks of a config, sorted by name.
+pub fn from_hcl(config: &HclConfig) -> Result<Vec<Blocklist>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut lists = Vec::new();
+    let mut names: Vec<_> = config.blocklist.iter().collect();
+    names.sort_by_key(|(name, _)| name.as_str());
+    for (name, b) in names {
+        let context = format!("blocklist \"{}\"", name);
+        if !objects::valid_name(name) {
+            errors.push(format!(
+                "{}: names must start with a letter and contain only letters, digits, '_' and '-'.",
+                context
+            ));
+        }
+        let source = match (&b.url, &b.file) {
+            (Some(url), None) if url.starts_with("https://") || url.starts_with("http://") => {
+                Source::Url(url.clone())
+            }
+            (Some(url), None) => {
+                errors.push(format!("{}.url: '{}' must be an http:// or https:// URL.", context, url));
+                continue;
+            }
+            (None, Some(file)) => Source::File(PathBuf::from(file)),
+            _ => {
+                errors.push(format!("{}: exactly one of url or file is required.", context));
+                continue;
+            }
+        };
+        let refresh = match b.refresh.as_deref().map(|r| parse_duration(r, "refresh interval")) {
+            None => DEFAULT_REFRESH,
+            Some(Ok(d)) if d >= MIN_REFRESH => d,
+            Some(Ok(_)) => {
+                errors.push(format!("{}.refresh: must be at least 5m.", context));
+                continue;
+            }
+            Some(Err(e)) => {
+                errors.push(format!("{}.refresh: {}", context, e));
+                continue;
+            }
+        };
+        let direction = match b.direction.as_deref().unwrap_or("inbound") {
+            "inbound" => Direction::Inbound,
+            "outbound" => Direction::Outbound,
+            "both" => Direction::Both,
+            other => {
+                errors.push(format!(
+                    "{}.direction: '{}' must be \"inbound\", \"outbound\" or \"both\".",
+                    context, other
+                ));
+                continue;
+            }
+        };
+        lists.push(Blocklist { name: name.clone(), source, refresh, direction });
+    }
+    if errors.is_empty() {
+        Ok(lists)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Extract IPv4 and IPv6 networks from blocklist text: one address or CIDR
+/// per line, with `#` or `;` comments (the Spamhaus DROP format). Lines that
+/// do not start with an address are skipped.
+pub fn parse_entries(text: &str) -> (Vec<String>, Vec<String>) {
+    let mut seen = HashSet::new();
+    let (mut v4, mut v6) = (Vec::new(), Vec::new());
+    for line in text.lines() {
+        let line = line.split(['#', ';']).next().unwrap_or("");
+        let Some(token) = line.split_whitespace().next() else { continue };
+        let Ok(net) = token.parse::<IpNetwork>() else { continue };
+        // nft rejects interval elements with host bits set
+        let entry = format!("{}/{}", net.network(), net.prefix());
+        if seen.insert(entry.clone()) {
+            if net.is_ipv4() {
+                v4.push(entry);
+            } else {
+                v6.push(entry);
+            }
+        }
+    }
+    (v4, v6)
+}
+
+/// nft script replacing a blocklist's set contents. `nft -f` applies it as
+/// one transaction, so the sets are never seen half-filled or empty.
+pub fn update_script(list: &Blocklist, v4: &[String], v6: &[String]) -> String {
+    let mut script = String::new();
+    for (family, entries) in [("v4", v4), ("v6", v6)] {
+        let set = list.set_name(family);
+        script.push_str(&format!("flush set inet {} {}\n", tables::FILTER, set));
+        if !entries.is_empty() {
+            script.push_str(&format!("add element inet {} {} {{ {} }}\n", tables::FILTER, set, entries.join(", ")));
+        }
+    }
+    script
+}
+
+/// Replace the cached copy of a list in one step, so a crash mid-write
+/// never leaves a truncated list to be loaded at the next start.
+fn write_cache(cache: &Path, text: &str) -> std::io::Result<()> {
+    let tmp = cache.with_extension("txt.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, cache)
+}
+
+/// Load one blocklist into its sets. A cached copy younger than the refresh
+/// interval is used instead of fetching unless `force` is set. Returns when
+/// the list is next due.
+fn refresh(
+    list: &Blocklist,
+    fetcher: &dyn Fetcher,
+    applier: &dyn Applier,
+    cache_dir: &Path,
+    force: bool,
+) -> SystemTime {
+    let now = SystemTime::now();
+    let cache = list.cache_file(cache_dir);
+    let cached_at = fs::metadata(&cache).and_then(|m| m.modified()).ok();
+    let fresh = cached_at.is_some_and(|t| now.duration_since(t).unwrap_or_default() < list.refresh);
+
+    let (text, fetched_at) = if fresh && !force {
+        (fs::read_to_string(&cache).ok(), cached_at.unwrap())
+    } else {
+        match fetcher.fetch(&list.source) {
+            Ok(text) => {
+                let _ = fs::create_dir_all(cache_dir);
+                if let Err(e) = write_cache(&cache, &text) {
+                    eprintln!("blocklist-sync: {}: cannot cache to {}: {}", list.name, cache.display(), e);
+                }
+                (Some(text), now)
+            }
+            Err(e) => {
+                // Keep serving the last good copy; try again sooner
+                eprintln!("blocklist-sync: {}: {}", list.name, e);
+                let retry = now + list.refresh.min(Duration::from_secs(15 * 60));
+                return match fs::read_to_string(&cache) {
+                    Ok(text) => {
+                        load(list, applier, &text);
+                        retry
+                    }
+                    Err(_) => retry,
+                };
+            }
+        }
+    };
+    if let Some(text) = text {
+        load(list, applier, &text);
+    }
+    fetched_at + list.refresh
+}
+
+fn load(list: &Blocklist, applier: &dyn Applier, text: &str) {
+    let (v4, v6) = parse_entries(text);
+    match applier.apply(&update_script(list, &v4, &v6)) {
+        Ok(()) => eprintln!(
+            "blocklist-sync: {}: loaded {} IPv4 and {} IPv6 entries from {}",
+            list.name,
+            v4.len(),
+            v6.len(),
+            list.source
+        ),
+        Err(e) => eprintln!("blocklist-sync: {}: {}", list.name, e),
+    }
+}
+
+/// Refill every blocklist's sets from its cached copy, however old, without
+/// fetching. Used right after a ruleset load, which must not wait on
+/// downloads; lists never cached stay empty until the next sync.
+pub fn load_cached(lists: &[Blocklist], applier: &dyn Applier, cache_dir: &Path) {
+    for list in lists {
+        match fs::read_to_string(list.cache_file(cache_dir)) {
+            Ok(text) => load(list, applier, &text),
+            Err(_) => eprintln!("blocklist-sync: {}: no cached copy yet", list.name),
+        }
+    }
+}
+
+/// Fill every blocklist's sets. With `watch`, keep running and refresh
+/// each list when its interval elapses; otherwise load once and return.
+pub fn sync(lists: &[Blocklist], fetcher: &dyn Fetcher, applier: &dyn Applier, cache_dir: &Path, watch: bool) {
+    let mut due: Vec<SystemTime> =
+        lists.iter().map(|l| refresh(l, fetcher, applier, cache_dir, false)).collect();
+    if !watch {
+        return;
+    }
+    loop {
+        let next = due.iter().min().copied().unwrap_or_else(SystemTime::now);
+        if let Ok(wait) = next.duration_since(SystemTime::now()) {
+            thread::sleep(wait.min(Duration::from_secs(60)));
+            continue;
+        }
+        for (list, due) in lists.iter().zip(due.iter_mut()) {
+            if *due <= SystemTime::now() {
+                *due = refresh(list, fetcher, applier, cache_dir, true);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+    use std::cell::{Cell, RefCell};
+    use tempfile::TempDir;
+
+    fn config(blocklists: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{ enable_ipv4 = true }}
+{}
+"#,
+            blocklists
+        ))
+        .unwrap()
+    }
+
+    struct CountingFetcher {
+        text: &'static str,
+        calls: Cell<u32>,
+    }
+
+    impl Fetcher for CountingFetcher {
+        fn fetch(&self, _source: &Source) -> Result<String, String> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(self.text.to_string())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingApplier {
+        scripts: RefCell<Vec<String>>,
+    }
+
+    impl Applier for RecordingApplier {
+        fn apply(&self, script: &str) -> Result<(), String> {
+            self.scripts.borrow_mut().push(script.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_from_hcl() {
+        let lists = from_hcl(&config(
+            r#"
+blocklist "spamhaus-drop" {
+  url       = "https://www.spamhaus.org/drop/drop.txt"
+  refresh   = "6h"
+  direction = "both"
+}
+blocklist "local" { file = "/etc/nifty-filter/block.txt" }
+"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            lists,
+            vec![
+                Blocklist {
+                    name: "local".into(),
+                    source: Source::File("/etc/nifty-filter/block.txt".into()),
+                    refresh: Duration::from_secs(86400),
+                    direction: Direction::Inbound,
+                },
+                Blocklist {
+                    name: "spamhaus-drop".into(),
+                    source: Source::Url("https://www.spamhaus.org/drop/drop.txt".into()),
+                    refresh: Duration::from_secs(6 * 3600),
+                    direction: Direction::Both,
+                },
+            ]
+        );
+        assert_eq!(lists[1].set_name("v4"), "blocklist_spamhaus-drop_v4");
+    }
+
+    #[test]
+    fn test_from_hcl_errors() {
+        let errors = from_hcl(&config(
+            r#"
+blocklist "a" {
+  url  = "https://example.com/a.txt"
+  file = "/tmp/a.txt"
+}
+blocklist "b" {
+  file    = "/tmp/b.txt"
+  refresh = "1m"
+}
+blocklist "c" {
+  url       = "ftp://example.com/c.txt"
+}
+blocklist "d" {
+  file      = "/tmp/d.txt"
+  direction = "sideways"
+}
+blocklist "e" {
+  file    = "/tmp/e.txt"
+  refresh = "often"
+}
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "blocklist \"a\": exactly one of url or file is required.",
+                "blocklist \"b\".refresh: must be at least 5m.",
+                "blocklist \"c\".url: 'ftp://example.com/c.txt' must be an http:// or https:// URL.",
+                "blocklist \"d\".direction: 'sideways' must be \"inbound\", \"outbound\" or \"both\".",
+                "blocklist \"e\".refresh: Invalid refresh interval: 'often'. Expected e.g. \"30m\", \"6h\" or \"1d\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let text = "\
+; Spamhaus DROP List
+1.10.16.0/20 ; SBL256894
+2.57.122.0/24 ; SBL636050
+# a comment
+203.0.113.7
+203.0.113.7/32
+2001:db8::/32
+198.51.100.9/24
+not an address
+";
+        let (v4, v6) = parse_entries(text);
+        assert_eq!(v4, vec!["1.10.16.0/20", "2.57.122.0/24", "203.0.113.7/32", "198.51.100.0/24"]);
+        assert_eq!(v6, vec!["2001:db8::/32"]);
+    }
+
+    #[test]
+    fn test_update_script() {
+        let list = from_hcl(&config(r#"blocklist "drop" { file = "/tmp/drop.txt" }"#)).unwrap().remove(0);
+        assert_eq!(
+            update_script(&list, &["1.10.16.0/20".into(), "2.57.122.0/24".into()], &[]),
+            "flush set inet nifty_filter blocklist_drop_v4\n\
+             add element inet nifty_filter blocklist_drop_v4 { 1.10.16.0/20, 2.57.122.0/24 }\n\
+             flush set inet nifty_filter blocklist_drop_v6\n"
+        );
+    }
+
+    #[test]
+    fn test_system_fetcher_reads_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("list.txt");
+        fs::write(&path, "192.0.2.0/24\n").unwrap();
+        assert_eq!(SystemFetcher.fetch(&Source::File(path)).unwrap(), "192.0.2.0/24\n");
+        assert!(SystemFetcher.fetch(&Source::File(dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn test_refresh_uses_fresh_cache() {
+        let dir = TempDir::new().unwrap();
+        let list = Blocklist {
+            name: "drop".into(),
+            source: Source::Url("https://example.com/drop.txt".into()),
+            refresh: Duration::from_secs(3600),
+            direction: Direction::Inbound,
+        };
+        let fetcher = CountingFetcher { text: "192.0.2.0/24\n", calls: Cell::new(0) };
+        let applier = RecordingApplier::default();
+
+        refresh(&list, &fetcher, &applier, dir.path(), false);
+        assert_eq!(fetcher.calls.get(), 1);
+        assert_eq!(fs::read_to_string(dir.path().join("drop.txt")).unwrap(), "192.0.2.0/24\n");
+        assert!(!dir.path().join("drop.txt.tmp").exists());
+
+        // A reload within the refresh interval uses the cached copy
+        refresh(&list, &fetcher, &applier, dir.path(), false);
+        assert_eq!(fetcher.calls.get(), 1);
+        refresh(&list, &fetcher, &applier, dir.path(), true);
+        assert_eq!(fetcher.calls.get(), 2);
+        assert_eq!(applier.scripts.borrow().len(), 3);
+        assert_eq!(applier.scripts.borrow()[0], update_script(&list, &["192.0.2.0/24".into()], &[]));
+    }
+
+    #[test]
+    fn test_load_cached_never_fetches() {
+        let dir = TempDir::new().unwrap();
+        let lists = from_hcl(&config(
+            r#"
+blocklist "cached" { url = "https://example.com/cached.txt" }
+blocklist "missing" { url = "https://example.com/missing.txt" }
+"#,
+        ))
+        .unwrap();
+        fs::write(dir.path().join("cached.txt"), "198.51.100.0/24\n").unwrap();
+        let applier = RecordingApplier::default();
+
+        load_cached(&lists, &applier, dir.path());
+        assert_eq!(*applier.scripts.borrow(), vec![update_script(&lists[0], &["198.51.100.0/24".into()], &[])]);
+    }
+}
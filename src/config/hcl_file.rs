@@ -149,6 +149,16 @@ pub fn format_hcl(config: &HclConfig) -> String {
     // wan
     write_wan(&mut w, &config.wan);
     w.blank();
+    write_wan_uplinks(&mut w, &config.wan);
+
+    // named objects referenced by rules
+    write_objects(&mut w, config);
+
+    // drop logging
+    if let Some(ref logging) = config.logging {
+        write_logging(&mut w, logging);
+        w.blank();
+    }
 
     // vlans (sorted by id)
     let mut vlans: Vec<(&String, &VlanHclConfig)> = config.vlan.iter().collect();
@@ -158,6 +168,22 @@ pub fn format_hcl(config: &HclConfig) -> String {
         w.blank();
     }
 
+    // wireguard zones (sorted by name)
+    let mut zones: Vec<(&String, &WireguardHclConfig)> = config.wireguard.iter().collect();
+    zones.sort_by_key(|(name, _)| name.as_str());
+    for (name, wg) in zones {
+        write_wireguard(&mut w, name, wg);
+        w.blank();
+    }
+
+    // wireguard client tunnels (sorted by name)
+    let mut clients: Vec<(&String, &WireguardClientHclConfig)> = config.wireguard_client.iter().collect();
+    clients.sort_by_key(|(name, _)| name.as_str());
+    for (name, client) in clients {
+        write_wireguard_client(&mut w, name, client);
+        w.blank();
+    }
+
     // qos
     if let Some(ref qos) = config.qos {
         write_qos(&mut w, qos);
@@ -242,9 +268,154 @@ fn write_wan(w: &mut HclWriter, wan: &WanConfig) {
         }
         w.string_array("udp_forward", &wan.udp_forward);
     }
+    for (description, forward) in &wan.forward {
+        w.blank();
+        w.open_labeled("forward", description);
+        w.str_attr("protocol", &forward.protocol);
+        w.string_array("ports", &forward.ports);
+        w.str_attr("to", &forward.to);
+        if let Some(port) = forward.to_port {
+            w.num_attr("to_port", port);
+        }
+        if !forward.from.is_empty() {
+            w.string_array("from", &forward.from);
+        }
+        if let Some(ref during) = forward.during {
+            w.str_attr("during", during);
+        }
+        w.close();
+    }
+    if wan.hairpin {
+        if !wan.forward.is_empty() {
+            w.blank();
+        }
+        w.bool_attr("hairpin", true);
+    }
+    for (port, limit) in &wan.port_limit {
+        w.blank();
+        w.open_labeled("port_limit", port);
+        if let Some(ref rate) = limit.limit {
+            w.str_attr("limit", rate);
+        }
+        if let Some(ct_limit) = limit.ct_limit {
+            w.num_attr("ct_limit", ct_limit);
+        }
+        w.close();
+    }
+    if let Some(ref guard) = wan.ssh_guard {
+        w.blank();
+        w.open("ssh_guard");
+        if let Some(port) = guard.port {
+            w.num_attr("port", port);
+        }
+        if let Some(ref threshold) = guard.threshold {
+            w.str_attr("threshold", threshold);
+        }
+        if let Some(ref ban) = guard.ban {
+            w.str_attr("ban", ban);
+        }
+        w.close();
+    }
+    if let Some(ref knock) = wan.knock {
+        w.blank();
+        w.open("knock");
+        w.string_array("sequence", &knock.sequence);
+        if let Some(ref timeout) = knock.timeout {
+            w.str_attr("timeout", timeout);
+        }
+        w.string_array("opens", &knock.opens);
+        w.close();
+    }
+    write_wan_addressing(
+        w,
+        wan.mode.as_deref(),
+        &wan.address,
+        wan.gateway.as_deref(),
+        wan.gateway6.as_deref(),
+        &wan.dns,
+        wan.pppoe.as_ref(),
+    );
+    if let Some(ref policy) = wan.policy {
+        w.blank();
+        w.str_attr("policy", policy);
+    }
+    if let Some(interval) = wan.health_interval {
+        w.num_attr("health_interval", interval);
+    }
     w.close();
 }
 
+/// Top-level `wan "name" {}` blocks, one per uplink.
+fn write_wan_uplinks(w: &mut HclWriter, wan: &WanConfig) {
+    for (name, uplink) in &wan.uplink {
+        w.open_labeled("wan", name);
+        w.str_attr("interface", &uplink.interface);
+        w.num_attr("priority", uplink.priority);
+        w.num_attr("weight", uplink.weight);
+        if !uplink.health_check.is_empty() {
+            w.string_array("health_check", &uplink.health_check);
+        }
+        if let Some(table) = uplink.table {
+            w.num_attr("table", table);
+        }
+        write_wan_addressing(
+            w,
+            uplink.mode.as_deref(),
+            &uplink.address,
+            uplink.gateway.as_deref(),
+            uplink.gateway6.as_deref(),
+            &uplink.dns,
+            uplink.pppoe.as_ref(),
+        );
+        w.close();
+        w.blank();
+    }
+}
+
+/// Addressing attributes shared by `wan {}` and `wan "name" {}`.
+fn write_wan_addressing(
+    w: &mut HclWriter,
+    mode: Option<&str>,
+    address: &[String],
+    gateway: Option<&str>,
+    gateway6: Option<&str>,
+    dns: &[String],
+    pppoe: Option<&PppoeConfig>,
+) {
+    let Some(mode) = mode else {
+        return;
+    };
+    w.blank();
+    w.str_attr("mode", mode);
+    if !address.is_empty() {
+        w.string_array("address", address);
+    }
+    if let Some(gw) = gateway {
+        w.str_attr("gateway", gw);
+    }
+    if let Some(gw) = gateway6 {
+        w.str_attr("gateway6", gw);
+    }
+    if !dns.is_empty() {
+        w.string_array("dns", dns);
+    }
+    if let Some(p) = pppoe {
+        w.open("pppoe");
+        w.str_attr("username", &p.username);
+        w.str_attr("password", &p.password);
+        if let Some(ref ifname) = p.ifname {
+            w.str_attr("ifname", ifname);
+        }
+        if let Some(ref service) = p.service_name {
+            w.str_attr("service_name", service);
+        }
+        if let Some(mtu) = p.mtu {
+            w.num_attr("mtu", mtu);
+        }
+        w.close();
+    }
+}
+
 fn write_vlan(w: &mut HclWriter, name: &str, vlan: &VlanHclConfig) {
     w.open_labeled("vlan", name);
     w.num_attr("id", vlan.id);
@@ -282,21 +453,20 @@ fn write_vlan(w: &mut HclWriter, name: &str, vlan: &VlanHclConfig) {
     if let Some(ref ipv6) = vlan.ipv6 {
         w.blank();
         w.open("ipv6");
-        w.str_attr("subnet", &ipv6.subnet);
+        if ipv6.delegated {
+            w.bool_attr("delegated", true);
+            if let Some(id) = ipv6.subnet_id {
+                w.str_attr("subnet_id", &format!("{:#x}", id));
+            }
+        } else {
+            w.str_attr("subnet", &ipv6.subnet);
+        }
         w.string_array("egress", &ipv6.egress);
         w.close();
     }
 
     if let Some(ref fw) = vlan.firewall {
-        w.blank();
-        w.open("firewall");
-        w.string_array("icmp_accept", &fw.icmp_accept);
-        if !fw.icmpv6_accept.is_empty() {
-            w.string_array("icmpv6_accept", &fw.icmpv6_accept);
-        }
-        w.u16_array("tcp_accept", &fw.tcp_accept);
-        w.u16_array("udp_accept", &fw.udp_accept);
-        w.close();
+        write_firewall(w, fw);
     }
 
     if let Some(ref dhcp) = vlan.dhcp {
@@ -336,6 +506,39 @@ fn write_vlan(w: &mut HclWriter, name: &str, vlan: &VlanHclConfig) {
         w.bool_attr("iperf_enabled", true);
     }
 
+    if let Some(table) = vlan.routing_table {
+        w.blank();
+        w.num_attr("routing_table", table);
+        if let Some(fwmark) = vlan.fwmark {
+            w.num_attr("fwmark", fwmark);
+        }
+    }
+
+    if let Some(ref tunnel) = vlan.egress_via {
+        w.blank();
+        w.str_attr("egress_via", tunnel);
+    }
+
+    if let Some(ref upnp) = vlan.upnp {
+        w.blank();
+        w.open("upnp");
+        w.bool_attr("enabled", upnp.enabled);
+        if let Some(ref ports) = upnp.allowed_ports {
+            w.str_attr("allowed_ports", ports);
+        }
+        w.close();
+    }
+
+    if !vlan.egress_blocked.is_empty() {
+        w.string_array("egress_blocked", &vlan.egress_blocked);
+    }
+    if !vlan.egress_domains.is_empty() {
+        w.string_array("egress_domains", &vlan.egress_domains);
+    }
+    if let Some(ref log) = vlan.log {
+        log_attr(w, "log", log);
+    }
+
     if !vlan.tcp_forward.is_empty() {
         w.blank();
         w.string_array("tcp_forward", &vlan.tcp_forward);
@@ -352,8 +555,237 @@ fn write_vlan(w: &mut HclWriter, name: &str, vlan: &VlanHclConfig) {
         w.string_array("allow_inbound_udp", &vlan.allow_inbound_udp);
     }
 
-    // inter-vlan rules
-    let mut allow_from: Vec<_> = vlan.allow_from.iter().collect();
+    write_allow_from(w, &vlan.allow_from);
+
+    let mut clients: Vec<_> = vlan.client.iter().collect();
+    clients.sort_by_key(|(key, _)| key.as_str());
+    for (key, client) in clients {
+        w.blank();
+        w.open_labeled("client", key);
+        if !client.egress.is_empty() {
+            w.string_array("egress", &client.egress);
+        }
+        w.close();
+    }
+
+    w.close();
+}
+
+fn write_wireguard_client(w: &mut HclWriter, name: &str, client: &WireguardClientHclConfig) {
+    w.open_labeled("wireguard_client", name);
+    w.str_attr("private_key_file", &client.private_key_file);
+    w.string_array("address", &client.address);
+    if let Some(mtu) = client.mtu {
+        w.num_attr("mtu", mtu);
+    }
+    if let Some(table) = client.table {
+        w.num_attr("table", table);
+    }
+
+    w.blank();
+    w.open("peer");
+    w.str_attr("public_key", &client.peer.public_key);
+    w.str_attr("endpoint", &client.peer.endpoint);
+    if let Some(ref psk) = client.peer.preshared_key_file {
+        w.str_attr("preshared_key_file", psk);
+    }
+    if let Some(keepalive) = client.peer.persistent_keepalive {
+        w.num_attr("persistent_keepalive", keepalive);
+    }
+    w.close();
+
+    w.close();
+}
+
+fn write_wireguard(w: &mut HclWriter, name: &str, wg: &WireguardHclConfig) {
+    w.open_labeled("wireguard", name);
+    w.num_attr("listen_port", wg.listen_port);
+    w.str_attr("private_key_file", &wg.private_key_file);
+    if let Some(ref endpoint) = wg.endpoint {
+        w.str_attr("endpoint", endpoint);
+    }
+    if let Some(mtu) = wg.mtu {
+        w.num_attr("mtu", mtu);
+    }
+
+    if let Some(ref ipv4) = wg.ipv4 {
+        w.blank();
+        w.open("ipv4");
+        w.str_attr("subnet", &ipv4.subnet);
+        w.string_array("egress", &ipv4.egress);
+        w.close();
+    }
+
+    if let Some(ref ipv6) = wg.ipv6 {
+        w.blank();
+        w.open("ipv6");
+        w.str_attr("subnet", &ipv6.subnet);
+        w.string_array("egress", &ipv6.egress);
+        w.close();
+    }
+
+    if let Some(ref fw) = wg.firewall {
+        write_firewall(w, fw);
+    }
+
+    write_allow_from(w, &wg.allow_from);
+
+    for (peer_name, peer) in &wg.peer {
+        w.blank();
+        w.open_labeled("peer", peer_name);
+        w.str_attr("public_key", &peer.public_key);
+        w.string_array("allowed_ips", &peer.allowed_ips);
+        if let Some(ref psk) = peer.preshared_key_file {
+            w.str_attr("preshared_key_file", psk);
+        }
+        w.close();
+    }
+
+    w.close();
+}
+
+/// Host, group and service objects, each kind sorted by name.
+fn write_objects(w: &mut HclWriter, config: &HclConfig) {
+    let mut hosts: Vec<_> = config.host.iter().collect();
+    hosts.sort_by_key(|(name, _)| name.as_str());
+    for (name, host) in hosts {
+        w.open_labeled("host", name);
+        if let Some(ref ipv4) = host.ipv4 {
+            w.str_attr("ipv4", ipv4);
+        }
+        if let Some(ref ipv6) = host.ipv6 {
+            w.str_attr("ipv6", ipv6);
+        }
+        w.close();
+        w.blank();
+    }
+
+    let mut groups: Vec<_> = config.group.iter().collect();
+    groups.sort_by_key(|(name, _)| name.as_str());
+    for (name, group) in groups {
+        w.open_labeled("group", name);
+        w.string_array("members", &group.members);
+        w.close();
+        w.blank();
+    }
+
+    let mut services: Vec<_> = config.service.iter().collect();
+    services.sort_by_key(|(name, _)| name.as_str());
+    for (name, service) in services {
+        w.open_labeled("service", name);
+        if !service.tcp.is_empty() {
+            w.u16_array("tcp", &service.tcp);
+        }
+        if !service.udp.is_empty() {
+            w.u16_array("udp", &service.udp);
+        }
+        w.close();
+        w.blank();
+    }
+
+    let mut blocklists: Vec<_> = config.blocklist.iter().collect();
+    blocklists.sort_by_key(|(name, _)| name.as_str());
+    for (name, list) in blocklists {
+        w.open_labeled("blocklist", name);
+        if let Some(ref url) = list.url {
+            w.str_attr("url", url);
+        }
+        if let Some(ref file) = list.file {
+            w.str_attr("file", file);
+        }
+        if let Some(ref refresh) = list.refresh {
+            w.str_attr("refresh", refresh);
+        }
+        if let Some(ref direction) = list.direction {
+            w.str_attr("direction", direction);
+        }
+        w.close();
+        w.blank();
+    }
+
+    let mut routes: Vec<_> = config.route.iter().collect();
+    routes.sort_by_key(|(name, _)| name.as_str());
+    for (name, route) in routes {
+        w.open_labeled("route", name);
+        w.str_attr("destination", &route.destination);
+        w.str_attr("gateway", &route.gateway);
+        if let Some(metric) = route.metric {
+            w.num_attr("metric", metric);
+        }
+        if let Some(table) = route.table {
+            w.num_attr("table", table);
+        }
+        w.close();
+        w.blank();
+    }
+
+    let mut schedules: Vec<_> = config.schedule.iter().collect();
+    schedules.sort_by_key(|(name, _)| name.as_str());
+    for (name, schedule) in schedules {
+        w.open_labeled("schedule", name);
+        if !schedule.days.is_empty() {
+            w.string_array("days", &schedule.days);
+        }
+        if let Some(ref from) = schedule.from {
+            w.str_attr("from", from);
+        }
+        if let Some(ref to) = schedule.to {
+            w.str_attr("to", to);
+        }
+        w.close();
+        w.blank();
+    }
+}
+
+fn log_attr(w: &mut HclWriter, key: &str, setting: &LogSetting) {
+    match setting {
+        LogSetting::Enabled(on) => w.bool_attr(key, *on),
+        LogSetting::Mode(mode) => w.str_attr(key, mode),
+    }
+}
+
+fn write_logging(w: &mut HclWriter, logging: &LoggingHclConfig) {
+    w.open("logging");
+    if let Some(group) = logging.nflog_group {
+        w.num_attr("nflog_group", group);
+    }
+    if let Some(ref rate) = logging.rate {
+        w.str_attr("rate", rate);
+    }
+    for (key, setting) in [
+        ("input", &logging.input),
+        ("forward", &logging.forward),
+        ("trunk", &logging.trunk),
+        ("mgmt", &logging.mgmt),
+    ] {
+        if let Some(setting) = setting {
+            log_attr(w, key, setting);
+        }
+    }
+    if logging.counters {
+        w.bool_attr("counters", true);
+    }
+    w.close();
+}
+
+fn write_firewall(w: &mut HclWriter, fw: &FirewallConfig) {
+    w.blank();
+    w.open("firewall");
+    w.string_array("icmp_accept", &fw.icmp_accept);
+    if !fw.icmpv6_accept.is_empty() {
+        w.string_array("icmpv6_accept", &fw.icmpv6_accept);
+    }
+    w.u16_array("tcp_accept", &fw.tcp_accept);
+    w.u16_array("udp_accept", &fw.udp_accept);
+    w.close();
+}
+
+/// Inter-zone rules, sorted by source name.
+fn write_allow_from(
+    w: &mut HclWriter,
+    allow_from: &std::collections::HashMap<String, InterVlanHclConfig>,
+) {
+    let mut allow_from: Vec<_> = allow_from.iter().collect();
     allow_from.sort_by_key(|(name, _)| (*name).clone());
     for (from_name, rules) in allow_from {
         w.blank();
@@ -366,8 +798,6 @@ fn write_vlan(w: &mut HclWriter, name: &str, vlan: &VlanHclConfig) {
         }
         w.close();
     }
-
-    w.close();
 }
 
 fn write_qos(w: &mut HclWriter, qos: &QosHclConfig) {
@@ -605,4 +1035,328 @@ vlan "lab" {
         assert_eq!(from.tcp, vec!["10.99.40.5:80"]);
         assert_eq!(from.udp, vec!["10.99.40.5:53"]);
     }
+
+    #[test]
+    fn round_trip_wireguard() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan "trusted" { id = 10 }
+wireguard "wg0" {
+  listen_port      = 51820
+  private_key_file = "/var/lib/nifty-filter/wg0.key"
+  endpoint         = "vpn.example.com"
+  ipv4 {
+    subnet = "10.99.100.1/24"
+    egress = ["0.0.0.0/0"]
+  }
+  allow_from "trusted" {
+    tcp = ["10.99.100.10:22"]
+  }
+  peer "phone" {
+    public_key         = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    allowed_ips        = ["10.99.100.10/32"]
+    preshared_key_file = "/var/lib/nifty-filter/phone.psk"
+  }
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        let reparsed = parse_hcl(&output).unwrap();
+        let wg = reparsed.wireguard.get("wg0").unwrap();
+        assert_eq!(wg.listen_port, 51820);
+        assert_eq!(wg.endpoint.as_deref(), Some("vpn.example.com"));
+        assert_eq!(wg.ipv4.as_ref().unwrap().subnet, "10.99.100.1/24");
+        assert_eq!(wg.allow_from.get("trusted").unwrap().tcp, vec!["10.99.100.10:22"]);
+        let peer = wg.peer.get("phone").unwrap();
+        assert_eq!(peer.allowed_ips, vec!["10.99.100.10/32"]);
+        assert_eq!(peer.preshared_key_file.as_deref(), Some("/var/lib/nifty-filter/phone.psk"));
+    }
+
+    #[test]
+    fn round_trip_wan_uplinks() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+  wan2  { name = "wan2" }
+}
+wan {
+  policy          = "balance"
+  health_interval = 10
+}
+wan "fiber" {
+  interface    = "wan"
+  weight       = 3
+  health_check = ["1.1.1.1"]
+}
+wan "lte" {
+  interface = "wan2"
+  priority  = 2
+  table     = 120
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        assert!(output.contains("wan \"fiber\" {"));
+        let reparsed = parse_hcl(&output).unwrap();
+        assert_eq!(reparsed.wan.policy.as_deref(), Some("balance"));
+        assert_eq!(reparsed.wan.uplink.len(), 2);
+        assert_eq!(reparsed.wan.uplink["fiber"].weight, 3);
+        assert_eq!(reparsed.wan.uplink["lte"].table, Some(120));
+    }
+
+    #[test]
+    fn round_trip_wireguard_client() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan "privacy" {
+  id         = 50
+  egress_via = "vpn-provider"
+}
+wireguard_client "vpn-provider" {
+  private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+  address          = ["10.64.0.2/32", "fc00:bbbb::2/128"]
+  table            = 210
+  peer {
+    public_key           = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    endpoint             = "vpn.example.net:51820"
+    persistent_keepalive = 15
+  }
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        let reparsed = parse_hcl(&output).unwrap();
+        assert_eq!(reparsed.vlan["privacy"].egress_via.as_deref(), Some("vpn-provider"));
+        let client = &reparsed.wireguard_client["vpn-provider"];
+        assert_eq!(client.address, vec!["10.64.0.2/32", "fc00:bbbb::2/128"]);
+        assert_eq!(client.table, Some(210));
+        assert_eq!(client.peer.endpoint, "vpn.example.net:51820");
+        assert_eq!(client.peer.persistent_keepalive, Some(15));
+    }
+
+    #[test]
+    fn round_trip_objects() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+host "nas" {
+  ipv4 = "10.99.10.5"
+  ipv6 = "fd00:10::5"
+}
+group "cameras" { members = ["nas", "10.99.20.16/28"] }
+service "dns" {
+  tcp = [53]
+  udp = [53]
+}
+blocklist "drop" {
+  url       = "https://www.spamhaus.org/drop/drop.txt"
+  refresh   = "6h"
+  direction = "both"
+}
+schedule "nights" {
+  days = ["mon", "tue"]
+  from = "22:00"
+  to   = "06:30"
+}
+vlan "trusted" {
+  id = 10
+  egress_blocked = ["nights"]
+  egress_domains = ["*.vendor-cloud.com"]
+  allow_from "trusted" {
+    tcp = ["@cameras:@nas:@dns"]
+  }
+  client "aa:bb:cc:dd:ee:01" {
+    egress = ["@nas"]
+  }
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        let reparsed = parse_hcl(&output).unwrap();
+        let nas = reparsed.host.get("nas").unwrap();
+        assert_eq!(nas.ipv4.as_deref(), Some("10.99.10.5"));
+        assert_eq!(nas.ipv6.as_deref(), Some("fd00:10::5"));
+        assert_eq!(reparsed.group.get("cameras").unwrap().members, vec!["nas", "10.99.20.16/28"]);
+        let dns = reparsed.service.get("dns").unwrap();
+        assert_eq!((dns.tcp.as_slice(), dns.udp.as_slice()), (&[53][..], &[53][..]));
+        let drop = reparsed.blocklist.get("drop").unwrap();
+        assert_eq!(drop.url.as_deref(), Some("https://www.spamhaus.org/drop/drop.txt"));
+        assert_eq!((drop.refresh.as_deref(), drop.direction.as_deref()), (Some("6h"), Some("both")));
+        let nights = reparsed.schedule.get("nights").unwrap();
+        assert_eq!(nights.days, vec!["mon", "tue"]);
+        assert_eq!((nights.from.as_deref(), nights.to.as_deref()), (Some("22:00"), Some("06:30")));
+        assert_eq!(reparsed.vlan["trusted"].egress_blocked, vec!["nights"]);
+        assert_eq!(reparsed.vlan["trusted"].egress_domains, vec!["*.vendor-cloud.com"]);
+        assert_eq!(reparsed.vlan["trusted"].client["aa:bb:cc:dd:ee:01"].egress, vec!["@nas"]);
+    }
+
+    #[test]
+    fn round_trip_wan_limits() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  tcp_accept = [22, 443]
+  port_limit "tcp/443" {
+    limit    = "10/second burst 20"
+    ct_limit = 50
+  }
+  ssh_guard {
+    threshold = "3/minute"
+    ban       = "6h"
+  }
+  knock {
+    sequence = ["tcp/7000", "udp/8000"]
+    timeout  = "15s"
+    opens    = ["tcp/2222"]
+  }
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        let reparsed = parse_hcl(&output).unwrap();
+        let limit = &reparsed.wan.port_limit["tcp/443"];
+        assert_eq!(limit.limit.as_deref(), Some("10/second burst 20"));
+        assert_eq!(limit.ct_limit, Some(50));
+        let guard = reparsed.wan.ssh_guard.as_ref().unwrap();
+        assert_eq!(guard.port, None);
+        assert_eq!((guard.threshold.as_deref(), guard.ban.as_deref()), (Some("3/minute"), Some("6h")));
+        let knock = reparsed.wan.knock.as_ref().unwrap();
+        assert_eq!(knock.sequence, vec!["tcp/7000", "udp/8000"]);
+        assert_eq!((knock.timeout.as_deref(), knock.opens.as_slice()), (Some("15s"), &["tcp/2222".to_string()][..]));
+    }
+
+    #[test]
+    fn round_trip_wan_forwards() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  tcp_forward = ["8443:192.168.10.5:443"]
+  forward "Game server" {
+    protocol = "tcp+udp"
+    ports    = ["27015-27030", "27036"]
+    to       = "192.168.10.6"
+    from     = ["203.0.113.0/24"]
+    during   = "weekends"
+  }
+  hairpin     = true
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        assert!(output.contains("hairpin = true"));
+        let reparsed = parse_hcl(&output).unwrap();
+        assert!(reparsed.wan.hairpin);
+        assert_eq!(reparsed.wan.tcp_forward, vec!["8443:192.168.10.5:443"]);
+        let forward = &reparsed.wan.forward["Game server"];
+        assert_eq!((forward.protocol.as_str(), forward.to.as_str()), ("tcp+udp", "192.168.10.6"));
+        assert_eq!(forward.ports, vec!["27015-27030", "27036"]);
+        assert_eq!(forward.from, vec!["203.0.113.0/24"]);
+        assert_eq!((forward.to_port, forward.during.as_deref()), (None, Some("weekends")));
+        assert!(!format_hcl(&parse_hcl(&hcl.replace("hairpin     = true", "")).unwrap()).contains("hairpin"));
+    }
+
+    #[test]
+    fn round_trip_vlan_upnp() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan "games" {
+  id = 20
+  ipv4 { subnet = "10.20.0.1/24" }
+  upnp {
+    enabled       = true
+    allowed_ports = "3074-3079"
+  }
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let reparsed = parse_hcl(&format_hcl(&config)).unwrap();
+        let upnp = reparsed.vlan["games"].upnp.as_ref().unwrap();
+        assert!(upnp.enabled);
+        assert_eq!(upnp.allowed_ports.as_deref(), Some("3074-3079"));
+    }
+
+    #[test]
+    fn round_trip_routes() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+route "via-vpn" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.254"
+  metric      = 10
+  table       = 200
+}
+vlan "guest" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  routing_table = 200
+  fwmark        = 30
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let reparsed = parse_hcl(&format_hcl(&config)).unwrap();
+        let route = &reparsed.route["via-vpn"];
+        assert_eq!((route.destination.as_str(), route.gateway.as_str()), ("0.0.0.0/0", "10.99.40.254"));
+        assert_eq!((route.metric, route.table), (Some(10), Some(200)));
+        assert_eq!((reparsed.vlan["guest"].routing_table, reparsed.vlan["guest"].fwmark), (Some(200), Some(30)));
+    }
+
+    #[test]
+    fn round_trip_logging() {
+        let hcl = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+logging {
+  nflog_group = 2
+  rate        = "10/second"
+  input       = true
+  trunk       = false
+  mgmt        = "sampled"
+  counters    = true
+}
+vlan "iot" {
+  id  = 20
+  log = false
+}
+"#;
+        let config = parse_hcl(hcl).unwrap();
+        let output = format_hcl(&config);
+        let reparsed = parse_hcl(&output).unwrap();
+        let logging = reparsed.logging.as_ref().unwrap();
+        assert_eq!((logging.nflog_group, logging.rate.as_deref()), (Some(2), Some("10/second")));
+        assert_eq!(logging.input, Some(LogSetting::Enabled(true)));
+        assert_eq!(logging.forward, None);
+        assert_eq!(logging.trunk, Some(LogSetting::Enabled(false)));
+        assert_eq!(logging.mgmt, Some(LogSetting::Mode("sampled".into())));
+        assert!(logging.counters);
+        assert_eq!(reparsed.vlan["iot"].log, Some(LogSetting::Enabled(false)));
+    }
 }
@@ -164,6 +164,14 @@ fn save_config(config: &HclConfig) {
     if let Err(e) = hcl_file::save(config, Path::new(HCL_FILE)) {
         eprintln!("  Error saving config: {e}");
     }
+    warn_addressing(config);
+}
+
+/// Warn about addressing mistakes right away rather than at the next apply.
+fn warn_addressing(config: &HclConfig) {
+    for problem in crate::validate::check_addressing(config) {
+        eprintln!("  Warning: {problem}");
+    }
 }
 
 // --- Editor functions ---
@@ -284,10 +292,14 @@ fn edit_vlan_subnet_ipv6(config: &mut HclConfig, vlan_name: &str) {
     // Update or create ipv6 block
     if let Some(ref mut ipv6) = vlan.ipv6 {
         ipv6.subnet = val.clone();
+        ipv6.delegated = false;
+        ipv6.subnet_id = None;
     } else {
         vlan.ipv6 = Some(Ipv6Config {
             subnet: val.clone(),
             egress: vec!["::/0".to_string()],
+            delegated: false,
+            subnet_id: None,
         });
     }
 
@@ -459,6 +471,7 @@ fn edit_forwards(forwards: &mut Vec<String>, label: &str) {
     let current = format_forwards(forwards);
     println!("  Format: incoming_port:dest_ip:dest_port (comma-separated)");
     println!("  IPv6:   incoming_port:[ipv6_addr]:dest_port");
+    println!("  Range:  first_port-last_port:dest_ip (same ports on the destination)");
     let val = match prompt_text_allow_blank(label, &current) {
         Some(v) => v,
         None => return,
@@ -678,28 +691,39 @@ fn edit_vlan_dhcpv6_pool(config: &mut HclConfig, vlan_name: &str) {
     println!("  Set VLAN \"{vlan_name}\" DHCPv6 pool: {start} - {end}");
 }
 
-fn apply_changes(config: &HclConfig) {
-    for (service, label) in [
-        ("nifty-filter", "Firewall rules"),
-        ("nifty-network", "Network"),
-        ("nifty-dnsmasq", "DHCP/DNS"),
-    ] {
-        println!("  Restarting {service}...");
-        match Command::new("sudo")
-            .args(["systemctl", "restart", service])
-            .status()
-        {
-            Ok(s) if s.success() => println!("  {label} applied."),
-            _ => println!("  Failed! Check: journalctl -u {service}"),
-        }
+fn apply_changes() {
+    // Changes are rolled back automatically unless confirmed, so a bad
+    // firewall edit over SSH can't lock us out
+    let timeout = match prompt_text("Roll back unless confirmed within (seconds, 0 = never)", "120") {
+        Some(v) => match v.trim().parse::<u64>() {
+            Ok(t) => t,
+            Err(_) => {
+                println!("  Invalid number.");
+                return;
+            }
+        },
+        None => return,
+    };
+    if let Err(e) = crate::apply::apply(HCL_FILE, (timeout > 0).then_some(timeout)) {
+        println!("  Error: {e}");
+        return;
+    }
+    if timeout == 0 {
+        println!("  Done.");
+        return;
     }
-    if let Some(ref hostname) = config.hostname {
-        println!("  Setting hostname...");
-        let _ = Command::new("sudo")
-            .args(["hostname", hostname])
-            .status();
+    let keep = prompt_text("Keep these changes? (yes/no)", "yes")
+        .map(|v| v.trim().eq_ignore_ascii_case("yes") || v.trim().eq_ignore_ascii_case("y"))
+        .unwrap_or(false);
+    let result = if keep {
+        crate::apply::confirm(HCL_FILE)
+    } else {
+        crate::apply::rollback(HCL_FILE)
+    };
+    match result {
+        Ok(()) => println!("  Done."),
+        Err(e) => println!("  Error: {e}"),
     }
-    println!("  Done.");
 }
 
 fn show_status() {
@@ -878,7 +902,14 @@ fn show_config(config: &HclConfig) {
             );
         }
         if let Some(ref ipv6) = vlan.ipv6 {
-            println!("  IPv6 subnet:    {}", subnet_label(&ipv6.subnet));
+            if ipv6.delegated {
+                println!(
+                    "  IPv6 subnet:    delegated (subnet ID {:#x})",
+                    ipv6.delegated_subnet_id(vlan.id)
+                );
+            } else {
+                println!("  IPv6 subnet:    {}", subnet_label(&ipv6.subnet));
+            }
             println!(
                 "  egress IPv6:    {}",
                 if ipv6.egress.is_empty() {
@@ -1649,11 +1680,14 @@ pub fn run() {
                     "Firewall" => menu_firewall(&mut config),
                     "Port forwarding" => menu_port_forwarding(&mut config),
                     "DHCP / DNS" => menu_dhcp_dns(&mut config),
-                    "Apply changes" => apply_changes(&config),
+                    "Apply changes" => apply_changes(),
                     "Edit nifty-filter.hcl" => {
                         launch_editor(HCL_FILE);
                         match hcl_file::load(Path::new(HCL_FILE)) {
-                            Ok(new_config) => config = new_config,
+                            Ok(new_config) => {
+                                warn_addressing(&new_config);
+                                config = new_config;
+                            }
                             Err(e) => eprintln!("  Warning: {e}"),
                         }
                     }
@@ -1,8 +1,18 @@
-use crate::hcl_config::HclConfig;
+use crate::hcl_config::{HclConfig, Ipv6Config, VlanHclConfig};
+use crate::parsers::WanMode;
+use crate::routing::{self, Policy, StaticRoute};
+use crate::wan::{WanUplink, WanUplinks, MAIN_RULE_PRIORITY};
+use crate::wireguard;
+use nifty_nft::tables;
 use std::fs;
 use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 
+/// Length of the delegated prefix requested from the ISP. A /60 holds 16
+/// /64s, so delegated subnet IDs run from 0x0 to 0xf.
+pub const PD_HINT_LENGTH: u8 = 60;
+
 /// Check whether a network interface exists on this system.
 fn interface_exists(name: &str) -> bool {
     Path::new(&format!("/sys/class/net/{}", name)).exists()
@@ -101,25 +111,41 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
     let dir = Path::new(output_dir);
     fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", output_dir, e))?;
 
-    let wan = config.interfaces.wan_name();
     let trunk = config.interfaces.trunk_name();
 
-    // --- WAN (DHCP client) ---
-    let mut wan_network = String::from("[Network]\n");
-    if config.wan.enable_ipv4 {
-        wan_network.push_str("DHCP=ipv4\n");
-    }
-    if config.wan.enable_ipv6 {
-        wan_network.push_str("IPv6AcceptRA=yes\nIPv6Forwarding=no\n");
+    // --- WAN uplinks ---
+    let wan_uplinks = WanUplinks::from_hcl(config).map_err(|e| e.join("\n"))?;
+    let routes = routing::from_hcl(config).map_err(|e| e.join("\n"))?;
+    let multi = wan_uplinks.is_multi();
+    for uplink in &wan_uplinks.uplinks {
+        let is_primary = uplink.name == wan_uplinks.primary().name;
+        let stem = if multi {
+            format!("10-wan-{}", uplink.name)
+        } else {
+            "10-wan".to_string()
+        };
+        let table = multi.then_some(uplink.table);
+        write_file(
+            dir,
+            &format!("{}.network", stem),
+            &uplink_network(config, uplink, is_primary, table),
+        )?;
+        if uplink.pppoe.is_some() {
+            // The PPPoE session runs on the bare device; IP config lives on the PPP interface
+            write_file(
+                dir,
+                &format!("{}-pppoe.network", stem),
+                &format!(
+                    "[Match]\nName={}\n\n[Network]\nLinkLocalAddressing=no\nIPv6AcceptRA=no\n",
+                    uplink.device
+                ),
+            )?;
+        }
     }
 
-    let wan_content = format!(
-        "[Match]\nName={}\n\n{}\n[DHCPv4]\nUseDNS=yes\n\n[IPv6AcceptRA]\nUseDNS=yes\nDHCPv6Client=always\n\n[DHCPv6]\nUseDNS=no\nPrefixDelegationHint=::/60\n",
-        wan, wan_network
-    );
-    write_file(dir, "10-wan.network", &wan_content)?;
-
     // --- Trunk + VLANs ---
+    // Delegated VLAN prefixes come from the primary uplink's DHCPv6-PD lease
+    let pd_uplink = wan_uplinks.primary().interface_name.as_str();
     // Sort VLANs by ID for deterministic output
     let mut vlans_sorted: Vec<_> = config.vlan.iter().collect();
     vlans_sorted.sort_by_key(|(_, v)| v.id);
@@ -148,8 +174,10 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
                     }
                 }
                 if let Some(ipv6) = &vlan.ipv6 {
-                    net.push_str(&format!("Address={}\nIPv6SendRA=yes\n", ipv6.subnet));
+                    net.push_str(&ipv6_network_lines(ipv6));
+                    net.push_str(&prefix_delegation_section(ipv6, vlan.id, pd_uplink));
                 }
+                net.push_str(&routing_sections(config, &routes, name, vlan));
                 write_file(dir, &format!("10-{}.network", iface), &net)?;
             } else {
                 // Trunk subinterface: .netdev + .network
@@ -169,11 +197,11 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
                     }
                 }
                 if let Some(ipv6) = &vlan.ipv6 {
-                    vlan_net.push_str(&format!("Address={}\nIPv6SendRA=yes\n", ipv6.subnet));
+                    vlan_net.push_str(&ipv6_network_lines(ipv6));
                 }
 
                 // IPv6 RA settings
-                if vlan.ipv6.is_some() {
+                if let Some(ipv6) = &vlan.ipv6 {
                     let has_dhcpv6 = vlan.dhcpv6.is_some();
                     let (managed, other, autonomous) = if has_dhcpv6 {
                         ("yes", "yes", "no")
@@ -181,12 +209,18 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
                         ("no", "no", "yes")
                     };
                     vlan_net.push_str(&format!(
-                        "\n[IPv6SendRA]\nManaged={}\nOtherInformation={}\n\n[IPv6Prefix]\nPrefix={}\nAutonomous={}\n",
-                        managed, other,
-                        vlan.ipv6.as_ref().unwrap().subnet,
-                        autonomous
+                        "\n[IPv6SendRA]\nManaged={}\nOtherInformation={}\n",
+                        managed, other
                     ));
+                    if !ipv6.delegated {
+                        vlan_net.push_str(&format!(
+                            "\n[IPv6Prefix]\nPrefix={}\nAutonomous={}\n",
+                            ipv6.subnet, autonomous
+                        ));
+                    }
+                    vlan_net.push_str(&prefix_delegation_section(ipv6, vlan.id, pd_uplink));
                 }
+                vlan_net.push_str(&routing_sections(config, &routes, name, vlan));
 
                 write_file(dir, &format!("20-{}.network", iface), &vlan_net)?;
 
@@ -203,17 +237,17 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
     } else {
         // Simple mode (no VLANs on trunk): trunk gets the LAN IP directly
         // Use VLAN 1's subnets if available
-        let vlan1 = config.vlan.values().find(|v| v.id == 1);
+        let vlan1 = config.vlan.iter().find(|(_, v)| v.id == 1);
         let mut trunk_net = format!("[Match]\nName={}\n\n[Network]\n", trunk);
 
-        if let Some(v1) = vlan1 {
+        if let Some((v1_name, v1)) = vlan1 {
             if let Some(ipv4) = &v1.ipv4 {
                 if config.wan.enable_ipv4 {
                     trunk_net.push_str(&format!("Address={}\n", ipv4.subnet));
                 }
             }
             if let Some(ipv6) = &v1.ipv6 {
-                trunk_net.push_str(&format!("Address={}\nIPv6SendRA=yes\n", ipv6.subnet));
+                trunk_net.push_str(&ipv6_network_lines(ipv6));
             }
 
             // IPv6 RA for simple mode
@@ -225,10 +259,18 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
                     ("no", "no", "yes")
                 };
                 trunk_net.push_str(&format!(
-                    "\n[IPv6SendRA]\nManaged={}\nOtherInformation={}\n\n[IPv6Prefix]\nPrefix={}\nAutonomous={}\n",
-                    managed, other, ipv6.subnet, autonomous
+                    "\n[IPv6SendRA]\nManaged={}\nOtherInformation={}\n",
+                    managed, other
                 ));
+                if !ipv6.delegated {
+                    trunk_net.push_str(&format!(
+                        "\n[IPv6Prefix]\nPrefix={}\nAutonomous={}\n",
+                        ipv6.subnet, autonomous
+                    ));
+                }
+                trunk_net.push_str(&prefix_delegation_section(ipv6, v1.id, pd_uplink));
             }
+            trunk_net.push_str(&routing_sections(config, &routes, v1_name, v1));
         }
 
         write_file(dir, "10-trunk.network", &trunk_net)?;
@@ -245,11 +287,215 @@ pub fn generate_networkd(config: &HclConfig, output_dir: &str) -> Result<(), Str
         write_file(dir, "10-mgmt.network", &mgmt_content)?;
     }
 
+    // --- WireGuard zones ---
+    for (name, wg) in &config.wireguard {
+        write_file(dir, &format!("30-{}.netdev", name), &wireguard::netdev(name, wg))?;
+        write_file(
+            dir,
+            &format!("30-{}.network", name),
+            &wireguard::network(name, wg, config.wan.enable_ipv4),
+        )?;
+    }
+
+    // --- WireGuard client tunnels ---
+    for (name, client) in &config.wireguard_client {
+        let Some(table) = wireguard::client_table(config, name) else {
+            continue;
+        };
+        write_file(dir, &format!("30-{}.netdev", name), &wireguard::client_netdev(name, client))?;
+        write_file(dir, &format!("30-{}.network", name), &wireguard::client_network(name, client, table))?;
+    }
+
     Ok(())
 }
 
+/// `[Network]` lines for a VLAN's IPv6 config: a static router address,
+/// or a /64 assigned from the delegated prefix.
+fn ipv6_network_lines(ipv6: &Ipv6Config) -> String {
+    if ipv6.delegated {
+        "DHCPPrefixDelegation=yes\nIPv6SendRA=yes\n".to_string()
+    } else {
+        format!("Address={}\nIPv6SendRA=yes\n", ipv6.subnet)
+    }
+}
+
+/// `[Route]` sections for the static routes through a VLAN's gateways,
+/// and the policy rule for its own routing table.
+fn routing_sections(config: &HclConfig, routes: &[StaticRoute], name: &str, vlan: &VlanHclConfig) -> String {
+    let mut out: String = routes.iter().filter(|r| r.vlan == name).map(StaticRoute::network_section).collect();
+    if let Some(policy) = Policy::of(config, vlan) {
+        out.push_str(&policy.network_section(config.wan.enable_ipv6));
+    }
+    out
+}
+
+/// `[DHCPPrefixDelegation]` section for a delegated VLAN, empty otherwise.
+/// networkd assigns `<prefix>:<subnet_id>::1/64` to the VLAN and announces
+/// the /64 in its router advertisements, following prefix changes.
+fn prefix_delegation_section(ipv6: &Ipv6Config, vlan_id: u16, uplink: &str) -> String {
+    if !ipv6.delegated {
+        return String::new();
+    }
+    format!(
+        "\n[DHCPPrefixDelegation]\nUplinkInterface={}\nSubnetId={:#x}\nAnnounce=yes\nAssign=yes\nToken=::1\n",
+        uplink,
+        ipv6.delegated_subnet_id(vlan_id)
+    )
+}
+
+/// Build the .network file for a WAN uplink's IP interface.
+///
+/// With multiple uplinks (`table` set), routes go into the uplink's own
+/// routing table. Two policy rules select it: one for connections marked
+/// with the uplink's table ID (sticky and balanced flows), and an
+/// unconditional fallback ordered by priority, which skips uplinks that
+/// currently have no default route. The primary uplink also carries the
+/// main-table rule that keeps LAN routes preferred, and is the only one
+/// that requests an IPv6 delegated prefix.
+fn uplink_network(config: &HclConfig, uplink: &WanUplink, is_primary: bool, table: Option<u32>) -> String {
+    let route_table = table.map(|t| format!("RouteTable={}\n", t)).unwrap_or_default();
+    let mut out = format!("[Match]\nName={}\n\n[Network]\n", uplink.interface_name);
+    match uplink.mode {
+        WanMode::Dhcp => {
+            if config.wan.enable_ipv4 {
+                out.push_str("DHCP=ipv4\n");
+            }
+            if config.wan.enable_ipv6 {
+                out.push_str("IPv6AcceptRA=yes\nIPv6Forwarding=no\n");
+            }
+        }
+        WanMode::Static => {
+            for addr in &uplink.addresses {
+                out.push_str(&format!("Address={}\n", addr));
+            }
+            out.push_str("IPv6AcceptRA=no\n");
+        }
+        WanMode::Pppoe => {
+            // pppd assigns the IPv4 address; networkd must leave it alone
+            out.push_str("KeepConfiguration=static\n");
+            if config.wan.enable_ipv6 {
+                out.push_str("IPv6AcceptRA=yes\nIPv6Forwarding=no\n");
+            }
+        }
+    }
+    for server in &uplink.dns {
+        out.push_str(&format!("DNS={}\n", server));
+    }
+
+    if uplink.mode == WanMode::Dhcp {
+        out.push_str(&format!("\n[DHCPv4]\nUseDNS=yes\n{}", route_table));
+    }
+    if uplink.mode == WanMode::Dhcp || (uplink.mode == WanMode::Pppoe && config.wan.enable_ipv6) {
+        out.push_str(&format!(
+            "\n[IPv6AcceptRA]\nUseDNS=yes\nDHCPv6Client=always\n{}\n[DHCPv6]\nUseDNS=no\n",
+            route_table
+        ));
+        if is_primary {
+            out.push_str(&format!("PrefixDelegationHint=::/{}\n", PD_HINT_LENGTH));
+        }
+    }
+
+    let table_line = table.map(|t| format!("Table={}\n", t)).unwrap_or_default();
+    match uplink.mode {
+        WanMode::Dhcp => {}
+        WanMode::Static => {
+            if let Some(gw) = uplink.gateway {
+                out.push_str(&format!("\n[Route]\nGateway={}\n{}", gw, table_line));
+            }
+            if let Some(gw) = uplink.gateway6 {
+                out.push_str(&format!("\n[Route]\nGateway={}\n{}", gw, table_line));
+            }
+        }
+        WanMode::Pppoe => {
+            // Point-to-point link: the default route needs no gateway
+            out.push_str(&format!("\n[Route]\nDestination=0.0.0.0/0\n{}", table_line));
+        }
+    }
+
+    let family = if config.wan.enable_ipv6 { "Family=both\n" } else { "" };
+    if let Some(table) = table {
+        out.push_str(&format!(
+            "\n[RoutingPolicyRule]\nFirewallMark={}\nTable={}\nPriority={}\n{}",
+            table, table, uplink.mark_rule_priority, family
+        ));
+        out.push_str(&format!(
+            "\n[RoutingPolicyRule]\nTable={}\nPriority={}\n{}",
+            table, uplink.fallback_rule_priority, family
+        ));
+    }
+    // VLAN routing tables also rely on it to keep LAN routes preferred
+    if is_primary && (table.is_some() || routing::has_policies(config)) {
+        out.push_str(&format!(
+            "\n[RoutingPolicyRule]\nTable=main\nPriority={}\nSuppressPrefixLength=0\n{}",
+            MAIN_RULE_PRIORITY, family
+        ));
+    }
+    out
+}
+
+/// Generate pppd option files, one `<uplink>.conf` per PPPoE uplink.
+///
+/// Each file is passed to `pppd file <path>`. The default route is left
+/// to networkd so multi-WAN routing tables keep working. Files contain
+/// the PPPoE password and are written with mode 0600.
+pub fn generate_ppp(config: &HclConfig, output_dir: &str) -> Result<(), String> {
+    let dir = Path::new(output_dir);
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", output_dir, e))?;
+
+    let wan_uplinks = WanUplinks::from_hcl(config).map_err(|e| e.join("\n"))?;
+    for uplink in &wan_uplinks.uplinks {
+        let Some(pppoe) = &uplink.pppoe else {
+            continue;
+        };
+        let mut out = format!(
+            "plugin pppoe.so\nnic-{}\nifname {}\nuser {}\npassword {}\n",
+            uplink.device,
+            uplink.interface_name,
+            ppp_quote(&pppoe.username),
+            ppp_quote(&pppoe.password),
+        );
+        if let Some(service) = &pppoe.service_name {
+            out.push_str(&format!("rp_pppoe_service {}\n", ppp_quote(service)));
+        }
+        out.push_str(&format!(
+            "mtu {}\nmru {}\nnoauth\nnoipdefault\nnodefaultroute\npersist\nmaxfail 0\nholdoff 5\nlcp-echo-interval 10\nlcp-echo-failure 3\n",
+            pppoe.mtu, pppoe.mtu
+        ));
+        if config.wan.enable_ipv6 {
+            out.push_str("+ipv6\n");
+        }
+        let path = dir.join(format!("{}.conf", uplink.name));
+        write_private_file(&path, &out)?;
+    }
+    Ok(())
+}
+
+/// Quote a pppd option value, escaping backslashes and double quotes.
+fn ppp_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Write a file readable only by its owner (for credentials).
+fn write_private_file(path: &Path, content: &str) -> Result<(), String> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
 /// Generate dnsmasq.conf from HCL configuration.
 pub fn generate_dnsmasq(config: &HclConfig, output: &str) -> Result<(), String> {
+    let problems = crate::validate::check_addressing(config);
+    if !problems.is_empty() {
+        let messages: Vec<String> = problems.iter().map(|d| d.to_string()).collect();
+        return Err(messages.join("\n"));
+    }
+
     let path = Path::new(output);
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
@@ -287,6 +533,27 @@ pub fn generate_dnsmasq(config: &HclConfig, output: &str) -> Result<(), String>
     let mut vlans_sorted: Vec<_> = config.vlan.iter().collect();
     vlans_sorted.sort_by_key(|(_, v)| v.id);
 
+    // Egress domains: answers are added to every matching VLAN's timed
+    // nftables sets. Clients may not cache an answer past the set timeout.
+    let domain_sets = egress_domain_sets(&vlans_sorted)?;
+    if !domain_sets.is_empty() {
+        writeln!(out).ok();
+        writeln!(out, "# Egress domains").ok();
+        writeln!(out, "max-ttl={}", crate::vlan::DOMAIN_SET_TIMEOUT).ok();
+        for (domains, suffixes) in &domain_sets {
+            let sets: Vec<String> = suffixes
+                .iter()
+                .flat_map(|suffix| {
+                    [
+                        format!("4#inet#{}#{}", tables::FILTER, crate::vlan::domain_set_name(suffix, "v4")),
+                        format!("6#inet#{}#{}", tables::FILTER, crate::vlan::domain_set_name(suffix, "v6")),
+                    ]
+                })
+                .collect();
+            writeln!(out, "nftset=/{}/{}", domains.join("/"), sets.join(",")).ok();
+        }
+    }
+
     let trunk = config.interfaces.trunk_name();
 
     for (name, vlan) in &vlans_sorted {
@@ -350,7 +617,12 @@ pub fn generate_dnsmasq(config: &HclConfig, output: &str) -> Result<(), String>
         // DHCPv6
         if let Some(dhcpv6) = &vlan.dhcpv6 {
             if let Some(ipv6) = &vlan.ipv6 {
-                let router_v6 = ipv6.subnet.split('/').next().unwrap_or("");
+                // On a delegated prefix, "[::]" makes dnsmasq advertise its own address
+                let router_v6 = if ipv6.delegated {
+                    "::"
+                } else {
+                    ipv6.subnet.split('/').next().unwrap_or("")
+                };
                 // Use DHCP DNS if set, otherwise router's IPv6
                 let dns_v6 = vlan
                     .dhcp
@@ -363,12 +635,22 @@ pub fn generate_dnsmasq(config: &HclConfig, output: &str) -> Result<(), String>
                 } else {
                     router_v6.to_string()
                 };
-                writeln!(
-                    out,
-                    "dhcp-range=interface:{},{},{},64,24h",
-                    iface, dhcpv6.pool_start, dhcpv6.pool_end
-                )
-                .ok();
+                if ipv6.delegated {
+                    // Pool bounds are suffixes, combined with the interface's current prefix
+                    writeln!(
+                        out,
+                        "dhcp-range=interface:{},{},{},constructor:{},64,24h",
+                        iface, dhcpv6.pool_start, dhcpv6.pool_end, iface
+                    )
+                    .ok();
+                } else {
+                    writeln!(
+                        out,
+                        "dhcp-range=interface:{},{},{},64,24h",
+                        iface, dhcpv6.pool_start, dhcpv6.pool_end
+                    )
+                    .ok();
+                }
                 writeln!(
                     out,
                     "dhcp-option=interface:{},option6:dns-server,[{}]",
@@ -381,9 +663,56 @@ pub fn generate_dnsmasq(config: &HclConfig, output: &str) -> Result<(), String>
         }
     }
 
+    // WireGuard zones get DNS only; peer addresses are static
+    let mut wg_names: Vec<_> = config.wireguard.keys().collect();
+    wg_names.sort();
+    for name in wg_names {
+        writeln!(out).ok();
+        writeln!(out, "# WireGuard {}", name).ok();
+        writeln!(out, "interface={}", name).ok();
+    }
+
     Ok(())
 }
 
+/// Domains and the VLAN set suffixes their answers are added to.
+type DomainGroup = (Vec<String>, Vec<String>);
+
+/// Group the VLANs' egress domains by the VLAN set suffixes they fill.
+///
+/// dnsmasq honours only the most specific `nftset` line matching a name, so
+/// each domain lists every VLAN that allows it or one of its parents.
+fn egress_domain_sets(
+    vlans: &[(&String, &VlanHclConfig)],
+) -> Result<Vec<DomainGroup>, String> {
+    let mut entries = Vec::new();
+    for (name, vlan) in vlans {
+        for domain in &vlan.egress_domains {
+            let domain = crate::vlan::parse_domain(domain)
+                .map_err(|e| format!("vlan \"{}\".egress_domains: {}", name, e))?;
+            entries.push((domain, format!("vlan_{}", vlan.id)));
+        }
+    }
+    let mut groups: Vec<DomainGroup> = Vec::new();
+    for (domain, _) in &entries {
+        if groups.iter().any(|(domains, _)| domains.contains(domain)) {
+            continue;
+        }
+        let mut suffixes: Vec<String> = Vec::new();
+        for (parent, suffix) in &entries {
+            let covers = domain == parent || domain.ends_with(&format!(".{}", parent));
+            if covers && !suffixes.contains(suffix) {
+                suffixes.push(suffix.clone());
+            }
+        }
+        match groups.iter_mut().find(|(_, s)| *s == suffixes) {
+            Some((domains, _)) => domains.push(domain.clone()),
+            None => groups.push((vec![domain.clone()], suffixes)),
+        }
+    }
+    Ok(groups)
+}
+
 /// Generate a minimal DNS-only dnsmasq.conf (when no HCL config exists).
 pub fn generate_dnsmasq_minimal(output: &str) -> Result<(), String> {
     let content = "pid-file=/run/dnsmasq/dnsmasq.pid\nlisten-address=127.0.0.1\nbind-interfaces\nno-resolv\nserver=1.1.1.1\nserver=1.0.0.1\n";
@@ -609,6 +938,275 @@ vlan "lab" {
         assert!(lab_net.contains("Managed=yes")); // DHCPv6 enabled
     }
 
+    #[test]
+    fn test_generate_networkd_multi_wan() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+  wan2  { name = "wan2" }
+}
+wan {}
+
+wan "fiber" {
+  interface = "wan"
+  priority  = 1
+}
+
+wan "lte" {
+  interface = "wan2"
+  priority  = 2
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        assert!(!dir.path().join("10-wan.network").exists());
+
+        let fiber = fs::read_to_string(dir.path().join("10-wan-fiber.network")).unwrap();
+        assert!(fiber.contains("Name=wan\n"));
+        assert!(fiber.contains("RouteTable=101"));
+        assert!(fiber.contains("FirewallMark=101\nTable=101\nPriority=1000"));
+        assert!(fiber.contains("Table=101\nPriority=2000"));
+        assert!(fiber.contains("Table=main\nPriority=500\nSuppressPrefixLength=0"));
+        assert!(fiber.contains("PrefixDelegationHint"));
+
+        let lte = fs::read_to_string(dir.path().join("10-wan-lte.network")).unwrap();
+        assert!(lte.contains("Name=wan2\n"));
+        assert!(lte.contains("RouteTable=102"));
+        assert!(lte.contains("Table=102\nPriority=2001"));
+        assert!(!lte.contains("Table=main"));
+        assert!(!lte.contains("PrefixDelegationHint"));
+    }
+
+    #[test]
+    fn test_generate_networkd_static_wan() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  enable_ipv6 = true
+  mode        = "static"
+  address     = ["203.0.113.10/29", "2001:db8::10/64"]
+  gateway     = "203.0.113.9"
+  gateway6    = "2001:db8::1"
+  dns         = ["9.9.9.9"]
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let wan = fs::read_to_string(dir.path().join("10-wan.network")).unwrap();
+        assert!(wan.contains("Address=203.0.113.10/29\nAddress=2001:db8::10/64\n"));
+        assert!(wan.contains("IPv6AcceptRA=no\n"));
+        assert!(wan.contains("DNS=9.9.9.9\n"));
+        assert!(wan.contains("[Route]\nGateway=203.0.113.9\n"));
+        assert!(wan.contains("[Route]\nGateway=2001:db8::1\n"));
+        assert!(!wan.contains("DHCP="));
+        assert!(!wan.contains("RoutingPolicyRule"));
+    }
+
+    #[test]
+    fn test_generate_networkd_routes() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan_aware_switch = true
+route "lab-behind-r2" {
+  destination = "192.168.50.0/24"
+  gateway     = "10.99.40.2"
+  metric      = 100
+}
+route "via-vpn" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.254"
+  table       = 200
+}
+vlan "guest" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  routing_table = 200
+}
+vlan "lab" {
+  id = 40
+  ipv4 { subnet = "10.99.40.1/24" }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let lab = fs::read_to_string(dir.path().join("20-lab.network")).unwrap();
+        assert!(lab.contains("\n[Route]\nDestination=192.168.50.0/24\nGateway=10.99.40.2\nMetric=100\n"));
+        assert!(lab.contains("\n[Route]\nDestination=0.0.0.0/0\nGateway=10.99.40.254\nTable=200\n"));
+        assert!(!lab.contains("RoutingPolicyRule"));
+        let guest = fs::read_to_string(dir.path().join("20-guest.network")).unwrap();
+        assert!(guest.contains("\n[RoutingPolicyRule]\nFirewallMark=200\nTable=200\nPriority=900\n"));
+        assert!(!guest.contains("[Route]"));
+        // LAN routes in the main table still win over the VLAN's table
+        let wan = fs::read_to_string(dir.path().join("10-wan.network")).unwrap();
+        assert!(wan.contains("[RoutingPolicyRule]\nTable=main\nPriority=500\nSuppressPrefixLength=0\n"));
+    }
+
+    #[test]
+    fn test_generate_networkd_wireguard_client() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { enable_ipv6 = true }
+vlan_aware_switch = true
+vlan "privacy" {
+  id = 50
+  ipv4 { subnet = "10.99.50.1/24" }
+  egress_via = "vpn-provider"
+}
+wireguard_client "vpn-provider" {
+  private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+  address          = ["10.64.0.2/32", "fc00:bbbb::2/128"]
+  peer {
+    public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    endpoint   = "vpn.example.net:51820"
+  }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let netdev = fs::read_to_string(dir.path().join("30-vpn-provider.netdev")).unwrap();
+        assert!(netdev.contains("Endpoint=vpn.example.net:51820\nAllowedIPs=0.0.0.0/0,::/0\n"));
+        let tunnel = fs::read_to_string(dir.path().join("30-vpn-provider.network")).unwrap();
+        assert!(tunnel.contains("\n[Route]\nDestination=0.0.0.0/0\nTable=200\n"));
+        assert!(tunnel.contains("\n[Route]\nDestination=::/0\nTable=200\n"));
+        let privacy = fs::read_to_string(dir.path().join("20-privacy.network")).unwrap();
+        assert!(privacy.contains("\n[RoutingPolicyRule]\nFirewallMark=200\nTable=200\nPriority=900\nFamily=both\n"));
+        let wan = fs::read_to_string(dir.path().join("10-wan.network")).unwrap();
+        assert!(wan.contains("[RoutingPolicyRule]\nTable=main\nPriority=500\nSuppressPrefixLength=0\n"));
+    }
+
+    #[test]
+    fn test_generate_networkd_pppoe_wan() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  enable_ipv6 = true
+  mode        = "pppoe"
+  pppoe {
+    username = "user@isp"
+    password = "secret"
+  }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let carrier = fs::read_to_string(dir.path().join("10-wan-pppoe.network")).unwrap();
+        assert!(carrier.contains("Name=wan\n"));
+        assert!(carrier.contains("LinkLocalAddressing=no\n"));
+
+        let ppp = fs::read_to_string(dir.path().join("10-wan.network")).unwrap();
+        assert!(ppp.contains("Name=ppp0\n"));
+        assert!(ppp.contains("KeepConfiguration=static\n"));
+        assert!(ppp.contains("IPv6AcceptRA=yes\n"));
+        assert!(ppp.contains("PrefixDelegationHint=::/60\n"));
+        assert!(ppp.contains("[Route]\nDestination=0.0.0.0/0\n"));
+        assert!(!ppp.contains("[DHCPv4]"));
+    }
+
+    #[test]
+    fn test_generate_ppp() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  mode = "pppoe"
+  pppoe {
+    username     = "user@isp"
+    password     = "se\"cret"
+    service_name = "dsl"
+    mtu          = 1480
+  }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_ppp(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let path = dir.path().join("wan.conf");
+        let conf = fs::read_to_string(&path).unwrap();
+        assert!(conf.contains("plugin pppoe.so\nnic-wan\nifname ppp0\n"));
+        assert!(conf.contains("user \"user@isp\"\n"));
+        assert!(conf.contains(r#"password "se\"cret""#));
+        assert!(conf.contains("rp_pppoe_service \"dsl\"\n"));
+        assert!(conf.contains("mtu 1480\nmru 1480\n"));
+        assert!(conf.contains("nodefaultroute\n"));
+        assert!(!conf.contains("+ipv6"));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_generate_ppp_none() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_ppp(&config, dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_generate_networkd_delegated_prefix() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { enable_ipv6 = true }
+vlan_aware_switch = true
+vlan "lan" {
+  id = 10
+  ipv6 {
+    delegated = true
+    subnet_id = "0x40"
+  }
+}
+vlan "lab" {
+  id = 40
+  ipv6 { subnet = "fd00:40::1/64" }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let lan = fs::read_to_string(dir.path().join("20-lan.network")).unwrap();
+        assert!(lan.contains("DHCPPrefixDelegation=yes\nIPv6SendRA=yes\n"));
+        assert!(lan.contains("[DHCPPrefixDelegation]\nUplinkInterface=wan\nSubnetId=0x40\nAnnounce=yes\nAssign=yes\nToken=::1\n"));
+        assert!(lan.contains("[IPv6SendRA]"));
+        assert!(!lan.contains("[IPv6Prefix]"));
+        assert!(!lan.contains("Address="));
+
+        // Static subnets are unchanged
+        let lab = fs::read_to_string(dir.path().join("20-lab.network")).unwrap();
+        assert!(lab.contains("Address=fd00:40::1/64\n"));
+        assert!(lab.contains("[IPv6Prefix]\nPrefix=fd00:40::1/64\n"));
+        assert!(!lab.contains("DHCPPrefixDelegation"));
+    }
+
     #[test]
     fn test_generate_networkd_simple_mode() {
         let config = parse_test_config(r#"
@@ -634,6 +1232,45 @@ vlan "default" {
         assert!(!dir.path().join("20-default.netdev").exists());
     }
 
+    #[test]
+    fn test_generate_networkd_wireguard() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { enable_ipv4 = true }
+vlan "default" {
+  id = 1
+  ipv4 { subnet = "10.99.1.1/24" }
+}
+wireguard "wg0" {
+  listen_port      = 51820
+  private_key_file = "/var/lib/nifty-filter/wg0.key"
+  ipv4 { subnet = "10.99.100.1/24" }
+  peer "laptop" {
+    public_key  = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    allowed_ips = ["10.99.100.11/32"]
+  }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        generate_networkd(&config, dir.path().to_str().unwrap()).unwrap();
+
+        let netdev = fs::read_to_string(dir.path().join("30-wg0.netdev")).unwrap();
+        assert!(netdev.contains("Kind=wireguard"));
+        assert!(netdev.contains("ListenPort=51820"));
+        assert!(netdev.contains("AllowedIPs=10.99.100.11/32"));
+        let network = fs::read_to_string(dir.path().join("30-wg0.network")).unwrap();
+        assert!(network.contains("Name=wg0"));
+        assert!(network.contains("Address=10.99.100.1/24"));
+
+        let output = dir.path().join("dnsmasq.conf");
+        generate_dnsmasq(&config, output.to_str().unwrap()).unwrap();
+        let content = fs::read_to_string(&output).unwrap();
+        assert!(content.contains("# WireGuard wg0\ninterface=wg0\n"));
+    }
+
     #[test]
     fn test_generate_networkd_mgmt() {
         let config = parse_test_config(r#"
@@ -768,6 +1405,96 @@ vlan "lab" {
         assert!(content.contains("ra-param=lab,60,600"));
     }
 
+    #[test]
+    fn test_generate_dnsmasq_dhcpv6_delegated() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { enable_ipv6 = true }
+vlan_aware_switch = true
+vlan "lan" {
+  id = 10
+  ipv6 { delegated = true }
+  dhcpv6 {
+    pool_start = "::100"
+    pool_end   = "::1ff"
+  }
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("dnsmasq.conf");
+        generate_dnsmasq(&config, output.to_str().unwrap()).unwrap();
+
+        let content = fs::read_to_string(&output).unwrap();
+        assert!(content.contains("dhcp-range=interface:lan,::100,::1ff,constructor:lan,64,24h"));
+        assert!(content.contains("dhcp-option=interface:lan,option6:dns-server,[::]"));
+    }
+
+    #[test]
+    fn test_generate_dnsmasq_egress_domains() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan_aware_switch = true
+vlan "iot" {
+  id = 20
+  ipv4 { subnet = "10.99.20.1/24" }
+  egress_domains = ["*.vendor-cloud.com", "time.example.org"]
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("dnsmasq.conf");
+        generate_dnsmasq(&config, output.to_str().unwrap()).unwrap();
+
+        let content = fs::read_to_string(&output).unwrap();
+        assert!(content.contains("max-ttl=3600\n"));
+        assert!(content.contains(
+            "nftset=/vendor-cloud.com/time.example.org/4#inet#nifty_filter#domains_vlan_20_v4,6#inet#nifty_filter#domains_vlan_20_v6"
+        ));
+    }
+
+    #[test]
+    fn test_generate_dnsmasq_shared_egress_domains() {
+        let config = parse_test_config(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan_aware_switch = true
+vlan "iot" {
+  id = 20
+  ipv4 { subnet = "10.99.20.1/24" }
+  egress_domains = ["vendor-cloud.com", "time.example.org"]
+}
+vlan "cameras" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  egress_domains = ["api.vendor-cloud.com", "time.example.org"]
+}
+"#);
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("dnsmasq.conf");
+        generate_dnsmasq(&config, output.to_str().unwrap()).unwrap();
+
+        // One line per domain group; a subdomain also fills its parent's sets
+        let content = fs::read_to_string(&output).unwrap();
+        let lines: Vec<&str> = content.lines().filter(|l| l.starts_with("nftset=")).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "nftset=/vendor-cloud.com/4#inet#nifty_filter#domains_vlan_20_v4,6#inet#nifty_filter#domains_vlan_20_v6",
+                "nftset=/time.example.org/api.vendor-cloud.com/4#inet#nifty_filter#domains_vlan_20_v4,6#inet#nifty_filter#domains_vlan_20_v6,\
+                 4#inet#nifty_filter#domains_vlan_30_v4,6#inet#nifty_filter#domains_vlan_30_v6",
+            ]
+        );
+    }
+
     #[test]
     fn test_generate_dnsmasq_ntp_option() {
         let config = parse_test_config(r#"
@@ -23,6 +23,34 @@ pub struct HclConfig {
     pub switch: Option<SwitchConfig>,
     #[serde(default)]
     pub vlan: HashMap<String, VlanHclConfig>,
+    /// WireGuard server zones, keyed by interface name.
+    #[serde(default)]
+    pub wireguard: HashMap<String, WireguardHclConfig>,
+    /// WireGuard tunnels to an upstream VPN provider, keyed by interface
+    /// name. VLANs send their internet traffic through one with `egress_via`.
+    #[serde(default)]
+    pub wireguard_client: HashMap<String, WireguardClientHclConfig>,
+    /// Named addresses, referenced from rules as `@name`.
+    #[serde(default)]
+    pub host: HashMap<String, HostObjectConfig>,
+    /// Named lists of hosts, groups, addresses and CIDRs.
+    #[serde(default)]
+    pub group: HashMap<String, GroupObjectConfig>,
+    /// Named port lists, referenced from rule ports as `@name`.
+    #[serde(default)]
+    pub service: HashMap<String, ServiceObjectConfig>,
+    /// Named time windows, referenced from rules as `during <name>`.
+    #[serde(default)]
+    pub schedule: HashMap<String, ScheduleHclConfig>,
+    /// Address blocklists loaded into nftables sets by `blocklist-sync`.
+    #[serde(default)]
+    pub blocklist: HashMap<String, BlocklistHclConfig>,
+    /// Static routes to networks behind other routers.
+    #[serde(default)]
+    pub route: HashMap<String, RouteHclConfig>,
+    /// Logging of dropped traffic.
+    #[serde(default)]
+    pub logging: Option<LoggingHclConfig>,
     #[serde(default)]
     pub services: Option<serde_json::Value>,
     #[serde(default)]
@@ -133,6 +161,13 @@ impl InterfacesConfig {
     pub fn wan_name(&self) -> &str {
         &self.wan.name
     }
+    /// Whether an interface with this name is declared in the block.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.wan.name == name
+            || self.trunk.name == name
+            || self.mgmt_name() == Some(name)
+            || self.extra.values().any(|e| e.name == name)
+    }
     /// Trunk interface name.
     pub fn trunk_name(&self) -> &str {
         &self.trunk.name
@@ -166,12 +201,186 @@ pub struct WanConfig {
     pub tcp_forward: Vec<String>,
     #[serde(default)]
     pub udp_forward: Vec<String>,
+    /// Forwards of port ranges or both protocols, optionally limited to some
+    /// sources, labeled with a description.
+    #[serde(default)]
+    pub forward: IndexMap<String, ForwardConfig>,
+    /// NAT reflection: let LAN clients reach the IPv4 forwards above through
+    /// the router's public address.
+    #[serde(default)]
+    pub hairpin: bool,
+    /// Per-source limits on WAN accepts and forwards, keyed by the WAN-side
+    /// port as "tcp/<port>" or "udp/<port>".
+    #[serde(default)]
+    pub port_limit: IndexMap<String, PortLimitConfig>,
+    /// Ban sources that open SSH connections too fast.
+    #[serde(default)]
+    pub ssh_guard: Option<SshGuardConfig>,
+    /// Ports that open per source only after a port-knock sequence.
+    #[serde(default)]
+    pub knock: Option<KnockConfig>,
+    /// Addressing of the single WAN uplink: "dhcp" (default), "static" or "pppoe".
+    /// With `uplink` blocks, set this on each uplink instead.
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Static addresses with prefix length, IPv4 and/or IPv6 (static mode).
+    #[serde(default)]
+    pub address: Vec<String>,
+    /// IPv4 default gateway (static mode).
+    #[serde(default)]
+    pub gateway: Option<String>,
+    /// IPv6 default gateway (static mode).
+    #[serde(default)]
+    pub gateway6: Option<String>,
+    /// DNS servers for the router itself (static and PPPoE modes).
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// PPPoE credentials (pppoe mode).
+    #[serde(default)]
+    pub pppoe: Option<PppoeConfig>,
+    /// Multi-WAN policy: "failover" (default) or "balance".
+    #[serde(default)]
+    pub policy: Option<String>,
+    /// Seconds between uplink health checks (multi-WAN only).
+    #[serde(default)]
+    pub health_interval: Option<u64>,
+    /// Named uplinks, written as top-level `wan "name" {}` blocks. When
+    /// empty, `interfaces.wan` is the only uplink.
+    #[serde(default)]
+    pub uplink: IndexMap<String, WanUplinkConfig>,
+}
+
+/// Limits for one WAN-exposed port, applied per source address.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortLimitConfig {
+    /// New connection rate, e.g. "10/second burst 20"
+    #[serde(default)]
+    pub limit: Option<String>,
+    /// Maximum concurrent connections
+    #[serde(default)]
+    pub ct_limit: Option<u32>,
+}
+
+/// Dynamic blacklist for SSH brute-force attempts.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SshGuardConfig {
+    /// WAN-side port to guard (default 22)
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// New connection rate that gets a source banned (default "5/minute")
+    #[serde(default)]
+    pub threshold: Option<String>,
+    /// How long a source stays banned, e.g. "1h" (default)
+    #[serde(default)]
+    pub ban: Option<String>,
+}
+
+/// A `wan.forward` block. Ranges forward each port to the same port on the
+/// destination; `to_port` remaps a single port.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardConfig {
+    /// "tcp", "udp" or "tcp+udp"
+    pub protocol: String,
+    /// WAN-side ports and ranges, e.g. ["27015-27030", "27036"]
+    pub ports: Vec<String>,
+    /// Destination address or @host
+    pub to: String,
+    #[serde(default)]
+    pub to_port: Option<u16>,
+    /// Only forward from these addresses, CIDRs or @host/@group names. With
+    /// `hairpin`, LAN clients must be listed too.
+    #[serde(default)]
+    pub from: Vec<String>,
+    /// Only forward during this schedule
+    #[serde(default)]
+    pub during: Option<String>,
+    /// Log the forwarded connections (off by default)
+    #[serde(default)]
+    pub log: Option<LogSetting>,
+}
+
+/// Port knocking: a source that hits `sequence` in order, each knock
+/// within `timeout` of the last, may connect to `opens` for `timeout`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KnockConfig {
+    /// Knock ports in order, e.g. ["tcp/7000", "udp/8000", "tcp/9000"]
+    pub sequence: Vec<String>,
+    /// Time allowed between knocks and to connect afterwards (default "10s")
+    #[serde(default)]
+    pub timeout: Option<String>,
+    /// Router ports opened by the knock, e.g. ["tcp/22"]
+    pub opens: Vec<String>,
+}
+
+/// A single WAN uplink for multi-WAN setups: a `wan "name" {}` block.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WanUplinkConfig {
+    /// Interface name, as declared in the `interfaces` block.
+    pub interface: String,
+    /// Failover order: lower values are preferred.
+    #[serde(default = "default_one")]
+    pub priority: u32,
+    /// Share of new connections in balance mode.
+    #[serde(default = "default_one")]
+    pub weight: u32,
+    /// Addresses probed through this uplink; it is taken out of service
+    /// when none of them answer.
+    #[serde(default)]
+    pub health_check: Vec<String>,
+    /// Routing table (and fwmark) for this uplink. Defaults to 100 + position.
+    #[serde(default)]
+    pub table: Option<u32>,
+    /// Addressing: "dhcp" (default), "static" or "pppoe".
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Static addresses with prefix length, IPv4 and/or IPv6 (static mode).
+    #[serde(default)]
+    pub address: Vec<String>,
+    /// IPv4 default gateway (static mode).
+    #[serde(default)]
+    pub gateway: Option<String>,
+    /// IPv6 default gateway (static mode).
+    #[serde(default)]
+    pub gateway6: Option<String>,
+    /// DNS servers for the router itself (static and PPPoE modes).
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// PPPoE credentials (pppoe mode).
+    #[serde(default)]
+    pub pppoe: Option<PppoeConfig>,
+}
+
+/// PPPoE session settings. The session runs on the WAN interface and
+/// creates a separate point-to-point interface (`ifname`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PppoeConfig {
+    pub username: String,
+    pub password: String,
+    /// PPP interface name (default "ppp0").
+    #[serde(default)]
+    pub ifname: Option<String>,
+    /// Access concentrator service name, if the ISP requires one.
+    #[serde(default)]
+    pub service_name: Option<String>,
+    /// PPP MTU/MRU (default 1492).
+    #[serde(default)]
+    pub mtu: Option<u16>,
 }
 
 fn default_true() -> bool {
     true
 }
 
+fn default_one() -> u32 {
+    1
+}
+
 /// Per-VLAN configuration block.
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -200,6 +409,20 @@ pub struct VlanHclConfig {
     pub iperf_enabled: bool,
     #[serde(default)]
     pub mdns_reflector: bool,
+    /// Automatic port mapping (UPnP IGD, NAT-PMP, PCP) for this VLAN's clients.
+    #[serde(default)]
+    pub upnp: Option<UpnpConfig>,
+    /// Route this VLAN's traffic by the routing table with this ID, e.g.
+    /// one holding a `route` out another gateway.
+    #[serde(default)]
+    pub routing_table: Option<u32>,
+    /// Firewall mark selecting `routing_table` (default: the table ID).
+    #[serde(default)]
+    pub fwmark: Option<u32>,
+    /// Send this VLAN's internet traffic through the `wireguard_client`
+    /// tunnel of this name. It is dropped rather than leave by the WAN.
+    #[serde(default)]
+    pub egress_via: Option<String>,
     #[serde(default)]
     pub tcp_forward: Vec<String>,
     #[serde(default)]
@@ -210,6 +433,218 @@ pub struct VlanHclConfig {
     pub allow_inbound_udp: Vec<String>,
     #[serde(default)]
     pub allow_from: HashMap<String, InterVlanHclConfig>,
+    /// Schedules during which this VLAN has no internet access.
+    #[serde(default)]
+    pub egress_blocked: Vec<String>,
+    /// Domains (and their subdomains) this VLAN may reach on the WAN. The
+    /// router's DNS server adds resolved addresses to a timed set.
+    #[serde(default)]
+    pub egress_domains: Vec<String>,
+    /// Logging of this VLAN's dropped traffic, overriding `logging.input`
+    /// and `logging.forward`.
+    #[serde(default)]
+    pub log: Option<LogSetting>,
+    /// Per-device rules, keyed by MAC address or DHCP reservation hostname.
+    #[serde(default)]
+    pub client: HashMap<String, ClientHclConfig>,
+}
+
+/// A VLAN's `upnp` block. Clients may only map `allowed_ports`, on the WAN
+/// side and on their own address, and only to themselves.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpnpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port range clients may map (default "1024-65535")
+    #[serde(default)]
+    pub allowed_ports: Option<String>,
+}
+
+/// Egress policy for a single device. It replaces the VLAN's `egress`
+/// lists for that device.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientHclConfig {
+    /// WAN destinations (addresses, CIDRs or `@host`/`@group`) the device
+    /// may reach. Empty blocks its internet access entirely.
+    #[serde(default)]
+    pub egress: Vec<String>,
+}
+
+/// WireGuard server zone. Behaves like a VLAN for firewalling: the tunnel
+/// interface gets `input`/`forward` chains with the same `egress`,
+/// `firewall` and `allow_from` semantics.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireguardHclConfig {
+    pub listen_port: u16,
+    /// Server private key, readable by systemd-networkd.
+    pub private_key_file: String,
+    /// Public hostname or address that peers connect to (for exported
+    /// peer configs).
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub mtu: Option<u16>,
+    #[serde(default)]
+    pub ipv4: Option<Ipv4Config>,
+    #[serde(default)]
+    pub ipv6: Option<Ipv6Config>,
+    #[serde(default)]
+    pub firewall: Option<FirewallConfig>,
+    #[serde(default)]
+    pub allow_from: HashMap<String, InterVlanHclConfig>,
+    #[serde(default)]
+    pub peer: IndexMap<String, WireguardPeerConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireguardPeerConfig {
+    pub public_key: String,
+    /// Tunnel addresses routed to this peer, e.g. ["10.99.100.10/32"].
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub preshared_key_file: Option<String>,
+}
+
+/// WireGuard tunnel to an upstream VPN provider.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireguardClientHclConfig {
+    /// Client private key, readable by systemd-networkd.
+    pub private_key_file: String,
+    /// Tunnel addresses assigned by the provider, e.g. ["10.64.0.2/32"].
+    pub address: Vec<String>,
+    #[serde(default)]
+    pub mtu: Option<u16>,
+    /// Routing table (and fwmark) of the VLANs egressing through this
+    /// tunnel. Defaults to 200 + position.
+    #[serde(default)]
+    pub table: Option<u32>,
+    pub peer: WireguardClientPeerConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WireguardClientPeerConfig {
+    pub public_key: String,
+    /// Provider server as "host:port"
+    pub endpoint: String,
+    #[serde(default)]
+    pub preshared_key_file: Option<String>,
+    /// Seconds between keepalives (default 25)
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// A named host with up to one address per family.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostObjectConfig {
+    #[serde(default)]
+    pub ipv4: Option<String>,
+    #[serde(default)]
+    pub ipv6: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupObjectConfig {
+    /// Host or group names, addresses, or CIDRs
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceObjectConfig {
+    #[serde(default)]
+    pub tcp: Vec<u16>,
+    #[serde(default)]
+    pub udp: Vec<u16>,
+}
+
+/// `log = true | false | "sampled"`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum LogSetting {
+    Enabled(bool),
+    Mode(String),
+}
+
+/// Which drops are logged and where, and whether rules count their hits.
+/// Each drop rule defaults to "sampled".
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingHclConfig {
+    /// Send log entries to this NFLOG group instead of the kernel log
+    #[serde(default)]
+    pub nflog_group: Option<u16>,
+    /// Rate limit for "sampled" logging (default "5/minute")
+    #[serde(default)]
+    pub rate: Option<String>,
+    /// Default drop of traffic to the router
+    #[serde(default)]
+    pub input: Option<LogSetting>,
+    /// Default drop of forwarded traffic
+    #[serde(default)]
+    pub forward: Option<LogSetting>,
+    /// Untagged traffic on a VLAN-aware trunk
+    #[serde(default)]
+    pub trunk: Option<LogSetting>,
+    /// Traffic to the management network from elsewhere
+    #[serde(default)]
+    pub mgmt: Option<LogSetting>,
+    /// Add a packet/byte counter to every generated rule
+    #[serde(default)]
+    pub counters: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlocklistHclConfig {
+    /// http(s) URL to download the list from
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Local file holding the list, instead of `url`
+    #[serde(default)]
+    pub file: Option<String>,
+    /// How often to reload, e.g. "6h" (default "24h")
+    #[serde(default)]
+    pub refresh: Option<String>,
+    /// "inbound" (default), "outbound" or "both"
+    #[serde(default)]
+    pub direction: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteHclConfig {
+    /// Network reached through the gateway, e.g. "192.168.50.0/24"
+    pub destination: String,
+    /// Next hop, in the subnet of a VLAN
+    pub gateway: String,
+    #[serde(default)]
+    pub metric: Option<u32>,
+    /// Routing table to add the route to (default: main). VLANs with a
+    /// matching `routing_table` use it.
+    #[serde(default)]
+    pub table: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleHclConfig {
+    /// Days of the week ("mon" or "monday"); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Start time "HH:MM", router local time
+    #[serde(default)]
+    pub from: Option<String>,
+    /// End time "HH:MM"; earlier than `from` wraps past midnight
+    #[serde(default)]
+    pub to: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -223,9 +658,26 @@ pub struct Ipv4Config {
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Ipv6Config {
+    /// Router address and prefix, e.g. "fd00:10::1/64". Leave unset when
+    /// `delegated` is true.
+    #[serde(default)]
     pub subnet: String,
     #[serde(default)]
     pub egress: Vec<String>,
+    /// Take a /64 from the WAN's DHCPv6-delegated prefix instead of `subnet`.
+    #[serde(default)]
+    pub delegated: bool,
+    /// Which /64 of the delegated /60 to use, 0x0 to 0xf, as a number or
+    /// hex string ("0xa"). Defaults to the VLAN ID.
+    #[serde(default, deserialize_with = "number_or_hex")]
+    pub subnet_id: Option<u64>,
+}
+
+impl Ipv6Config {
+    /// Subnet ID used for a delegated prefix on VLAN `vlan_id`.
+    pub fn delegated_subnet_id(&self, vlan_id: u16) -> u64 {
+        self.subnet_id.unwrap_or(vlan_id as u64)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -263,6 +715,41 @@ pub struct DhcpHost {
     pub hostname: Option<String>,
 }
 
+/// Deserialize a number, or a string holding a decimal or `0x` hex number.
+/// HCL has no hex literals, but subnet IDs are usually written in hex.
+fn number_or_hex<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct NumberOrHexVisitor;
+
+    impl de::Visitor<'_> for NumberOrHexVisitor {
+        type Value = Option<u64>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a non-negative number or a hex string like \"0x40\"")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(Some(v))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            u64::try_from(v).map(Some).map_err(|_| E::custom(format!("negative number {}", v)))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            let parsed = match v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => v.parse(),
+            };
+            parsed.map(Some).map_err(|_| E::custom(format!("invalid number '{}'", v)))
+        }
+    }
+
+    deserializer.deserialize_any(NumberOrHexVisitor)
+}
+
 /// Deserialize a single item or a list of items into a Vec.
 /// HCL represents a single repeated block as a map, not a sequence.
 fn one_or_many<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
@@ -315,6 +802,9 @@ pub struct InterVlanHclConfig {
     pub tcp: Vec<String>,
     #[serde(default)]
     pub udp: Vec<String>,
+    /// Log the allowed connections (off by default)
+    #[serde(default)]
+    pub log: Option<LogSetting>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -432,9 +922,53 @@ impl SwitchPortConfig {
     }
 }
 
+/// Move labeled `wan "name" {}` blocks into the unlabeled `wan {}` block as
+/// its `uplink` entries. hcl-rs would otherwise drop the unlabeled block
+/// when both forms appear at the top level.
+fn nest_wan_uplinks(body: hcl::Body) -> Result<hcl::Body, String> {
+    let mut structures = Vec::new();
+    let mut uplinks = Vec::new();
+    for structure in body {
+        match structure {
+            hcl::Structure::Block(block) if block.identifier.as_str() == "wan" && !block.labels.is_empty() => {
+                uplinks.push(hcl::Block {
+                    identifier: hcl::Identifier::new("uplink").unwrap(),
+                    labels: block.labels,
+                    body: block.body,
+                });
+            }
+            other => structures.push(other),
+        }
+    }
+    let wan = structures.iter_mut().find_map(|s| match s {
+        hcl::Structure::Block(block) if block.identifier.as_str() == "wan" => Some(block),
+        _ => None,
+    });
+    if let Some(wan) = &wan {
+        if wan.body.blocks().any(|b| b.identifier.as_str() == "uplink") {
+            return Err("HCL parse error: declare uplinks as top-level wan \"name\" {} blocks.".to_string());
+        }
+    }
+    let uplinks = uplinks.into_iter().map(hcl::Structure::Block);
+    match wan {
+        Some(wan) => wan.body.0.extend(uplinks),
+        None if uplinks.len() > 0 => {
+            structures.push(hcl::Structure::Block(hcl::Block {
+                identifier: hcl::Identifier::new("wan").unwrap(),
+                labels: Vec::new(),
+                body: hcl::Body(uplinks.collect()),
+            }));
+        }
+        None => {}
+    }
+    Ok(hcl::Body(structures))
+}
+
 /// Parse an HCL configuration string into an HclConfig.
 pub fn parse_hcl(input: &str) -> Result<HclConfig, String> {
-    let config: HclConfig = hcl::from_str(input).map_err(|e| format!("HCL parse error: {}", e))?;
+    let body = hcl::parse(input).map_err(|e| format!("HCL parse error: {}", e))?;
+    let config: HclConfig =
+        hcl::from_body(nest_wan_uplinks(body)?).map_err(|e| format!("HCL parse error: {}", e))?;
     if let Some(sw) = &config.switch {
         for (port_id, port) in &sw.port {
             if let Some(vlans) = &port.vlans {
@@ -585,6 +1119,130 @@ wan {
         assert!(config.wan.udp_forward.is_empty());
     }
 
+    #[test]
+    fn test_parse_wan_uplinks() {
+        let input = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+  wan2  { name = "wan2" }
+}
+wan {
+  policy = "balance"
+}
+
+wan "isp1" {
+  interface    = "wan"
+  weight       = 3
+  health_check = ["1.1.1.1"]
+}
+
+wan "isp2" {
+  interface = "wan2"
+  priority  = 2
+}
+"#;
+        let config = parse_hcl(input).unwrap();
+        assert_eq!(config.wan.policy.as_deref(), Some("balance"));
+        assert_eq!(config.wan.uplink.len(), 2);
+        let isp1 = &config.wan.uplink["isp1"];
+        assert_eq!(isp1.interface, "wan");
+        assert_eq!(isp1.priority, 1);
+        assert_eq!(isp1.weight, 3);
+        assert_eq!(isp1.health_check, vec!["1.1.1.1"]);
+        let isp2 = &config.wan.uplink["isp2"];
+        assert_eq!(isp2.priority, 2);
+        assert_eq!(isp2.weight, 1);
+        assert!(isp2.table.is_none());
+        assert!(config.interfaces.has_name("wan2"));
+
+        // Uplinks alone imply an empty wan block
+        let bare = parse_hcl(&input.replace("wan {\n  policy = \"balance\"\n}\n", "")).unwrap();
+        assert_eq!(bare.wan.uplink.len(), 2);
+        // Uplinks are only declared at the top level
+        let nested = input.replace("policy = \"balance\"", "uplink \"isp3\" { interface = \"wan2\" }");
+        assert!(parse_hcl(&nested).unwrap_err().contains("top-level wan \"name\""));
+    }
+
+    #[test]
+    fn test_parse_wan_pppoe() {
+        let input = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  mode = "pppoe"
+  dns  = ["1.1.1.1"]
+  pppoe {
+    username     = "user@isp"
+    password     = "secret"
+    ifname       = "ppp1"
+    service_name = "dsl"
+  }
+}
+"#;
+        let config = parse_hcl(input).unwrap();
+        assert_eq!(config.wan.mode.as_deref(), Some("pppoe"));
+        assert_eq!(config.wan.dns, vec!["1.1.1.1"]);
+        let pppoe = config.wan.pppoe.as_ref().unwrap();
+        assert_eq!(pppoe.username, "user@isp");
+        assert_eq!(pppoe.ifname.as_deref(), Some("ppp1"));
+        assert_eq!(pppoe.service_name.as_deref(), Some("dsl"));
+        assert!(pppoe.mtu.is_none());
+    }
+
+    #[test]
+    fn test_parse_ipv6_delegated() {
+        let config = parse_with_prefix(r#"
+vlan "lan" {
+  id = 10
+  ipv6 {
+    delegated = true
+    subnet_id = "0x40"
+  }
+}
+vlan "iot" {
+  id = 20
+  ipv6 {
+    delegated = true
+    subnet_id = 3
+  }
+}
+vlan "lab" {
+  id = 30
+  ipv6 { delegated = true }
+}
+"#);
+        let lan = config.vlan["lan"].ipv6.as_ref().unwrap();
+        assert!(lan.delegated);
+        assert!(lan.subnet.is_empty());
+        assert_eq!(lan.delegated_subnet_id(10), 0x40);
+        assert_eq!(config.vlan["iot"].ipv6.as_ref().unwrap().delegated_subnet_id(20), 3);
+        // Defaults to the VLAN ID
+        assert_eq!(config.vlan["lab"].ipv6.as_ref().unwrap().delegated_subnet_id(30), 30);
+    }
+
+    #[test]
+    fn test_parse_ipv6_subnet_id_invalid() {
+        let input = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {}
+vlan "lan" {
+  id = 10
+  ipv6 {
+    delegated = true
+    subnet_id = "0xzz"
+  }
+}
+"#;
+        let err = parse_hcl(input).unwrap_err();
+        assert!(err.contains("invalid number '0xzz'"));
+    }
+
     #[test]
     fn test_parse_inbound_rules() {
         let config = parse_with_prefix(r#"
@@ -1099,6 +1757,11 @@ dashboard_tls {
         assert!(config.wan.enable_ipv4);
         assert!(!config.wan.enable_ipv6);
         assert!(config.wan.icmp_accept.is_empty());
+        assert!(config.wan.policy.is_none());
+        assert!(config.wan.mode.is_none());
+        assert!(config.wan.address.is_empty());
+        assert!(config.wan.pppoe.is_none());
+        assert!(config.wan.uplink.is_empty());
     }
 
     #[test]
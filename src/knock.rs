@@ -0,0 +1,158 @@
+//! Port knocking: router ports that stay closed on the WAN until a source
+//! knocks on a sequence of other ports.
+
+use std::time::Duration;
+
+use crate::hcl_config::HclConfig;
+use crate::parsers::{parse_duration, ProtoPort};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A resolved `wan.knock` block. Progress is tracked per source in the sets
+/// `knock_<stage>_v4`/`_v6`; a source that completes the sequence is added
+/// to `knock_open_v4`/`_v6`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Knock {
+    pub sequence: Vec<ProtoPort>,
+    /// Time allowed between knocks, and to connect after the last one
+    pub timeout: Duration,
+    pub opens: Vec<ProtoPort>,
+}
+
+impl Knock {
+    /// Set of sources that have knocked the first `stage` ports.
+    pub fn stage_set(stage: usize, family: &str) -> String {
+        format!("knock_{}_{}", stage, family)
+    }
+
+    /// Set of sources that completed the sequence.
+    pub fn open_set(family: &str) -> String {
+        format!("knock_open_{}", family)
+    }
+
+    pub fn opens(&self, proto: &str, port: u16) -> bool {
+        self.opens.iter().any(|p| p.proto == proto && p.port == port)
+    }
+}
+
+fn accepted(config: &HclConfig, p: &ProtoPort) -> bool {
+    let ports = if p.proto == "tcp" { &config.wan.tcp_accept } else { &config.wan.udp_accept };
+    ports.contains(&p.port)
+}
+
+/// Resolve the `wan.knock` block of a config.
+pub fn from_hcl(config: &HclConfig) -> Result<Option<Knock>, Vec<String>> {
+    let Some(knock) = &config.wan.knock else {
+        return Ok(None);
+    };
+    let mut errors = Vec::new();
+    let mut parse = |key: &str, entries: &[String]| -> Vec<ProtoPort> {
+        if entries.is_empty() {
+            errors.push(format!("wan.knock.{}: must not be empty.", key));
+        }
+        entries
+            .iter()
+            .filter_map(|e| ProtoPort::new(e).map_err(|err| errors.push(format!("wan.knock.{}: {}", key, err))).ok())
+            .collect()
+    };
+    let sequence = parse("sequence", &knock.sequence);
+    let opens = parse("opens", &knock.opens);
+
+    for p in &sequence {
+        if accepted(config, p) || opens.contains(p) {
+            errors.push(format!("wan.knock.sequence: {} is an open port and cannot be a knock.", p));
+        }
+    }
+    for p in &opens {
+        if accepted(config, p) {
+            errors.push(format!("wan.knock.opens: {} is already open in wan.{}_accept.", p, p.proto));
+        }
+    }
+    let timeout = match knock.timeout.as_deref().map(|t| parse_duration(t, "knock timeout")) {
+        None => DEFAULT_TIMEOUT,
+        Some(Ok(t)) if t.as_secs() > 0 => t,
+        Some(Ok(_)) => {
+            errors.push("wan.knock.timeout: must be greater than 0.".to_string());
+            DEFAULT_TIMEOUT
+        }
+        Some(Err(e)) => {
+            errors.push(format!("wan.knock.timeout: {}", e));
+            DEFAULT_TIMEOUT
+        }
+    };
+
+    if errors.is_empty() {
+        Ok(Some(Knock { sequence, timeout, opens }))
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn config(knock: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{
+  tcp_accept = [443]
+  {}
+}}
+"#,
+            knock
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_hcl() {
+        let knock = from_hcl(&config(
+            r#"knock {
+    sequence = ["tcp/7000", "udp/8000", "tcp/7000"]
+    opens    = ["tcp/22"]
+  }"#,
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(knock.sequence.len(), 3);
+        assert_eq!(knock.sequence[1], ProtoPort { proto: "udp", port: 8000 });
+        assert_eq!(knock.timeout, Duration::from_secs(10));
+        assert!(knock.opens("tcp", 22));
+        assert!(!knock.opens("udp", 22));
+        assert_eq!(Knock::stage_set(2, "v6"), "knock_2_v6");
+        assert_eq!(Knock::open_set("v4"), "knock_open_v4");
+
+        assert_eq!(from_hcl(&config("")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_from_hcl_errors() {
+        let errors = from_hcl(&config(
+            r#"knock {
+    sequence = ["tcp/443", "tcp/22", "knock"]
+    timeout  = "soon"
+    opens    = ["tcp/22", "tcp/443"]
+  }"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "wan.knock.sequence: 'knock' must look like \"tcp/22\" or \"udp/51820\".",
+                "wan.knock.sequence: tcp/443 is an open port and cannot be a knock.",
+                "wan.knock.sequence: tcp/22 is an open port and cannot be a knock.",
+                "wan.knock.opens: tcp/443 is already open in wan.tcp_accept.",
+                "wan.knock.timeout: Invalid knock timeout: 'soon'. Expected e.g. \"30m\", \"6h\" or \"1d\"",
+            ]
+        );
+
+        let errors = from_hcl(&config("knock {\n sequence = []\n opens = []\n }")).unwrap_err();
+        assert_eq!(errors, vec!["wan.knock.sequence: must not be empty.", "wan.knock.opens: must not be empty."]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Per-source limits on WAN-exposed ports, and the SSH brute-force guard.
+
+use std::time::Duration;
+
+use crate::hcl_config::HclConfig;
+use crate::parsers::{parse_duration, ProtoPort, Rate};
+
+const DEFAULT_SSH_PORT: u16 = 22;
+const DEFAULT_SSH_THRESHOLD: &str = "5/minute";
+const DEFAULT_BAN: Duration = Duration::from_secs(3600);
+
+/// Upper bound on the sources tracked by each dynamic set.
+pub const SET_SIZE: u32 = 65535;
+
+/// Limits on new connections to one WAN-side port, per source address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortLimit {
+    /// "tcp" or "udp"
+    pub proto: &'static str,
+    pub port: u16,
+    pub rate: Option<Rate>,
+    pub ct_limit: Option<u32>,
+}
+
+impl PortLimit {
+    /// Set metering each source's connection rate.
+    pub fn rate_set(&self, family: &str) -> String {
+        format!("limit_{}_{}_{}", self.proto, self.port, family)
+    }
+
+    /// Set counting each source's connections.
+    pub fn conn_set(&self, family: &str) -> String {
+        format!("conns_{}_{}_{}", self.proto, self.port, family)
+    }
+}
+
+/// Bans sources that open connections to the SSH port faster than
+/// `threshold`, for `ban`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshGuard {
+    pub port: u16,
+    pub threshold: Rate,
+    pub ban: Duration,
+}
+
+impl SshGuard {
+    pub fn meter_set(&self, family: &str) -> String {
+        format!("ssh_guard_meter_{}", family)
+    }
+
+    pub fn ban_set(&self, family: &str) -> String {
+        format!("ssh_guard_ban_{}", family)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WanLimits {
+    pub ports: Vec<PortLimit>,
+    pub ssh_guard: Option<SshGuard>,
+}
+
+impl WanLimits {
+    /// Resolve `wan.port_limit` and `wan.ssh_guard`. Whether the ports are
+    /// exposed at all is checked against the parsed accepts and forwards
+    /// by the caller.
+    pub fn from_hcl(config: &HclConfig) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut ports = Vec::new();
+        for (key, limit) in &config.wan.port_limit {
+            let context = format!("wan.port_limit \"{}\"", key);
+            let ProtoPort { proto, port } = match ProtoPort::new(key) {
+                Ok(p) => p,
+                Err(e) => {
+                    errors.push(format!("{}: {}", context, e));
+                    continue;
+                }
+            };
+            let rate = match limit.limit.as_deref().map(Rate::new) {
+                None => None,
+                Some(Ok(rate)) => Some(rate),
+                Some(Err(e)) => {
+                    errors.push(format!("{}.limit: {}", context, e));
+                    continue;
+                }
+            };
+            if limit.ct_limit == Some(0) {
+                errors.push(format!("{}.ct_limit: must be greater than 0.", context));
+                continue;
+            }
+            if rate.is_none() && limit.ct_limit.is_none() {
+                errors.push(format!("{}: set limit, ct_limit or both.", context));
+                continue;
+            }
+            ports.push(PortLimit { proto, port, rate, ct_limit: limit.ct_limit });
+        }
+
+        let ssh_guard = config.wan.ssh_guard.as_ref().and_then(|guard| {
+            let threshold = Rate::new(guard.threshold.as_deref().unwrap_or(DEFAULT_SSH_THRESHOLD))
+                .map_err(|e| errors.push(format!("wan.ssh_guard.threshold: {}", e)))
+                .ok()?;
+            let ban = match guard.ban.as_deref().map(|b| parse_duration(b, "ban time")) {
+                None => DEFAULT_BAN,
+                Some(Ok(ban)) if ban.as_secs() > 0 => ban,
+                Some(Ok(_)) => {
+                    errors.push("wan.ssh_guard.ban: must be greater than 0.".to_string());
+                    return None;
+                }
+                Some(Err(e)) => {
+                    errors.push(format!("wan.ssh_guard.ban: {}", e));
+                    return None;
+                }
+            };
+            Some(SshGuard { port: guard.port.unwrap_or(DEFAULT_SSH_PORT), threshold, ban })
+        });
+
+        if errors.is_empty() {
+            Ok(WanLimits { ports, ssh_guard })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The limits for a WAN-side port, if any.
+    pub fn port(&self, proto: &str, port: u16) -> Option<&PortLimit> {
+        self.ports.iter().find(|l| l.proto == proto && l.port == port)
+    }
+
+    /// The SSH guard, if it covers this WAN-side port.
+    pub fn guard(&self, proto: &str, port: u16) -> Option<&SshGuard> {
+        self.ssh_guard.as_ref().filter(|g| proto == "tcp" && g.port == port)
+    }
+
+    /// Every limited port with the setting that limits it, for checking
+    /// that the port is exposed.
+    pub fn limited_ports(&self) -> Vec<(&'static str, u16, String)> {
+        let mut ports: Vec<_> = self
+            .ports
+            .iter()
+            .map(|l| (l.proto, l.port, format!("wan.port_limit \"{}/{}\"", l.proto, l.port)))
+            .collect();
+        if let Some(guard) = &self.ssh_guard {
+            ports.push(("tcp", guard.port, "wan.ssh_guard".to_string()));
+        }
+        ports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn config(wan: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{
+  tcp_accept = [22, 443]
+  {}
+}}
+"#,
+            wan
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_hcl() {
+        let limits = WanLimits::from_hcl(&config(
+            r#"
+  port_limit "tcp/443" {
+    limit    = "10/second burst 20"
+    ct_limit = 50
+  }
+  port_limit "udp/51820" { limit = "20/second" }
+  ssh_guard { ban = "30m" }
+"#,
+        ))
+        .unwrap();
+        let https = limits.port("tcp", 443).unwrap();
+        assert_eq!(https.rate.as_ref().unwrap().to_string(), "10/second burst 20");
+        assert_eq!(https.ct_limit, Some(50));
+        assert_eq!(https.rate_set("v4"), "limit_tcp_443_v4");
+        assert_eq!(https.conn_set("v6"), "conns_tcp_443_v6");
+        assert_eq!(limits.port("udp", 51820).unwrap().ct_limit, None);
+        assert!(limits.port("udp", 443).is_none());
+
+        let guard = limits.guard("tcp", 22).unwrap();
+        assert_eq!(guard.threshold.to_string(), "5/minute");
+        assert_eq!(guard.ban, Duration::from_secs(1800));
+        assert!(limits.guard("udp", 22).is_none());
+        assert_eq!(limits.limited_ports().len(), 3);
+
+        assert_eq!(WanLimits::from_hcl(&config("")).unwrap(), WanLimits::default());
+    }
+
+    #[test]
+    fn test_from_hcl_errors() {
+        let errors = WanLimits::from_hcl(&config(
+            r#"
+  port_limit "ssh" { ct_limit = 5 }
+  port_limit "tcp/0" { ct_limit = 5 }
+  port_limit "tcp/22" { limit = "fast" }
+  port_limit "tcp/80" { ct_limit = 0 }
+  port_limit "tcp/443" {}
+  ssh_guard { ban = "forever" }
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "wan.port_limit \"ssh\": 'ssh' must look like \"tcp/22\" or \"udp/51820\".",
+                "wan.port_limit \"tcp/0\": invalid port '0'.",
+                "wan.port_limit \"tcp/22\".limit: Invalid rate: 'fast'. Expected e.g. \"10/second\" or \"10/second burst 20\" (per second, minute, hour or day)",
+                "wan.port_limit \"tcp/80\".ct_limit: must be greater than 0.",
+                "wan.port_limit \"tcp/443\": set limit, ct_limit or both.",
+                "wan.ssh_guard.ban: Invalid ban time: 'forever'. Expected e.g. \"30m\", \"6h\" or \"1d\"",
+            ]
+        );
+    }
+}
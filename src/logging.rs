@@ -0,0 +1,209 @@
+use nifty_nft::Expr;
+
+use crate::hcl_config::{HclConfig, LogSetting};
+use crate::parsers::Rate;
+
+/// How a rule logs the packets it drops or accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogMode {
+    Off,
+    All,
+    Sampled,
+}
+
+impl LogMode {
+    pub fn from_setting(setting: Option<&LogSetting>) -> Result<Self, String> {
+        match setting {
+            None => Ok(LogMode::Sampled),
+            Some(LogSetting::Enabled(true)) => Ok(LogMode::All),
+            Some(LogSetting::Enabled(false)) => Ok(LogMode::Off),
+            Some(LogSetting::Mode(mode)) if mode == "sampled" => Ok(LogMode::Sampled),
+            Some(LogSetting::Mode(mode)) => Err(format!("'{}' must be true, false or \"sampled\".", mode)),
+        }
+    }
+
+    /// The mode of a forward or `allow_from` rule, which logs nothing
+    /// unless its block sets `log`.
+    pub fn for_rule(setting: Option<&LogSetting>) -> Result<Self, String> {
+        match setting {
+            None => Ok(LogMode::Off),
+            Some(_) => LogMode::from_setting(setting),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        *self != LogMode::Off
+    }
+}
+
+/// Drop logging and rule counter settings for the generated ruleset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logging {
+    pub input: LogMode,
+    pub forward: LogMode,
+    pub trunk: LogMode,
+    pub mgmt: LogMode,
+    /// NFLOG group, or `None` for the kernel log
+    pub nflog_group: Option<u16>,
+    /// Rate of sampled log entries
+    pub rate: Rate,
+    /// Count packets and bytes on every rule
+    pub counters: bool,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Logging {
+            input: LogMode::Sampled,
+            forward: LogMode::Sampled,
+            trunk: LogMode::Sampled,
+            mgmt: LogMode::Sampled,
+            nflog_group: None,
+            rate: Rate { count: 5, per: "minute".to_string(), burst: None },
+            counters: false,
+        }
+    }
+}
+
+impl Logging {
+    /// Resolve the `logging` block of a config.
+    pub fn from_hcl(config: &HclConfig) -> Result<Self, Vec<String>> {
+        let Some(cfg) = &config.logging else {
+            return Ok(Logging::default());
+        };
+        let mut errors = Vec::new();
+        let mut mode = |key: &str, setting: Option<&LogSetting>| {
+            LogMode::from_setting(setting).unwrap_or_else(|e| {
+                errors.push(format!("logging.{}: {}", key, e));
+                LogMode::Sampled
+            })
+        };
+        let mut logging = Logging {
+            input: mode("input", cfg.input.as_ref()),
+            forward: mode("forward", cfg.forward.as_ref()),
+            trunk: mode("trunk", cfg.trunk.as_ref()),
+            mgmt: mode("mgmt", cfg.mgmt.as_ref()),
+            nflog_group: cfg.nflog_group,
+            counters: cfg.counters,
+            ..Logging::default()
+        };
+        if let Some(rate) = &cfg.rate {
+            match Rate::new(rate) {
+                Ok(rate) => logging.rate = rate,
+                Err(e) => errors.push(format!("logging.rate: {}", e)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(logging)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The statements of a log rule, e.g.
+    /// `limit rate 5/minute log prefix "(sample) Dropped input: "`.
+    /// Empty when `mode` is off.
+    pub fn exprs(&self, mode: &LogMode, what: &str) -> Vec<Expr> {
+        let mut exprs = Vec::new();
+        match mode {
+            LogMode::Off => return exprs,
+            LogMode::All => {}
+            LogMode::Sampled => exprs.push(self.rate.limit()),
+        }
+        let prefix = match self.nflog_group {
+            // The collector looks the rule up by its prefix, so keep it bare
+            Some(_) => what.to_string(),
+            None if *mode == LogMode::Sampled => format!("(sample) {}: ", what),
+            None => format!("{}: ", what),
+        };
+        exprs.push(Expr::Log { prefix, group: self.nflog_group });
+        exprs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn config(logging: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{ enable_ipv4 = true }}
+{}
+"#,
+            logging
+        ))
+        .unwrap()
+    }
+
+    fn statement(logging: &Logging, mode: &LogMode, what: &str) -> String {
+        logging.exprs(mode, what).iter().map(|e| e.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn test_default_statements() {
+        let logging = Logging::from_hcl(&config("")).unwrap();
+        assert_eq!(
+            statement(&logging, &logging.input, "Dropped input"),
+            r#"limit rate 5/minute log prefix "(sample) Dropped input: ""#
+        );
+    }
+
+    #[test]
+    fn test_nflog_statements() {
+        let logging = Logging::from_hcl(&config(
+            r#"
+logging {
+  nflog_group = 2
+  rate        = "10/second"
+  input       = true
+  trunk       = false
+}
+"#,
+        ))
+        .unwrap();
+        assert_eq!(statement(&logging, &logging.input, "Dropped input"), r#"log prefix "Dropped input" group 2"#);
+        assert_eq!(
+            statement(&logging, &logging.forward, "Dropped forward"),
+            r#"limit rate 10/second log prefix "Dropped forward" group 2"#
+        );
+        assert_eq!(statement(&logging, &logging.trunk, "Dropped untagged trunk input"), "");
+        assert_eq!(
+            statement(&Logging { nflog_group: None, ..logging.clone() }, &LogMode::All, "Dropped input"),
+            r#"log prefix "Dropped input: ""#
+        );
+    }
+
+    #[test]
+    fn test_rule_modes() {
+        assert_eq!(LogMode::for_rule(None), Ok(LogMode::Off));
+        assert_eq!(LogMode::for_rule(Some(&LogSetting::Enabled(true))), Ok(LogMode::All));
+        assert_eq!(LogMode::for_rule(Some(&LogSetting::Mode("sampled".to_string()))), Ok(LogMode::Sampled));
+        assert!(LogMode::for_rule(Some(&LogSetting::Mode("loud".to_string()))).is_err());
+    }
+
+    #[test]
+    fn test_logging_errors() {
+        let errors = Logging::from_hcl(&config(
+            r#"
+logging {
+  rate  = "lots"
+  input = "always"
+}
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "logging.input: 'always' must be true, false or \"sampled\".",
+                "logging.rate: Invalid rate: 'lots'. Expected e.g. \"10/second\" or \"10/second burst 20\" (per second, minute, hour or day)",
+            ]
+        );
+    }
+}
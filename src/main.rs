@@ -1,27 +1,48 @@
 use askama::Template;
 use clap::{Parser, Subcommand};
-use env_logger;
 use log::{error, info};
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::process::exit;
 #[cfg(feature = "nixos")]
+mod apply;
+pub mod blocklist;
+#[cfg(feature = "nixos")]
 mod config;
-mod format;
 pub mod generate;
 pub mod hcl_config;
 #[cfg(feature = "nixos")]
 mod install;
+pub mod knock;
+pub mod limits;
+pub mod logging;
+mod nflog;
+pub mod objects;
 mod parsers;
+pub mod pd;
+mod plan;
 #[cfg(feature = "nixos")]
 mod pve_setup;
 pub mod qos;
+mod ruleset;
+pub mod routing;
+pub mod upnp;
+mod validate;
 pub mod vlan;
+pub mod wan;
+pub mod wireguard;
+use blocklist::Blocklist;
 use hcl_config::{parse_hcl, HclConfig};
+use knock::Knock;
+use limits::WanLimits;
+use logging::{LogMode, Logging};
+use objects::{NamedSet, Objects};
 use parsers::*;
 use qos::{QosConfig, QosOverride};
-use vlan::Vlan;
+use vlan::{ClientRule, EgressBlock, Vlan};
+use wan::WanUplinks;
 #[allow(unused_imports)]
+use ipnetwork::IpNetwork;
 use std::net::IpAddr;
 use std::process::{Command, Stdio};
 
@@ -47,6 +68,33 @@ enum Commands {
     #[cfg(feature = "nixos")]
     Maintenance,
 
+    /// Apply the HCL config by restarting the firewall, network and DHCP/DNS services
+    #[cfg(feature = "nixos")]
+    Apply {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Roll back automatically unless `confirm` is run within this many seconds
+        #[arg(long)]
+        confirm_timeout: Option<u64>,
+    },
+
+    /// Keep changes made by `apply --confirm-timeout`
+    #[cfg(feature = "nixos")]
+    Confirm {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+    },
+
+    /// Revert changes made by `apply --confirm-timeout` now
+    #[cfg(feature = "nixos")]
+    Rollback {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+    },
+
     /// Interactive PVE VM setup wizard (outputs shell variables)
     #[cfg(feature = "nixos")]
     PveSetup {
@@ -75,11 +123,104 @@ enum Commands {
         #[arg(long)]
         validate: bool,
 
+        /// Print libnftables JSON (for `nft -j -f`) instead of nft syntax
+        #[arg(long)]
+        json: bool,
+
+        /// Copy the loaded egress domain sets' elements into the new ruleset
+        #[arg(long, conflicts_with = "json")]
+        keep_domain_sets: bool,
+
         /// Enable verbose output
         #[arg(short, long)]
         verbose: bool,
     },
 
+    /// Print an nft script removing nifty-filter's own tables (no HCL config needed)
+    NftablesTeardown {
+        /// Replace them with a lockdown filter allowing only loopback and established traffic
+        #[arg(long)]
+        lockdown: bool,
+    },
+
+    /// Monitor WAN uplink health and update policy routing (multi-WAN)
+    WanMonitor {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+    },
+
+    /// Load delegated IPv6 VLAN prefixes into their nftables sets
+    PdSync {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Keep running and update the sets when a prefix changes
+        #[arg(long)]
+        watch: bool,
+        /// Seconds between prefix checks with --watch
+        #[arg(long, default_value_t = 10)]
+        interval: u64,
+    },
+
+    /// Load blocklists into their nftables sets
+    BlocklistSync {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Keep running and reload each list when its refresh interval elapses
+        #[arg(long)]
+        watch: bool,
+        /// Load only the cached copies, whatever their age, without fetching
+        #[arg(long, conflicts_with = "watch")]
+        cached: bool,
+        /// Directory holding the last good copy of each list
+        #[arg(long, default_value = blocklist::CACHE_DIR)]
+        cache_dir: String,
+    },
+
+    /// Collect NFLOG drop records into a JSON file for the dashboard
+    Nflog {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// File holding the most recent drops
+        #[arg(long, default_value = nflog::DROPS_FILE)]
+        output: String,
+        /// Number of drops to keep
+        #[arg(long, default_value_t = 500)]
+        keep: usize,
+    },
+
+    /// Show what changing to a new HCL config would do to the generated outputs
+    Plan {
+        /// Path to the proposed HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Path to the currently applied HCL config file
+        #[arg(long)]
+        against: String,
+        /// Exit with status 2 when there are changes
+        #[arg(long)]
+        detailed_exitcode: bool,
+    },
+
+    /// Check an HCL config and report every problem with its location
+    Validate {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Output format: text or json
+        #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+        format: String,
+    },
+
+    /// WireGuard server zone utilities
+    Wireguard {
+        #[command(subcommand)]
+        what: WireguardCommands,
+    },
+
     /// Print hostname from config (or "nifty-filter" if not set)
     Hostname {
         /// Path to the HCL config file
@@ -87,7 +228,7 @@ enum Commands {
         config: String,
     },
 
-    /// Print a config value by key (wan-name, trunk-name, mgmt-name, wan-mac, trunk-mac, mgmt-mac, mgmt-subnet, enable-ipv6, dashboard-port, iperf-port, mdns-interfaces, dashboard-tls-enabled, dashboard-tls-acme-url, dashboard-tls-acme-email, dashboard-tls-client-cert, dashboard-tls-client-key, dashboard-tls-sans)
+    /// Print a config value by key (wan-name, wan-interfaces, pppoe-uplinks, trunk-name, mgmt-name, wan-mac, trunk-mac, mgmt-mac, mgmt-subnet, enable-ipv6, dashboard-port, iperf-port, mdns-interfaces, upnp-interfaces, dashboard-tls-enabled, dashboard-tls-acme-url, dashboard-tls-acme-email, dashboard-tls-client-cert, dashboard-tls-client-key, dashboard-tls-sans)
     Get {
         /// Path to the HCL config file
         #[arg(long, short)]
@@ -103,6 +244,28 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum WireguardCommands {
+    /// Print a client config for a peer (requires `wg`; `--qr` requires `qrencode`)
+    PeerConfig {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// WireGuard zone (interface) name
+        #[arg(long)]
+        zone: String,
+        /// Peer name within the zone
+        #[arg(long)]
+        peer: String,
+        /// Client private key to embed (otherwise a placeholder is written)
+        #[arg(long)]
+        private_key_file: Option<String>,
+        /// Print the config as a terminal QR code
+        #[arg(long)]
+        qr: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum GenerateCommands {
     /// Generate systemd .link files for interface renaming by MAC address
@@ -123,6 +286,15 @@ enum GenerateCommands {
         #[arg(long, short)]
         output_dir: String,
     },
+    /// Generate pppd option files for PPPoE uplinks
+    Ppp {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Output directory for pppd option files
+        #[arg(long, short)]
+        output_dir: String,
+    },
     /// Generate dnsmasq.conf
     Dnsmasq {
         /// Path to the HCL config file
@@ -147,35 +319,67 @@ enum GenerateCommands {
         #[arg(long, short = 'O')]
         output: String,
     },
+    /// Generate miniupnpd.conf for VLANs with UPnP enabled
+    Miniupnpd {
+        /// Path to the HCL config file
+        #[arg(long, short)]
+        config: String,
+        /// Output file path
+        #[arg(long, short = 'O')]
+        output: String,
+    },
 }
 
-#[derive(Template)]
-#[template(path = "router.nft.txt")]
-struct RouterTemplate {
+/// A resolved config, ready to be turned into the router's ruleset (see
+/// `ruleset.rs`).
+struct Router {
     interface_trunk: Interface,
-    interface_wan: Interface,
     interface_mgmt: String,
     subnet_mgmt_ipv4: String,
 
+    // WAN uplinks
+    wan: WanUplinks,
+
     // Protocol enablement
     enable_ipv4: bool,
     enable_ipv6: bool,
 
-    // VLAN configuration
+    // VLAN configuration (WireGuard zones are appended to `vlans`)
     vlan_aware_switch: bool,
     vlans: Vec<Vlan>,
+    wireguard_ports: Vec<u16>,
+
+    // Named host/group/service sets referenced by rules as @name
+    object_sets: Vec<NamedSet>,
+
+    // Blocklist sets, filled at runtime by `blocklist-sync`
+    blocklists: Vec<Blocklist>,
+
+    // Drop logging
+    logging: Logging,
 
     // WAN-side ICMP
-    icmp_accept_wan: String,
-    icmpv6_accept_wan: String,
+    icmp_accept_wan: Vec<IcmpType>,
+    icmpv6_accept_wan: Vec<Icmpv6Type>,
 
     // WAN-side port accepts
-    tcp_accept_wan: String,
-    udp_accept_wan: String,
+    tcp_accept_wan: Vec<u16>,
+    udp_accept_wan: Vec<u16>,
 
     // WAN-side forward routes
     tcp_forward_wan: ForwardRouteList,
     udp_forward_wan: ForwardRouteList,
+    // Reflect WAN forwards for LAN clients that use the public address
+    hairpin: bool,
+
+    // Static routes through gateways on the VLANs
+    routes: Vec<routing::StaticRoute>,
+
+    // Per-source limits on WAN accepts and forwards, and the SSH guard
+    wan_limits: WanLimits,
+
+    // Router ports opened per source by a port-knock sequence
+    knock: Option<Knock>,
 
     // Dashboard port (for mgmt firewall rule)
     dashboard_port: u16,
@@ -184,8 +388,8 @@ struct RouterTemplate {
     iperf_port: u16,
 
     // Anti-spoofing: bogon source addresses to drop on WAN
-    wan_bogons_ipv4: String,
-    wan_bogons_ipv6: String,
+    wan_bogons_ipv4: Vec<IpNetwork>,
+    wan_bogons_ipv6: Vec<IpNetwork>,
 
     // QoS: DSCP marking for upload traffic prioritization
     qos_enabled: bool,
@@ -195,15 +399,20 @@ struct RouterTemplate {
     has_download_bandwidth: bool,
 }
 
-impl RouterTemplate {
+impl Router {
     fn from_hcl(config: &HclConfig) -> Result<Self, Vec<String>> {
         let mut errors = Vec::new();
 
         // Interfaces
-        let interface_trunk = Interface::new(&config.interfaces.trunk_name())
-            .unwrap_or_else(|e| { errors.push(e); Interface::new("eth0").unwrap() });
-        let interface_wan = Interface::new(&config.interfaces.wan_name())
+        let interface_trunk = Interface::new(config.interfaces.trunk_name())
             .unwrap_or_else(|e| { errors.push(e); Interface::new("eth0").unwrap() });
+        if let Err(e) = Interface::new(config.interfaces.wan_name()) {
+            errors.push(e);
+        }
+        let wan = WanUplinks::from_hcl(config).unwrap_or_else(|e| {
+            errors.extend(e);
+            WanUplinks { policy: WanPolicy::Failover, uplinks: Vec::new() }
+        });
         let interface_mgmt = config.interfaces.mgmt_name().unwrap_or("").to_string();
         let subnet_mgmt_ipv4 = if !interface_mgmt.is_empty() {
             match config.interfaces.mgmt_subnet() {
@@ -231,18 +440,17 @@ impl RouterTemplate {
 
         // WAN ICMP
         let icmp_accept_wan = if enable_ipv4 {
-            let types: Vec<IcmpType> = config.wan.icmp_accept.iter()
+            config.wan.icmp_accept.iter()
                 .filter_map(|s| IcmpType::new(s).map_err(|e| errors.push(format!("wan.icmp_accept: {}", e))).ok())
-                .collect();
-            IcmpType::vec_to_string(&types)
+                .collect()
         } else {
-            String::new()
+            Vec::new()
         };
 
         let icmpv6_accept_wan = if enable_ipv6 {
             if config.wan.icmpv6_accept.is_empty() {
                 // Defaults required for IPv6 to function (ND, error types)
-                Icmpv6Type::vec_to_string(&[
+                vec![
                     Icmpv6Type::NdNeighborSolicit,
                     Icmpv6Type::NdNeighborAdvert,
                     Icmpv6Type::NdRouterSolicit,
@@ -250,42 +458,71 @@ impl RouterTemplate {
                     Icmpv6Type::DestinationUnreachable,
                     Icmpv6Type::PacketTooBig,
                     Icmpv6Type::TimeExceeded,
-                ])
+                ]
             } else {
-                let types: Vec<Icmpv6Type> = config.wan.icmpv6_accept.iter()
+                config.wan.icmpv6_accept.iter()
                     .filter_map(|s| Icmpv6Type::new(s).map_err(|e| errors.push(format!("wan.icmpv6_accept: {}", e))).ok())
-                    .collect();
-                Icmpv6Type::vec_to_string(&types)
+                    .collect()
             }
         } else {
-            String::new()
+            Vec::new()
         };
 
         // WAN ports
-        let tcp_accept_wan = config.wan.tcp_accept.iter()
-            .map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
-        let udp_accept_wan = config.wan.udp_accept.iter()
-            .map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+        let tcp_accept_wan = config.wan.tcp_accept.clone();
+        let udp_accept_wan = config.wan.udp_accept.clone();
+
+        // Named objects
+        let objects = Objects::from_hcl(config).unwrap_or_else(|e| {
+            errors.extend(e);
+            Objects::default()
+        });
+        let object_sets = objects.sets();
+        let blocklists = blocklist::from_hcl(config).unwrap_or_else(|e| { errors.extend(e); Vec::new() });
+        let logging = Logging::from_hcl(config).unwrap_or_else(|e| { errors.extend(e); Logging::default() });
+        let routes = routing::from_hcl(config).unwrap_or_else(|e| { errors.extend(e); Vec::new() });
 
         // WAN forwards
-        let tcp_forward_wan = ForwardRouteList::new(&config.wan.tcp_forward.join(", "))
-            .unwrap_or_else(|e| { errors.push(format!("wan.tcp_forward: {}", e)); ForwardRouteList::new("").unwrap() });
-        let udp_forward_wan = ForwardRouteList::new(&config.wan.udp_forward.join(", "))
-            .unwrap_or_else(|e| { errors.push(format!("wan.udp_forward: {}", e)); ForwardRouteList::new("").unwrap() });
+        let mut tcp_forward_wan = Self::forward_list(&config.wan.tcp_forward, &objects, "wan.tcp_forward", &mut errors);
+        let mut udp_forward_wan = Self::forward_list(&config.wan.udp_forward, &objects, "wan.udp_forward", &mut errors);
+        let (tcp_blocks, udp_blocks) = Self::forward_blocks(config, &objects, &mut errors);
+        tcp_forward_wan.routes.extend(tcp_blocks);
+        udp_forward_wan.routes.extend(udp_blocks);
+
+        let knock = knock::from_hcl(config).unwrap_or_else(|e| { errors.extend(e); None });
+
+        // WAN limits only make sense on ports that are open on the WAN
+        let wan_limits = WanLimits::from_hcl(config).unwrap_or_else(|e| { errors.extend(e); WanLimits::default() });
+        for (proto, port, context) in wan_limits.limited_ports() {
+            let (accepted, forwards) = match proto {
+                "tcp" => (&config.wan.tcp_accept, &tcp_forward_wan),
+                _ => (&config.wan.udp_accept, &udp_forward_wan),
+            };
+            let knocked = knock.as_ref().is_some_and(|k| k.opens(proto, port));
+            if !accepted.contains(&port) && !forwards.routes.iter().any(|r| r.covers(port)) && !knocked {
+                errors.push(format!(
+                    "{}: {} port {} is not in wan.{}_accept, wan.{}_forward, wan.forward or wan.knock.opens.",
+                    context, proto.to_uppercase(), port, proto, proto
+                ));
+            }
+        }
 
         let dashboard_port = config.dashboard_port.unwrap_or(3000);
         let iperf_port = config.iperf_port.unwrap_or(5201);
 
+        let mut wireguard_ports: Vec<u16> = config.wireguard.values().map(|w| w.listen_port).collect();
+        wireguard_ports.sort();
+
         // Bogons (hardcoded defaults)
         let wan_bogons_ipv4 = if enable_ipv4 {
-            CidrList::new("0.0.0.0/8, 10.0.0.0/8, 100.64.0.0/10, 127.0.0.0/8, 169.254.0.0/16, 172.16.0.0/12, 192.0.0.0/24, 192.0.2.0/24, 192.168.0.0/16, 198.18.0.0/15, 198.51.100.0/24, 203.0.113.0/24, 224.0.0.0/4, 240.0.0.0/4").unwrap().to_string()
+            CidrList::new("0.0.0.0/8, 10.0.0.0/8, 100.64.0.0/10, 127.0.0.0/8, 169.254.0.0/16, 172.16.0.0/12, 192.0.0.0/24, 192.0.2.0/24, 192.168.0.0/16, 198.18.0.0/15, 198.51.100.0/24, 203.0.113.0/24, 224.0.0.0/4, 240.0.0.0/4").unwrap().cidrs
         } else {
-            String::new()
+            Vec::new()
         };
         let wan_bogons_ipv6 = if enable_ipv6 {
-            CidrList::new("::/128, ::1/128, fc00::/7, ff00::/8").unwrap().to_string()
+            CidrList::new("::/128, ::1/128, fc00::/7, ff00::/8").unwrap().cidrs
         } else {
-            String::new()
+            Vec::new()
         };
 
         // QoS
@@ -309,7 +546,7 @@ impl RouterTemplate {
                     (qos::QosClass::Bulk, &ovr.bulk),
                 ] {
                     if !cidrs.is_empty() {
-                        match CidrList::new(&cidrs.join(", ")) {
+                        match objects.expand_addresses(cidrs).and_then(|c| CidrList::new(&c.join(", "))) {
                             Ok(list) => {
                                 let o = QosOverride::from_cidr_list(class, &list);
                                 if !o.cidrs_ipv4.is_empty() || !o.cidrs_ipv6.is_empty() {
@@ -326,8 +563,10 @@ impl RouterTemplate {
             (false, Vec::new())
         };
 
-        // VLANs
-        let vlans = Self::convert_vlans(config, enable_ipv4, &mut errors);
+        // VLANs and WireGuard zones
+        let vlans = Self::convert_vlans(config, &objects, enable_ipv4, &mut errors);
+        errors.extend(wireguard::validate_ports(config));
+        errors.extend(wireguard::validate_clients(config));
 
         // Validate: bandwidth requires qos block
         if !qos_enabled {
@@ -346,15 +585,19 @@ impl RouterTemplate {
             return Err(errors);
         }
 
-        Ok(RouterTemplate {
+        Ok(Router {
             interface_trunk,
-            interface_wan,
             interface_mgmt,
             subnet_mgmt_ipv4,
+            wan,
             enable_ipv4,
             enable_ipv6,
             vlan_aware_switch,
             vlans,
+            wireguard_ports,
+            object_sets,
+            blocklists,
+            logging,
             dashboard_port,
             icmp_accept_wan,
             icmpv6_accept_wan,
@@ -362,6 +605,10 @@ impl RouterTemplate {
             udp_accept_wan,
             tcp_forward_wan,
             udp_forward_wan,
+            hairpin: config.wan.hairpin,
+            routes,
+            wan_limits,
+            knock,
             iperf_port,
             wan_bogons_ipv4,
             wan_bogons_ipv6,
@@ -373,6 +620,7 @@ impl RouterTemplate {
 
     fn convert_vlans(
         config: &HclConfig,
+        objects: &Objects,
         enable_ipv4: bool,
         errors: &mut Vec<String>,
     ) -> Vec<Vlan> {
@@ -403,8 +651,12 @@ impl RouterTemplate {
             }
         }
 
-        // Build name -> (id, interface_name) lookup for inter-VLAN rules
-        let name_lookup: HashMap<&str, (u16, String)> = entries.iter().map(|(name, v)| {
+        // WireGuard zones, sorted by interface name
+        let mut wg_entries: Vec<(&String, &hcl_config::WireguardHclConfig)> = config.wireguard.iter().collect();
+        wg_entries.sort_by_key(|(name, _)| name.as_str());
+
+        // Build name -> (label, interface_name) lookup for inter-zone rules
+        let mut name_lookup: HashMap<&str, (String, String)> = entries.iter().map(|(name, v)| {
             let iface = if let Some(ref dedicated) = v.interface {
                 // VLAN has a dedicated interface (not on trunk)
                 dedicated.name.clone()
@@ -413,71 +665,105 @@ impl RouterTemplate {
             } else {
                 name.to_string()
             };
-            (name.as_str(), (v.id, iface))
+            (name.as_str(), (format!("VLAN {}", v.id), iface))
         }).collect();
+        for (name, _) in &wg_entries {
+            if name_lookup.contains_key(name.as_str()) {
+                errors.push(format!("wireguard \"{}\": name is already used by a VLAN.", name));
+                continue;
+            }
+            name_lookup.insert(name.as_str(), (format!("WireGuard {}", name), name.to_string()));
+        }
 
         let mut vlans: Vec<Vlan> = Vec::new();
+        let mut seen_subnet_ids = HashSet::new();
 
         for (name, vhcl) in &entries {
-            let (_, ref interface_name) = name_lookup[name.as_str()];
+            let (ref label, ref interface_name) = name_lookup[name.as_str()];
 
             // IPv4
             let subnet_ipv4 = vhcl.ipv4.as_ref().map(|v| v.subnet.clone()).unwrap_or_default();
             if enable_ipv4 && subnet_ipv4.is_empty() {
                 errors.push(format!("vlan \"{}\".ipv4.subnet is required when wan.enable_ipv4 is true.", name));
             }
-            let egress_allowed_ipv4 = match &vhcl.ipv4 {
-                Some(ipv4) if !ipv4.egress.is_empty() => {
-                    let joined = ipv4.egress.join(", ");
-                    match CidrList::new(&joined) {
-                        Ok(list) => list.to_string(),
-                        Err(e) => { errors.push(format!("vlan \"{}\".ipv4.egress: {}", name, e)); String::new() }
-                    }
-                }
-                _ => String::new(),
-            };
+            let egress_allowed_ipv4 = vhcl.ipv4.as_ref()
+                .map(|v| Self::egress_list(&v.egress, &format!("vlan \"{}\".ipv4.egress", name), errors))
+                .unwrap_or_default();
 
-            // IPv6
-            let subnet_ipv6 = vhcl.ipv6.as_ref().map(|v| v.subnet.clone()).unwrap_or_default();
-            let egress_allowed_ipv6 = match &vhcl.ipv6 {
-                Some(ipv6) if !ipv6.egress.is_empty() => {
-                    let joined = ipv6.egress.join(", ");
-                    match CidrList::new(&joined) {
-                        Ok(list) => list.to_string(),
-                        Err(e) => { errors.push(format!("vlan \"{}\".ipv6.egress: {}", name, e)); String::new() }
+            // IPv6 (delegated prefixes are matched through a runtime-updated set)
+            let ipv6_delegated = vhcl.ipv6.as_ref().is_some_and(|v| v.delegated);
+            if let Some(ipv6) = &vhcl.ipv6 {
+                if ipv6.delegated {
+                    if !ipv6.subnet.is_empty() {
+                        errors.push(format!("vlan \"{}\".ipv6: subnet and delegated = true are mutually exclusive.", name));
+                    }
+                    if !config.wan.enable_ipv6 {
+                        errors.push(format!("vlan \"{}\".ipv6.delegated requires wan.enable_ipv6 = true.", name));
+                    }
+                    let subnet_id = ipv6.delegated_subnet_id(vhcl.id);
+                    let subnets = 1u64 << (64 - generate::PD_HINT_LENGTH);
+                    if subnet_id >= subnets {
+                        let from_id = if ipv6.subnet_id.is_none() { " (the VLAN ID)" } else { "" };
+                        errors.push(format!(
+                            "vlan \"{}\".ipv6.subnet_id {:#x}{} does not fit the /{} delegated prefix; set subnet_id to 0x0-{:#x}.",
+                            name, subnet_id, from_id, generate::PD_HINT_LENGTH, subnets - 1
+                        ));
+                    }
+                    if !seen_subnet_ids.insert(subnet_id) {
+                        errors.push(format!("vlan \"{}\".ipv6: duplicate delegated subnet_id {:#x}.", name, subnet_id));
                     }
+                    if let Some(dhcpv6) = &vhcl.dhcpv6 {
+                        if !dhcpv6.pool_start.starts_with("::") || !dhcpv6.pool_end.starts_with("::") {
+                            errors.push(format!(
+                                "vlan \"{}\".dhcpv6: pools on a delegated prefix must be address suffixes (e.g. \"::100\").",
+                                name
+                            ));
+                        }
+                    }
+                } else if ipv6.subnet.is_empty() {
+                    errors.push(format!("vlan \"{}\".ipv6.subnet is required unless delegated = true.", name));
+                } else if ipv6.subnet_id.is_some() {
+                    errors.push(format!("vlan \"{}\".ipv6.subnet_id is only valid with delegated = true.", name));
                 }
-                _ => String::new(),
+            }
+            let subnet_ipv6 = match &vhcl.ipv6 {
+                Some(v) if v.delegated => format!("@{}", pd::set_name(vhcl.id)),
+                Some(v) => v.subnet.clone(),
+                None => String::new(),
             };
+            let egress_allowed_ipv6 = vhcl.ipv6.as_ref()
+                .map(|v| Self::egress_list(&v.egress, &format!("vlan \"{}\".ipv6.egress", name), errors))
+                .unwrap_or_default();
+            let mut egress_blocked = Vec::new();
+            for schedule in &vhcl.egress_blocked {
+                match objects.schedule(schedule) {
+                    Ok(windows) => egress_blocked.extend(windows.iter().map(|window| {
+                        EgressBlock { schedule: schedule.clone(), window: window.clone() }
+                    })),
+                    Err(e) => errors.push(format!("vlan \"{}\".egress_blocked: {}", name, e)),
+                }
+            }
 
-            // Firewall
-            let (icmp_accept, icmpv6_accept, tcp_accept, udp_accept) = match &vhcl.firewall {
-                Some(fw) => {
-                    let icmp = if enable_ipv4 {
-                        let types: Vec<IcmpType> = fw.icmp_accept.iter()
-                            .filter_map(|s| IcmpType::new(s).map_err(|e| errors.push(format!("vlan \"{}\".firewall: {}", name, e))).ok())
-                            .collect();
-                        IcmpType::vec_to_string(&types)
-                    } else {
-                        String::new()
-                    };
-
-                    let icmpv6 = if !subnet_ipv6.is_empty() {
-                        let types: Vec<Icmpv6Type> = fw.icmpv6_accept.iter()
-                            .filter_map(|s| Icmpv6Type::new(s).map_err(|e| errors.push(format!("vlan \"{}\".firewall: {}", name, e))).ok())
-                            .collect();
-                        Icmpv6Type::vec_to_string(&types)
-                    } else {
-                        String::new()
-                    };
-
-                    let tcp = fw.tcp_accept.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
-                    let udp = fw.udp_accept.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+            let clients = Self::client_rules(name, vhcl, objects, errors);
+            let log = vhcl.log.as_ref()
+                .map(|l| LogMode::from_setting(Some(l)).unwrap_or_else(|e| {
+                    errors.push(format!("vlan \"{}\".log: {}", name, e));
+                    LogMode::Sampled
+                }));
+            let egress_domains = vhcl.egress_domains.iter()
+                .filter_map(|d| vlan::parse_domain(d)
+                    .map_err(|e| errors.push(format!("vlan \"{}\".egress_domains: {}", name, e)))
+                    .ok())
+                .collect();
 
-                    (icmp, icmpv6, tcp, udp)
-                }
-                None => (String::new(), String::new(), String::new(), String::new()),
-            };
+            // Firewall
+            let (icmp_accept, icmpv6_accept, tcp_accept, udp_accept) = Self::zone_firewall(
+                vhcl.firewall.as_ref(),
+                enable_ipv4,
+                !subnet_ipv6.is_empty(),
+                &format!("vlan \"{}\".firewall", name),
+                errors,
+            );
 
             // QoS class
             let qos_class = vhcl.qos_class.as_ref().map(|s| {
@@ -507,6 +793,15 @@ impl RouterTemplate {
                 (None, None)
             };
 
+            // UPnP maps IPv4 ports only
+            let upnp_ports = upnp::ports(vhcl.upnp.as_ref()).unwrap_or_else(|e| {
+                errors.push(format!("vlan \"{}\".upnp.{}", name, e));
+                None
+            });
+            if upnp_ports.is_some() && subnet_ipv4.is_empty() {
+                errors.push(format!("vlan \"{}\".upnp requires an ipv4 subnet.", name));
+            }
+
             // DHCP
             let dhcp_enabled = vhcl.dhcp.is_some();
             let (dhcp_pool_start, dhcp_pool_end, dhcp_router, dhcp_dns) = vhcl.dhcp.as_ref()
@@ -520,25 +815,30 @@ impl RouterTemplate {
                 .unwrap_or_default();
 
             // Forward routes
-            let tcp_forward = ForwardRouteList::new(&vhcl.tcp_forward.join(", "))
-                .unwrap_or_else(|e| { errors.push(format!("vlan \"{}\".tcp_forward: {}", name, e)); ForwardRouteList::new("").unwrap() });
-            let udp_forward = ForwardRouteList::new(&vhcl.udp_forward.join(", "))
-                .unwrap_or_else(|e| { errors.push(format!("vlan \"{}\".udp_forward: {}", name, e)); ForwardRouteList::new("").unwrap() });
+            let tcp_forward = Self::forward_list(&vhcl.tcp_forward, objects, &format!("vlan \"{}\".tcp_forward", name), errors);
+            let udp_forward = Self::forward_list(&vhcl.udp_forward, objects, &format!("vlan \"{}\".udp_forward", name), errors);
 
             // Inbound rules
-            let tcp_allow_inbound = InboundRuleList::new(&vhcl.allow_inbound_tcp.join(", "))
+            let tcp_allow_inbound = InboundRuleList::parse(&vhcl.allow_inbound_tcp.join(", "), objects, "tcp")
                 .unwrap_or_else(|e| { errors.push(format!("vlan \"{}\".allow_inbound_tcp: {}", name, e)); InboundRuleList::new("").unwrap() });
-            let udp_allow_inbound = InboundRuleList::new(&vhcl.allow_inbound_udp.join(", "))
+            let udp_allow_inbound = InboundRuleList::parse(&vhcl.allow_inbound_udp.join(", "), objects, "udp")
                 .unwrap_or_else(|e| { errors.push(format!("vlan \"{}\".allow_inbound_udp: {}", name, e)); InboundRuleList::new("").unwrap() });
 
             vlans.push(Vlan {
                 id: vhcl.id,
                 name: name.to_string(),
+                label: label.clone(),
+                chain_suffix: format!("vlan_{}", vhcl.id),
                 interface_name: interface_name.clone(),
                 subnet_ipv4,
                 subnet_ipv6,
+                ipv6_delegated,
                 egress_allowed_ipv4,
                 egress_allowed_ipv6,
+                egress_blocked,
+                clients,
+                egress_domains,
+                log,
                 icmp_accept,
                 icmpv6_accept,
                 tcp_accept,
@@ -554,6 +854,9 @@ impl RouterTemplate {
                 bandwidth_download_kbit,
                 iperf_enabled: vhcl.iperf_enabled,
                 mdns_reflector: vhcl.mdns_reflector,
+                upnp_ports,
+                routing: routing::Policy::of(config, vhcl),
+                egress_via: vhcl.egress_via.clone(),
                 dhcp_enabled,
                 dhcp_pool_start,
                 dhcp_pool_end,
@@ -565,32 +868,334 @@ impl RouterTemplate {
             });
         }
 
-        // Process inter-VLAN rules (needs all VLANs built first for name lookup)
-        for (name, vhcl) in &entries {
-            for (src_name, rules) in &vhcl.allow_from {
-                if let Some(&(src_id, ref src_iface)) = name_lookup.get(src_name.as_str()) {
-                    if let Some(target) = vlans.iter_mut().find(|v| v.name == **name) {
+        for (name, wg) in &wg_entries {
+            if let Some(zone) = Self::convert_wireguard(name, wg, config, enable_ipv4, errors) {
+                vlans.push(zone);
+            }
+        }
+
+        // Process inter-zone rules (needs all zones built first for name lookup)
+        let allow_from = entries.iter().map(|(name, v)| ("vlan", *name, &v.allow_from))
+            .chain(wg_entries.iter().map(|(name, w)| ("wireguard", *name, &w.allow_from)));
+        for (kind, name, sources) in allow_from {
+            for (src_name, rules) in sources {
+                if let Some((src_label, src_iface)) = name_lookup.get(src_name.as_str()) {
+                    if let Some(target) = vlans.iter_mut().find(|v| v.name == *name) {
+                        let log = LogMode::for_rule(rules.log.as_ref()).unwrap_or_else(|e| {
+                            errors.push(format!("{} \"{}\".allow_from \"{}\".log: {}", kind, name, src_name, e));
+                            LogMode::Off
+                        });
                         let joined_tcp = rules.tcp.join(", ");
                         if !joined_tcp.is_empty() {
-                            if let Err(e) = target.tcp_allow_inter_vlan.add_entry(src_id, src_iface.clone(), &joined_tcp) {
-                                errors.push(format!("vlan \"{}\".allow_from \"{}\".tcp: {}", name, src_name, e));
+                            if let Err(e) = target.tcp_allow_inter_vlan.add_entry(src_label.clone(), src_iface.clone(), &joined_tcp, objects, "tcp", log) {
+                                errors.push(format!("{} \"{}\".allow_from \"{}\".tcp: {}", kind, name, src_name, e));
                             }
                         }
                         let joined_udp = rules.udp.join(", ");
                         if !joined_udp.is_empty() {
-                            if let Err(e) = target.udp_allow_inter_vlan.add_entry(src_id, src_iface.clone(), &joined_udp) {
-                                errors.push(format!("vlan \"{}\".allow_from \"{}\".udp: {}", name, src_name, e));
+                            if let Err(e) = target.udp_allow_inter_vlan.add_entry(src_label.clone(), src_iface.clone(), &joined_udp, objects, "udp", log) {
+                                errors.push(format!("{} \"{}\".allow_from \"{}\".udp: {}", kind, name, src_name, e));
                             }
                         }
                     }
                 } else {
-                    errors.push(format!("vlan \"{}\".allow_from \"{}\": unknown source VLAN name.", name, src_name));
+                    errors.push(format!("{} \"{}\".allow_from \"{}\": unknown source VLAN or WireGuard name.", kind, name, src_name));
                 }
             }
         }
 
         vlans
     }
+
+    /// Build the firewall zone for a WireGuard interface. Tunnels carry no
+    /// DHCP, QoS or port forwards; everything else matches a VLAN.
+    fn convert_wireguard(
+        name: &str,
+        wg: &hcl_config::WireguardHclConfig,
+        config: &HclConfig,
+        enable_ipv4: bool,
+        errors: &mut Vec<String>,
+    ) -> Option<Vlan> {
+        if let Err(e) = Interface::new(name) {
+            errors.push(format!("wireguard \"{}\": {}", name, e));
+            return None;
+        }
+        if let Err(e) = wireguard::validate(name, wg) {
+            errors.extend(e);
+        }
+
+        let subnet_ipv4 = wg.ipv4.as_ref().map(|v| v.subnet.clone()).unwrap_or_default();
+        if enable_ipv4 && subnet_ipv4.is_empty() {
+            errors.push(format!("wireguard \"{}\".ipv4.subnet is required when wan.enable_ipv4 is true.", name));
+        }
+        let egress_allowed_ipv4 = wg.ipv4.as_ref()
+            .map(|v| Self::egress_list(&v.egress, &format!("wireguard \"{}\".ipv4.egress", name), errors))
+            .unwrap_or_default();
+
+        let subnet_ipv6 = match &wg.ipv6 {
+            Some(ipv6) if ipv6.delegated => {
+                errors.push(format!("wireguard \"{}\".ipv6: delegated prefixes are not supported on WireGuard zones.", name));
+                String::new()
+            }
+            Some(ipv6) if ipv6.subnet.is_empty() => {
+                errors.push(format!("wireguard \"{}\".ipv6.subnet is required.", name));
+                String::new()
+            }
+            Some(ipv6) => {
+                if !config.wan.enable_ipv6 {
+                    errors.push(format!("wireguard \"{}\".ipv6 requires wan.enable_ipv6 = true.", name));
+                }
+                ipv6.subnet.clone()
+            }
+            None => String::new(),
+        };
+        let egress_allowed_ipv6 = wg.ipv6.as_ref()
+            .map(|v| Self::egress_list(&v.egress, &format!("wireguard \"{}\".ipv6.egress", name), errors))
+            .unwrap_or_default();
+
+        let (icmp_accept, icmpv6_accept, tcp_accept, udp_accept) = Self::zone_firewall(
+            wg.firewall.as_ref(),
+            enable_ipv4,
+            !subnet_ipv6.is_empty(),
+            &format!("wireguard \"{}\".firewall", name),
+            errors,
+        );
+
+        Some(Vlan {
+            id: 0,
+            name: name.to_string(),
+            label: format!("WireGuard {}", name),
+            chain_suffix: format!("wg_{}", name),
+            interface_name: name.to_string(),
+            subnet_ipv4,
+            subnet_ipv6,
+            ipv6_delegated: false,
+            egress_allowed_ipv4,
+            egress_allowed_ipv6,
+            egress_blocked: Vec::new(),
+            clients: Vec::new(),
+            egress_domains: Vec::new(),
+            log: None,
+            icmp_accept,
+            icmpv6_accept,
+            tcp_accept,
+            udp_accept,
+            tcp_forward: ForwardRouteList::new("").unwrap(),
+            udp_forward: ForwardRouteList::new("").unwrap(),
+            tcp_allow_inbound: InboundRuleList::new("").unwrap(),
+            udp_allow_inbound: InboundRuleList::new("").unwrap(),
+            tcp_allow_inter_vlan: InterVlanRuleList::new(),
+            udp_allow_inter_vlan: InterVlanRuleList::new(),
+            qos_class: None,
+            bandwidth_upload_kbit: None,
+            bandwidth_download_kbit: None,
+            iperf_enabled: false,
+            mdns_reflector: false,
+            upnp_ports: None,
+            routing: None,
+            egress_via: None,
+            dhcp_enabled: false,
+            dhcp_pool_start: String::new(),
+            dhcp_pool_end: String::new(),
+            dhcp_router: String::new(),
+            dhcp_dns: String::new(),
+            dhcpv6_enabled: false,
+            dhcpv6_pool_start: String::new(),
+            dhcpv6_pool_end: String::new(),
+        })
+    }
+
+    /// Parse a port forward list, resolving `@host` destinations.
+    fn forward_list(entries: &[String], objects: &Objects, context: &str, errors: &mut Vec<String>) -> ForwardRouteList {
+        match ForwardRouteList::parse(&entries.join(", "), objects) {
+            Ok(list) => list,
+            Err(e) => { errors.push(format!("{}: {}", context, e)); ForwardRouteList::new("").unwrap() }
+        }
+    }
+
+    /// Resolve `wan.forward` blocks into TCP and UDP routes.
+    fn forward_blocks(config: &HclConfig, objects: &Objects, errors: &mut Vec<String>) -> (Vec<ForwardRoute>, Vec<ForwardRoute>) {
+        let (mut tcp, mut udp) = (Vec::new(), Vec::new());
+        for (description, block) in &config.wan.forward {
+            let context = format!("wan.forward \"{}\"", description);
+            if description.trim().is_empty()
+                || description.len() > forward_route::DESCRIPTION_MAX_LEN
+                || description.chars().any(|c| c == '"' || c.is_control())
+            {
+                errors.push(format!(
+                    "{}: the label must be 1 to {} bytes, without double quotes or control characters.",
+                    context, forward_route::DESCRIPTION_MAX_LEN
+                ));
+            }
+            let (to_tcp, to_udp) = match block.protocol.as_str() {
+                "tcp" => (true, false),
+                "udp" => (false, true),
+                "tcp+udp" => (true, true),
+                other => {
+                    errors.push(format!("{}.protocol: '{}' must be \"tcp\", \"udp\" or \"tcp+udp\".", context, other));
+                    continue;
+                }
+            };
+            if block.ports.is_empty() {
+                errors.push(format!("{}.ports: must not be empty.", context));
+                continue;
+            }
+            if block.to_port.is_some() && (block.ports.len() > 1 || block.ports[0].contains('-')) {
+                errors.push(format!("{}.to_port: only a single port can be forwarded to another port.", context));
+                continue;
+            }
+            let to = match block.to.parse::<IpAddr>() {
+                Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+                _ => block.to.clone(),
+            };
+            let mut routes = Vec::new();
+            for ports in &block.ports {
+                let entry = match block.to_port {
+                    Some(port) => format!("{}:{}:{}", ports, to, port),
+                    None if ports.contains('-') => format!("{}:{}", ports, to),
+                    None => format!("{}:{}:{}", ports, to, ports),
+                };
+                match objects.resolve_forward(&entry).and_then(|e| e.parse::<ForwardRoute>()) {
+                    Ok(route) => routes.push(route),
+                    Err(e) => errors.push(format!("{}: {}", context, e)),
+                }
+            }
+            let Some(first) = routes.first() else { continue };
+
+            // Sources of the other family could never reach the destination
+            let sources = match objects.expand_addresses(&block.from) {
+                Ok(sources) => sources,
+                Err(e) => {
+                    errors.push(format!("{}.from: {}", context, e));
+                    continue;
+                }
+            };
+            let mut from = Vec::new();
+            for source in &sources {
+                match source.parse::<ipnetwork::IpNetwork>() {
+                    Ok(net) if net.is_ipv4() == first.is_ipv4() => from.push(net),
+                    Ok(_) => {}
+                    Err(_) => errors.push(format!("{}.from: invalid address or CIDR '{}'.", context, source)),
+                }
+            }
+            if !sources.is_empty() && from.is_empty() {
+                errors.push(format!("{}.from: no sources of the destination's address family.", context));
+                continue;
+            }
+
+            let windows = match block.during.as_deref().map(|name| objects.schedule(name)) {
+                None => vec![None],
+                Some(Ok(windows)) => windows.iter().cloned().map(Some).collect(),
+                Some(Err(e)) => {
+                    errors.push(format!("{}.during: {}", context, e));
+                    continue;
+                }
+            };
+            let log = match LogMode::for_rule(block.log.as_ref()) {
+                Ok(log) => log,
+                Err(e) => {
+                    errors.push(format!("{}.log: {}", context, e));
+                    continue;
+                }
+            };
+            for route in routes {
+                for window in &windows {
+                    let route = ForwardRoute {
+                        sources: from.clone(),
+                        description: Some(description.clone()),
+                        window: window.clone(),
+                        log,
+                        ..route.clone()
+                    };
+                    if to_tcp {
+                        tcp.push(route.clone());
+                    }
+                    if to_udp {
+                        udp.push(route);
+                    }
+                }
+            }
+        }
+        (tcp, udp)
+    }
+
+    /// Resolve a VLAN's `client` blocks. A block is keyed by a MAC address
+    /// or by the hostname of one of the VLAN's DHCP reservations.
+    fn client_rules(name: &str, vhcl: &hcl_config::VlanHclConfig, objects: &Objects, errors: &mut Vec<String>) -> Vec<ClientRule> {
+        let mut clients: Vec<_> = vhcl.client.iter().collect();
+        clients.sort_by_key(|(key, _)| key.as_str());
+        let mut rules: Vec<ClientRule> = Vec::new();
+        for (key, client) in clients {
+            let context = format!("vlan \"{}\".client \"{}\"", name, key);
+            let reservation = vhcl.dhcp.iter()
+                .flat_map(|d| &d.host)
+                .find(|h| h.hostname.as_deref() == Some(key.as_str()));
+            let mac = match reservation {
+                Some(host) => vlan::parse_mac(&host.mac),
+                None => vlan::parse_mac(key).map_err(|_| {
+                    "not a MAC address or the hostname of a DHCP reservation on this VLAN".to_string()
+                }),
+            };
+            let mac = match mac {
+                Ok(mac) => mac,
+                Err(e) => { errors.push(format!("{}: {}", context, e)); continue; }
+            };
+            if let Some(other) = rules.iter().find(|r| r.mac == mac) {
+                errors.push(format!("{}: {} already has a client block (\"{}\").", context, mac, other.name));
+                continue;
+            }
+            let cidrs = objects.expand_addresses(&client.egress)
+                .and_then(|e| CidrList::new(&e.join(", ")))
+                .unwrap_or_else(|e| { errors.push(format!("{}.egress: {}", context, e)); CidrList::new("").unwrap() });
+            let family = |ipv4: bool| cidrs.cidrs.iter()
+                .filter(|c| c.is_ipv4() == ipv4)
+                .copied()
+                .collect();
+            rules.push(ClientRule { name: key.clone(), mac, egress_ipv4: family(true), egress_ipv6: family(false) });
+        }
+        rules
+    }
+
+    /// Validate a zone's egress CIDR list.
+    fn egress_list(egress: &[String], context: &str, errors: &mut Vec<String>) -> Vec<IpNetwork> {
+        if egress.is_empty() {
+            return Vec::new();
+        }
+        match CidrList::new(&egress.join(", ")) {
+            Ok(list) => list.cidrs,
+            Err(e) => { errors.push(format!("{}: {}", context, e)); Vec::new() }
+        }
+    }
+
+    /// Parse a zone's `firewall` block into ICMP, ICMPv6, TCP and UDP accept lists.
+    fn zone_firewall(
+        firewall: Option<&hcl_config::FirewallConfig>,
+        enable_ipv4: bool,
+        has_ipv6: bool,
+        context: &str,
+        errors: &mut Vec<String>,
+    ) -> (Vec<IcmpType>, Vec<Icmpv6Type>, Vec<u16>, Vec<u16>) {
+        let Some(fw) = firewall else {
+            return (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        };
+        let icmp = if enable_ipv4 {
+            fw.icmp_accept.iter()
+                .filter_map(|s| IcmpType::new(s).map_err(|e| errors.push(format!("{}: {}", context, e))).ok())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let icmpv6 = if has_ipv6 {
+            fw.icmpv6_accept.iter()
+                .filter_map(|s| Icmpv6Type::new(s).map_err(|e| errors.push(format!("{}: {}", context, e))).ok())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        (icmp, icmpv6, fw.tcp_accept.clone(), fw.udp_accept.clone())
+    }
 }
 
 #[derive(Template)]
@@ -604,45 +1209,136 @@ struct QosTemplate {
     default_upload_kbit: u32,
 }
 
-pub fn validate_nftables_config(config: &str) -> Result<(), String> {
-    let output = Command::new("nft")
-        .arg("-c")
-        .arg("-f")
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .and_then(|mut child| {
-            if let Some(stdin) = child.stdin.as_mut() {
-                use std::io::Write;
-                stdin.write_all(config.as_bytes())?;
+impl QosTemplate {
+    /// Build the QoS script template, or `None` when no `qos` block is set.
+    fn from_hcl(config: &HclConfig) -> Result<Option<Self>, Vec<String>> {
+        let Some(qos_hcl) = &config.qos else {
+            return Ok(None);
+        };
+        let mut errors = Vec::new();
+        // Shape the primary uplink's IP interface (the PPP interface for PPPoE)
+        let wan_name = match WanUplinks::from_hcl(config) {
+            Ok(wan) => wan.primary().interface_name.clone(),
+            Err(e) => {
+                errors.extend(e);
+                config.interfaces.wan_name().to_string()
             }
-            child.wait_with_output()
-        })
-        .map_err(|e| format!("Failed to run nft command: {}", e))?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(String::from_utf8_lossy(&output.stderr).to_string())
-    }
-}
+        };
+        let interface_wan = Interface::new(&wan_name)
+            .unwrap_or_else(|e| { errors.push(e); Interface::new("eth0").unwrap() });
 
-/// Read and parse an HCL config file, exiting on error.
-fn load_hcl_config(path: &str) -> HclConfig {
-    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
-        eprintln!("Error: Failed to read {}: {}", path, e);
-        exit(1);
-    });
-    parse_hcl(&contents).unwrap_or_else(|e| {
-        eprintln!("Error: {}", e);
-        exit(1);
-    })
-}
+        let qos_config = match QosConfig::from_hcl(qos_hcl) {
+            Ok(qos_config) => qos_config,
+            Err(qos_errors) => {
+                errors.extend(qos_errors);
+                return Err(errors);
+            }
+        };
 
-#[cfg(feature = "nixos")]
-fn run_maintenance() {
+        // Collect per-VLAN bandwidth limits
+        let mut vlan_upload_limits = Vec::new();
+        let mut vlan_downloads = Vec::new();
+        let trunk_name = config.interfaces.trunk_name();
+        let vlan_aware = config.vlan_aware_switch;
+        let mut entries: Vec<_> = config.vlan.iter().collect();
+        entries.sort_by_key(|(_, v)| v.id);
+        for (name, vhcl) in &entries {
+            if let Some(bw) = &vhcl.bandwidth {
+                if let Some(up) = bw.upload_mbps {
+                    if up == 0 {
+                        errors.push(format!("vlan \"{}\".bandwidth.upload_mbps must be greater than 0.", name));
+                    } else {
+                        vlan_upload_limits.push(qos::QosVlanBandwidth {
+                            vlan_id: vhcl.id,
+                            kbit: up * 1000,
+                        });
+                    }
+                }
+                if let Some(down) = bw.download_mbps {
+                    if down == 0 {
+                        errors.push(format!("vlan \"{}\".bandwidth.download_mbps must be greater than 0.", name));
+                    } else {
+                        let iface = if vhcl.id == 1 && !vlan_aware {
+                            trunk_name.to_string()
+                        } else {
+                            name.to_string()
+                        };
+                        vlan_downloads.push(qos::QosVlanDownload {
+                            interface_name: iface,
+                            kbit: down * 1000,
+                        });
+                    }
+                }
+            }
+        }
+
+        let upload_bw_sum: u32 = vlan_upload_limits.iter().map(|v| v.kbit).sum();
+        let default_upload_kbit = if !vlan_upload_limits.is_empty() && upload_bw_sum >= qos_config.upload_kbit {
+            errors.push("Sum of per-VLAN bandwidth.upload_mbps exceeds total qos.upload_mbps (after shave).".to_string());
+            0
+        } else if vlan_upload_limits.is_empty() {
+            qos_config.upload_kbit
+        } else {
+            qos_config.upload_kbit - upload_bw_sum
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Some(QosTemplate {
+            interface_wan,
+            upload_kbit: qos_config.upload_kbit,
+            download_kbit: qos_config.download_kbit,
+            vlan_upload_limits,
+            vlan_downloads,
+            default_upload_kbit,
+        }))
+    }
+}
+
+pub fn validate_nftables_config(config: &str, json: bool) -> Result<(), String> {
+    let mut nft = Command::new("nft");
+    if json {
+        nft.arg("-j");
+    }
+    let output = nft
+        .arg("-c")
+        .arg("-f")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            if let Some(stdin) = child.stdin.as_mut() {
+                use std::io::Write;
+                stdin.write_all(config.as_bytes())?;
+            }
+            child.wait_with_output()
+        })
+        .map_err(|e| format!("Failed to run nft command: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).to_string())
+    }
+}
+
+/// Read and parse an HCL config file, exiting on error.
+fn load_hcl_config(path: &str) -> HclConfig {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to read {}: {}", path, e);
+        exit(1);
+    });
+    parse_hcl(&contents).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        exit(1);
+    })
+}
+
+#[cfg(feature = "nixos")]
+fn run_maintenance() {
     use std::process::{exit, Command};
 
     let script = include_str!("../nix/scripts/nifty-maintenance.sh");
@@ -669,100 +1365,49 @@ fn app() {
         #[cfg(feature = "nixos")]
         Commands::Maintenance => run_maintenance(),
         #[cfg(feature = "nixos")]
+        Commands::Apply { config, confirm_timeout } => {
+            if let Err(e) = apply::apply(&config, confirm_timeout) {
+                eprintln!("Error: {}", e);
+                exit(1);
+            }
+        }
+        #[cfg(feature = "nixos")]
+        Commands::Confirm { config } => {
+            if let Err(e) = apply::confirm(&config) {
+                eprintln!("Error: {}", e);
+                exit(1);
+            }
+        }
+        #[cfg(feature = "nixos")]
+        Commands::Rollback { config } => {
+            if let Err(e) = apply::rollback(&config) {
+                eprintln!("Error: {}", e);
+                exit(1);
+            }
+        }
+        #[cfg(feature = "nixos")]
         Commands::PveSetup { pve_host } => pve_setup::run(&pve_host),
         Commands::Version => {
             println!("nifty-filter {} ({})", env!("CARGO_PKG_VERSION"), option_env!("GIT_SHA").unwrap_or("unknown"));
         }
         Commands::Qos { config } => {
             let hcl_config = load_hcl_config(&config);
-
-            match &hcl_config.qos {
-                Some(qos_hcl) => {
-                    let mut errors = Vec::new();
-                    let interface_wan = Interface::new(&hcl_config.interfaces.wan_name())
-                        .unwrap_or_else(|e| { errors.push(e); Interface::new("eth0").unwrap() });
-
-                    match QosConfig::from_hcl(qos_hcl) {
-                        Ok(qos_config) => {
-                            // Collect per-VLAN bandwidth limits
-                            let mut vlan_upload_limits = Vec::new();
-                            let mut vlan_downloads = Vec::new();
-                            let trunk_name = hcl_config.interfaces.trunk_name();
-                            let vlan_aware = hcl_config.vlan_aware_switch;
-                            let mut entries: Vec<_> = hcl_config.vlan.iter().collect();
-                            entries.sort_by_key(|(_, v)| v.id);
-                            for (name, vhcl) in &entries {
-                                if let Some(bw) = &vhcl.bandwidth {
-                                    if let Some(up) = bw.upload_mbps {
-                                        if up == 0 {
-                                            errors.push(format!("vlan \"{}\".bandwidth.upload_mbps must be greater than 0.", name));
-                                        } else {
-                                            vlan_upload_limits.push(qos::QosVlanBandwidth {
-                                                vlan_id: vhcl.id,
-                                                kbit: up * 1000,
-                                            });
-                                        }
-                                    }
-                                    if let Some(down) = bw.download_mbps {
-                                        if down == 0 {
-                                            errors.push(format!("vlan \"{}\".bandwidth.download_mbps must be greater than 0.", name));
-                                        } else {
-                                            let iface = if vhcl.id == 1 && !vlan_aware {
-                                                trunk_name.to_string()
-                                            } else {
-                                                name.to_string()
-                                            };
-                                            vlan_downloads.push(qos::QosVlanDownload {
-                                                interface_name: iface,
-                                                kbit: down * 1000,
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-
-                            let upload_bw_sum: u32 = vlan_upload_limits.iter().map(|v| v.kbit).sum();
-                            let default_upload_kbit = if !vlan_upload_limits.is_empty() && upload_bw_sum >= qos_config.upload_kbit {
-                                errors.push("Sum of per-VLAN bandwidth.upload_mbps exceeds total qos.upload_mbps (after shave).".to_string());
-                                0
-                            } else if vlan_upload_limits.is_empty() {
-                                qos_config.upload_kbit
-                            } else {
-                                qos_config.upload_kbit - upload_bw_sum
-                            };
-
-                            if !errors.is_empty() {
-                                for err in errors {
-                                    eprintln!("Error: {}", err);
-                                }
-                                exit(1);
-                            }
-                            let tmpl = QosTemplate {
-                                interface_wan,
-                                upload_kbit: qos_config.upload_kbit,
-                                download_kbit: qos_config.download_kbit,
-                                vlan_upload_limits,
-                                vlan_downloads,
-                                default_upload_kbit,
-                            };
-                            println!("{}", tmpl.render().unwrap());
-                        }
-                        Err(qos_errors) => {
-                            for err in qos_errors {
-                                eprintln!("Error: {}", err);
-                            }
-                            exit(1);
-                        }
+            match QosTemplate::from_hcl(&hcl_config) {
+                Ok(Some(tmpl)) => println!("{}", tmpl.render().unwrap()),
+                Ok(None) => eprintln!("QoS not configured (no qos block in config), skipping."),
+                Err(errors) => {
+                    for err in errors {
+                        eprintln!("Error: {}", err);
                     }
-                }
-                None => {
-                    eprintln!("QoS not configured (no qos block in config), skipping.");
+                    exit(1);
                 }
             }
         }
         Commands::Nftables {
             config,
             validate,
+            json,
+            keep_domain_sets,
             verbose,
         } => {
             if verbose {
@@ -773,11 +1418,22 @@ fn app() {
             let hcl_config = load_hcl_config(&config);
             info!("Loaded configuration from file: {}", config);
 
-            match RouterTemplate::from_hcl(&hcl_config) {
+            match Router::from_hcl(&hcl_config) {
                 Ok(router) => {
-                    let text = format::reduce_blank_lines(&router.render().unwrap());
+                    // Checks `validate` reports as errors only warn here, so
+                    // a config that loaded before keeps loading
+                    for diagnostic in validate::check(&hcl_config) {
+                        eprintln!("Warning: {}", diagnostic);
+                    }
+                    let text = if json {
+                        serde_json::to_string_pretty(&router.ruleset().to_json()).unwrap()
+                    } else if keep_domain_sets {
+                        format!("{}\n{}", router.render(), vlan::carry_domain_sets(&router.domain_sets()))
+                    } else {
+                        router.render()
+                    };
                     if validate {
-                        match validate_nftables_config(&text) {
+                        match validate_nftables_config(&text, json) {
                             Ok(_valid) => {}
                             Err(e) => {
                                 error!("Error validating nftables config: {}", e);
@@ -785,7 +1441,7 @@ fn app() {
                             }
                         }
                     }
-                    println!("{}", text)
+                    println!("{}", text.trim_end())
                 }
                 Err(errors) => {
                     for err in errors {
@@ -795,6 +1451,124 @@ fn app() {
                 }
             }
         }
+        Commands::NftablesTeardown { lockdown } => {
+            let ruleset = if lockdown { ruleset::lockdown() } else { ruleset::teardown() };
+            println!("{}", ruleset.to_string().trim_end());
+        }
+        Commands::WanMonitor { config } => {
+            let hcl_config = load_hcl_config(&config);
+            let wan = WanUplinks::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                for err in errors {
+                    eprintln!("Error: {}", err);
+                }
+                exit(1);
+            });
+            if !wan.is_multi() {
+                eprintln!("Only one WAN uplink configured, nothing to monitor.");
+                return;
+            }
+            let interval = std::time::Duration::from_secs(hcl_config.wan.health_interval.unwrap_or(10));
+            wan::run_monitor(&wan, interval, hcl_config.wan.enable_ipv6);
+        }
+        Commands::PdSync { config, watch, interval } => {
+            let hcl_config = load_hcl_config(&config);
+            if pd::delegated_vlans(&hcl_config).is_empty() {
+                eprintln!("No VLANs use a delegated IPv6 prefix, nothing to sync.");
+                return;
+            }
+            let router = Router::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                for err in errors {
+                    eprintln!("Error: {}", err);
+                }
+                exit(1);
+            });
+            let watch = watch.then(|| std::time::Duration::from_secs(interval));
+            pd::sync(&hcl_config, &router.ruleset(), watch);
+        }
+        Commands::BlocklistSync { config, watch, cached, cache_dir } => {
+            let hcl_config = load_hcl_config(&config);
+            let lists = blocklist::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                for err in errors {
+                    eprintln!("Error: {}", err);
+                }
+                exit(1);
+            });
+            if lists.is_empty() {
+                eprintln!("No blocklists configured, nothing to sync.");
+                return;
+            }
+            let cache_dir = std::path::Path::new(&cache_dir);
+            if cached {
+                blocklist::load_cached(&lists, &blocklist::SystemApplier, cache_dir);
+            } else {
+                blocklist::sync(&lists, &blocklist::SystemFetcher, &blocklist::SystemApplier, cache_dir, watch);
+            }
+        }
+        Commands::Nflog { config, output, keep } => {
+            let hcl_config = load_hcl_config(&config);
+            let Some(group) = hcl_config.logging.as_ref().and_then(|l| l.nflog_group) else {
+                eprintln!("logging.nflog_group is not set, drops go to the kernel log.");
+                return;
+            };
+            // Drops carry their rule's log prefix; name them by its comment
+            let comments = Router::from_hcl(&hcl_config).map(|r| nflog::rule_comments(&r.ruleset())).unwrap_or_default();
+            if let Err(e) = nflog::collect(group, std::path::Path::new(&output), keep.max(1), &comments) {
+                eprintln!("Error: nflog group {}: {}", group, e);
+                exit(1);
+            }
+        }
+        Commands::Plan { config, against, detailed_exitcode } => {
+            let new_config = load_hcl_config(&config);
+            let current_config = load_hcl_config(&against);
+            match plan::plan(&current_config, &new_config) {
+                Ok(plan) => {
+                    print!("{}", plan.report);
+                    if detailed_exitcode && plan.changes > 0 {
+                        exit(2);
+                    }
+                }
+                Err(errors) => {
+                    for err in errors {
+                        eprintln!("Error: {}", err);
+                    }
+                    exit(1);
+                }
+            }
+        }
+        Commands::Validate { config, format } => {
+            let contents = std::fs::read_to_string(&config).unwrap_or_else(|e| {
+                eprintln!("Error: Failed to read {}: {}", config, e);
+                exit(1);
+            });
+            let diagnostics = validate::validate_source(&contents);
+            if format == "json" {
+                let report = validate::Report {
+                    file: &config,
+                    valid: diagnostics.is_empty(),
+                    diagnostics: &diagnostics,
+                };
+                println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            } else {
+                print!("{}", validate::format_text(&config, &diagnostics));
+            }
+            if !diagnostics.is_empty() {
+                exit(1);
+            }
+        }
+        Commands::Wireguard { what } => match what {
+            WireguardCommands::PeerConfig { config, zone, peer, private_key_file, qr } => {
+                let hcl_config = load_hcl_config(&config);
+                let result = wireguard::peer_config(&hcl_config, &zone, &peer, private_key_file.as_deref())
+                    .and_then(|out| if qr { wireguard::qr_code(&out) } else { Ok(out) });
+                match result {
+                    Ok(out) => print!("{}", out),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        exit(1);
+                    }
+                }
+            }
+        },
         Commands::Hostname { config } => {
             let hcl_config = load_hcl_config(&config);
             println!(
@@ -806,6 +1580,29 @@ fn app() {
             let hcl_config = load_hcl_config(&config);
             let value = match key.as_str() {
                 "wan-name" => Some(hcl_config.interfaces.wan_name().to_string()),
+                "wan-interfaces" => {
+                    let wan = WanUplinks::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                        for err in errors {
+                            eprintln!("Error: {}", err);
+                        }
+                        exit(1);
+                    });
+                    let names: Vec<String> = wan.uplinks.iter().map(|u| u.device.clone()).collect();
+                    Some(names.join(" "))
+                },
+                "pppoe-uplinks" => {
+                    let wan = WanUplinks::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                        for err in errors {
+                            eprintln!("Error: {}", err);
+                        }
+                        exit(1);
+                    });
+                    let names: Vec<String> = wan.uplinks.iter()
+                        .filter(|u| u.pppoe.is_some())
+                        .map(|u| u.name.clone())
+                        .collect();
+                    Some(names.join(" "))
+                },
                 "trunk-name" => Some(hcl_config.interfaces.trunk_name().to_string()),
                 "mgmt-name" => hcl_config.interfaces.mgmt_name().map(|s| s.to_string()),
                 "wan-mac" => hcl_config.interfaces.wan.mac.clone(),
@@ -856,6 +1653,16 @@ fn app() {
                     }
                     if ifaces.is_empty() { None } else { Some(ifaces.join(" ")) }
                 },
+                "upnp-interfaces" => {
+                    let router = Router::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                        for err in errors {
+                            eprintln!("Error: {}", err);
+                        }
+                        exit(1);
+                    });
+                    let ifaces: Vec<_> = router.upnp_zones().map(|(vlan, _)| vlan.interface_name.clone()).collect();
+                    if ifaces.is_empty() { None } else { Some(ifaces.join(" ")) }
+                },
                 "switch-router-ip" => hcl_config.switch.as_ref().and_then(|s| s.router_ip.clone()),
                 "switch-mgmt-iface" => hcl_config.switch.as_ref().and_then(|s| s.mgmt_iface.clone()),
                 "dashboard-tls-enabled" => Some(hcl_config.dashboard_tls.is_some().to_string()),
@@ -903,6 +1710,13 @@ fn app() {
                     exit(1);
                 }
             }
+            GenerateCommands::Ppp { config, output_dir } => {
+                let hcl_config = load_hcl_config(&config);
+                if let Err(e) = generate::generate_ppp(&hcl_config, &output_dir) {
+                    eprintln!("Error: {}", e);
+                    exit(1);
+                }
+            }
             GenerateCommands::Dnsmasq { config, output } => {
                 let hcl_config = load_hcl_config(&config);
                 if let Err(e) = generate::generate_dnsmasq(&hcl_config, &output) {
@@ -923,6 +1737,29 @@ fn app() {
                     exit(1);
                 }
             }
+            GenerateCommands::Miniupnpd { config, output } => {
+                let hcl_config = load_hcl_config(&config);
+                let router = Router::from_hcl(&hcl_config).unwrap_or_else(|errors| {
+                    for err in errors {
+                        eprintln!("Error: {}", err);
+                    }
+                    exit(1);
+                });
+                let Some(conf) = router.miniupnpd_conf() else {
+                    eprintln!("Error: No VLANs have upnp enabled.");
+                    exit(1);
+                };
+                if let Some(parent) = std::path::Path::new(&output).parent() {
+                    if let Err(e) = std::fs::create_dir_all(parent) {
+                        eprintln!("Error: Cannot create {}: {}", parent.display(), e);
+                        exit(1);
+                    }
+                }
+                if let Err(e) = std::fs::write(&output, conf) {
+                    eprintln!("Error: Cannot write {}: {}", output, e);
+                    exit(1);
+                }
+            }
         },
     }
 }
@@ -986,8 +1823,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // VLAN 1 uses bare trunk interface
         assert!(rendered.contains(r#"iifname "trunk""#));
@@ -1031,8 +1868,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // VLAN sub-interfaces use names from HCL keys
         assert!(rendered.contains(r#"iifname "trusted""#));
@@ -1070,7 +1907,7 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let result = RouterTemplate::from_hcl(&config);
+        let result = Router::from_hcl(&config);
         let errors = match result {
             Err(e) => e,
             Ok(_) => panic!("expected error"),
@@ -1108,8 +1945,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // Custom names from HCL keys
         assert!(rendered.contains(r#"iifname "trusted""#));
@@ -1147,8 +1984,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         assert!(rendered.contains(r#"iifname "trusted" jump input_vlan_10"#));
         assert!(rendered.contains(r#"ip saddr 10.10.0.1/24 tcp dport { 22 }"#));
@@ -1191,8 +2028,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // 2-tuple: no saddr filter
         assert!(rendered.contains(
@@ -1212,120 +2049,1422 @@ mod tests {
     }
 
     #[test]
-    fn test_qos_disabled_no_mangle_table() {
+    fn test_named_objects() {
         let hcl = r#"
             interfaces {
                 trunk { name = "trunk" }
                 wan   { name = "wan" }
             }
-            wan { enable_ipv4 = true }
-            vlan "lan" {
-                id = 1
-                ipv4 { subnet = "192.168.10.1/24" }
-            }
-        "#;
-        let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
-
-        assert!(!rendered.contains("table inet mangle"));
-        assert!(!rendered.contains("dscp set"));
-        assert!(!rendered.contains("flowtable"));
-    }
-
-    #[test]
-    fn test_qos_enabled_dscp_marking() {
-        let hcl = r#"
-            interfaces {
-                trunk { name = "trunk" }
-                wan   { name = "wan" }
+            wan {
+                enable_ipv4 = true
+                enable_ipv6 = true
+                tcp_forward = ["443:@nas:443"]
             }
-            wan { enable_ipv4 = true }
             vlan_aware_switch = true
+            host "nas" {
+                ipv4 = "10.99.40.5"
+                ipv6 = "fd00:40::5"
+            }
+            host "laptop" { ipv4 = "10.99.10.50" }
+            group "admins" { members = ["laptop", "10.99.10.64/26"] }
+            service "web" { tcp = [80, 443] }
             qos {
-                upload_mbps = 20
+                upload_mbps   = 20
                 download_mbps = 300
+                overrides { voice = ["@laptop"] }
             }
             vlan "trusted" {
                 id = 10
-                ipv4 {
-                    subnet = "10.10.0.1/24"
-                    egress = ["0.0.0.0/0"]
-                }
-                firewall {
-                    tcp_accept = [22]
-                    udp_accept = [67, 68]
-                }
-                qos_class = "voice"
+                ipv4 { subnet = "10.99.10.1/24" }
+                ipv6 { subnet = "fd00:10::1/64" }
             }
-            vlan "iot" {
-                id = 20
-                ipv4 { subnet = "10.20.0.1/24" }
-                firewall {
-                    tcp_accept = []
-                    udp_accept = [67, 68]
+            vlan "lab" {
+                id = 40
+                ipv4 { subnet = "10.99.40.1/24" }
+                ipv6 { subnet = "fd00:40::1/64" }
+                allow_inbound_tcp = ["@web:@nas"]
+                allow_from "trusted" {
+                    tcp = ["@admins:@nas:@web", "@nas:22"]
                 }
-                qos_class = "bulk"
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
-        assert!(rendered.contains("table inet mangle"));
-        // VLAN 10 marked as voice (EF)
-        assert!(rendered.contains(r#"oifname "wan" ip saddr 10.10.0.1/24 ip dscp set ef"#));
-        // VLAN 20 marked as bulk (CS1)
-        assert!(rendered.contains(r#"oifname "wan" ip saddr 10.20.0.1/24 ip dscp set cs1"#));
+        // Objects become named sets
+        assert!(rendered.contains("set host_nas_v4 {\n        type ipv4_addr\n        flags interval\n        elements = { 10.99.40.5 }"));
+        assert!(rendered.contains("set group_admins_v4 {"));
+        assert!(rendered.contains("elements = { 10.99.10.50, 10.99.10.64/26 }"));
+        assert!(rendered.contains("set service_web_tcp {\n        type inet_service\n        elements = { 80, 443 }"));
+
+        // Rules reference the sets, one per address family the objects share
+        assert!(rendered.contains(
+            r#"iifname "trusted" oifname "lab" ip saddr @group_admins_v4 ip daddr @host_nas_v4 tcp dport @service_web_tcp accept"#
+        ));
+        assert!(!rendered.contains("ip6 saddr @group_admins_v4"));
+        assert!(rendered.contains(r#"iifname "trusted" oifname "lab" ip6 daddr @host_nas_v6 tcp dport 22 accept"#));
+        assert!(rendered.contains(r#"oifname "lab" ip daddr @host_nas_v4 tcp dport @service_web_tcp accept"#));
+        assert!(rendered.contains(r#"oifname "lab" ip6 daddr @host_nas_v6 tcp dport @service_web_tcp accept"#));
+
+        // DNAT and QoS need literal addresses
+        assert!(rendered.contains("tcp dport 443 dnat to 10.99.40.5:443"));
+        assert!(rendered.contains("ip saddr { 10.99.10.50/32 } ip dscp set ef"));
     }
 
     #[test]
-    fn test_qos_override_rules() {
+    fn test_named_object_errors() {
         let hcl = r#"
             interfaces {
                 trunk { name = "trunk" }
                 wan   { name = "wan" }
             }
-            wan { enable_ipv4 = true }
-            qos {
-                upload_mbps = 20
-                download_mbps = 300
-                overrides {
-                    voice = ["192.168.10.50", "192.168.10.51"]
-                    bulk  = ["192.168.10.0/24"]
-                }
+            wan {
+                enable_ipv4 = true
+                tcp_forward = ["80:@cams:80"]
             }
-            vlan "lan" {
-                id = 1
-                ipv4 { subnet = "192.168.10.1/24" }
+            group "cams" { members = ["10.99.20.0/28"] }
+            service "web" { tcp = [443] }
+            vlan "lab" {
+                id = 40
+                ipv4 { subnet = "10.99.40.1/24" }
+                allow_from "lab" {
+                    udp = ["@cams:@web"]
+                    tcp = ["@tv:80"]
+                }
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
-
-        assert!(rendered.contains("192.168.10.50/32, 192.168.10.51/32"));
-        assert!(rendered.contains("ip dscp set ef"));
-        assert!(rendered.contains("192.168.10.0/24"));
-        assert!(rendered.contains("ip dscp set cs1"));
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert!(errors.contains(&"wan.tcp_forward: '@cams' is a group; forwards need a single host".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"vlan \"lab\".allow_from \"lab\".tcp: Invalid destination in '@tv:80': unknown host or group '@tv'".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"vlan \"lab\".allow_from \"lab\".udp: Invalid port in '@cams:@web': service '@web' has no udp ports".to_string()), "{:?}", errors);
     }
 
     #[test]
-    fn test_qos_no_flowtable() {
+    fn test_schedules() {
         let hcl = r#"
             interfaces {
                 trunk { name = "trunk" }
                 wan   { name = "wan" }
             }
-            wan { enable_ipv4 = true }
-            vlan "lan" {
-                id = 1
-                ipv4 { subnet = "192.168.10.1/24" }
-            }
+            wan {
+                enable_ipv4 = true
+                tcp_forward = ["25565:10.99.30.5:25565 during weekends"]
+            }
+            vlan_aware_switch = true
+            schedule "school-nights" {
+                days = ["sun", "mon", "tue", "wed", "thu"]
+                from = "22:00"
+                to   = "06:30"
+            }
+            schedule "weekends" { days = ["sat", "sun"] }
+            vlan "trusted" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+            vlan "kids" {
+                id = 30
+                ipv4 {
+                    subnet = "10.99.30.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+                egress_blocked = ["school-nights"]
+                allow_from "trusted" {
+                    tcp = ["10.99.30.5:22 during weekends"]
+                }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        // The cutoff comes before established/related so open connections drop too
+        let block = r#"iifname "kids" oifname "wan" meta day { "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday" } meta hour "22:00"-"23:59:59" reject with icmpx type admin-prohibited comment "nf:Block VLAN 30 egress during school-nights""#;
+        let morning = r#"iifname "kids" oifname "wan" meta day { "Monday", "Tuesday", "Wednesday", "Thursday", "Friday" } meta hour "00:00"-"06:30" reject"#;
+        let forward = rendered.find("chain forward {").unwrap();
+        let established = forward + rendered[forward..].find("ct state established,related accept").unwrap();
+        assert!(rendered.find(block).unwrap() < established);
+        assert!(rendered.find(morning).unwrap() < established);
+
+        // Scheduled rules carry the time match
+        assert!(rendered.contains(
+            r#"iifname "trusted" oifname "kids" ip daddr 10.99.30.5 tcp dport 22 meta day { "Sunday", "Saturday" } accept"#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "wan" tcp dport 25565 meta day { "Sunday", "Saturday" } dnat to 10.99.30.5:25565"#
+        ));
+    }
+
+    #[test]
+    fn test_schedule_errors() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            schedule "nights" {
+                days = ["mon", "someday"]
+                from = "22:00"
+                to   = "06:00"
+            }
+            vlan "kids" {
+                id = 30
+                ipv4 { subnet = "10.99.30.1/24" }
+                egress_blocked = ["bedtime"]
+                allow_inbound_tcp = ["22:10.99.30.5 during bedtime"]
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert!(errors.contains(&"schedule \"nights\": Invalid day: 'someday'.".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"vlan \"kids\".egress_blocked: unknown schedule 'bedtime'".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"vlan \"kids\".allow_inbound_tcp: unknown schedule 'bedtime'".to_string()), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_inbound_suffix_rules() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                enable_ipv4 = true
+                enable_ipv6 = true
+            }
+            vlan_aware_switch = true
+            vlan "lab" {
+                id = 40
+                ipv4 { subnet = "10.99.40.1/24" }
+                ipv6 { subnet = "fd00:40::1/64" }
+                allow_inbound_tcp = ["443:[::abcd:50]/-64", "22:[eui64:52:54:00:12:34:56]"]
+                allow_inbound_udp = ["51820:[::7]/-16"]
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+        assert!(rendered.contains(
+            r#"iifname "wan" oifname "lab" ip6 daddr & ::ffff:ffff:ffff:ffff == ::abcd:50 tcp dport 443 accept"#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "wan" oifname "lab" ip6 daddr & ::ffff:ffff:ffff:ffff == ::5054:ff:fe12:3456 tcp dport 22 accept"#
+        ));
+        assert!(rendered.contains(r#"iifname "wan" oifname "lab" ip6 daddr & ::ffff == ::7 udp dport 51820 accept"#));
+
+        let bad = parse_hcl(&hcl.replace("[::7]/-16", "[::1:7]/-16")).unwrap();
+        let errors = Router::from_hcl(&bad).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["vlan \"lab\".allow_inbound_udp: Interface identifier ::1:7 in '51820:[::1:7]/-16' has bits outside its last 16."]
+        );
+    }
+
+    #[test]
+    fn test_routes_and_policy_routing() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            route "lab-behind-r2" {
+                destination = "192.168.50.0/24"
+                gateway     = "10.99.40.2"
+            }
+            route "via-vpn" {
+                destination = "0.0.0.0/0"
+                gateway     = "10.99.40.254"
+                table       = 200
+            }
+            vlan "guest" {
+                id = 30
+                ipv4 {
+                    subnet = "10.99.30.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+                routing_table = 200
+                fwmark        = 30
+            }
+            vlan "lab" {
+                id = 40
+                ipv4 { subnet = "10.99.40.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+
+        // The gateway's VLAN reaches the routed network through the router
+        assert!(rendered.contains(
+            r#"iifname "lab" oifname "lab" ip daddr 192.168.50.0/24 accept comment "nf:Allow VLAN 40 to routed network lab-behind-r2""#
+        ));
+        // Guest egress leaves via the gateway on the lab VLAN, but not to the lab itself
+        assert!(rendered.contains(
+            r#"ip saddr 10.99.30.1/24 ip daddr { 0.0.0.0/0 } ip daddr != 10.99.40.1/24 oifname "lab" accept comment "nf:Allow egress via route via-vpn""#
+        ));
+        // Guest connections are marked for table 200
+        assert!(rendered.contains("table inet nifty_wan_routing {"));
+        assert!(rendered.contains(r#"iifname "guest" ct state new ct mark set 30 comment "nf:Route VLAN 30 by table 200""#));
+        assert!(rendered.contains(r#"comment "nf:Route connection by its mark""#));
+        assert!(!rendered.contains("Pin inbound connections"));
+
+        let unused = parse_hcl(&hcl.replace("routing_table = 200", "routing_table = 300")).unwrap();
+        let errors = Router::from_hcl(&unused).err().unwrap();
+        assert_eq!(errors, vec!["vlan \"guest\".routing_table: no route has table = 300."]);
+    }
+
+    #[test]
+    fn test_vpn_egress() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            vlan "privacy" {
+                id = 50
+                ipv4 {
+                    subnet = "10.99.50.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+                egress_via = "vpn-provider"
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 {
+                    subnet = "10.99.10.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+            }
+            wireguard_client "vpn-provider" {
+                private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+                address          = ["10.64.0.2/32"]
+                peer {
+                    public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+                    endpoint   = "vpn.example.net:51820"
+                }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+
+        // Kill switch: privacy traffic never leaves by the WAN, even for
+        // connections established through the tunnel
+        let kill_switch = r#"iifname "privacy" oifname "wan" ct direction original drop comment "nf:Drop VLAN 50 egress to WAN outside vpn-provider""#;
+        assert!(rendered.contains(kill_switch));
+        let forward = &rendered[rendered.find("chain forward {").unwrap()..];
+        assert!(forward.find(kill_switch) < forward.find("ct state established,related accept"));
+        // Egress is allowed through the tunnel instead
+        assert!(rendered.contains(
+            r#"ip saddr 10.99.50.1/24 ip daddr { 0.0.0.0/0 } oifname "vpn-provider" accept comment "nf:Allow IPv4 egress via vpn-provider""#
+        ));
+        assert!(rendered.contains(
+            r#"ip saddr 10.99.10.1/24 ip daddr { 0.0.0.0/0 } oifname "wan" accept comment "nf:Allow IPv4 egress to WAN""#
+        ));
+        assert!(rendered.contains(r#"oifname "vpn-provider" masquerade comment "nf:Masquerade egress via vpn-provider (NAT)""#));
+        // Privacy connections are marked for the tunnel's table
+        assert!(rendered.contains(r#"iifname "privacy" ct state new ct mark set 200 comment "nf:Route VLAN 50 by table 200""#));
+
+        let unknown = parse_hcl(&hcl.replace(r#"egress_via = "vpn-provider""#, r#"egress_via = "vpn""#)).unwrap();
+        let errors = Router::from_hcl(&unknown).err().unwrap();
+        assert_eq!(errors, vec!["vlan \"privacy\".egress_via: no wireguard_client \"vpn\"."]);
+    }
+
+    #[test]
+    fn test_client_rules() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                enable_ipv4 = true
+                enable_ipv6 = true
+            }
+            vlan_aware_switch = true
+            host "cloud" { ipv4 = "52.1.2.3" }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.99.20.1/24" }
+                ipv6 { subnet = "fd00:20::1/64" }
+                dhcp {
+                    pool_start = "10.99.20.100"
+                    pool_end   = "10.99.20.250"
+                    router     = "10.99.20.1"
+                    dns        = "10.99.20.1"
+                    host {
+                        mac      = "AA:BB:CC:DD:EE:01"
+                        ip       = "10.99.20.10"
+                        hostname = "thermostat"
+                    }
+                }
+                client "thermostat" {
+                    egress = ["@cloud", "2600:1f18::/32"]
+                }
+                client "aa:bb:cc:dd:ee:02" {}
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        let chain = &rendered[rendered.find("chain forward_vlan_20 {").unwrap()..];
+        let chain = &chain[..chain.find("\n    }").unwrap()];
+        assert!(chain.contains(
+            r#"ether saddr aa:bb:cc:dd:ee:01 ip daddr { 52.1.2.3/32 } oifname "wan" accept comment "nf:Allow thermostat egress to WAN""#
+        ), "{}", chain);
+        assert!(chain.contains(r#"ether saddr aa:bb:cc:dd:ee:01 ip6 daddr { 2600:1f18::/32 } oifname "wan" accept"#));
+        assert!(chain.contains(r#"ether saddr aa:bb:cc:dd:ee:01 oifname "wan" reject"#));
+        // No egress list: no internet at all for that device
+        assert!(!chain.contains("ether saddr aa:bb:cc:dd:ee:02 ip"));
+        assert!(chain.contains(r#"ether saddr aa:bb:cc:dd:ee:02 oifname "wan" reject"#));
+    }
+
+    #[test]
+    fn test_client_rule_errors() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.99.20.1/24" }
+                client "fridge" {}
+                client "aa:bb:cc:dd:ee:01" { egress = ["cloud"] }
+                client "AA:BB:CC:DD:EE:01" {}
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert!(errors.contains(&"vlan \"iot\".client \"fridge\": not a MAC address or the hostname of a DHCP reservation on this VLAN".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"vlan \"iot\".client \"aa:bb:cc:dd:ee:01\": aa:bb:cc:dd:ee:01 already has a client block (\"AA:BB:CC:DD:EE:01\").".to_string()), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_egress_domains() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                enable_ipv4 = true
+                enable_ipv6 = true
+            }
+            vlan_aware_switch = true
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.99.20.1/24" }
+                ipv6 { subnet = "fd00:20::1/64" }
+                egress_domains = ["*.vendor-cloud.com"]
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains("set domains_vlan_20_v4 {\n        type ipv4_addr\n        size 65535\n        flags dynamic, timeout\n        timeout 1h\n    }"));
+        assert!(rendered.contains("set domains_vlan_20_v6 {\n        type ipv6_addr"));
+        assert!(rendered.contains(
+            r#"ip saddr 10.99.20.1/24 ip daddr @domains_vlan_20_v4 oifname "wan" update @domains_vlan_20_v4 { ip daddr } accept comment "nf:Allow IPv4 egress to resolved domains""#
+        ));
+        assert!(rendered.contains(r#"ip6 saddr fd00:20::1/64 ip6 daddr @domains_vlan_20_v6 oifname "wan" update @domains_vlan_20_v6 { ip6 daddr } accept"#));
+
+        let mut config = parse_hcl(hcl).unwrap();
+        config.vlan.get_mut("iot").unwrap().egress_domains.push("bad domain".to_string());
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert_eq!(errors, vec!["vlan \"iot\".egress_domains: Invalid domain: 'bad domain'"]);
+    }
+
+    #[test]
+    fn test_blocklists() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            blocklist "drop" {
+                url       = "https://www.spamhaus.org/drop/drop.txt"
+                refresh   = "6h"
+                direction = "both"
+            }
+            blocklist "scanners" { file = "/etc/nifty-filter/scanners.txt" }
+            vlan "trusted" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains("set blocklist_drop_v4 {\n        type ipv4_addr\n        flags interval\n        auto-merge\n    }"));
+        assert!(rendered.contains("set blocklist_scanners_v6 {"));
+        let input = &rendered[rendered.find("chain input_invalid_sources").unwrap()..];
+        assert!(input.contains(r#"iifname "wan" ip saddr @blocklist_drop_v4 drop comment "nf:Drop sources on blocklist drop""#));
+        assert!(input.contains(r#"iifname "wan" ip saddr @blocklist_scanners_v4 drop"#));
+        assert!(rendered.contains(r#"oifname "wan" ip daddr @blocklist_drop_v4 reject"#));
+        assert!(!rendered.contains("ip daddr @blocklist_scanners_v4"));
+
+        let mut config = parse_hcl(hcl).unwrap();
+        config.blocklist.get_mut("drop").unwrap().direction = Some("out".to_string());
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert_eq!(errors, vec!["blocklist \"drop\".direction: 'out' must be \"inbound\", \"outbound\" or \"both\"."]);
+    }
+
+    #[test]
+    fn test_logging() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            logging {
+                nflog_group = 2
+                input       = true
+                trunk       = false
+            }
+            vlan "trusted" {
+                id  = 10
+                log = true
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+            vlan "iot" {
+                id  = 20
+                log = false
+                ipv4 { subnet = "10.99.20.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+
+        let input = &rendered[rendered.find("chain input {").unwrap()..];
+        let input = &input[..input.find("\n    }").unwrap()];
+        assert!(input.contains(r#"iifname "trusted" log prefix "Dropped VLAN 10 input" group 2 comment"#));
+        assert!(input.contains(r#"iifname "iot" drop comment "nf:Default drop VLAN 20 input""#));
+        assert!(!input.contains(r#"iifname "iot" log"#));
+        // Per-VLAN drops come before the default log so they are not logged twice
+        assert!(input.find(r#"iifname "iot" drop"#) < input.find(r#"log prefix "Dropped input" group 2"#));
+        assert!(rendered.contains(r#"limit rate 5/minute log prefix "Dropped forward" group 2 comment "nf:Log dropped forward (rate-limited)""#));
+        assert!(!rendered.contains("Dropped untagged trunk"));
+        assert!(!rendered.contains("(sample)"));
+
+        let mut config = parse_hcl(hcl).unwrap();
+        config.vlan.get_mut("iot").unwrap().log = Some(hcl_config::LogSetting::Mode("loud".to_string()));
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert_eq!(errors, vec!["vlan \"iot\".log: 'loud' must be true, false or \"sampled\"."]);
+    }
+
+    #[test]
+    fn test_rule_logging() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                enable_ipv4 = true
+                forward "Minecraft" {
+                    protocol = "tcp"
+                    ports    = ["25565"]
+                    to       = "10.99.30.6"
+                    log      = true
+                }
+                forward "Quiet" {
+                    protocol = "tcp"
+                    ports    = ["8080"]
+                    to       = "10.99.30.7"
+                }
+            }
+            vlan "trusted" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+            vlan "lan" {
+                id = 30
+                ipv4 { subnet = "10.99.30.1/24" }
+                allow_from "trusted" {
+                    tcp = ["10.99.30.5:22"]
+                    log = "sampled"
+                }
+            }
+        "#;
+        let rendered = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().render();
+        assert!(rendered.contains(
+            r#"ct status dnat iifname "wan" ip daddr 10.99.30.6 tcp dport 25565 log prefix "Forwarded Minecraft: " comment "nf:Log DNAT forward TCP from WAN: Minecraft""#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "trusted" oifname "lan" ip daddr 10.99.30.5 tcp dport 22 limit rate 5/minute log prefix "(sample) Allowed VLAN 10 to VLAN 30: " comment "nf:Log Allow inter-VLAN TCP from VLAN 10 to VLAN 30""#
+        ));
+        // The log rule comes right before its accept
+        let log = rendered.find("Forwarded Minecraft").unwrap();
+        assert!(log < rendered.find(r#"accept comment "nf:DNAT forward TCP from WAN: Minecraft""#).unwrap());
+        // Rules log nothing unless their block sets log
+        assert!(!rendered.contains("Forwarded Quiet"));
+        assert_eq!(rendered.matches(" log prefix \"Forwarded").count() + rendered.matches(" log prefix \"(sample) Allowed").count(), 2);
+
+        let mut config = parse_hcl(hcl).unwrap();
+        config.vlan.get_mut("lan").unwrap().allow_from.get_mut("trusted").unwrap().log =
+            Some(hcl_config::LogSetting::Mode("loud".to_string()));
+        let errors = Router::from_hcl(&config).err().unwrap();
+        assert_eq!(errors, vec!["vlan \"lan\".allow_from \"trusted\".log: 'loud' must be true, false or \"sampled\"."]);
+    }
+
+    #[test]
+    fn test_rule_counters() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.99.20.1/24" }
+                tcp_forward = ["8080:10.99.20.5:80"]
+            }
+        "#;
+        let mut config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+        assert!(!rendered.contains("counter"));
+
+        config.logging = Some(hcl_config::LoggingHclConfig { counters: true, ..Default::default() });
+        let rendered = Router::from_hcl(&config).unwrap().render();
+        assert!(rendered.contains(r#"ct state invalid counter drop comment "nf:Drop invalid conntrack state""#));
+        assert!(rendered.contains(r#"counter limit rate 5/minute log prefix "(sample) Dropped input: ""#));
+        assert!(rendered.contains(r#"iifname "iot" counter jump forward_vlan_20"#));
+        assert!(rendered.contains("tcp dport 8080 counter dnat to 10.99.20.5:80"));
+        // Every generated rule gets exactly one counter, ahead of its verdict
+        for line in rendered.lines().filter(|l| l.contains("comment \"nf:")) {
+            assert_eq!(line.matches("counter ").count(), 1, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_ruleset_structure() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.99.20.1/24" }
+                tcp_forward = ["8080:10.99.20.5:80"]
+            }
+        "#;
+        let ruleset = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().ruleset();
+        let names: Vec<&str> = ruleset.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["nifty_filter", "nifty_nat"]);
+
+        let filter = ruleset.table(nifty_nft::Family::Inet, "nifty_filter").unwrap();
+        let forward = filter.chain("forward").unwrap();
+        assert_eq!(forward.hook.as_ref().unwrap().policy, nifty_nft::Policy::Drop);
+        assert_eq!(forward.rules.last().unwrap().description(), Some("Default drop"));
+        // Every rule is marked as generated, so the dashboard can describe it
+        for chain in ruleset.tables.iter().flat_map(|t| &t.chains) {
+            assert!(chain.rules.iter().all(|r| r.description().is_some()), "{}", chain.name);
+        }
+
+        let json = ruleset.to_json();
+        let dnat = json["nftables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["add"]["rule"]["comment"] == "nf:DNAT TCP from VLAN 20")
+            .unwrap();
+        assert_eq!(
+            dnat["add"]["rule"]["expr"].as_array().unwrap().last().unwrap(),
+            &serde_json::json!({"dnat": {"family": "ip", "addr": "10.99.20.5", "port": 80}})
+        );
+    }
+
+    #[test]
+    fn test_wan_limits() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                enable_ipv4 = true
+                tcp_accept  = [22, 443]
+                tcp_forward = ["8443:10.99.10.50:443"]
+                port_limit "tcp/443" {
+                    limit    = "10/second burst 20"
+                    ct_limit = 50
+                }
+                port_limit "tcp/8443" { ct_limit = 20 }
+                ssh_guard { ban = "6h" }
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+        "#;
+        let output = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().render();
+        assert!(output.contains("    set limit_tcp_443_v4 {\n        type ipv4_addr\n        size 65535\n        flags dynamic, timeout\n        timeout 1m\n"));
+        assert!(output.contains("    set conns_tcp_443_v4 {\n        type ipv4_addr\n        size 65535\n        flags dynamic\n    }"));
+        assert!(output.contains("    set ssh_guard_ban_v6 {\n        type ipv6_addr\n        size 65535\n        flags dynamic, timeout\n        timeout 6h\n"));
+        assert!(output.contains(r#"iifname "wan" tcp dport 443 ct state new add @limit_tcp_443_v4 { ip saddr limit rate over 10/second burst 20 packets } drop comment "nf:Rate limit TCP 443 per source""#));
+        assert!(output.contains(r#"iifname "wan" tcp dport 443 ct state new add @conns_tcp_443_v4 { ip saddr ct count over 50 } drop comment "nf:Limit TCP 443 connections per source""#));
+        assert!(output.contains(r#"iifname "wan" tcp dport 22 ip saddr @ssh_guard_ban_v4 drop comment "nf:Drop sources banned by the SSH guard""#));
+        assert!(output.contains(r#"iifname "wan" tcp dport 22 ct state new add @ssh_guard_meter_v4 { ip saddr limit rate over 5/minute } add @ssh_guard_ban_v4 { ip saddr } drop comment "nf:Ban sources exceeding the SSH guard rate""#));
+        // IPv6 is disabled, so no IPv6 rules
+        assert!(!output.contains("add @limit_tcp_443_v6"));
+        // The limits come before the accept
+        let limit = output.find("add @limit_tcp_443_v4").unwrap();
+        assert!(limit < output.find("nf:Allow TCP ports on WAN").unwrap());
+        // Forwards are limited by their WAN-side port
+        assert!(output.contains(r#"ct status dnat iifname "wan" ip daddr 10.99.10.50 tcp dport 443 ct state new add @conns_tcp_8443_v4 { ip saddr ct count over 20 } drop comment "nf:Limit TCP 8443 connections per source""#));
+    }
+
+    #[test]
+    fn test_wan_limits_on_closed_port() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                tcp_accept = [443]
+                port_limit "udp/443" { ct_limit = 5 }
+                ssh_guard {}
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+        "#;
+        let errors = Router::from_hcl(&parse_hcl(hcl).unwrap()).err().unwrap();
+        assert!(errors.contains(&"wan.port_limit \"udp/443\": UDP port 443 is not in wan.udp_accept, wan.udp_forward, wan.forward or wan.knock.opens.".to_string()));
+        assert!(errors.contains(&"wan.ssh_guard: TCP port 22 is not in wan.tcp_accept, wan.tcp_forward, wan.forward or wan.knock.opens.".to_string()));
+    }
+
+    #[test]
+    fn test_port_knock() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                tcp_accept = [443]
+                knock {
+                    sequence = ["tcp/7000", "udp/8000", "tcp/9000"]
+                    timeout  = "15s"
+                    opens    = ["tcp/22"]
+                }
+                ssh_guard {}
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+        "#;
+        let output = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().render();
+        assert!(output.contains("    set knock_2_v4 {\n        type ipv4_addr\n        size 65535\n        flags dynamic, timeout\n        timeout 15s\n"));
+        assert!(!output.contains("set knock_3_v4"));
+        let rules = [
+            concat!(
+                r#"iifname "wan" tcp dport 9000 ip saddr @knock_2_v4 delete @knock_1_v4 { ip saddr } delete @knock_2_v4 { ip saddr } "#,
+                r#"update @knock_open_v4 { ip saddr } drop comment "nf:Port knock 3 of 3""#
+            ),
+            concat!(
+                r#"iifname "wan" udp dport 8000 ip saddr @knock_1_v4 delete @knock_1_v4 { ip saddr } "#,
+                r#"update @knock_2_v4 { ip saddr } drop comment "nf:Port knock 2 of 3""#
+            ),
+            r#"iifname "wan" tcp dport 7000 delete @knock_2_v4 { ip saddr } update @knock_1_v4 { ip saddr } drop comment "nf:Port knock 1 of 3""#,
+            // A wrong knock between the sequence's ports starts over
+            concat!(
+                r#"iifname "wan" tcp dport 7000-9000 delete @knock_1_v4 { ip saddr } delete @knock_2_v4 { ip saddr } "#,
+                r#"comment "nf:Reset port knocks on a wrong port""#
+            ),
+            r#"iifname "wan" udp dport 8000 delete @knock_1_v4 { ip saddr } delete @knock_2_v4 { ip saddr }"#,
+            // The SSH guard also covers the knocked port
+            r#"iifname "wan" tcp dport 22 ip saddr @ssh_guard_ban_v4 drop"#,
+            r#"iifname "wan" tcp dport 22 ip saddr @knock_open_v4 accept comment "nf:Allow TCP 22 after port knock""#,
+            r#"iifname "wan" tcp dport { 443 } accept"#,
+        ];
+        let positions: Vec<usize> = rules.iter().map(|r| output.find(r).unwrap_or_else(|| panic!("{}", r))).collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+        // Port 22 is not open without the knock
+        assert!(!output.contains("tcp dport { 22, 443 }"));
+    }
+
+    #[test]
+    fn test_hairpin_nat() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+                mgmt  {
+                    name   = "mgmt"
+                    subnet = "10.99.0.1/24"
+                }
+            }
+            wan {
+                tcp_forward = ["443:10.10.0.5:8443", "2222:10.20.0.9:22"]
+                udp_forward = ["51820:10.10.0.5:51820"]
+                hairpin     = true
+                forward "Game" {
+                    protocol = "tcp"
+                    ports    = ["7777"]
+                    to       = "10.10.0.5"
+                    from     = ["10.10.0.0/25", "203.0.113.0/24"]
+                }
+            }
+            vlan_aware_switch = true
+            vlan "trusted" {
+                id = 10
+                ipv4 { subnet = "10.10.0.1/24" }
+                allow_from "iot" {
+                    tcp = ["10.10.0.5:8000-9000"]
+                }
+            }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.20.0.1/24" }
+            }
+            vlan "guest" {
+                id = 30
+                ipv4 { subnet = "10.30.0.1/24" }
+            }
+        "#;
+        let output = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().render();
+        let lan = "ip daddr != { 10.10.0.0/24, 10.20.0.0/24, 10.30.0.0/24, 10.99.0.0/24 }";
+        let dnat = |iif: &str, rest: &str| format!(r#"iifname "{}" fib daddr type local {} {}"#, iif, lan, rest);
+        // The destination's zone, and zones allowed to reach it, but not others
+        assert!(output.contains(&dnat("trusted", r#"tcp dport 443 dnat to 10.10.0.5:8443 comment "nf:Hairpin DNAT TCP from VLAN 10""#)));
+        assert!(output.contains(&dnat("iot", r#"tcp dport 443 dnat to 10.10.0.5:8443 comment "nf:Hairpin DNAT TCP from VLAN 20""#)));
+        assert!(!output.contains(&dnat("guest", "tcp dport 443")));
+        assert!(!output.contains(&dnat("iot", "udp dport 51820")));
+        assert!(output.contains(&dnat("iot", "tcp dport 2222 dnat to 10.20.0.9:22")));
+        assert!(!output.contains(&dnat("trusted", "tcp dport 2222")));
+        // A forward's sources limit the clients it is reflected for
+        assert!(output.contains(&dnat("trusted", "ip saddr { 10.10.0.0/25, 203.0.113.0/24 } tcp dport 7777 dnat to 10.10.0.5")));
+        // Reflected forwards are not caught by the dashboard redirect
+        assert!(output.find("Hairpin DNAT").unwrap() < output.find("Redirect HTTP/HTTPS to dashboard").unwrap());
+
+        assert!(output.contains(
+            r#"iifname "trusted" oifname "trusted" ct status dnat ip daddr 10.10.0.5 udp dport 51820 masquerade comment "nf:Hairpin SNAT UDP within VLAN 10""#
+        ));
+        assert!(!output.contains(r#"iifname "iot" oifname "trusted" ct status dnat"#));
+        assert!(output.contains(
+            r#"ct status dnat iifname "iot" oifname "iot" ip daddr 10.20.0.9 tcp dport 22 accept comment "nf:Hairpin forward TCP within VLAN 20""#
+        ));
+
+        let without = Router::from_hcl(&parse_hcl(&hcl.replace("hairpin     = true", "")).unwrap()).unwrap().render();
+        assert!(!without.contains("Hairpin"));
+    }
+
+    #[test]
+    fn test_forward_blocks() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                forward "Steam server" {
+                    protocol = "tcp+udp"
+                    ports    = ["27015-27030", "27036"]
+                    to       = "10.99.30.5"
+                    from     = ["203.0.113.0/24", "2001:db8::/32"]
+                }
+                forward "Minecraft" {
+                    protocol = "tcp"
+                    ports    = ["25565"]
+                    to       = "10.99.30.6"
+                    to_port  = 25566
+                }
+                port_limit "udp/27020" { limit = "10/second" }
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.30.1/24" }
+            }
+        "#;
+        let output = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap().render();
+        for proto in ["tcp", "udp"] {
+            let upper = proto.to_uppercase();
+            assert!(output.contains(&format!(
+                r#"iifname "wan" ip saddr {{ 203.0.113.0/24 }} {} dport 27015-27030 dnat to 10.99.30.5 comment "nf:DNAT {} from WAN: Steam server""#,
+                proto, upper
+            )));
+            assert!(output.contains(&format!(
+                r#"ct status dnat iifname "wan" ip saddr {{ 203.0.113.0/24 }} ip daddr 10.99.30.5 {} dport 27015-27030 accept comment "nf:DNAT forward {} from WAN: Steam server""#,
+                proto, upper
+            )));
+            assert!(output.contains(&format!(r#"ip saddr {{ 203.0.113.0/24 }} {} dport 27036 dnat to 10.99.30.5:27036"#, proto)));
+        }
+        assert!(output.contains(r#"iifname "wan" tcp dport 25565 dnat to 10.99.30.6:25566 comment "nf:DNAT TCP from WAN: Minecraft""#));
+        assert!(!output.contains("udp dport 25565"));
+        // A limit inside a forwarded range applies to that port only
+        assert!(output.contains(
+            r#"ct status dnat iifname "wan" ip saddr { 203.0.113.0/24 } ip daddr 10.99.30.5 udp dport 27020 ct state new add @limit_udp_27020_v4"#
+        ));
+
+        let errors = Router::from_hcl(&parse_hcl(r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                forward "a" {
+                    protocol = "sctp"
+                    ports    = ["1"]
+                    to       = "10.99.30.5"
+                }
+                forward "b" {
+                    protocol = "udp"
+                    ports    = ["5000-5010"]
+                    to       = "10.99.30.5"
+                    to_port  = 6000
+                }
+                forward "c" {
+                    protocol = "udp"
+                    ports    = ["5000-4000"]
+                    to       = "10.99.30.5"
+                    from     = ["2001:db8::/32"]
+                }
+                forward "d" {
+                    protocol = "udp"
+                    ports    = ["5000"]
+                    to       = "10.99.30.5"
+                    from     = ["2001:db8::/32"]
+                }
+                forward "say \"hi\"" {
+                    protocol = "tcp"
+                    ports    = ["6000"]
+                    to       = "10.99.30.5"
+                }
+            }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.30.1/24" }
+            }
+        "#).unwrap()).err().unwrap();
+        for expected in [
+            r#"wan.forward "a".protocol: 'sctp' must be "tcp", "udp" or "tcp+udp"."#,
+            r#"wan.forward "b".to_port: only a single port can be forwarded to another port."#,
+            r#"wan.forward "c": Invalid incoming port range: '5000-4000'"#,
+            r#"wan.forward "d".from: no sources of the destination's address family."#,
+            r#"wan.forward "say "hi"": the label must be 1 to 64 bytes, without double quotes or control characters."#,
+        ] {
+            assert!(errors.contains(&expected.to_string()), "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn test_ruleset_replaces_owned_tables() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let router = Router::from_hcl(&parse_hcl(hcl).unwrap()).unwrap();
+        let output = router.render();
+        assert!(!output.contains("flush ruleset"));
+        // Tables that are not generated are still deleted, before any table is added
+        let delete = output.find("delete table inet nifty_wan_routing\n").unwrap();
+        assert!(output.contains("delete table inet nifty_mangle\n"));
+        assert!(delete < output.find("table inet nifty_filter {").unwrap());
+
+        let json = router.ruleset().to_json();
+        let commands = json["nftables"].as_array().unwrap();
+        assert!(commands.iter().all(|c| c.get("flush").is_none()));
+        let deletes: Vec<&str> =
+            commands.iter().filter_map(|c| c["delete"]["table"]["name"].as_str()).collect();
+        assert_eq!(deletes, ["nifty_filter", "nifty_nat", "nifty_wan_routing", "nifty_mangle", "filter", "nat", "mangle"]);
+        // Tables from before the nifty_ prefix are deleted on an in-place upgrade
+        for legacy in ["filter", "nat", "mangle"] {
+            assert!(output.contains(&format!("\ntable inet {legacy}\ndelete table inet {legacy}\n")));
+            assert!(!output.contains(&format!("table inet {legacy} {{")));
+        }
+
+        let lockdown = ruleset::lockdown().to_string();
+        assert!(lockdown.contains("\ntable inet nifty_filter\ndelete table inet nifty_filter\n"));
+        assert!(lockdown.contains("\ntable inet filter\ndelete table inet filter\n"));
+        assert!(ruleset::teardown().to_string().ends_with("delete table inet mangle\n"));
+        assert!(lockdown.contains("chain forward {\n        type filter hook forward priority 0; policy drop;\n    }"));
+        assert!(lockdown.contains(r#"oifname "lo" accept comment "nf:Allow localhost loopback""#));
+    }
+
+    #[test]
+    fn test_wireguard_zone() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            vlan "trusted" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+                allow_from "wg0" {
+                    tcp = ["10.99.10.20:22"]
+                }
+            }
+            wireguard "wg0" {
+                listen_port      = 51820
+                private_key_file = "/var/lib/nifty-filter/wg0.key"
+                ipv4 {
+                    subnet = "10.99.100.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+                firewall {
+                    icmp_accept = ["echo-request"]
+                    udp_accept  = [53]
+                }
+                allow_from "trusted" {
+                    tcp = ["10.99.100.10:22"]
+                }
+                peer "phone" {
+                    public_key  = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+                    allowed_ips = ["10.99.100.10/32"]
+                }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains(
+            r#"iifname "wan" udp dport { 51820 } accept comment "nf:Allow WireGuard on WAN""#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "wg0" jump input_wg_wg0 comment "nf:WireGuard wg0 input rules""#
+        ));
+        assert!(rendered.contains("chain input_wg_wg0 {"));
+        assert!(rendered.contains(r#"ip saddr 10.99.100.1/24 icmp type { echo-request }"#));
+        assert!(rendered.contains("chain forward_wg_wg0 {"));
+        assert!(rendered.contains(
+            r#"ip saddr 10.99.100.1/24 ip daddr { 0.0.0.0/0 } oifname "wan" accept"#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "wg0" oifname "trusted" ip daddr 10.99.10.20 tcp dport 22 accept comment "nf:Allow inter-VLAN TCP from WireGuard wg0 to VLAN 10""#
+        ));
+        assert!(rendered.contains(
+            r#"iifname "trusted" oifname "wg0" ip daddr 10.99.100.10 tcp dport 22 accept comment "nf:Allow inter-VLAN TCP from VLAN 10 to WireGuard wg0""#
+        ));
+        assert!(rendered.contains(r#"oifname "wg0" accept comment "nf:Allow outgoing to WireGuard wg0""#));
+        // VLAN chain names are unchanged
+        assert!(rendered.contains("chain input_vlan_10 {"));
+    }
+
+    #[test]
+    fn test_wireguard_zone_validation() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "10.99.10.1/24" }
+            }
+            wireguard "lan" {
+                listen_port      = 51820
+                private_key_file = "/var/lib/nifty-filter/lan.key"
+                ipv4 { subnet = "10.99.100.1/24" }
+            }
+            wireguard "wg1" {
+                listen_port      = 51820
+                private_key_file = "/var/lib/nifty-filter/wg1.key"
+                ipv6 { delegated = true }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let errors = match Router::from_hcl(&config) {
+            Ok(_) => panic!("expected validation errors"),
+            Err(e) => e,
+        };
+        assert!(errors.iter().any(|e| e.contains("wireguard \"lan\": name is already used by a VLAN")));
+        assert!(errors.iter().any(|e| e.contains("wireguard \"wg1\".ipv4.subnet is required")));
+        assert!(errors.iter().any(|e| e.contains("delegated prefixes are not supported")));
+        assert!(errors.iter().any(|e| e.contains("listen_port 51820 is used by another WireGuard zone")));
+    }
+
+    #[test]
+    fn test_multi_wan_failover() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+                wan2  { name = "wan2" }
+            }
+            wan {
+                enable_ipv4 = true
+                tcp_accept  = [22]
+            }
+
+            wan "fiber" { interface = "wan" }
+
+            wan "lte" {
+                interface = "wan2"
+                priority  = 2
+            }
+            vlan "lan" {
+                id = 1
+                ipv4 {
+                    subnet = "192.168.10.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+            }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        // WAN policy applies to every uplink
+        assert!(rendered.contains(r#"iifname { "wan", "wan2" } tcp dport { 22 } accept"#));
+        assert!(rendered.contains(r#"oifname { "wan", "wan2" } accept comment "nf:Allow outgoing WAN""#));
+        // Per-uplink masquerade
+        assert!(rendered.contains(r#"oifname "wan" masquerade comment "nf:Masquerade IPv4 LAN-to-WAN via fiber (NAT)""#));
+        assert!(rendered.contains(r#"oifname "wan2" masquerade comment "nf:Masquerade IPv4 LAN-to-WAN via lte (NAT)""#));
+        // Inbound connections are pinned to their uplink
+        assert!(rendered.contains("table inet nifty_wan_routing"));
+        assert!(rendered.contains(r#"iifname "wan2" ct state new ct mark set 102"#));
+        // Failover mode does not balance
+        assert!(!rendered.contains("numgen"));
+    }
+
+    #[test]
+    fn test_multi_wan_balance() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+                wan2  { name = "wan2" }
+            }
+            wan {
+                policy = "balance"
+            }
+
+            wan "isp1" {
+                interface = "wan"
+                weight    = 2
+            }
+
+            wan "isp2" { interface = "wan2" }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains("ct mark set numgen random mod 3 map { 0-1 : 101, 2 : 102 }"));
+    }
+
+    #[test]
+    fn test_single_wan_no_routing_table() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(!rendered.contains("table inet nifty_wan_routing {"));
+        assert!(rendered.contains(r#"meta nfproto ipv4 oifname "wan" masquerade comment "nf:Masquerade IPv4 LAN-to-WAN (NAT)""#));
+    }
+
+    #[test]
+    fn test_pppoe_wan_mss_clamp() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {
+                tcp_accept = [22]
+                mode       = "pppoe"
+                pppoe {
+                    username = "user@isp"
+                    password = "secret"
+                }
+            }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        // Firewall and NAT apply to the PPP interface, not the Ethernet device
+        assert!(rendered.contains(r#"iifname "ppp0" tcp dport { 22 } accept"#));
+        assert!(rendered.contains(r#"meta nfproto ipv4 oifname "ppp0" masquerade"#));
+        assert!(rendered.contains(r#"oifname "ppp0" tcp flags syn tcp option maxseg size set rt mtu"#));
+        // Clamp before established connections are accepted (SYN-ACK is established)
+        let clamp = rendered.find("maxseg").unwrap();
+        let established = rendered.find("ct state established,related accept comment \"nf:Allow established/related connections\"\n        ct state invalid drop comment \"nf:Drop invalid conntrack state\"\n        jump forward_invalid_sources").unwrap();
+        assert!(clamp < established);
+    }
+
+    #[test]
+    fn test_dhcp_wan_no_mss_clamp() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan {}
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let rendered = Router::from_hcl(&config).unwrap().render();
+        assert!(!rendered.contains("maxseg"));
+    }
+
+    #[test]
+    fn test_delegated_prefix_uses_named_set() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            vlan_aware_switch = true
+            wan { enable_ipv6 = true }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "192.168.10.1/24" }
+                ipv6 {
+                    delegated = true
+                    egress    = ["::/0"]
+                }
+                firewall { tcp_accept = [22] }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let router = Router::from_hcl(&config).unwrap();
+        let rendered = router.render();
+
+        assert!(rendered.contains("set pd_vlan_10 {\n        type ipv6_addr\n        flags interval\n    }"));
+        assert!(rendered.contains("ip6 saddr @pd_vlan_10 tcp dport { 22 } accept"));
+        assert!(rendered.contains(r#"ip6 saddr @pd_vlan_10 ip6 daddr { ::/0 } oifname "wan" accept"#));
+        // Declared only in the tables whose rules reference it
+        assert_eq!(pd::set_tables(&router.ruleset(), "pd_vlan_10"), ["nifty_filter"]);
+
+        let forwarding = hcl.replace(
+            "firewall { tcp_accept = [22] }",
+            "firewall { tcp_accept = [22] }\n                tcp_forward = [\"8080:[fd00:10::5]:80\"]",
+        );
+        let router = Router::from_hcl(&parse_hcl(&forwarding).unwrap()).unwrap();
+        assert_eq!(pd::set_tables(&router.ruleset(), "pd_vlan_10"), ["nifty_filter", "nifty_nat"]);
+    }
+
+    #[test]
+    fn test_delegated_prefix_validation() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            vlan_aware_switch = true
+            wan { enable_ipv4 = true }
+            vlan "lan" {
+                id = 10
+                ipv4 { subnet = "192.168.10.1/24" }
+                ipv6 {
+                    subnet    = "fd00:10::1/64"
+                    delegated = true
+                    subnet_id = 1
+                }
+                dhcpv6 {
+                    pool_start = "fd00:10::100"
+                    pool_end   = "fd00:10::1ff"
+                }
+            }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "192.168.20.1/24" }
+                ipv6 {
+                    delegated = true
+                    subnet_id = 1
+                }
+            }
+            vlan "lab" {
+                id = 30
+                ipv4 { subnet = "192.168.30.1/24" }
+                ipv6 { subnet_id = 2 }
+            }
+            vlan "guest" {
+                id = 40
+                ipv4 { subnet = "192.168.40.1/24" }
+                ipv6 { delegated = true }
+            }
+            vlan "cams" {
+                id = 50
+                ipv4 { subnet = "192.168.50.1/24" }
+                ipv6 {
+                    delegated = true
+                    subnet_id = "0x40"
+                }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let errors = match Router::from_hcl(&config) {
+            Ok(_) => panic!("expected validation errors"),
+            Err(e) => e,
+        };
+        assert!(errors.iter().any(|e| e.contains("mutually exclusive")));
+        assert!(errors.iter().any(|e| e.contains("requires wan.enable_ipv6")));
+        assert!(errors.iter().any(|e| e.contains("duplicate delegated subnet_id 0x1")));
+        assert!(errors.iter().any(|e| e.contains("must be address suffixes")));
+        assert!(errors.iter().any(|e| e.contains("vlan \"lab\".ipv6.subnet is required")));
+        assert!(errors.contains(&"vlan \"guest\".ipv6.subnet_id 0x28 (the VLAN ID) does not fit the /60 delegated prefix; set subnet_id to 0x0-0xf.".to_string()));
+        assert!(errors.contains(&"vlan \"cams\".ipv6.subnet_id 0x40 does not fit the /60 delegated prefix; set subnet_id to 0x0-0xf.".to_string()));
+    }
+
+    #[test]
+    fn test_qos_disabled_no_mangle_table() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(!rendered.contains("table inet nifty_mangle {"));
+        assert!(!rendered.contains("dscp set"));
+        assert!(!rendered.contains("flowtable"));
+    }
+
+    #[test]
+    fn test_qos_enabled_dscp_marking() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            qos {
+                upload_mbps = 20
+                download_mbps = 300
+            }
+            vlan "trusted" {
+                id = 10
+                ipv4 {
+                    subnet = "10.10.0.1/24"
+                    egress = ["0.0.0.0/0"]
+                }
+                firewall {
+                    tcp_accept = [22]
+                    udp_accept = [67, 68]
+                }
+                qos_class = "voice"
+            }
+            vlan "iot" {
+                id = 20
+                ipv4 { subnet = "10.20.0.1/24" }
+                firewall {
+                    tcp_accept = []
+                    udp_accept = [67, 68]
+                }
+                qos_class = "bulk"
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains("table inet nifty_mangle"));
+        // VLAN 10 marked as voice (EF)
+        assert!(rendered.contains(r#"oifname "wan" ip saddr 10.10.0.1/24 ip dscp set ef"#));
+        // VLAN 20 marked as bulk (CS1)
+        assert!(rendered.contains(r#"oifname "wan" ip saddr 10.20.0.1/24 ip dscp set cs1"#));
+    }
+
+    #[test]
+    fn test_qos_override_rules() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            qos {
+                upload_mbps = 20
+                download_mbps = 300
+                overrides {
+                    voice = ["192.168.10.50", "192.168.10.51"]
+                    bulk  = ["192.168.10.0/24"]
+                }
+            }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
+
+        assert!(rendered.contains("192.168.10.50/32, 192.168.10.51/32"));
+        assert!(rendered.contains("ip dscp set ef"));
+        assert!(rendered.contains("192.168.10.0/24"));
+        assert!(rendered.contains("ip dscp set cs1"));
+    }
+
+    #[test]
+    fn test_qos_no_flowtable() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan "lan" {
+                id = 1
+                ipv4 { subnet = "192.168.10.1/24" }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         assert!(!rendered.contains("flowtable"));
         assert!(!rendered.contains("flow add @ft"));
@@ -1368,8 +3507,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // VLAN 20 should get a fwmark rule
         assert!(rendered.contains(r#"oifname "wan" ip saddr 10.20.0.1/24 meta mark set 20"#));
@@ -1394,7 +3533,7 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        match RouterTemplate::from_hcl(&config) {
+        match Router::from_hcl(&config) {
             Err(errors) => assert!(errors.iter().any(|e| e.contains("bandwidth requires a qos block"))),
             Ok(_) => panic!("expected error for bandwidth without qos block"),
         }
@@ -1551,8 +3690,8 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         // Trusted VLAN (mdns_reflector=true) should have mDNS rules
         assert!(rendered.contains("udp dport 5353 ip daddr 224.0.0.251 accept"));
@@ -1585,10 +3724,68 @@ mod tests {
             }
         "#;
         let config = parse_hcl(hcl).unwrap();
-        let tmpl = RouterTemplate::from_hcl(&config).unwrap();
-        let rendered = tmpl.render().unwrap();
+        let tmpl = Router::from_hcl(&config).unwrap();
+        let rendered = tmpl.render();
 
         assert!(rendered.contains("udp dport 5353 ip daddr 224.0.0.251 accept"));
         assert!(rendered.contains("udp dport 5353 ip6 daddr ff02::fb accept"));
     }
+
+    #[test]
+    fn test_upnp() {
+        let hcl = r#"
+            interfaces {
+                trunk { name = "trunk" }
+                wan   { name = "wan" }
+            }
+            wan { enable_ipv4 = true }
+            vlan_aware_switch = true
+            vlan "games" {
+                id = 20
+                ipv4 { subnet = "10.20.0.1/24" }
+                firewall { udp_accept = [67, 68] }
+                upnp {
+                    enabled       = true
+                    allowed_ports = "3074-3079"
+                }
+            }
+            vlan "guest" {
+                id = 30
+                ipv4 { subnet = "10.30.0.1/24" }
+                firewall { udp_accept = [67, 68] }
+                upnp { enabled = false }
+            }
+        "#;
+        let config = parse_hcl(hcl).unwrap();
+        let router = Router::from_hcl(&config).unwrap();
+        let rendered = router.render();
+
+        // Discovery and control are reachable from the UPnP zone only
+        assert_eq!(rendered.matches("Allow UPnP discovery and NAT-PMP/PCP").count(), 1);
+        assert!(rendered.contains(r#"ip saddr 10.20.0.1/24 tcp dport 5000 accept comment "nf:Allow UPnP IGD control""#));
+        // miniupnpd's chains, jumped from the WAN side
+        assert!(rendered.contains("chain miniupnpd {"));
+        assert!(rendered.contains(r#"iifname "wan" jump miniupnpd comment"#));
+        assert!(rendered.contains("chain prerouting_miniupnpd {"));
+        assert!(rendered.contains(r#"iifname "wan" jump prerouting_miniupnpd comment"#));
+        assert!(rendered.contains(r#"oifname "wan" jump postrouting_miniupnpd comment"#));
+
+        let conf = router.miniupnpd_conf().unwrap();
+        assert!(conf.contains("ext_ifname=wan\nlistening_ip=games\n"));
+        assert!(conf.contains("upnp_table_name=nifty_filter\nupnp_nat_table_name=nifty_nat\n"));
+        assert!(conf.contains("upnp_forward_chain=miniupnpd\nupnp_nat_chain=prerouting_miniupnpd\n"));
+        assert!(conf.ends_with("allow 3074-3079 10.20.0.0/24 3074-3079\ndeny 0-65535 0.0.0.0/0 0-65535\n"));
+
+        let disabled = parse_hcl(&hcl.replace("enabled       = true", "enabled = false")).unwrap();
+        let router = Router::from_hcl(&disabled).unwrap();
+        assert!(router.miniupnpd_conf().is_none());
+        assert!(!router.render().contains("miniupnpd"));
+
+        let bad = parse_hcl(&hcl.replace("3074-3079", "3079-3074")).unwrap();
+        let errors = Router::from_hcl(&bad).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["vlan \"games\".upnp.allowed_ports: invalid port range '3079-3074'. Expected e.g. \"1024-65535\"."]
+        );
+    }
 }
@@ -0,0 +1,468 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use nifty_nft::{Expr, Ruleset};
+use serde::Serialize;
+
+/// Where the collector keeps the most recent drops.
+pub const DROPS_FILE: &str = "/run/nifty-filter/drops.json";
+
+// netlink / nfnetlink_log constants (linux/netfilter/nfnetlink_log.h)
+const NFNL_SUBSYS_ULOG: u16 = 4;
+const NFULNL_MSG_PACKET: u16 = 0;
+const NFULNL_MSG_CONFIG: u16 = 1;
+const NFULA_TIMESTAMP: u16 = 3;
+const NFULA_IFINDEX_INDEV: u16 = 4;
+const NFULA_IFINDEX_OUTDEV: u16 = 5;
+const NFULA_PAYLOAD: u16 = 9;
+const NFULA_PREFIX: u16 = 10;
+const NFULA_CFG_CMD: u16 = 1;
+const NFULA_CFG_MODE: u16 = 2;
+const NFULNL_CFG_CMD_BIND: u8 = 1;
+const NFULNL_COPY_PACKET: u8 = 2;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+const NLMSG_HDRLEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+/// Enough of each packet for the IP and transport headers
+const COPY_RANGE: u32 = 128;
+/// How long a quiet socket waits before pending drops are written
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A dropped packet reported through NFLOG.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Drop {
+    /// Unix time in seconds
+    pub time: u64,
+    /// Description in the comment of the rule that logged the packet, if
+    /// the running ruleset has one with this prefix
+    pub rule: Option<String>,
+    /// Log prefix of that rule
+    pub prefix: String,
+    pub in_iface: Option<String>,
+    pub out_iface: Option<String>,
+    pub protocol: String,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub sport: Option<u16>,
+    pub dport: Option<u16>,
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn push_attr(buf: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    let len = 4 + data.len();
+    buf.extend_from_slice(&(len as u16).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(data);
+    buf.resize(align4(buf.len()), 0);
+}
+
+/// An NFULNL_MSG_CONFIG request for `group` carrying one attribute.
+fn config_message(group: u16, seq: u32, kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut msg = vec![0u8; NLMSG_HDRLEN];
+    // nfgenmsg: family, version, resource id (the group, big-endian)
+    msg.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    msg.extend_from_slice(&group.to_be_bytes());
+    push_attr(&mut msg, kind, data);
+    let len = msg.len() as u32;
+    msg[0..4].copy_from_slice(&len.to_ne_bytes());
+    msg[4..6].copy_from_slice(&((NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_CONFIG).to_ne_bytes());
+    msg[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    msg[8..12].copy_from_slice(&seq.to_ne_bytes());
+    msg
+}
+
+/// Split a buffer of netlink messages into (type, payload) pairs.
+fn messages(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut out = Vec::new();
+    let mut rest = buf;
+    while rest.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes(rest[0..4].try_into().unwrap()) as usize;
+        if len < NLMSG_HDRLEN || len > rest.len() {
+            break;
+        }
+        let kind = u16::from_ne_bytes(rest[4..6].try_into().unwrap());
+        out.push((kind, &rest[NLMSG_HDRLEN..len]));
+        rest = &rest[align4(len).min(rest.len())..];
+    }
+    out
+}
+
+/// Split netlink attributes into (type, data) pairs.
+fn attributes(buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut out = Vec::new();
+    let mut rest = buf;
+    while rest.len() >= 4 {
+        let len = u16::from_ne_bytes(rest[0..2].try_into().unwrap()) as usize;
+        if len < 4 || len > rest.len() {
+            break;
+        }
+        // Drop the nested / byte-order flag bits
+        let kind = u16::from_ne_bytes(rest[2..4].try_into().unwrap()) & 0x3fff;
+        out.push((kind, &rest[4..len]));
+        rest = &rest[align4(len).min(rest.len())..];
+    }
+    out
+}
+
+fn be_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(0..4)?.try_into().ok()?))
+}
+
+/// The header fields of a logged packet.
+struct Packet {
+    protocol: String,
+    src: IpAddr,
+    dst: IpAddr,
+    sport: Option<u16>,
+    dport: Option<u16>,
+}
+
+/// Decode the L3 packet NFLOG copied: addresses, protocol and ports.
+fn decode_packet(payload: &[u8]) -> Option<Packet> {
+    let (proto, src, dst, l4) = match payload.first()? >> 4 {
+        4 => {
+            let ihl = ((payload[0] & 0x0f) as usize) * 4;
+            let src: [u8; 4] = payload.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = payload.get(16..20)?.try_into().ok()?;
+            // Only the first fragment carries the transport header
+            let offset = u16::from_be_bytes(payload.get(6..8)?.try_into().ok()?) & 0x1fff;
+            let l4 = if offset == 0 { payload.get(ihl..) } else { None };
+            (*payload.get(9)?, IpAddr::V4(Ipv4Addr::from(src)), IpAddr::V4(Ipv4Addr::from(dst)), l4)
+        }
+        6 => {
+            let src: [u8; 16] = payload.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = payload.get(24..40)?.try_into().ok()?;
+            (*payload.get(6)?, IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), payload.get(40..))
+        }
+        _ => return None,
+    };
+    let protocol = match proto {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        58 => "icmpv6".to_string(),
+        other => other.to_string(),
+    };
+    let (sport, dport) = match (proto, l4) {
+        (6 | 17, Some(l4)) if l4.len() >= 4 => (
+            Some(u16::from_be_bytes([l4[0], l4[1]])),
+            Some(u16::from_be_bytes([l4[2], l4[3]])),
+        ),
+        _ => (None, None),
+    };
+    Some(Packet { protocol, src, dst, sport, dport })
+}
+
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let ptr = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if ptr.is_null() {
+        return Some(format!("if{}", index));
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Descriptions of the rules logging to NFLOG, keyed by their log prefix.
+pub fn rule_comments(ruleset: &Ruleset) -> HashMap<String, String> {
+    let mut comments = HashMap::new();
+    let rules = ruleset.tables.iter().flat_map(|t| &t.chains).flat_map(|c| &c.rules);
+    for rule in rules {
+        let Some(description) = rule.description() else { continue };
+        for expr in &rule.exprs {
+            if let Expr::Log { prefix, group: Some(_) } = expr {
+                comments.entry(prefix.clone()).or_insert_with(|| description.to_string());
+            }
+        }
+    }
+    comments
+}
+
+/// Decode the NFULNL_MSG_PACKET messages in a receive buffer, naming each
+/// drop's rule from `comments`.
+fn parse_drops(buf: &[u8], iface: &dyn Fn(u32) -> Option<String>, comments: &HashMap<String, String>) -> Vec<Drop> {
+    let mut drops = Vec::new();
+    for (kind, body) in messages(buf) {
+        if kind != (NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_PACKET || body.len() < NFGENMSG_LEN {
+            continue;
+        }
+        let (mut prefix, mut time, mut in_iface, mut out_iface, mut packet) = (String::new(), None, None, None, None);
+        for (attr, data) in attributes(&body[NFGENMSG_LEN..]) {
+            match attr {
+                NFULA_PREFIX => {
+                    prefix = String::from_utf8_lossy(data).trim_end_matches('\0').trim().to_string();
+                }
+                NFULA_TIMESTAMP => {
+                    time = data.get(0..8).map(|s| u64::from_be_bytes(s.try_into().unwrap()));
+                }
+                NFULA_IFINDEX_INDEV => in_iface = be_u32(data).and_then(iface),
+                NFULA_IFINDEX_OUTDEV => out_iface = be_u32(data).and_then(iface),
+                NFULA_PAYLOAD => packet = decode_packet(data),
+                _ => {}
+            }
+        }
+        let Some(Packet { protocol, src, dst, sport, dport }) = packet else { continue };
+        let time = time.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+        });
+        let rule = comments.get(&prefix).cloned();
+        drops.push(Drop { time, rule, prefix, in_iface, out_iface, protocol, src, dst, sport, dport });
+    }
+    drops
+}
+
+fn netlink_error(buf: &[u8]) -> Option<io::Error> {
+    messages(buf).into_iter().find_map(|(kind, body)| {
+        let code = i32::from_ne_bytes(body.get(0..4)?.try_into().ok()?);
+        (kind == NLMSG_ERROR && code != 0).then(|| io::Error::from_raw_os_error(-code))
+    })
+}
+
+/// A netlink socket subscribed to one NFLOG group.
+struct NflogSocket {
+    fd: OwnedFd,
+}
+
+impl NflogSocket {
+    fn open(group: u16) -> io::Result<Self> {
+        let raw = unsafe {
+            libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_NETFILTER)
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = NflogSocket { fd: unsafe { OwnedFd::from_raw_fd(raw) } };
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let rc = unsafe {
+            libc::bind(
+                raw,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let timeout = libc::timeval { tv_sec: RECV_TIMEOUT.as_secs() as libc::time_t, tv_usec: 0 };
+        let rc = unsafe {
+            libc::setsockopt(
+                raw,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        socket.request(&config_message(group, 1, NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND]))?;
+        // nfulnl_msg_config_mode: copy_range (be32), copy_mode, padding
+        let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+        mode.extend_from_slice(&[NFULNL_COPY_PACKET, 0]);
+        socket.request(&config_message(group, 2, NFULA_CFG_MODE, &mode))?;
+        Ok(socket)
+    }
+
+    /// Send a request and wait for its acknowledgement.
+    fn request(&self, msg: &[u8]) -> io::Result<()> {
+        let sent = unsafe { libc::send(self.fd.as_raw_fd(), msg.as_ptr() as *const libc::c_void, msg.len(), 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; 4096];
+        let len = self.recv(&mut buf)?;
+        match netlink_error(&buf[..len]) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if len < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(len as usize)
+        }
+    }
+}
+
+/// Replace the drops file atomically so readers never see a partial write.
+fn write_drops(path: &Path, drops: &VecDeque<Drop>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    let list: Vec<&Drop> = drops.iter().collect();
+    fs::write(&tmp, serde_json::to_string_pretty(&list)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// Listen on an NFLOG group and keep the newest `keep` drops in `output`
+/// as a JSON array, oldest first, naming rules from `comments`. Runs until
+/// the socket fails.
+pub fn collect(group: u16, output: &Path, keep: usize, comments: &HashMap<String, String>) -> io::Result<()> {
+    let socket = NflogSocket::open(group)?;
+    eprintln!("nflog: listening on group {}, writing {}", group, output.display());
+    let mut recent: VecDeque<Drop> = VecDeque::with_capacity(keep);
+    let mut buf = vec![0u8; 65536];
+    let mut last_write = Instant::now() - RECV_TIMEOUT;
+    let mut pending = false;
+    loop {
+        match socket.recv(&mut buf) {
+            Ok(len) => {
+                for drop in parse_drops(&buf[..len], &interface_name, comments) {
+                    if recent.len() == keep {
+                        recent.pop_front();
+                    }
+                    recent.push_back(drop);
+                    pending = true;
+                }
+            }
+            // Quiet for a while: write what the last burst left pending
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            // The kernel drops messages when we fall behind; carry on
+            Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => continue,
+            Err(e) => return Err(e),
+        }
+        // Bursts of drops are written at most once a second
+        if pending && last_write.elapsed() >= RECV_TIMEOUT {
+            if let Err(e) = write_drops(output, &recent) {
+                eprintln!("nflog: cannot write {}: {}", output.display(), e);
+            }
+            last_write = Instant::now();
+            pending = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NFULNL_MSG_PACKET message as the kernel would send it.
+    fn packet_message(prefix: &str, indev: u32, payload: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0u8; NLMSG_HDRLEN];
+        msg.extend_from_slice(&[libc::AF_INET as u8, 0, 0, 1]);
+        let mut prefix = prefix.as_bytes().to_vec();
+        prefix.push(0);
+        push_attr(&mut msg, NFULA_PREFIX, &prefix);
+        push_attr(&mut msg, NFULA_IFINDEX_INDEV, &indev.to_be_bytes());
+        let mut ts = 1_700_000_000u64.to_be_bytes().to_vec();
+        ts.extend_from_slice(&0u64.to_be_bytes());
+        push_attr(&mut msg, NFULA_TIMESTAMP, &ts);
+        push_attr(&mut msg, NFULA_PAYLOAD, payload);
+        let len = msg.len() as u32;
+        msg[0..4].copy_from_slice(&len.to_ne_bytes());
+        msg[4..6].copy_from_slice(&((NFNL_SUBSYS_ULOG << 8) | NFULNL_MSG_PACKET).to_ne_bytes());
+        msg
+    }
+
+    fn ipv4_tcp(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16) -> Vec<u8> {
+        let mut p = vec![0x45, 0, 0, 40, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&dst);
+        p.extend_from_slice(&sport.to_be_bytes());
+        p.extend_from_slice(&dport.to_be_bytes());
+        p.extend_from_slice(&[0; 16]);
+        p
+    }
+
+    #[test]
+    fn test_parse_drops() {
+        let mut buf = packet_message("Dropped input", 2, &ipv4_tcp([203, 0, 113, 7], [198, 51, 100, 1], 51515, 22));
+        let mut v6 = vec![0x60, 0, 0, 0, 0, 8, 17, 64];
+        v6.extend_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        v6.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        v6.extend_from_slice(&[0x13, 0x88, 0x00, 0x35, 0, 8, 0, 0]);
+        buf.extend(packet_message("Dropped VLAN 20 forward", 3, &v6));
+
+        let names = |i: u32| Some(["lo", "wan", "iot"][i as usize - 1].to_string());
+        let comments = HashMap::from([("Dropped input".to_string(), "Log dropped input".to_string())]);
+        let drops = parse_drops(&buf, &names, &comments);
+        assert_eq!(
+            drops[0],
+            Drop {
+                time: 1_700_000_000,
+                rule: Some("Log dropped input".into()),
+                prefix: "Dropped input".into(),
+                in_iface: Some("wan".into()),
+                out_iface: None,
+                protocol: "tcp".into(),
+                src: "203.0.113.7".parse().unwrap(),
+                dst: "198.51.100.1".parse().unwrap(),
+                sport: Some(51515),
+                dport: Some(22),
+            }
+        );
+        assert_eq!((drops[1].rule.as_deref(), drops[1].prefix.as_str()), (None, "Dropped VLAN 20 forward"));
+        assert_eq!(drops[1].in_iface.as_deref(), Some("iot"));
+        assert_eq!((drops[1].protocol.as_str(), drops[1].dport), ("udp", Some(53)));
+        assert_eq!(drops[1].src, "2001:db8::7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_rule_comments() {
+        let mut chain = nifty_nft::Chain::new("forward");
+        chain.push(
+            nifty_nft::Rule::new()
+                .then(Expr::Log { prefix: "Dropped forward".into(), group: Some(2) })
+                .describe("Log dropped forward"),
+        );
+        chain.push(nifty_nft::Rule::new().then(Expr::Log { prefix: "Kernel".into(), group: None }).describe("Kernel log"));
+        chain.push(nifty_nft::Rule::new().drop().describe("Default drop"));
+        let mut table = nifty_nft::Table::new(nifty_nft::Family::Inet, "nifty_filter");
+        table.chains.push(chain);
+        let ruleset = Ruleset { replace: Vec::new(), tables: vec![table] };
+        let comments = rule_comments(&ruleset);
+        assert_eq!(comments, HashMap::from([("Dropped forward".to_string(), "Log dropped forward".to_string())]));
+    }
+
+    #[test]
+    fn test_icmp_has_no_ports() {
+        let mut p = ipv4_tcp([192, 0, 2, 1], [192, 0, 2, 2], 0, 0);
+        p[9] = 1;
+        let packet = decode_packet(&p).unwrap();
+        assert_eq!((packet.protocol.as_str(), packet.sport, packet.dport), ("icmp", None, None));
+        assert!(decode_packet(&[0x20, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_config_message() {
+        let msg = config_message(5, 1, NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND]);
+        // header + nfgenmsg + one padded attribute
+        assert_eq!(msg.len(), NLMSG_HDRLEN + NFGENMSG_LEN + 8);
+        assert_eq!(u32::from_ne_bytes(msg[0..4].try_into().unwrap()) as usize, msg.len());
+        assert_eq!(&msg[18..20], &5u16.to_be_bytes());
+        assert_eq!(attributes(&msg[20..]), vec![(NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND][..])]);
+    }
+
+    #[test]
+    fn test_write_drops() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("drops.json");
+        let drop = parse_drops(
+            &packet_message("Dropped input", 1, &ipv4_tcp([192, 0, 2, 1], [192, 0, 2, 2], 1, 2)),
+            &|_| None,
+            &HashMap::new(),
+        );
+        write_drops(&path, &drop.into_iter().collect()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json[0]["prefix"], "Dropped input");
+        assert_eq!(json[0]["rule"], serde_json::Value::Null);
+        assert_eq!(json[0]["dport"], 2);
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ipnetwork::IpNetwork;
+use nifty_nft::objects::{AddressBook, Addresses};
+
+use crate::hcl_config::HclConfig;
+use crate::parsers::inter_vlan_rule::RuleAddr;
+use crate::parsers::schedule::{self, TimeWindow};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Host,
+    Group,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Host => "host",
+            Kind::Group => "group",
+        }
+    }
+}
+
+/// An nftables named set rendered from an object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedSet {
+    pub name: String,
+    /// nftables set type: `ipv4_addr`, `ipv6_addr` or `inet_service`
+    pub kind: &'static str,
+    pub interval: bool,
+    pub elements: Vec<String>,
+}
+
+/// Named host, group and service objects. Rules refer to them as `@name`;
+/// each is rendered once as a named set (`host_nas_v4`, `service_web_tcp`).
+/// Schedules are referenced by a `during <name>` rule suffix instead.
+#[derive(Debug, Default)]
+pub struct Objects {
+    addresses: BTreeMap<String, (Kind, Addresses)>,
+    services: BTreeMap<String, (Vec<u16>, Vec<u16>)>,
+    schedules: BTreeMap<String, Vec<TimeWindow>>,
+}
+
+/// Whether `name` can name an object: a letter, then letters, digits, '_'
+/// and '-'.
+pub fn valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Objects {
+    /// Resolve the `host`, `group` and `service` blocks of a config.
+    pub fn from_hcl(config: &HclConfig) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut objects = Objects::default();
+
+        let mut seen = HashSet::new();
+        let names = config.host.keys().map(|n| ("host", n))
+            .chain(config.group.keys().map(|n| ("group", n)))
+            .chain(config.service.keys().map(|n| ("service", n)));
+        let mut names: Vec<_> = names.collect();
+        names.sort();
+        for (kind, name) in names {
+            if !valid_name(name) {
+                errors.push(format!(
+                    "{} \"{}\": names must start with a letter and contain only letters, digits, '_' and '-'.",
+                    kind, name
+                ));
+            }
+            if !seen.insert(name) {
+                errors.push(format!("{} \"{}\": name is already used by another host, group or service.", kind, name));
+            }
+        }
+
+        let mut book = AddressBook::default();
+        let mut hosts: Vec<_> = config.host.iter().collect();
+        hosts.sort_by_key(|(name, _)| name.as_str());
+        for (name, host) in hosts {
+            let mut addrs = Addresses::default();
+            if host.ipv4.is_none() && host.ipv6.is_none() {
+                errors.push(format!("host \"{}\": at least one of ipv4 or ipv6 is required.", name));
+            }
+            if let Some(ipv4) = &host.ipv4 {
+                match ipv4.parse::<Ipv4Addr>() {
+                    Ok(ip) => addrs.push(IpNetwork::from(IpAddr::V4(ip))),
+                    Err(_) => errors.push(format!("host \"{}\".ipv4: invalid IPv4 address '{}'.", name, ipv4)),
+                }
+            }
+            if let Some(ipv6) = &host.ipv6 {
+                match ipv6.parse::<Ipv6Addr>() {
+                    Ok(ip) => addrs.push(IpNetwork::from(IpAddr::V6(ip))),
+                    Err(_) => errors.push(format!("host \"{}\".ipv6: invalid IPv6 address '{}'.", name, ipv6)),
+                }
+            }
+            book.add_host(name, addrs.clone());
+            objects.addresses.insert(name.clone(), (Kind::Host, addrs));
+        }
+
+        let mut groups: Vec<_> = config.group.iter().collect();
+        groups.sort_by_key(|(name, _)| name.as_str());
+        for (name, group) in &groups {
+            book.add_group(name, group.members.clone());
+        }
+        for (name, _) in groups {
+            match book.resolve(name) {
+                Ok(addrs) => {
+                    objects.addresses.insert(name.clone(), (Kind::Group, addrs));
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let mut services: Vec<_> = config.service.iter().collect();
+        services.sort_by_key(|(name, _)| name.as_str());
+        for (name, service) in services {
+            if service.tcp.is_empty() && service.udp.is_empty() {
+                errors.push(format!("service \"{}\": at least one tcp or udp port is required.", name));
+            }
+            if service.tcp.contains(&0) || service.udp.contains(&0) {
+                errors.push(format!("service \"{}\": port 0 is not allowed.", name));
+            }
+            objects.services.insert(name.clone(), (service.tcp.clone(), service.udp.clone()));
+        }
+
+        let mut schedules: Vec<_> = config.schedule.iter().collect();
+        schedules.sort_by_key(|(name, _)| name.as_str());
+        for (name, sched) in schedules {
+            if !valid_name(name) {
+                errors.push(format!(
+                    "schedule \"{}\": names must start with a letter and contain only letters, digits, '_' and '-'.",
+                    name
+                ));
+            }
+            match schedule::time_windows(&sched.days, sched.from.as_deref(), sched.to.as_deref()) {
+                Ok(windows) => {
+                    objects.schedules.insert(name.clone(), windows);
+                }
+                Err(e) => errors.push(format!("schedule \"{}\": {}.", name, e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(objects)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn lookup(&self, reference: &str) -> Result<(&str, Kind, &Addresses), String> {
+        let name = reference.trim_start_matches('@');
+        match self.addresses.get_key_value(name) {
+            Some((name, (kind, addrs))) => Ok((name, *kind, addrs)),
+            None if self.services.contains_key(name) => {
+                Err(format!("'@{}' is a service, not a host or group", name))
+            }
+            None => Err(format!("unknown host or group '@{}'", name)),
+        }
+    }
+
+    /// Addresses of a host or group, for places that need literal values.
+    pub fn addresses(&self, reference: &str) -> Result<&Addresses, String> {
+        self.lookup(reference).map(|(_, _, addrs)| addrs)
+    }
+
+    /// Set references for a host or group, one per address family it has.
+    pub fn address_sets(&self, reference: &str) -> Result<Vec<RuleAddr>, String> {
+        let (name, kind, addrs) = self.lookup(reference)?;
+        let mut sets = Vec::new();
+        if !addrs.ipv4.is_empty() {
+            sets.push(RuleAddr::Set(format!("{}_{}_v4", kind.as_str(), name), true));
+        }
+        if !addrs.ipv6.is_empty() {
+            sets.push(RuleAddr::Set(format!("{}_{}_v6", kind.as_str(), name), false));
+        }
+        Ok(sets)
+    }
+
+    /// Set name holding a service's ports for `proto` ("tcp" or "udp").
+    pub fn port_set(&self, reference: &str, proto: &str) -> Result<String, String> {
+        let name = reference.trim_start_matches('@');
+        let (tcp, udp) = self.services.get(name).ok_or_else(|| {
+            if self.addresses.contains_key(name) {
+                format!("'@{}' is a host or group, not a service", name)
+            } else {
+                format!("unknown service '@{}'", name)
+            }
+        })?;
+        let ports = if proto == "udp" { udp } else { tcp };
+        if ports.is_empty() {
+            return Err(format!("service '@{}' has no {} ports", name, proto));
+        }
+        Ok(format!("service_{}_{}", name, proto))
+    }
+
+    /// The single address a DNAT rule should target for a host: IPv4 when
+    /// the host has one, otherwise IPv6.
+    pub fn host_address(&self, reference: &str) -> Result<IpAddr, String> {
+        let (name, kind, addrs) = self.lookup(reference)?;
+        if kind != Kind::Host {
+            return Err(format!("'@{}' is a group; forwards need a single host", name));
+        }
+        Ok(addrs.ipv4.first().or(addrs.ipv6.first()).unwrap().parse().unwrap())
+    }
+
+    /// Replace a `@host` destination in an `incoming:dest:port` forward, or
+    /// an `incoming:dest` port range forward.
+    pub fn resolve_forward(&self, entry: &str) -> Result<String, String> {
+        let entry = entry.trim();
+        let parts: Vec<&str> = entry.splitn(3, ':').collect();
+        let (incoming, dest, port) = match parts[..] {
+            [incoming, dest, port] if dest.starts_with('@') => (incoming, dest, format!(":{}", port)),
+            [incoming, dest] if dest.starts_with('@') => (incoming, dest, String::new()),
+            _ => return Ok(entry.to_string()),
+        };
+        match self.host_address(dest)? {
+            IpAddr::V4(ip) => Ok(format!("{}:{}{}", incoming, ip, port)),
+            IpAddr::V6(ip) => Ok(format!("{}:[{}]{}", incoming, ip, port)),
+        }
+    }
+
+    /// Expand `@name` entries of an address/CIDR list into their addresses.
+    pub fn expand_addresses(&self, entries: &[String]) -> Result<Vec<String>, String> {
+        let mut out = Vec::new();
+        for entry in entries {
+            if entry.trim().starts_with('@') {
+                let addrs = self.addresses(entry.trim())?;
+                out.extend(addrs.iter().cloned());
+            } else {
+                out.push(entry.clone());
+            }
+        }
+        Ok(out)
+    }
+
+    /// Time windows of a schedule.
+    pub fn schedule(&self, name: &str) -> Result<&[TimeWindow], String> {
+        self.schedules.get(name.trim()).map(|w| w.as_slice())
+            .ok_or_else(|| format!("unknown schedule '{}'", name.trim()))
+    }
+
+    /// Split a `... during <schedule>` suffix off a rule. Returns the rule
+    /// and the windows it applies in; `[None]` when it is unscheduled.
+    pub fn split_schedule<'a>(&self, input: &'a str) -> Result<(&'a str, Vec<Option<TimeWindow>>), String> {
+        match input.rsplit_once(" during ") {
+            Some((rule, name)) => {
+                let windows = self.schedule(name)?.iter().cloned().map(Some).collect();
+                Ok((rule.trim(), windows))
+            }
+            None => Ok((input, vec![None])),
+        }
+    }
+
+    /// Named sets for every object, in name order.
+    pub fn sets(&self) -> Vec<NamedSet> {
+        let mut sets = Vec::new();
+        for (name, (kind, addrs)) in &self.addresses {
+            for (family, kind_name, list) in [("v4", "ipv4_addr", &addrs.ipv4), ("v6", "ipv6_addr", &addrs.ipv6)] {
+                if !list.is_empty() {
+                    sets.push(NamedSet {
+                        name: format!("{}_{}_{}", kind.as_str(), name, family),
+                        kind: kind_name,
+                        interval: true,
+                        elements: list.clone(),
+                    });
+                }
+            }
+        }
+        for (name, (tcp, udp)) in &self.services {
+            for (proto, ports) in [("tcp", tcp), ("udp", udp)] {
+                if !ports.is_empty() {
+                    sets.push(NamedSet {
+                        name: format!("service_{}_{}", name, proto),
+                        kind: "inet_service",
+                        interval: false,
+                        elements: ports.iter().map(|p| p.to_string()).collect(),
+                    });
+                }
+            }
+        }
+        sets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn config(objects: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{ enable_ipv4 = true }}
+{}
+"#,
+            objects
+        ))
+        .unwrap()
+    }
+
+    const OBJECTS: &str = r#"
+host "nas" {
+  ipv4 = "10.99.10.5"
+  ipv6 = "fd00:10::5"
+}
+host "cam1" { ipv4 = "10.99.20.11" }
+group "cameras" { members = ["cam1", "10.99.20.16/28"] }
+group "media" { members = ["cameras", "@nas", "cam1"] }
+service "web" { tcp = [80, 443] }
+service "dns" {
+  tcp = [53]
+  udp = [53]
+}
+"#;
+
+    #[test]
+    fn test_resolve_objects() {
+        let objects = Objects::from_hcl(&config(OBJECTS)).unwrap();
+        assert_eq!(
+            objects.addresses("@media").unwrap(),
+            &Addresses {
+                ipv4: vec!["10.99.20.11".into(), "10.99.20.16/28".into(), "10.99.10.5".into()],
+                ipv6: vec!["fd00:10::5".into()],
+            }
+        );
+        assert_eq!(
+            objects.address_sets("@nas").unwrap(),
+            vec![RuleAddr::Set("host_nas_v4".into(), true), RuleAddr::Set("host_nas_v6".into(), false)]
+        );
+        assert_eq!(objects.address_sets("@cameras").unwrap(), vec![RuleAddr::Set("group_cameras_v4".into(), true)]);
+        assert_eq!(objects.port_set("@web", "tcp").unwrap(), "service_web_tcp");
+        assert_eq!(objects.port_set("@web", "udp").unwrap_err(), "service '@web' has no udp ports");
+        assert_eq!(objects.address_sets("@web").unwrap_err(), "'@web' is a service, not a host or group");
+        assert_eq!(objects.address_sets("@tv").unwrap_err(), "unknown host or group '@tv'");
+    }
+
+    #[test]
+    fn test_sets() {
+        let objects = Objects::from_hcl(&config(OBJECTS)).unwrap();
+        let sets = objects.sets();
+        let names: Vec<_> =
+            sets.iter().map(|s| (s.name.as_str(), s.elements.iter().map(String::as_str).collect::<Vec<_>>())).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("host_cam1_v4", vec!["10.99.20.11"]),
+                ("group_cameras_v4", vec!["10.99.20.11", "10.99.20.16/28"]),
+                ("group_media_v4", vec!["10.99.20.11", "10.99.20.16/28", "10.99.10.5"]),
+                ("group_media_v6", vec!["fd00:10::5"]),
+                ("host_nas_v4", vec!["10.99.10.5"]),
+                ("host_nas_v6", vec!["fd00:10::5"]),
+                ("service_dns_tcp", vec!["53"]),
+                ("service_dns_udp", vec!["53"]),
+                ("service_web_tcp", vec!["80", "443"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forwards_and_expansion() {
+        let objects = Objects::from_hcl(&config(OBJECTS)).unwrap();
+        assert_eq!(objects.resolve_forward("443:@nas:8443").unwrap(), "443:10.99.10.5:8443");
+        assert_eq!(objects.resolve_forward("80:10.99.10.6:80").unwrap(), "80:10.99.10.6:80");
+        assert_eq!(objects.resolve_forward("27015-27030:@nas").unwrap(), "27015-27030:10.99.10.5");
+        assert_eq!(
+            objects.resolve_forward("80:@cameras:80").unwrap_err(),
+            "'@cameras' is a group; forwards need a single host"
+        );
+        assert_eq!(
+            objects.expand_addresses(&["@nas".into(), "10.0.0.0/8".into()]).unwrap(),
+            vec!["10.99.10.5", "fd00:10::5", "10.0.0.0/8"]
+        );
+    }
+
+    #[test]
+    fn test_object_errors() {
+        let errors = Objects::from_hcl(&config(
+            r#"
+host "nas" {}
+host "bad name" { ipv4 = "10.0.0.1" }
+service "nas" { tcp = [0] }
+group "a" { members = ["b"] }
+group "b" { members = ["a"] }
+group "c" { members = ["tv"] }
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "host \"bad name\": names must start with a letter and contain only letters, digits, '_' and '-'.",
+                "service \"nas\": name is already used by another host, group or service.",
+                "host \"nas\": at least one of ipv4 or ipv6 is required.",
+                "group \"a\": members form a cycle (a -> b -> a).",
+                "group \"b\": members form a cycle (b -> a -> b).",
+                "group \"c\".members: 'tv' is not a host, group, address or CIDR.",
+                "service \"nas\": port 0 is not allowed.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schedules() {
+        let objects = Objects::from_hcl(&config(
+            r#"
+schedule "school-nights" {
+  days = ["sun", "mon", "tue", "wed", "thu"]
+  from = "22:00"
+  to   = "06:30"
+}
+"#,
+        ))
+        .unwrap();
+        let (rule, windows) = objects.split_schedule("443:10.99.10.5:443 during school-nights").unwrap();
+        assert_eq!(rule, "443:10.99.10.5:443");
+        assert_eq!(windows.len(), 2);
+        assert_eq!(objects.split_schedule("80").unwrap(), ("80", vec![None]));
+        assert_eq!(objects.split_schedule("80 during never").unwrap_err(), "unknown schedule 'never'");
+
+        let errors = Objects::from_hcl(&config(r#"schedule "late" { from = "22:00" }"#)).unwrap_err();
+        assert_eq!(errors, vec!["schedule \"late\": from and to must be set together."]);
+    }
+}
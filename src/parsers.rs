@@ -1,4 +1,5 @@
 pub mod cidr_list;
+pub mod duration;
 pub mod forward_route;
 pub mod icmp_type;
 pub mod icmpv6_type;
@@ -6,11 +7,17 @@ pub mod inbound_rule;
 pub mod inter_vlan_rule;
 pub mod interface;
 pub mod port;
+pub mod proto_port;
 pub mod qos_class;
+pub mod rate;
+pub mod schedule;
 pub mod subnet;
+pub mod wan_mode;
+pub mod wan_policy;
 
 pub use cidr_list::CidrList;
-pub use forward_route::ForwardRouteList;
+pub use duration::parse_duration;
+pub use forward_route::{ForwardRoute, ForwardRouteList};
 pub use icmp_type::IcmpType;
 pub use icmpv6_type::Icmpv6Type;
 pub use inbound_rule::InboundRuleList;
@@ -18,4 +25,8 @@ pub use inter_vlan_rule::InterVlanRuleList;
 pub use interface::Interface;
 #[allow(unused_imports)]
 pub use port::Port;
+pub use proto_port::ProtoPort;
+pub use rate::Rate;
 pub use subnet::Subnet;
+pub use wan_mode::WanMode;
+pub use wan_policy::WanPolicy;
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+/// Parse a duration such as "30s", "30m", "6h" or "1d". `what` names the
+/// setting in the error message. Durations must fit nftables' 32-bit
+/// timeouts in seconds.
+pub fn parse_duration(input: &str, what: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let err = || format!("Invalid {}: '{}'. Expected e.g. \"30m\", \"6h\" or \"1d\"", what, input);
+    let (index, unit) = input.char_indices().last().ok_or_else(err)?;
+    let number: u64 = input[..index].parse().map_err(|_| err())?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(err()),
+    };
+    match number.checked_mul(seconds) {
+        Some(total) if total <= u64::from(u32::MAX) => Ok(Duration::from_secs(total)),
+        _ => Err(format!("Invalid {}: '{}' is too long", what, input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s", "ban"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m", "ban"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration(" 6h ", "ban"), Ok(Duration::from_secs(6 * 3600)));
+        assert_eq!(parse_duration("1d", "ban"), Ok(Duration::from_secs(86400)));
+        assert_eq!(
+            parse_duration("often", "ban"),
+            Err("Invalid ban: 'often'. Expected e.g. \"30m\", \"6h\" or \"1d\"".to_string())
+        );
+        assert!(parse_duration("h", "ban").is_err());
+        assert!(parse_duration("5w", "ban").is_err());
+        assert!(parse_duration("", "ban").is_err());
+        assert!(parse_duration("1ч", "ban").is_err());
+        assert_eq!(
+            parse_duration("99999999999999999d", "ban"),
+            Err("Invalid ban: '99999999999999999d' is too long".to_string())
+        );
+        assert!(parse_duration("50000d", "ban").is_err());
+    }
+}
@@ -1,23 +1,133 @@
 use std::fmt;
 use std::net::IpAddr;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 
+use ipnetwork::IpNetwork;
+use nifty_nft::{Expr, Value};
+
+use super::inter_vlan_rule::time_match;
+use super::schedule::TimeWindow;
+use crate::logging::LogMode;
+use crate::objects::Objects;
+
+/// Parse an incoming port or `first-last` range.
+fn parse_ports(input: &str) -> Result<(u16, Option<u16>), String> {
+    let port = |p: &str| p.parse::<u16>().ok().filter(|p| *p > 0);
+    match input.split_once('-') {
+        Some((first, last)) => match (port(first), port(last)) {
+            (Some(first), Some(last)) if first < last => Ok((first, Some(last))),
+            _ => Err(format!("Invalid incoming port range: '{}'", input)),
+        },
+        None => port(input).map(|p| (p, None)).ok_or_else(|| format!("Invalid incoming port: '{}'", input)),
+    }
+}
+
+/// Longest `wan.forward` label, so rule comments stay within nft's limit.
+pub const DESCRIPTION_MAX_LEN: usize = 64;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ForwardRoute {
+    /// WAN-side port, or the first port of a range
     pub incoming_port: u16,
+    /// Last port of a range. Each port of a range forwards to the same port
+    /// on the destination.
+    pub range_end: Option<u16>,
     pub destination_ip: IpAddr,
     pub destination_port: u16,
+    /// Only forward from these sources; empty forwards from anywhere
+    pub sources: Vec<IpNetwork>,
+    pub description: Option<String>,
+    /// Only forward during this window (from a `during <schedule>` suffix)
+    pub window: Option<TimeWindow>,
+    /// Logging of the forwarded connections, set by `wan.forward` blocks
+    pub log: LogMode,
 }
 
 impl ForwardRoute {
     pub fn is_ipv4(&self) -> bool {
         self.destination_ip.is_ipv4()
     }
+
+    /// The route's time matches, empty if unscheduled.
+    pub fn time(&self) -> Vec<Expr> {
+        time_match(&self.window)
+    }
+
+    /// Whether a WAN-side port is forwarded by this route.
+    pub fn covers(&self, port: u16) -> bool {
+        (self.incoming_port..=self.range_end.unwrap_or(self.incoming_port)).contains(&port)
+    }
+
+    /// The destination port a WAN-side port forwards to.
+    pub fn destination_for(&self, port: u16) -> u16 {
+        if self.range_end.is_some() { port } else { self.destination_port }
+    }
+
+    /// The ports reached on the destination.
+    pub fn destination_ports(&self) -> RangeInclusive<u16> {
+        self.destination_port..=self.range_end.unwrap_or(self.destination_port)
+    }
+
+    /// The WAN-side port or range, as the right-hand side of a `dport` match.
+    pub fn incoming(&self) -> Value {
+        match self.range_end {
+            Some(end) => Value::literal(format!("{}-{}", self.incoming_port, end)),
+            None => Value::literal(self.incoming_port),
+        }
+    }
+
+    /// The destination port or range, as the right-hand side of a `dport` match.
+    pub fn destination(&self) -> Value {
+        match self.range_end {
+            Some(end) => Value::literal(format!("{}-{}", self.destination_port, end)),
+            None => Value::literal(self.destination_port),
+        }
+    }
+
+    /// The port to rewrite to; ranges keep the port unchanged.
+    pub fn dnat_port(&self) -> Option<u16> {
+        match self.range_end {
+            Some(_) => None,
+            None => Some(self.destination_port),
+        }
+    }
+
+    /// Build a route from its parts. A range forwards to the same ports, so
+    /// its destination port may only be omitted or repeat the range.
+    fn from_parts(input: &str, incoming: &str, destination_ip: IpAddr, destination: Option<&str>) -> Result<Self, String> {
+        let (incoming_port, range_end) = parse_ports(incoming)?;
+        let destination_port = match (range_end, destination) {
+            (Some(_), None) => incoming_port,
+            (Some(_), Some(dest)) if dest == incoming => incoming_port,
+            (Some(_), Some(_)) => {
+                return Err(format!("Port range in '{}' must forward to the same ports on the destination", input));
+            }
+            (None, Some(dest)) => dest
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid destination port in: '{}'", input))?,
+            (None, None) => {
+                return Err(format!("Invalid forward route format: '{}'. Expected format: 'incoming_port:destination_ip:destination_port' (use brackets for IPv6: 'port:[ipv6_addr]:port')", input));
+            }
+        };
+        Ok(ForwardRoute {
+            incoming_port,
+            range_end,
+            destination_ip,
+            destination_port,
+            sources: Vec::new(),
+            description: None,
+            window: None,
+            log: LogMode::Off,
+        })
+    }
 }
 
 impl FromStr for ForwardRoute {
     type Err = String;
 
+    /// `incoming_port:destination_ip:destination_port`, or
+    /// `first-last:destination_ip` for a range of ports forwarded unchanged.
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         // Support IPv6 bracket notation: incoming_port:[ipv6_addr]:destination_port
         // IPv4 format remains: incoming_port:ipv4_addr:destination_port
@@ -26,51 +136,34 @@ impl FromStr for ForwardRoute {
                 .find(']')
                 .ok_or_else(|| format!("Missing closing bracket in forward route: '{}'", input))?;
 
-            let incoming_port = input[..bracket_start]
-                .trim_end_matches(':')
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid incoming port in: '{}'", input))?;
             let destination_ip = input[bracket_start + 1..bracket_end]
                 .parse::<IpAddr>()
                 .map_err(|_| format!("Invalid destination IP in: '{}'", input))?;
-            let destination_port = input[bracket_end + 1..]
-                .trim_start_matches(':')
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid destination port in: '{}'", input))?;
-
-            Ok(ForwardRoute {
-                incoming_port,
-                destination_ip,
-                destination_port,
-            })
+            let destination = input[bracket_end + 1..].trim_start_matches(':');
+            let destination = (!destination.is_empty()).then_some(destination);
+            Self::from_parts(input, input[..bracket_start].trim_end_matches(':'), destination_ip, destination)
         } else {
             let parts: Vec<&str> = input.split(':').collect();
-            if parts.len() != 3 {
+            if parts.len() != 2 && parts.len() != 3 {
                 return Err(format!("Invalid forward route format: '{}'. Expected format: 'incoming_port:destination_ip:destination_port' (use brackets for IPv6: 'port:[ipv6_addr]:port')", input));
             }
 
-            let incoming_port = parts[0]
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid incoming port: '{}'", parts[0]))?;
             let destination_ip = parts[1]
                 .parse::<IpAddr>()
                 .map_err(|_| format!("Invalid destination IP: '{}'", parts[1]))?;
-            let destination_port = parts[2]
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid destination port: '{}'", parts[2]))?;
-
-            Ok(ForwardRoute {
-                incoming_port,
-                destination_ip,
-                destination_port,
-            })
+            Self::from_parts(input, parts[0], destination_ip, parts.get(2).copied())
         }
     }
 }
 
 impl fmt::Display for ForwardRoute {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.destination_ip.is_ipv6() {
+        if let Some(end) = self.range_end {
+            match self.destination_ip {
+                IpAddr::V4(ip) => write!(f, "{}-{}:{}", self.incoming_port, end, ip),
+                IpAddr::V6(ip) => write!(f, "{}-{}:[{}]", self.incoming_port, end, ip),
+            }
+        } else if self.destination_ip.is_ipv6() {
             write!(
                 f,
                 "{}:[{}]:{}",
@@ -108,6 +201,22 @@ impl ForwardRouteList {
         }
     }
 
+    /// Parse a comma-separated list, resolving `@host` destinations and
+    /// `during <schedule>` suffixes.
+    pub fn parse(input: &str, objects: &Objects) -> Result<Self, String> {
+        let mut routes = Vec::new();
+        if !input.is_empty() {
+            for entry in input.split(',').map(str::trim) {
+                let (entry, windows) = objects.split_schedule(entry)?;
+                let route = ForwardRoute::from_str(&objects.resolve_forward(entry)?)?;
+                for window in windows {
+                    routes.push(ForwardRoute { window, ..route.clone() });
+                }
+            }
+        }
+        Ok(Self { routes })
+    }
+
     /// Retrieve the list of forward routes as a `Vec<ForwardRoute>`.
     #[allow(dead_code)]
     pub fn get_routes(&self) -> Vec<ForwardRoute> {
@@ -192,4 +301,31 @@ mod tests {
         assert!(route_list.routes[0].is_ipv4());
         assert!(!route_list.routes[1].is_ipv4());
     }
+
+    #[test]
+    fn test_forward_route_range() {
+        let route = ForwardRoute::from_str("27015-27030:10.99.30.5").unwrap();
+        assert_eq!((route.incoming_port, route.range_end, route.destination_port), (27015, Some(27030), 27015));
+        assert!(route.covers(27020) && !route.covers(27031));
+        assert_eq!(route.destination_for(27020), 27020);
+        assert_eq!(route.dnat_port(), None);
+        assert_eq!(route.incoming().to_string(), "27015-27030");
+        assert_eq!(route.to_string(), "27015-27030:10.99.30.5");
+        assert_eq!(ForwardRoute::from_str("27015-27030:10.99.30.5:27015-27030").unwrap(), route);
+        assert_eq!(ForwardRoute::from_str("5060-5061:[fd00::5]").unwrap().to_string(), "5060-5061:[fd00::5]");
+
+        let single = ForwardRoute::from_str("8443:10.99.30.5:443").unwrap();
+        assert!(single.covers(8443) && !single.covers(443));
+        assert_eq!((single.destination_for(8443), single.dnat_port()), (443, Some(443)));
+
+        assert_eq!(
+            ForwardRoute::from_str("27015-27030:10.99.30.5:28015-28030").unwrap_err(),
+            "Port range in '27015-27030:10.99.30.5:28015-28030' must forward to the same ports on the destination"
+        );
+        assert_eq!(
+            ForwardRoute::from_str("27030-27015:10.99.30.5").unwrap_err(),
+            "Invalid incoming port range: '27030-27015'"
+        );
+        assert!(ForwardRoute::from_str("8080:10.99.30.5").is_err());
+    }
 }
@@ -37,12 +37,3 @@ impl IcmpType {
             .collect()
     }
 }
-
-impl IcmpType {
-    pub fn vec_to_string(vec: &[IcmpType]) -> String {
-        vec.iter()
-            .map(|t| t.to_string())
-            .collect::<Vec<_>>()
-            .join(", ")
-    }
-}
@@ -34,12 +34,3 @@ impl Icmpv6Type {
             .collect()
     }
 }
-
-impl Icmpv6Type {
-    pub fn vec_to_string(vec: &[Icmpv6Type]) -> String {
-        vec.iter()
-            .map(|t| t.to_string())
-            .collect::<Vec<_>>()
-            .join(", ")
-    }
-}
@@ -1,66 +1,147 @@
 use std::fmt;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
 
+use nifty_nft::{Expr, Field, Value};
+
+use super::inter_vlan_rule::{split_rule, time_match, PortSpec, RuleAddr};
+use super::schedule::TimeWindow;
+use crate::objects::Objects;
+use crate::vlan::parse_mac;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InboundRule {
-    pub port: u16,
-    pub address: IpAddr,
+    pub port: PortSpec,
+    pub address: RuleAddr,
+    /// Match only the last N bits of the IPv6 address (`[::abcd:50]/-64`),
+    /// so the rule survives renumbering of the prefix
+    pub suffix: Option<u8>,
+    /// Only match during this window (from a `during <schedule>` suffix)
+    pub window: Option<TimeWindow>,
 }
 
 impl InboundRule {
     pub fn is_ipv4(&self) -> bool {
         self.address.is_ipv4()
     }
+
+    /// The rule's destination match.
+    pub fn daddr(&self) -> Expr {
+        match (&self.address, self.suffix) {
+            (RuleAddr::Ip(ip), Some(bits)) => Expr::MaskedMatch {
+                left: Field::IP6_DADDR,
+                mask: Value::literal(suffix_mask(bits)),
+                right: Value::literal(ip),
+            },
+            (address, _) => Expr::eq(Field::daddr(!address.is_ipv4()), address.value()),
+        }
+    }
+
+    /// The rule's time matches, empty if unscheduled.
+    pub fn time(&self) -> Vec<Expr> {
+        time_match(&self.window)
+    }
+
+    /// Parse `port:address` for `proto`. The port may be a `@service` and
+    /// the address a `@host`/`@group`, giving one rule per address family.
+    /// An IPv6 interface identifier with a `/-N` suffix length, or
+    /// `[eui64:<mac>]`, matches that host under any prefix. A
+    /// `during <schedule>` suffix gives one rule per time window.
+    pub fn parse(input: &str, objects: &Objects, proto: &str) -> Result<Vec<Self>, String> {
+        let (input, windows) = objects.split_schedule(input)?;
+        let rule = input;
+        let (input, mut suffix) = split_suffix(input)?;
+        let parts = split_rule(input)
+            .map_err(|e| format!("Invalid inbound rule: {}", e))?;
+        let [port, address] = parts[..] else {
+            return Err(format!(
+                "Invalid inbound rule format: '{}'. Expected: 'port:address' or 'port:[ipv6_addr]'",
+                input
+            ));
+        };
+        let port = PortSpec::parse_with(port, objects, proto)
+            .map_err(|e| format!("Invalid port in inbound rule '{}': {}", input, e))?;
+        let addresses = match address.strip_prefix("eui64:") {
+            Some(mac) => {
+                suffix = suffix.or(Some(64));
+                vec![RuleAddr::Ip(IpAddr::V6(eui64(mac)?))]
+            }
+            None => RuleAddr::parse(address, objects)
+                .map_err(|e| format!("Invalid address in inbound rule '{}': {}", input, e))?,
+        };
+        if let Some(bits) = suffix {
+            match addresses[..] {
+                [RuleAddr::Ip(IpAddr::V6(id))] if u128::from(id) & !u128::from(suffix_mask(bits)) == 0 => {}
+                [RuleAddr::Ip(IpAddr::V6(id))] => {
+                    return Err(format!("Interface identifier {} in '{}' has bits outside its last {}.", id, rule, bits))
+                }
+                _ => {
+                    return Err(format!(
+                        "Suffix match in '{}' needs an IPv6 interface identifier, e.g. '443:[::abcd:50]/-64'.",
+                        rule
+                    ))
+                }
+            }
+        }
+        let mut rules = Vec::new();
+        for address in addresses {
+            for window in &windows {
+                rules.push(InboundRule {
+                    port: port.clone(),
+                    address: address.clone(),
+                    suffix,
+                    window: window.clone(),
+                });
+            }
+        }
+        Ok(rules)
+    }
+}
+
+/// Split a trailing `/-N` suffix length off a rule.
+fn split_suffix(input: &str) -> Result<(&str, Option<u8>), String> {
+    let Some((rule, bits)) = input.rsplit_once("/-") else {
+        return Ok((input, None));
+    };
+    match bits.parse::<u8>() {
+        Ok(bits) if (1..=127).contains(&bits) => Ok((rule, Some(bits))),
+        _ => Err(format!("Invalid suffix length in inbound rule '{}': expected /-1 to /-127", input)),
+    }
+}
+
+/// The mask selecting the last `bits` bits of an IPv6 address.
+fn suffix_mask(bits: u8) -> Ipv6Addr {
+    Ipv6Addr::from(u128::MAX >> (128 - u32::from(bits)))
+}
+
+/// The modified EUI-64 interface identifier SLAAC derives from a MAC.
+fn eui64(mac: &str) -> Result<Ipv6Addr, String> {
+    let mac = parse_mac(mac)?;
+    let b: Vec<u8> = mac.split(':').map(|p| u8::from_str_radix(p, 16).unwrap()).collect();
+    let id = [b[0] ^ 0x02, b[1], b[2], 0xff, 0xfe, b[3], b[4], b[5]];
+    let mut octets = [0u8; 16];
+    octets[8..].copy_from_slice(&id);
+    Ok(Ipv6Addr::from(octets))
 }
 
 impl FromStr for InboundRule {
     type Err = String;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        // Support IPv6 bracket notation: port:[ipv6_addr]
-        if let Some(bracket_start) = input.find('[') {
-            let bracket_end = input
-                .find(']')
-                .ok_or_else(|| format!("Missing closing bracket in inbound rule: '{}'", input))?;
-
-            let port = input[..bracket_start]
-                .trim_end_matches(':')
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid port in inbound rule: '{}'", input))?;
-            let address = input[bracket_start + 1..bracket_end]
-                .parse::<IpAddr>()
-                .map_err(|_| format!("Invalid address in inbound rule: '{}'", input))?;
-
-            Ok(InboundRule { port, address })
-        } else {
-            // IPv4 format: port:address
-            let parts: Vec<&str> = input.splitn(2, ':').collect();
-            if parts.len() != 2 {
-                return Err(format!(
-                    "Invalid inbound rule format: '{}'. Expected: 'port:address' or 'port:[ipv6_addr]'",
-                    input
-                ));
-            }
-
-            let port = parts[0]
-                .parse::<u16>()
-                .map_err(|_| format!("Invalid port: '{}'", parts[0]))?;
-            let address = parts[1]
-                .parse::<IpAddr>()
-                .map_err(|_| format!("Invalid address: '{}'", parts[1]))?;
-
-            Ok(InboundRule { port, address })
-        }
+        let mut rules = Self::parse(input, &Objects::default(), "tcp")?;
+        Ok(rules.remove(0))
     }
 }
 
 impl fmt::Display for InboundRule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.address.is_ipv6() {
-            write!(f, "{}:[{}]", self.port, self.address)
-        } else {
-            write!(f, "{}:{}", self.port, self.address)
+        match &self.address {
+            RuleAddr::Ip(ip) if ip.is_ipv6() => write!(f, "{}:[{}]", self.port, ip)?,
+            address => write!(f, "{}:{}", self.port, address)?,
+        }
+        match self.suffix {
+            Some(bits) => write!(f, "/-{}", bits),
+            None => Ok(()),
         }
     }
 }
@@ -72,16 +153,18 @@ pub struct InboundRuleList {
 
 impl InboundRuleList {
     pub fn new(input: &str) -> Result<Self, String> {
-        if input.is_empty() {
-            Ok(Self { rules: vec![] })
-        } else {
-            let rules = input
-                .split(',')
-                .map(str::trim)
-                .map(InboundRule::from_str)
-                .collect::<Result<Vec<InboundRule>, _>>()?;
-            Ok(Self { rules })
+        Self::parse(input, &Objects::default(), "tcp")
+    }
+
+    /// Parse a comma-separated list, resolving object references.
+    pub fn parse(input: &str, objects: &Objects, proto: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        if !input.is_empty() {
+            for rule in input.split(',').map(str::trim) {
+                rules.extend(InboundRule::parse(rule, objects, proto)?);
+            }
         }
+        Ok(Self { rules })
     }
 
     pub fn len(&self) -> usize {
@@ -106,6 +189,7 @@ impl fmt::Display for InboundRuleList {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::IpAddr;
 
     #[test]
     fn test_ipv4_parse() {
@@ -149,6 +233,30 @@ mod tests {
         assert_eq!(rule.to_string(), "80:10.0.0.1");
     }
 
+    #[test]
+    fn test_suffix_parse() {
+        let rule = InboundRule::from_str("443:[::abcd:50]/-64").unwrap();
+        assert_eq!(rule.address, "::abcd:50".parse::<IpAddr>().unwrap());
+        assert_eq!(rule.suffix, Some(64));
+        assert_eq!(rule.to_string(), "443:[::abcd:50]/-64");
+        assert_eq!(rule.daddr().to_string(), "ip6 daddr & ::ffff:ffff:ffff:ffff == ::abcd:50");
+
+        let rule = InboundRule::from_str("443:[eui64:52:54:00:12:34:56]").unwrap();
+        assert_eq!(rule.address, "::5054:ff:fe12:3456".parse::<IpAddr>().unwrap());
+        assert_eq!(rule.suffix, Some(64));
+
+        let rule = InboundRule::from_str("443:[2001:db8::50]").unwrap();
+        assert_eq!(rule.daddr().to_string(), "ip6 daddr 2001:db8::50");
+
+        assert_eq!(
+            InboundRule::from_str("443:[2001:db8::50]/-64").unwrap_err(),
+            "Interface identifier 2001:db8::50 in '443:[2001:db8::50]/-64' has bits outside its last 64."
+        );
+        assert!(InboundRule::from_str("443:10.0.0.5/-8").is_err());
+        assert!(InboundRule::from_str("443:[::50]/-128").is_err());
+        assert!(InboundRule::from_str("443:[eui64:52:54:00:12:34]").is_err());
+    }
+
     #[test]
     fn test_invalid_format() {
         assert!(InboundRule::from_str("noport").is_err());
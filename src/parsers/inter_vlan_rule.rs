@@ -2,15 +2,23 @@ use std::fmt;
 use std::net::IpAddr;
 use std::str::FromStr;
 
-/// A port specification: either a single port or an inclusive range.
+use nifty_nft::{Expr, Value};
+
+use super::schedule::TimeWindow;
+use crate::logging::LogMode;
+use crate::objects::Objects;
+
+/// A port specification: a single port, an inclusive range, or a service
+/// object's named set.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PortSpec {
     Single(u16),
     Range(u16, u16),
+    Set(String),
 }
 
 impl PortSpec {
-    fn parse(input: &str) -> Result<Self, String> {
+    pub fn parse(input: &str) -> Result<Self, String> {
         if let Some((start_str, end_str)) = input.split_once('-') {
             let start = start_str
                 .parse::<u16>()
@@ -32,6 +40,29 @@ impl PortSpec {
             Ok(PortSpec::Single(port))
         }
     }
+
+    /// Parse a port, range, or `@service` reference for `proto`.
+    pub fn parse_with(input: &str, objects: &Objects, proto: &str) -> Result<Self, String> {
+        if input.starts_with('@') {
+            objects.port_set(input, proto).map(PortSpec::Set)
+        } else {
+            Self::parse(input)
+        }
+    }
+
+    /// The port as the right-hand side of a `dport` match.
+    pub fn value(&self) -> Value {
+        match self {
+            PortSpec::Set(name) => Value::SetRef(name.clone()),
+            port => Value::literal(port),
+        }
+    }
+}
+
+impl PartialEq<u16> for PortSpec {
+    fn eq(&self, other: &u16) -> bool {
+        *self == PortSpec::Single(*other)
+    }
 }
 
 impl fmt::Display for PortSpec {
@@ -39,6 +70,99 @@ impl fmt::Display for PortSpec {
         match self {
             PortSpec::Single(p) => write!(f, "{}", p),
             PortSpec::Range(start, end) => write!(f, "{}-{}", start, end),
+            PortSpec::Set(name) => write!(f, "@{}", name),
+        }
+    }
+}
+
+/// An address in a rule: a literal, or the named set of a host or group
+/// object for one family.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleAddr {
+    Ip(IpAddr),
+    /// Set name and whether it holds IPv4 addresses
+    Set(String, bool),
+}
+
+impl RuleAddr {
+    pub fn is_ipv4(&self) -> bool {
+        match self {
+            RuleAddr::Ip(ip) => ip.is_ipv4(),
+            RuleAddr::Set(_, ipv4) => *ipv4,
+        }
+    }
+
+    /// Parse a literal address (IPv6 without brackets) or an `@name`
+    /// reference, which yields one set per family the object has.
+    pub fn parse(input: &str, objects: &Objects) -> Result<Vec<Self>, String> {
+        if input.starts_with('@') {
+            objects.address_sets(input)
+        } else {
+            input
+                .parse::<IpAddr>()
+                .map(|ip| vec![RuleAddr::Ip(ip)])
+                .map_err(|_| format!("Invalid address: '{}'", input))
+        }
+    }
+
+    /// The address as the right-hand side of a `saddr`/`daddr` match.
+    pub fn value(&self) -> Value {
+        match self {
+            RuleAddr::Ip(ip) => Value::literal(ip),
+            RuleAddr::Set(name, _) => Value::SetRef(name.clone()),
+        }
+    }
+}
+
+impl PartialEq<IpAddr> for RuleAddr {
+    fn eq(&self, other: &IpAddr) -> bool {
+        *self == RuleAddr::Ip(*other)
+    }
+}
+
+impl fmt::Display for RuleAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleAddr::Ip(ip) => write!(f, "{}", ip),
+            RuleAddr::Set(name, _) => write!(f, "@{}", name),
+        }
+    }
+}
+
+/// The matches of an optional time window.
+pub fn time_match(window: &Option<TimeWindow>) -> Vec<Expr> {
+    window.as_ref().map(TimeWindow::exprs).unwrap_or_default()
+}
+
+/// Split a rule on ':' outside of brackets, stripping the brackets from
+/// IPv6 addresses.
+pub fn split_rule(input: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut rest = input;
+    loop {
+        if let Some(inner) = rest.strip_prefix('[') {
+            let end = inner
+                .find(']')
+                .ok_or_else(|| format!("Missing closing bracket in '{}'", input))?;
+            parts.push(&inner[..end]);
+            rest = &inner[end + 1..];
+            if rest.is_empty() {
+                return Ok(parts);
+            }
+            rest = rest
+                .strip_prefix(':')
+                .ok_or_else(|| format!("Expected ':' after address in '{}'", input))?;
+        } else {
+            match rest.split_once(':') {
+                Some((part, tail)) => {
+                    parts.push(part);
+                    rest = tail;
+                }
+                None => {
+                    parts.push(rest);
+                    return Ok(parts);
+                }
+            }
         }
     }
 }
@@ -49,11 +173,14 @@ impl fmt::Display for PortSpec {
 /// 3-tuple format: `src:dest:port` — allow only src to reach dest:port
 ///
 /// Port can be a single port (e.g. `8008`) or a range (e.g. `32768-61000`).
+/// Addresses may be `@host`/`@group` objects and the port a `@service`.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct InterVlanRule {
-    pub dest: IpAddr,
+    pub dest: RuleAddr,
     pub port: PortSpec,
-    pub src: Option<IpAddr>,
+    pub src: Option<RuleAddr>,
+    /// Only match during this window (from a `during <schedule>` suffix)
+    pub window: Option<TimeWindow>,
 }
 
 impl InterVlanRule {
@@ -66,131 +193,95 @@ impl InterVlanRule {
     }
 
     pub fn src_is_ipv4(&self) -> bool {
-        self.src.map_or(false, |s| s.is_ipv4())
+        self.src.as_ref().is_some_and(|s| s.is_ipv4())
+    }
+
+    /// The rule's time matches, empty if unscheduled.
+    pub fn time(&self) -> Vec<Expr> {
+        time_match(&self.window)
     }
-}
 
-/// Parse an IPv6 bracket address starting at `input[start..]`.
-/// Returns (IpAddr, end_index_after_bracket).
-fn parse_bracketed_ipv6(input: &str, start: usize) -> Result<(IpAddr, usize), String> {
-    if input.as_bytes().get(start) != Some(&b'[') {
-        return Err(format!("Expected '[' at position {} in '{}'", start, input));
-    }
-    let bracket_end = input[start..]
-        .find(']')
-        .ok_or_else(|| format!("Missing closing bracket in '{}'", input))?
-        + start;
-    let addr = input[start + 1..bracket_end]
-        .parse::<IpAddr>()
-        .map_err(|_| format!("Invalid IPv6 address in '{}'", input))?;
-    Ok((addr, bracket_end + 1))
+    /// Parse a rule for `proto`, expanding object references into one rule
+    /// per address family they cover, and schedules into one per window.
+    pub fn parse(input: &str, objects: &Objects, proto: &str) -> Result<Vec<Self>, String> {
+        let (input, windows) = objects.split_schedule(input.trim())?;
+        let parts = split_rule(input)?;
+        let (src, dest, port) = match parts[..] {
+            [dest, port] => (None, dest, port),
+            [src, dest, port] => (Some(src), dest, port),
+            _ => {
+                return Err(format!(
+                    "Invalid inter-VLAN rule format: '{}'. Expected 'dest:port' or 'src:dest:port'",
+                    input
+                ))
+            }
+        };
+        let dests = RuleAddr::parse(dest, objects)
+            .map_err(|e| format!("Invalid destination in '{}': {}", input, e))?;
+        let srcs = match src {
+            Some(src) => RuleAddr::parse(src, objects)
+                .map_err(|e| format!("Invalid source in '{}': {}", input, e))?
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => vec![None],
+        };
+        let port = PortSpec::parse_with(port, objects, proto)
+            .map_err(|e| format!("Invalid port in '{}': {}", input, e))?;
+
+        let mut rules = Vec::new();
+        for dest in &dests {
+            for src in &srcs {
+                if src.as_ref().is_none_or(|s| s.is_ipv4() == dest.is_ipv4()) {
+                    for window in &windows {
+                        rules.push(InterVlanRule {
+                            dest: dest.clone(),
+                            port: port.clone(),
+                            src: src.clone(),
+                            window: window.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if rules.is_empty() {
+            return Err(format!("Source and destination in '{}' share no address family", input));
+        }
+        Ok(rules)
+    }
 }
 
 impl FromStr for InterVlanRule {
     type Err = String;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let input = input.trim();
-
-        // Detect IPv6 by bracket presence
-        if input.contains('[') {
-            // Could be [dest]:port or [src]:[dest]:port
-            let (first_addr, after_first) = parse_bracketed_ipv6(input, 0)?;
-
-            // After first bracket group, expect ':'
-            if input.as_bytes().get(after_first) != Some(&b':') {
-                return Err(format!("Expected ':' after address in '{}'", input));
-            }
-            let rest = &input[after_first + 1..];
-
-            if rest.starts_with('[') {
-                // 3-tuple: [src]:[dest]:port
-                let (second_addr, after_second) = parse_bracketed_ipv6(rest, 0)?;
-                if rest.as_bytes().get(after_second) != Some(&b':') {
-                    return Err(format!("Expected ':' after second address in '{}'", input));
-                }
-                let port = PortSpec::parse(&rest[after_second + 1..])
-                    .map_err(|_| format!("Invalid port in '{}'", input))?;
-                Ok(InterVlanRule {
-                    dest: second_addr,
-                    port,
-                    src: Some(first_addr),
-                })
-            } else {
-                // 2-tuple: [dest]:port
-                let port = PortSpec::parse(rest)
-                    .map_err(|_| format!("Invalid port in '{}'", input))?;
-                Ok(InterVlanRule {
-                    dest: first_addr,
-                    port,
-                    src: None,
-                })
-            }
-        } else {
-            // IPv4: dest:port or src:dest:port (port may contain '-' for ranges)
-            let parts: Vec<&str> = input.split(':').collect();
-            match parts.len() {
-                2 => {
-                    // dest:port
-                    let dest = parts[0]
-                        .parse::<IpAddr>()
-                        .map_err(|_| format!("Invalid destination address: '{}'", parts[0]))?;
-                    let port = PortSpec::parse(parts[1])
-                        .map_err(|_| format!("Invalid port: '{}'", parts[1]))?;
-                    Ok(InterVlanRule {
-                        dest,
-                        port,
-                        src: None,
-                    })
-                }
-                3 => {
-                    // src:dest:port
-                    let src = parts[0]
-                        .parse::<IpAddr>()
-                        .map_err(|_| format!("Invalid source address: '{}'", parts[0]))?;
-                    let dest = parts[1]
-                        .parse::<IpAddr>()
-                        .map_err(|_| format!("Invalid destination address: '{}'", parts[1]))?;
-                    let port = PortSpec::parse(parts[2])
-                        .map_err(|_| format!("Invalid port: '{}'", parts[2]))?;
-                    Ok(InterVlanRule {
-                        dest,
-                        port,
-                        src: Some(src),
-                    })
-                }
-                _ => Err(format!(
-                    "Invalid inter-VLAN rule format: '{}'. Expected 'dest:port' or 'src:dest:port'",
-                    input
-                )),
-            }
-        }
+        let mut rules = Self::parse(input, &Objects::default(), "tcp")?;
+        Ok(rules.remove(0))
     }
 }
 
 impl fmt::Display for InterVlanRule {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = |a: &RuleAddr| match a {
+            RuleAddr::Ip(ip) if ip.is_ipv6() => format!("[{}]", ip),
+            _ => a.to_string(),
+        };
         if let Some(src) = &self.src {
-            if src.is_ipv6() {
-                write!(f, "[{}]:", src)?;
-            } else {
-                write!(f, "{}:", src)?;
-            }
-        }
-        if self.dest.is_ipv6() {
-            write!(f, "[{}]:{}", self.dest, self.port)
-        } else {
-            write!(f, "{}:{}", self.dest, self.port)
+            write!(f, "{}:", addr(src))?;
         }
+        write!(f, "{}:{}", addr(&self.dest), self.port)
     }
 }
 
-/// A keyed set of inter-VLAN allow rules, indexed by source VLAN ID.
+/// A keyed set of inter-VLAN allow rules, indexed by source zone.
 #[derive(Debug, PartialEq, Eq)]
 pub struct InterVlanRuleEntry {
-    pub source_vlan_id: u16,
+    /// Human-readable source zone, e.g. "VLAN 10" or "WireGuard wg0".
+    pub source_label: String,
     pub source_interface: String,
     pub rules: Vec<InterVlanRule>,
+    /// Logging of the allowed connections, from the `allow_from` block
+    pub log: LogMode,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -209,23 +300,26 @@ impl InterVlanRuleList {
 
     pub fn add_entry(
         &mut self,
-        source_vlan_id: u16,
+        source_label: String,
         source_interface: String,
         input: &str,
+        objects: &Objects,
+        proto: &str,
+        log: LogMode,
     ) -> Result<(), String> {
         if input.is_empty() {
             return Ok(());
         }
-        let rules = input
-            .split(',')
-            .map(str::trim)
-            .map(InterVlanRule::from_str)
-            .collect::<Result<Vec<InterVlanRule>, _>>()?;
+        let mut rules = Vec::new();
+        for rule in input.split(',').map(str::trim) {
+            rules.extend(InterVlanRule::parse(rule, objects, proto)?);
+        }
         if !rules.is_empty() {
             self.entries.push(InterVlanRuleEntry {
-                source_vlan_id,
+                source_label,
                 source_interface,
                 rules,
+                log,
             });
         }
         Ok(())
@@ -248,7 +342,7 @@ mod tests {
     #[test]
     fn test_ipv4_3tuple() {
         let rule = InterVlanRule::from_str("10.99.10.50:10.99.40.5:443").unwrap();
-        assert_eq!(rule.src.unwrap(), "10.99.10.50".parse::<IpAddr>().unwrap());
+        assert_eq!(rule.src.clone().unwrap(), "10.99.10.50".parse::<IpAddr>().unwrap());
         assert_eq!(rule.dest, "10.99.40.5".parse::<IpAddr>().unwrap());
         assert_eq!(rule.port, PortSpec::Single(443));
         assert!(rule.has_src());
@@ -268,7 +362,7 @@ mod tests {
     fn test_ipv6_3tuple() {
         let rule = InterVlanRule::from_str("[fd00:10::50]:[fd00:40::5]:443").unwrap();
         assert_eq!(
-            rule.src.unwrap(),
+            rule.src.clone().unwrap(),
             "fd00:10::50".parse::<IpAddr>().unwrap()
         );
         assert_eq!(rule.dest, "fd00:40::5".parse::<IpAddr>().unwrap());
@@ -286,7 +380,7 @@ mod tests {
     #[test]
     fn test_ipv4_3tuple_port_range() {
         let rule = InterVlanRule::from_str("10.99.10.50:10.99.20.5:32768-61000").unwrap();
-        assert_eq!(rule.src.unwrap(), "10.99.10.50".parse::<IpAddr>().unwrap());
+        assert_eq!(rule.src.clone().unwrap(), "10.99.10.50".parse::<IpAddr>().unwrap());
         assert_eq!(rule.dest, "10.99.20.5".parse::<IpAddr>().unwrap());
         assert_eq!(rule.port, PortSpec::Range(32768, 61000));
     }
@@ -335,10 +429,10 @@ mod tests {
     #[test]
     fn test_list_add_entry() {
         let mut list = InterVlanRuleList::new();
-        list.add_entry(10, "trusted".to_string(), "10.99.40.5:80, 10.99.10.50:10.99.40.5:443")
+        list.add_entry("VLAN 10".to_string(), "trusted".to_string(), "10.99.40.5:80, 10.99.10.50:10.99.40.5:443", &Objects::default(), "tcp", LogMode::Off)
             .unwrap();
         assert_eq!(list.len(), 1);
-        assert_eq!(list.entries[0].source_vlan_id, 10);
+        assert_eq!(list.entries[0].source_label, "VLAN 10");
         assert_eq!(list.entries[0].source_interface, "trusted");
         assert_eq!(list.entries[0].rules.len(), 2);
     }
@@ -346,7 +440,7 @@ mod tests {
     #[test]
     fn test_list_empty_input() {
         let mut list = InterVlanRuleList::new();
-        list.add_entry(10, "trusted".to_string(), "").unwrap();
+        list.add_entry("VLAN 10".to_string(), "trusted".to_string(), "", &Objects::default(), "tcp", LogMode::Off).unwrap();
         assert_eq!(list.len(), 0);
     }
 
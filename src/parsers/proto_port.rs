@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// A protocol and port, written "tcp/22" or "udp/51820".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtoPort {
+    /// "tcp" or "udp"
+    pub proto: &'static str,
+    pub port: u16,
+}
+
+impl ProtoPort {
+    pub fn new(input: &str) -> Result<Self, String> {
+        let (proto, port) = match input.split_once('/') {
+            Some(("tcp", port)) => ("tcp", port),
+            Some(("udp", port)) => ("udp", port),
+            _ => return Err(format!("'{}' must look like \"tcp/22\" or \"udp/51820\".", input)),
+        };
+        match port.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(ProtoPort { proto, port }),
+            _ => Err(format!("invalid port '{}'.", port)),
+        }
+    }
+}
+
+impl fmt::Display for ProtoPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.proto, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proto_port() {
+        assert_eq!(ProtoPort::new("tcp/22"), Ok(ProtoPort { proto: "tcp", port: 22 }));
+        assert_eq!(ProtoPort::new("udp/51820").unwrap().to_string(), "udp/51820");
+        assert_eq!(ProtoPort::new("ssh"), Err("'ssh' must look like \"tcp/22\" or \"udp/51820\".".to_string()));
+        assert_eq!(ProtoPort::new("icmp/1"), Err("'icmp/1' must look like \"tcp/22\" or \"udp/51820\".".to_string()));
+        assert_eq!(ProtoPort::new("tcp/0"), Err("invalid port '0'.".to_string()));
+        assert!(ProtoPort::new("tcp/70000").is_err());
+    }
+}
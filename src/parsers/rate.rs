@@ -0,0 +1,86 @@
+use std::fmt;
+
+use nifty_nft::Expr;
+
+/// A packet rate such as "10/second" or "10/second burst 20".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rate {
+    pub count: u32,
+    pub per: String,
+    pub burst: Option<u32>,
+}
+
+const UNITS: [(&str, u32); 4] = [("second", 1), ("minute", 60), ("hour", 3600), ("day", 86400)];
+
+impl Rate {
+    pub fn new(input: &str) -> Result<Self, String> {
+        let err = || {
+            format!(
+                "Invalid rate: '{}'. Expected e.g. \"10/second\" or \"10/second burst 20\" (per second, minute, hour or day)",
+                input
+            )
+        };
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let (rate, burst) = match words.as_slice() {
+            [rate] => (*rate, None),
+            [rate, "burst", burst] => (*rate, Some(burst.parse::<u32>().ok().filter(|b| *b > 0).ok_or_else(err)?)),
+            _ => return Err(err()),
+        };
+        let (count, per) = rate.split_once('/').ok_or_else(err)?;
+        let count = count.parse::<u32>().ok().filter(|c| *c > 0).ok_or_else(err)?;
+        if !UNITS.iter().any(|(unit, _)| *unit == per) {
+            return Err(err());
+        }
+        Ok(Rate { count, per: per.to_string(), burst })
+    }
+
+    /// Length of one `per` unit in seconds.
+    pub fn window(&self) -> u32 {
+        UNITS.iter().find(|(unit, _)| *unit == self.per).map_or(1, |(_, secs)| *secs)
+    }
+
+    /// `limit rate ...`, matching packets within the rate.
+    pub fn limit(&self) -> Expr {
+        Expr::Limit { rate: self.count, per: self.per.clone(), burst: self.burst, over: false }
+    }
+
+    /// `limit rate over ...`, matching packets beyond the rate.
+    pub fn over(&self) -> Expr {
+        Expr::Limit { rate: self.count, per: self.per.clone(), burst: self.burst, over: true }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.count, self.per)?;
+        match self.burst {
+            Some(burst) => write!(f, " burst {}", burst),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_new() {
+        let rate = Rate::new("10/second burst 20").unwrap();
+        assert_eq!(rate, Rate { count: 10, per: "second".into(), burst: Some(20) });
+        assert_eq!(rate.to_string(), "10/second burst 20");
+        assert_eq!(rate.over().to_string(), "limit rate over 10/second burst 20 packets");
+
+        let rate = Rate::new("5/minute").unwrap();
+        assert_eq!(rate.burst, None);
+        assert_eq!(rate.window(), 60);
+    }
+
+    #[test]
+    fn test_rate_invalid() {
+        for input in ["", "10", "0/second", "10/week", "10/second burst", "10/second burst 0", "10/second bursts 5"] {
+            let err = Rate::new(input).unwrap_err();
+            assert!(err.contains("Invalid rate"), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use nifty_nft::{Expr, Field, Value};
+
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// A span of time matched with nftables `meta day` and `meta hour`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    /// Days of the week, 0 = Sunday. Empty means every day.
+    pub days: Vec<u8>,
+    /// Inclusive start and end in minutes after midnight. `None` covers
+    /// the whole day.
+    pub hours: Option<(u32, u32)>,
+}
+
+impl TimeWindow {
+    /// The `meta day` and `meta hour` matches of the window.
+    pub fn exprs(&self) -> Vec<Expr> {
+        let mut exprs = Vec::new();
+        if !self.days.is_empty() {
+            let days = self.days.iter().map(|d| Value::str(DAYS[*d as usize])).collect();
+            exprs.push(Expr::eq(Field::DAY, Value::Set(days)));
+        }
+        if let Some((from, to)) = self.hours {
+            // The end of the day is the last second, nftables has no 24:00
+            let end = if to >= 24 * 60 {
+                "23:59:59".to_string()
+            } else {
+                format!("{:02}:{:02}", to / 60, to % 60)
+            };
+            let start = format!("{:02}:{:02}", from / 60, from % 60);
+            exprs.push(Expr::eq(Field::HOUR, Value::Range(Box::new(Value::Str(start)), Box::new(Value::Str(end)))));
+        }
+        exprs
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.exprs().iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+fn parse_day(input: &str) -> Result<u8, String> {
+    let lower = input.trim().to_ascii_lowercase();
+    DAYS.iter()
+        .position(|d| {
+            let d = d.to_ascii_lowercase();
+            lower == d || (lower.len() >= 3 && d.starts_with(&lower))
+        })
+        .map(|i| i as u8)
+        .ok_or_else(|| format!("Invalid day: '{}'", input))
+}
+
+/// Parse "HH:MM" into minutes after midnight; "24:00" means end of day.
+fn parse_time(input: &str) -> Result<u32, String> {
+    let err = || format!("Invalid time: '{}'. Expected HH:MM", input);
+    let (h, m) = input.trim().split_once(':').ok_or_else(err)?;
+    let (h, m) = (h.parse::<u32>().map_err(|_| err())?, m.parse::<u32>().map_err(|_| err())?);
+    if m > 59 || h > 24 || (h == 24 && m != 0) {
+        return Err(err());
+    }
+    Ok(h * 60 + m)
+}
+
+/// Turn a schedule's days and hours into nftables time windows. A window
+/// that crosses midnight is split so the early-morning part lands on the
+/// following days.
+pub fn time_windows(days: &[String], from: Option<&str>, to: Option<&str>) -> Result<Vec<TimeWindow>, String> {
+    let mut day_list = days.iter().map(|d| parse_day(d)).collect::<Result<Vec<u8>, _>>()?;
+    day_list.sort();
+    day_list.dedup();
+    if day_list.len() == 7 {
+        day_list.clear();
+    }
+
+    let (from, to) = match (from, to) {
+        (Some(from), Some(to)) => (parse_time(from)?, parse_time(to)?),
+        (None, None) => {
+            if day_list.is_empty() {
+                return Err("a schedule needs days, or from and to times".to_string());
+            }
+            return Ok(vec![TimeWindow { days: day_list, hours: None }]);
+        }
+        _ => return Err("from and to must be set together".to_string()),
+    };
+    if from == to {
+        return Err("from and to must differ".to_string());
+    }
+    if from == 24 * 60 {
+        return Err("from must be before 24:00".to_string());
+    }
+
+    if from < to {
+        return Ok(vec![TimeWindow { days: day_list, hours: Some((from, to)) }]);
+    }
+    let mut windows = vec![TimeWindow { days: day_list.clone(), hours: Some((from, 24 * 60)) }];
+    if to > 0 {
+        let mut next_days: Vec<u8> = day_list.iter().map(|d| (d + 1) % 7).collect();
+        next_days.sort();
+        windows.push(TimeWindow { days: next_days, hours: Some((0, to)) });
+    }
+    Ok(windows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_daytime_window() {
+        let windows = time_windows(&days(&["sat", "Sunday"]), Some("09:00"), Some("17:30")).unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(
+            windows[0].to_string(),
+            r#"meta day { "Sunday", "Saturday" } meta hour "09:00"-"17:30""#
+        );
+    }
+
+    #[test]
+    fn test_overnight_window_splits() {
+        let windows = time_windows(&days(&["sun", "mon", "tue", "wed", "thu"]), Some("22:00"), Some("06:30")).unwrap();
+        let rendered: Vec<String> = windows.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            rendered,
+            vec![
+                r#"meta day { "Sunday", "Monday", "Tuesday", "Wednesday", "Thursday" } meta hour "22:00"-"23:59:59""#,
+                r#"meta day { "Monday", "Tuesday", "Wednesday", "Thursday", "Friday" } meta hour "00:00"-"06:30""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_every_day_and_whole_days() {
+        let windows = time_windows(&[], Some("23:00"), Some("00:00")).unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].to_string(), r#"meta hour "23:00"-"23:59:59""#);
+
+        let windows = time_windows(&days(&["sat", "sun"]), None, None).unwrap();
+        assert_eq!(windows[0].to_string(), r#"meta day { "Sunday", "Saturday" }"#);
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        assert!(time_windows(&days(&["funday"]), Some("09:00"), Some("17:00")).is_err());
+        assert!(time_windows(&[], Some("9am"), Some("17:00")).is_err());
+        assert!(time_windows(&[], Some("09:00"), None).is_err());
+        assert!(time_windows(&[], Some("09:00"), Some("09:00")).is_err());
+        assert!(time_windows(&[], Some("25:00"), Some("09:00")).is_err());
+        assert!(time_windows(&[], None, None).is_err());
+    }
+}
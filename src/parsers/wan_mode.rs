@@ -0,0 +1,47 @@
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString};
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, EnumIter, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum WanMode {
+    Dhcp,
+    Static,
+    Pppoe,
+}
+
+impl WanMode {
+    pub fn new(input: &str) -> Result<Self, String> {
+        input.to_lowercase().parse::<WanMode>().map_err(|_| {
+            format!(
+                "Invalid WAN mode: '{}'. Acceptable values are: {}",
+                input,
+                WanMode::variants().join(", ")
+            )
+        })
+    }
+
+    fn variants() -> Vec<String> {
+        WanMode::iter()
+            .map(|variant| variant.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wan_mode_from_string() {
+        assert_eq!(WanMode::new("dhcp").unwrap(), WanMode::Dhcp);
+        assert_eq!(WanMode::new("Static").unwrap(), WanMode::Static);
+        assert_eq!(WanMode::new("PPPoE").unwrap(), WanMode::Pppoe);
+    }
+
+    #[test]
+    fn test_wan_mode_invalid() {
+        let err = WanMode::new("slaac").unwrap_err();
+        assert!(err.contains("Invalid WAN mode"));
+        assert!(err.contains("dhcp, static, pppoe"));
+    }
+}
@@ -0,0 +1,46 @@
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString};
+
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, EnumIter, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum WanPolicy {
+    Failover,
+    Balance,
+}
+
+impl WanPolicy {
+    pub fn new(input: &str) -> Result<Self, String> {
+        input.to_lowercase().parse::<WanPolicy>().map_err(|_| {
+            format!(
+                "Invalid WAN policy: '{}'. Acceptable values are: {}",
+                input,
+                WanPolicy::variants().join(", ")
+            )
+        })
+    }
+
+    fn variants() -> Vec<String> {
+        WanPolicy::iter()
+            .map(|variant| variant.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wan_policy_from_string() {
+        assert_eq!(WanPolicy::new("failover").unwrap(), WanPolicy::Failover);
+        assert_eq!(WanPolicy::new("Balance").unwrap(), WanPolicy::Balance);
+    }
+
+    #[test]
+    fn test_wan_policy_invalid() {
+        let err = WanPolicy::new("roundrobin").unwrap_err();
+        assert!(err.contains("Invalid WAN policy"));
+        assert!(err.contains("failover"));
+        assert!(err.contains("balance"));
+    }
+}
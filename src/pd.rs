@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use ipnetwork::IpNetwork;
+use nifty_nft::Ruleset;
+
+use crate::hcl_config::HclConfig;
+
+/// Named nftables set holding the current delegated prefix of a VLAN.
+/// Rules match `ip6 saddr @<set>` instead of a literal subnet.
+pub fn set_name(vlan_id: u16) -> String {
+    format!("pd_vlan_{}", vlan_id)
+}
+
+/// A VLAN whose IPv6 subnet comes from the WAN's delegated prefix.
+#[derive(Debug, PartialEq)]
+pub struct DelegatedVlan {
+    pub id: u16,
+    pub interface_name: String,
+}
+
+/// All VLANs with `ipv6.delegated = true`, sorted by ID.
+pub fn delegated_vlans(config: &HclConfig) -> Vec<DelegatedVlan> {
+    let trunk = config.interfaces.trunk_name();
+    let mut entries: Vec<_> = config.vlan.iter().collect();
+    entries.sort_by_key(|(_, v)| v.id);
+    entries.into_iter()
+        .filter(|(_, v)| v.ipv6.as_ref().is_some_and(|i| i.delegated))
+        .map(|(name, v)| {
+            let interface_name = if let Some(ref dedicated) = v.interface {
+                dedicated.name.clone()
+            } else if v.id == 1 && !config.vlan_aware_switch {
+                trunk.to_string()
+            } else {
+                name.to_string()
+            };
+            DelegatedVlan { id: v.id, interface_name }
+        })
+        .collect()
+}
+
+/// Extract global prefixes from `ip -6 -o addr show` output, e.g.
+/// `2001:db8:0:40::1/64` becomes `2001:db8:0:40::/64`.
+fn parse_prefixes(output: &str) -> Vec<String> {
+    let mut prefixes: Vec<String> = output.lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            tokens.find(|t| *t == "inet6")?;
+            let net: IpNetwork = tokens.next()?.parse().ok()?;
+            Some(format!("{}/{}", net.network(), net.prefix()))
+        })
+        .collect();
+    prefixes.sort();
+    prefixes.dedup();
+    prefixes
+}
+
+/// Global IPv6 prefixes currently assigned to an interface.
+fn interface_prefixes(iface: &str) -> Vec<String> {
+    Command::new("ip")
+        .args(["-6", "-o", "addr", "show", "dev", iface, "scope", "global"])
+        .output()
+        .map(|o| parse_prefixes(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default()
+}
+
+/// The tables of a ruleset that declare a set. A prefix set is only
+/// declared in the tables whose rules use it.
+pub fn set_tables<'a>(ruleset: &'a Ruleset, set: &str) -> Vec<&'a str> {
+    ruleset.tables.iter().filter(|t| t.set(set).is_some()).map(|t| t.name.as_str()).collect()
+}
+
+/// Replace the contents of a VLAN's prefix set in `tables`. Tables and
+/// sets that aren't loaded are skipped; other failures are logged.
+fn update_sets(vlan_id: u16, prefixes: &[String], tables: &[&str]) {
+    let set = set_name(vlan_id);
+    for table in tables {
+        let mut script = format!("flush set inet {} {}\n", table, set);
+        if !prefixes.is_empty() {
+            script.push_str(&format!(
+                "add element inet {} {} {{ {} }}\n",
+                table, set, prefixes.join(", ")
+            ));
+        }
+        if let Err(e) = run_nft(&script) {
+            if !is_missing(&e) {
+                eprintln!("pd-sync: cannot update {} in table {}: {}", set, table, e);
+            }
+        }
+    }
+}
+
+/// Run an nft script, returning nft's error output when it fails.
+fn run_nft(script: &str) -> Result<(), String> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run nft: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes()).map_err(|e| format!("cannot write to nft: {}", e))?;
+    }
+    let output = child.wait_with_output().map_err(|e| format!("cannot run nft: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Whether nft failed only because the table or set is not loaded.
+fn is_missing(error: &str) -> bool {
+    error.contains("No such file or directory")
+}
+
+/// Load each delegated VLAN's current prefix into its sets in `ruleset`,
+/// the ruleset generated from `config`. With `watch`, keep polling at that
+/// interval and update the sets whenever a prefix changes; otherwise sync
+/// once and return.
+pub fn sync(config: &HclConfig, ruleset: &Ruleset, watch: Option<Duration>) {
+    let vlans = delegated_vlans(config);
+    let mut current: HashMap<u16, Vec<String>> = HashMap::new();
+    loop {
+        for vlan in &vlans {
+            let prefixes = interface_prefixes(&vlan.interface_name);
+            if current.get(&vlan.id) != Some(&prefixes) {
+                eprintln!(
+                    "pd-sync: VLAN {} ({}) prefix: {}",
+                    vlan.id,
+                    vlan.interface_name,
+                    if prefixes.is_empty() { "none".to_string() } else { prefixes.join(", ") }
+                );
+                update_sets(vlan.id, &prefixes, &set_tables(ruleset, &set_name(vlan.id)));
+                current.insert(vlan.id, prefixes);
+            }
+        }
+        match watch {
+            Some(interval) => thread::sleep(interval),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    #[test]
+    fn test_parse_prefixes() {
+        let output = "\
+5: lan    inet6 2001:db8:0:40::1/64 scope global dynamic mngtmpaddr noprefixroute \\       valid_lft 86000sec preferred_lft 14000sec
+5: lan    inet6 2001:db8:0:40:aa:bb:cc:dd/64 scope global dynamic \\       valid_lft 86000sec preferred_lft 14000sec
+5: lan    inet6 2001:db8:1:40::1/64 scope global deprecated dynamic \\       valid_lft 600sec preferred_lft 0sec
+";
+        assert_eq!(
+            parse_prefixes(output),
+            vec!["2001:db8:0:40::/64", "2001:db8:1:40::/64"]
+        );
+        assert!(parse_prefixes("").is_empty());
+    }
+
+    #[test]
+    fn test_delegated_vlans() {
+        let config = parse_hcl(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { enable_ipv6 = true }
+vlan_aware_switch = true
+vlan "iot" {
+  id = 20
+  ipv6 { delegated = true }
+}
+vlan "lan" {
+  id = 10
+  ipv6 {
+    delegated = true
+    subnet_id = "0x40"
+  }
+}
+vlan "lab" {
+  id = 30
+  ipv6 { subnet = "fd00:30::1/64" }
+}
+"#).unwrap();
+        assert_eq!(
+            delegated_vlans(&config),
+            vec![
+                DelegatedVlan { id: 10, interface_name: "lan".to_string() },
+                DelegatedVlan { id: 20, interface_name: "iot".to_string() },
+            ]
+        );
+        assert_eq!(set_name(10), "pd_vlan_10");
+    }
+
+    #[test]
+    fn test_is_missing() {
+        assert!(is_missing(
+            "/dev/stdin:1:1-37: Error: No such file or directory; did you mean table 'nifty_filter' in family inet?\n\
+             flush set inet nifty_mangle pd_vlan_40"
+        ));
+        assert!(!is_missing("/dev/stdin:2:1-56: Error: Could not process rule: Operation not permitted"));
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use askama::Template;
+
+use crate::generate;
+use crate::hcl_config::HclConfig;
+
+/// Everything the router generates from one HCL config, keyed by a display
+/// name such as "nftables" or "networkd/20-trusted.network".
+pub fn render_outputs(config: &HclConfig) -> Result<BTreeMap<String, String>, Vec<String>> {
+    let mut outputs = BTreeMap::new();
+    let router = crate::Router::from_hcl(config)?;
+    outputs.insert("nftables".to_string(), router.render());
+    if let Some(conf) = router.miniupnpd_conf() {
+        outputs.insert("miniupnpd.conf".to_string(), conf);
+    }
+    if let Some(qos) = crate::QosTemplate::from_hcl(config)? {
+        outputs.insert(
+            "qos.sh".to_string(),
+            qos.render().map_err(|e| vec![e.to_string()])?,
+        );
+    }
+
+    // The file generators write to disk; render into a scratch directory
+    let scratch = tempfile::Builder::new()
+        .prefix("nifty-plan-")
+        .tempdir()
+        .map_err(|e| vec![format!("Cannot create a scratch directory: {}", e)])?;
+    render_files(config, scratch.path(), &mut outputs).map_err(|e| vec![e])?;
+    Ok(outputs)
+}
+
+fn render_files(
+    config: &HclConfig,
+    scratch: &Path,
+    outputs: &mut BTreeMap<String, String>,
+) -> Result<(), String> {
+    let networkd = scratch.join("networkd");
+    generate::generate_networkd(config, &networkd.to_string_lossy())?;
+    let mut names: Vec<_> = fs::read_dir(&networkd)
+        .map_err(|e| format!("Cannot read {}: {}", networkd.display(), e))?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    for name in names {
+        let content = fs::read_to_string(networkd.join(&name))
+            .map_err(|e| format!("Cannot read {}: {}", name, e))?;
+        outputs.insert(format!("networkd/{}", name), content);
+    }
+
+    let mut files = vec![("dnsmasq.conf", scratch.join("dnsmasq.conf"))];
+    generate::generate_dnsmasq(config, &files[0].1.to_string_lossy())?;
+    // avahi only runs when some VLAN reflects mDNS
+    if config.vlan.values().any(|v| v.mdns_reflector) {
+        let avahi = scratch.join("avahi-daemon.conf");
+        generate::generate_avahi(config, &avahi.to_string_lossy())?;
+        files.push(("avahi-daemon.conf", avahi));
+    }
+    for (name, path) in files {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Cannot read {}: {}", name, e))?;
+        outputs.insert(name.to_string(), content);
+    }
+    Ok(())
+}
+
+/// Lines present in `a` but not in `b`, keeping duplicates and order.
+fn missing_lines<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<&'a str> {
+    let mut remaining: BTreeMap<&str, usize> = BTreeMap::new();
+    for line in b {
+        *remaining.entry(line).or_default() += 1;
+    }
+    a.iter()
+        .filter(|line| match remaining.get_mut(*line) {
+            Some(n) if *n > 0 => {
+                *n -= 1;
+                false
+            }
+            _ => true,
+        })
+        .copied()
+        .collect()
+}
+
+/// Split an nftables ruleset into blocks (`table inet nifty_filter / chain input`)
+/// and the normalized statements directly inside each of them.
+fn nft_blocks(ruleset: &str) -> BTreeMap<String, Vec<String>> {
+    let mut blocks: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut path: Vec<String> = Vec::new();
+    for line in ruleset.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "}" {
+            path.pop();
+        } else if let Some(header) = line.strip_suffix(" {") {
+            path.push(header.to_string());
+            blocks.entry(path.join(" / ")).or_default();
+        } else {
+            blocks.entry(path.join(" / ")).or_default().push(line);
+        }
+    }
+    blocks
+}
+
+/// A port or forward opened to traffic arriving on the WAN.
+fn wan_exposure(config: &HclConfig) -> BTreeSet<String> {
+    let mut exposed = BTreeSet::new();
+    for port in &config.wan.tcp_accept {
+        exposed.insert(format!("tcp {} to router (wan.tcp_accept)", port));
+    }
+    for port in &config.wan.udp_accept {
+        exposed.insert(format!("udp {} to router (wan.udp_accept)", port));
+    }
+    for forward in &config.wan.tcp_forward {
+        exposed.insert(format!("tcp {} (wan.tcp_forward)", forward));
+    }
+    for forward in &config.wan.udp_forward {
+        exposed.insert(format!("udp {} (wan.udp_forward)", forward));
+    }
+    for (name, forward) in &config.wan.forward {
+        let mut to = forward.to.clone();
+        if let Some(port) = forward.to_port {
+            to = format!("{}:{}", to, port);
+        }
+        let from = if forward.from.is_empty() { String::new() } else { format!(" from {}", forward.from.join(", ")) };
+        exposed.insert(format!(
+            "{} {} to {}{} (wan.forward \"{}\")",
+            forward.protocol,
+            forward.ports.join(", "),
+            to,
+            from,
+            name
+        ));
+    }
+    if config.wan.hairpin {
+        exposed.insert("IPv4 forwards to LAN clients via the public address (wan.hairpin)".to_string());
+    }
+    if let Some(knock) = &config.wan.knock {
+        let sequence = knock.sequence.join(", ");
+        for open in &knock.opens {
+            let (protocol, port) = open.split_once('/').unwrap_or(("", open));
+            exposed.insert(format!("{} {} to router after knocking {} (wan.knock)", protocol, port, sequence));
+        }
+    }
+    for (name, wg) in &config.wireguard {
+        exposed.insert(format!("udp {} to router (wireguard \"{}\")", wg.listen_port, name));
+    }
+    for (name, vlan) in &config.vlan {
+        for rule in &vlan.allow_inbound_tcp {
+            exposed.insert(format!("tcp {} (vlan \"{}\".allow_inbound_tcp)", rule, name));
+        }
+        for rule in &vlan.allow_inbound_udp {
+            exposed.insert(format!("udp {} (vlan \"{}\".allow_inbound_udp)", rule, name));
+        }
+        if let Some(upnp) = vlan.upnp.as_ref().filter(|u| u.enabled) {
+            let ports = upnp.allowed_ports.as_deref().unwrap_or(crate::upnp::DEFAULT_PORTS);
+            exposed.insert(format!("tcp/udp {} on request (vlan \"{}\".upnp)", ports, name));
+        }
+    }
+    exposed
+}
+
+/// Static DHCP reservations keyed by (VLAN, MAC), valued by "ip (hostname)".
+fn dhcp_reservations(config: &HclConfig) -> BTreeMap<(String, String), String> {
+    let mut hosts = BTreeMap::new();
+    for (name, vlan) in &config.vlan {
+        for host in vlan.dhcp.iter().flat_map(|d| &d.host) {
+            let value = match &host.hostname {
+                Some(hostname) => format!("{} ({})", host.ip, hostname),
+                None => host.ip.clone(),
+            };
+            hosts.insert((name.clone(), host.mac.to_lowercase()), value);
+        }
+    }
+    hosts
+}
+
+/// Change counts and a human-readable report of a plan.
+pub struct Plan {
+    pub changes: usize,
+    pub report: String,
+}
+
+/// Compare what `new` would generate against `current`.
+pub fn plan(current: &HclConfig, new: &HclConfig) -> Result<Plan, Vec<String>> {
+    let old_out = render_outputs(current)?;
+    let new_out = render_outputs(new)?;
+    let mut report = String::new();
+    let mut changes = 0;
+
+    // Ports opened to the WAN
+    let (old_wan, new_wan) = (wan_exposure(current), wan_exposure(new));
+    let mut section = String::new();
+    for item in new_wan.difference(&old_wan) {
+        writeln!(section, "  + {}", item).ok();
+        changes += 1;
+    }
+    for item in old_wan.difference(&new_wan) {
+        writeln!(section, "  - {}", item).ok();
+        changes += 1;
+    }
+    push_section(&mut report, "WAN exposure", &section);
+
+    // DHCP reservations
+    let (old_hosts, new_hosts) = (dhcp_reservations(current), dhcp_reservations(new));
+    let mut section = String::new();
+    for (key, new_value) in &new_hosts {
+        let (vlan, mac) = key;
+        match old_hosts.get(key) {
+            None => writeln!(section, "  + vlan \"{}\" {} -> {}", vlan, mac, new_value),
+            Some(old_value) if old_value != new_value => writeln!(
+                section,
+                "  ~ vlan \"{}\" {}: {} -> {}",
+                vlan, mac, old_value, new_value
+            ),
+            Some(_) => continue,
+        }
+        .ok();
+        changes += 1;
+    }
+    for ((vlan, mac), old_value) in &old_hosts {
+        if !new_hosts.contains_key(&(vlan.clone(), mac.clone())) {
+            writeln!(section, "  - vlan \"{}\" {} -> {}", vlan, mac, old_value).ok();
+            changes += 1;
+        }
+    }
+    push_section(&mut report, "DHCP reservations", &section);
+
+    // Firewall chains and rules
+    let empty = String::new();
+    let old_blocks = nft_blocks(old_out.get("nftables").unwrap_or(&empty));
+    let new_blocks = nft_blocks(new_out.get("nftables").unwrap_or(&empty));
+    let mut section = String::new();
+    let block_names: BTreeSet<_> = old_blocks.keys().chain(new_blocks.keys()).collect();
+    for name in block_names {
+        match (old_blocks.get(name), new_blocks.get(name)) {
+            (None, Some(lines)) => {
+                writeln!(section, "  + {} ({} statements)", name, lines.len()).ok();
+                changes += 1;
+            }
+            (Some(lines), None) => {
+                writeln!(section, "  - {} ({} statements)", name, lines.len()).ok();
+                changes += 1;
+            }
+            (Some(old), Some(new)) => {
+                let old: Vec<&str> = old.iter().map(String::as_str).collect();
+                let new: Vec<&str> = new.iter().map(String::as_str).collect();
+                changes += push_line_diff(&mut section, name, &old, &new);
+            }
+            (None, None) => {}
+        }
+    }
+    push_section(&mut report, "Firewall (nftables)", &section);
+
+    // Generated files
+    let mut section = String::new();
+    let file_names: BTreeSet<_> = old_out.keys().chain(new_out.keys())
+        .filter(|name| *name != "nftables")
+        .collect();
+    for name in file_names {
+        match (old_out.get(name), new_out.get(name)) {
+            (None, Some(_)) => {
+                writeln!(section, "  + {}", name).ok();
+                changes += 1;
+            }
+            (Some(_), None) => {
+                writeln!(section, "  - {}", name).ok();
+                changes += 1;
+            }
+            (Some(old), Some(new)) => {
+                let significant = |text: &'_ str| -> Vec<String> {
+                    text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect()
+                };
+                let (old, new) = (significant(old), significant(new));
+                let old: Vec<&str> = old.iter().map(String::as_str).collect();
+                let new: Vec<&str> = new.iter().map(String::as_str).collect();
+                changes += push_line_diff(&mut section, name, &old, &new);
+            }
+            (None, None) => {}
+        }
+    }
+    push_section(&mut report, "Generated files", &section);
+
+    if changes == 0 {
+        report.push_str("No changes.\n");
+    } else {
+        writeln!(report, "Plan: {} change{}.", changes, if changes == 1 { "" } else { "s" }).ok();
+    }
+    Ok(Plan { changes, report })
+}
+
+/// Append "~ name" with its removed/added lines; returns the number of
+/// changed lines.
+fn push_line_diff(out: &mut String, name: &str, old: &[&str], new: &[&str]) -> usize {
+    let removed = missing_lines(old, new);
+    let added = missing_lines(new, old);
+    if removed.is_empty() && added.is_empty() {
+        return 0;
+    }
+    writeln!(out, "  ~ {}", name).ok();
+    for line in &removed {
+        writeln!(out, "      - {}", line).ok();
+    }
+    for line in &added {
+        writeln!(out, "      + {}", line).ok();
+    }
+    removed.len() + added.len()
+}
+
+fn push_section(report: &mut String, title: &str, body: &str) {
+    if !body.is_empty() {
+        writeln!(report, "{}:\n{}", title, body).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    const BASE: &str = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  enable_ipv4 = true
+  tcp_accept  = [22]
+}
+vlan_aware_switch = true
+vlan "trusted" {
+  id = 10
+  ipv4 {
+    subnet = "10.99.10.1/24"
+    egress = ["0.0.0.0/0"]
+  }
+  dhcp {
+    pool_start = "10.99.10.100"
+    pool_end   = "10.99.10.250"
+    router     = "10.99.10.1"
+    dns        = "10.99.10.1"
+    host {
+      mac      = "aa:bb:cc:dd:ee:01"
+      ip       = "10.99.10.5"
+      hostname = "nas"
+    }
+  }
+}
+"#;
+
+    #[test]
+    fn test_no_changes() {
+        let config = parse_hcl(BASE).unwrap();
+        let plan = plan(&config, &parse_hcl(BASE).unwrap()).unwrap();
+        assert_eq!(plan.changes, 0);
+        assert_eq!(plan.report, "No changes.\n");
+    }
+
+    #[test]
+    fn test_plan_changes() {
+        let current = parse_hcl(BASE).unwrap();
+        let new = parse_hcl(&BASE
+            .replace("tcp_accept  = [22]", "tcp_accept  = [443]")
+            .replace("10.99.10.5", "10.99.10.6")
+            .replace(
+                "vlan_aware_switch = true",
+                "vlan_aware_switch = true\nvlan \"iot\" {\n  id = 20\n  ipv4 { subnet = \"10.99.20.1/24\" }\n}",
+            ))
+            .unwrap();
+        let plan = plan(&current, &new).unwrap();
+        let report = &plan.report;
+        assert!(report.contains("WAN exposure:\n  + tcp 443 to router (wan.tcp_accept)\n  - tcp 22 to router (wan.tcp_accept)\n"), "{}", report);
+        assert!(report.contains("  ~ vlan \"trusted\" aa:bb:cc:dd:ee:01: 10.99.10.5 (nas) -> 10.99.10.6 (nas)\n"), "{}", report);
+        assert!(report.contains("  + table inet nifty_filter / chain input_vlan_20 ("), "{}", report);
+        assert!(report.contains("  ~ table inet nifty_filter / chain input\n"), "{}", report);
+        assert!(report.contains("      + iifname \"wan\" tcp dport { 443 } accept"), "{}", report);
+        assert!(report.contains("  + networkd/20-iot.netdev\n"), "{}", report);
+        assert!(report.contains("  ~ dnsmasq.conf\n"), "{}", report);
+        assert!(report.ends_with(&format!("Plan: {} changes.\n", plan.changes)));
+    }
+
+    #[test]
+    fn test_wan_exposure_forwards() {
+        let config = parse_hcl(&BASE.replace(
+            "tcp_accept  = [22]",
+            "tcp_accept  = [22]\n  forward \"Steam\" {\n    protocol = \"tcp+udp\"\n    ports    = [\"27015-27030\", \"27036\"]\n    to       = \"10.99.10.5\"\n    from     = [\"203.0.113.0/24\"]\n  }",
+        ))
+        .unwrap();
+        assert!(wan_exposure(&config)
+            .contains("tcp+udp 27015-27030, 27036 to 10.99.10.5 from 203.0.113.0/24 (wan.forward \"Steam\")"));
+    }
+
+    #[test]
+    fn test_wan_exposure_knock_and_hairpin() {
+        let config = parse_hcl(&BASE.replace(
+            "tcp_accept  = [22]",
+            "hairpin     = true
+  knock {
+    sequence = [\"tcp/7000\", \"udp/8000\"]
+    opens    = [\"tcp/22\"]
+  }",
+        ))
+        .unwrap();
+        let exposed = wan_exposure(&config);
+        assert!(exposed.contains("tcp 22 to router after knocking tcp/7000, udp/8000 (wan.knock)"), "{:?}", exposed);
+        assert!(exposed.contains("IPv4 forwards to LAN clients via the public address (wan.hairpin)"), "{:?}", exposed);
+    }
+
+    #[test]
+    fn test_nft_blocks() {
+        let blocks = nft_blocks("table inet nifty_filter {\n    chain input {\n        type filter hook input priority 0; policy drop;\n        ct state established accept\n    }\n}\n");
+        assert_eq!(
+            blocks["table inet nifty_filter / chain input"],
+            vec!["type filter hook input priority 0; policy drop;", "ct state established accept"]
+        );
+        assert!(blocks["table inet nifty_filter"].is_empty());
+    }
+
+    #[test]
+    fn test_missing_lines() {
+        assert_eq!(missing_lines(&["a", "b", "b", "c"], &["b", "c"]), vec!["a", "b"]);
+    }
+}
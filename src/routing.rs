@@ -0,0 +1,412 @@
+//! Static routes and per-VLAN policy routing.
+//!
+//! A `route` block adds a route through a gateway on one of the VLANs,
+//! to the main table or to a numbered one. A VLAN with `routing_table`
+//! marks its new connections (like a multi-WAN uplink pin) and a policy
+//! rule sends marked packets to that table; LAN and directly-connected
+//! routes in the main table still win. A VLAN with `egress_via` does the
+//! same with the table of its `wireguard_client` tunnel.
+
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+
+use crate::hcl_config::{HclConfig, VlanHclConfig};
+use crate::objects;
+use crate::wan::WanUplinks;
+use crate::wireguard;
+
+/// `ip rule` priority of the per-VLAN fwmark rules: after the main-table
+/// rule that ignores default routes, before the multi-WAN uplink rules.
+pub const POLICY_RULE_PRIORITY: u32 = 900;
+
+/// A resolved `route` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticRoute {
+    pub name: String,
+    pub destination: IpNetwork,
+    pub gateway: IpAddr,
+    pub metric: Option<u32>,
+    /// Routing table, None for main
+    pub table: Option<u32>,
+    /// The VLAN whose subnet holds the gateway
+    pub vlan: String,
+}
+
+impl StaticRoute {
+    /// `[Route]` section for the gateway VLAN's .network file.
+    pub fn network_section(&self) -> String {
+        let mut out = format!("\n[Route]\nDestination={}\nGateway={}\n", self.destination, self.gateway);
+        if let Some(metric) = self.metric {
+            out.push_str(&format!("Metric={}\n", metric));
+        }
+        if let Some(table) = self.table {
+            out.push_str(&format!("Table={}\n", table));
+        }
+        out
+    }
+}
+
+/// A VLAN's routing policy: connections marked `fwmark` use `table`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    pub table: u32,
+    pub fwmark: u32,
+}
+
+impl Policy {
+    pub fn of(config: &HclConfig, vlan: &VlanHclConfig) -> Option<Policy> {
+        if let Some(tunnel) = &vlan.egress_via {
+            return wireguard::client_table(config, tunnel).map(|table| Policy { table, fwmark: table });
+        }
+        vlan.routing_table.map(|table| Policy { table, fwmark: vlan.fwmark.unwrap_or(table) })
+    }
+
+    /// `[RoutingPolicyRule]` section for the VLAN's .network file.
+    pub fn network_section(&self, enable_ipv6: bool) -> String {
+        let family = if enable_ipv6 { "Family=both\n" } else { "" };
+        format!(
+            "\n[RoutingPolicyRule]\nFirewallMark={}\nTable={}\nPriority={}\n{}",
+            self.fwmark, self.table, POLICY_RULE_PRIORITY, family
+        )
+    }
+}
+
+/// Whether any VLAN routes by its own table.
+pub fn has_policies(config: &HclConfig) -> bool {
+    config.vlan.values().any(|vlan| vlan.routing_table.is_some() || vlan.egress_via.is_some())
+}
+
+/// Table IDs the kernel reserves (unspec, default, main, local).
+fn reserved(table: u32) -> bool {
+    matches!(table, 0 | 253..=255)
+}
+
+/// The VLAN whose static subnet holds `ip`.
+fn vlan_of(config: &HclConfig, ip: IpAddr) -> Option<&str> {
+    let mut names: Vec<_> = config.vlan.iter().collect();
+    names.sort_by_key(|(_, vlan)| vlan.id);
+    names.into_iter().find_map(|(name, vlan)| {
+        let subnet = if ip.is_ipv4() {
+            vlan.ipv4.as_ref().map(|v| v.subnet.as_str())
+        } else {
+            vlan.ipv6.as_ref().filter(|v| !v.delegated).map(|v| v.subnet.as_str())
+        };
+        subnet
+            .and_then(|s| s.parse::<IpNetwork>().ok())
+            .filter(|net| net.contains(ip))
+            .map(|_| name.as_str())
+    })
+}
+
+/// Resolve the `route` blocks of a config, sorted by name, and check the
+/// VLAN routing policies against them.
+pub fn from_hcl(config: &HclConfig) -> Result<Vec<StaticRoute>, Vec<String>> {
+    let mut errors = Vec::new();
+    let uplink_tables: Vec<(String, u32)> = match WanUplinks::from_hcl(config) {
+        Ok(wan) if wan.is_multi() => wan.uplinks.iter().map(|u| (u.name.clone(), u.table)).collect(),
+        _ => Vec::new(),
+    };
+    let uplink_using = |id: u32| uplink_tables.iter().find(|(_, table)| *table == id).map(|(name, _)| name);
+
+    let mut routes = Vec::new();
+    let mut names: Vec<_> = config.route.iter().collect();
+    names.sort_by_key(|(name, _)| name.as_str());
+    for (name, r) in names {
+        let context = format!("route \"{}\"", name);
+        if !objects::valid_name(name) {
+            errors.push(format!(
+                "{}: names must start with a letter and contain only letters, digits, '_' and '-'.",
+                context
+            ));
+        }
+        let destination = match r.destination.parse::<IpNetwork>() {
+            Ok(net) => IpNetwork::new(net.network(), net.prefix()).unwrap(),
+            Err(_) => {
+                errors.push(format!("{}.destination: Invalid network: '{}'", context, r.destination));
+                continue;
+            }
+        };
+        let Ok(gateway) = r.gateway.parse::<IpAddr>() else {
+            errors.push(format!("{}.gateway: Invalid address: '{}'", context, r.gateway));
+            continue;
+        };
+        if gateway.is_ipv4() != destination.is_ipv4() {
+            errors.push(format!("{}: gateway {} and destination {} are different address families.", context, gateway, destination));
+            continue;
+        }
+        let Some(vlan) = vlan_of(config, gateway) else {
+            errors.push(format!("{}.gateway: {} is not in the subnet of any VLAN.", context, gateway));
+            continue;
+        };
+        if let Some(table) = r.table {
+            if reserved(table) {
+                errors.push(format!("{}.table: {} is reserved; leave table unset for the main table.", context, table));
+            } else if let Some(uplink) = uplink_using(table) {
+                errors.push(format!("{}.table: {} is the routing table of uplink \"{}\".", context, table, uplink));
+            }
+        }
+        routes.push(StaticRoute {
+            name: name.clone(),
+            destination,
+            gateway,
+            metric: r.metric,
+            table: r.table,
+            vlan: vlan.to_string(),
+        });
+    }
+
+    let mut vlans: Vec<_> = config.vlan.iter().collect();
+    vlans.sort_by_key(|(_, vlan)| vlan.id);
+    let mut marks: Vec<(&str, Policy)> = Vec::new();
+    for (name, vlan) in &vlans {
+        let context = format!("vlan \"{}\"", name);
+        if let Some(tunnel) = &vlan.egress_via {
+            if !config.wireguard_client.contains_key(tunnel) {
+                errors.push(format!("{}.egress_via: no wireguard_client \"{}\".", context, tunnel));
+            }
+            if vlan.routing_table.is_some() || vlan.fwmark.is_some() {
+                errors.push(format!("{}: egress_via and routing_table/fwmark are mutually exclusive.", context));
+            }
+            continue;
+        }
+        let Some(policy) = Policy::of(config, vlan) else {
+            if vlan.fwmark.is_some() {
+                errors.push(format!("{}.fwmark requires routing_table.", context));
+            }
+            continue;
+        };
+        if reserved(policy.table) {
+            errors.push(format!("{}.routing_table: {} is reserved.", context, policy.table));
+        } else if !routes.iter().any(|r| r.table == Some(policy.table)) {
+            errors.push(format!("{}.routing_table: no route has table = {}.", context, policy.table));
+        }
+        if policy.fwmark == 0 {
+            errors.push(format!("{}.fwmark: must not be 0.", context));
+        } else if let Some(uplink) = uplink_using(policy.fwmark) {
+            errors.push(format!("{}.fwmark: {} is the connection mark of uplink \"{}\".", context, policy.fwmark, uplink));
+        } else if let Some((other, _)) = marks.iter().find(|(_, p)| p.fwmark == policy.fwmark && p.table != policy.table) {
+            errors.push(format!(
+                "{}.fwmark: {} is already the mark of vlan \"{}\", which routes to another table.",
+                context, policy.fwmark, other
+            ));
+        }
+        marks.push((name, policy));
+    }
+
+    let mut clients: Vec<_> = config.wireguard_client.keys().collect();
+    clients.sort();
+    let mut seen = Vec::new();
+    for name in clients {
+        let context = format!("wireguard_client \"{}\".table", name);
+        let Some(table) = wireguard::client_table(config, name) else {
+            continue;
+        };
+        let vlan_using = vlans.iter().find(|(_, vlan)| {
+            vlan.egress_via.is_none() && Policy::of(config, vlan).is_some_and(|p| p.table == table || p.fwmark == table)
+        });
+        if reserved(table) {
+            errors.push(format!("{}: {} is reserved.", context, table));
+        } else if let Some(uplink) = uplink_using(table) {
+            errors.push(format!("{}: {} is the routing table of uplink \"{}\".", context, table, uplink));
+        } else if let Some(route) = routes.iter().find(|r| r.table == Some(table)) {
+            errors.push(format!("{}: {} is already used by route \"{}\".", context, table, route.name));
+        } else if let Some((vlan, _)) = vlan_using {
+            errors.push(format!("{}: {} is already used by vlan \"{}\".", context, table, vlan));
+        } else if seen.contains(&table) {
+            errors.push(format!("{}: {} is used by another wireguard_client.", context, table));
+        }
+        seen.push(table);
+    }
+
+    if errors.is_empty() {
+        Ok(routes)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn config(extra: &str) -> HclConfig {
+        parse_hcl(&format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{}}
+vlan_aware_switch = true
+vlan "lab" {{
+  id = 40
+  ipv4 {{ subnet = "10.99.40.1/24" }}
+}}
+{}
+"#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_hcl() {
+        let config = config(
+            r#"
+route "lab-behind-r2" {
+  destination = "192.168.50.1/24"
+  gateway     = "10.99.40.2"
+  metric      = 100
+}
+route "via-vpn" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.254"
+  table       = 200
+}
+vlan "guest" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  routing_table = 200
+}
+"#,
+        );
+        let routes = from_hcl(&config).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].destination.to_string(), "192.168.50.0/24");
+        assert_eq!(routes[0].vlan, "lab");
+        assert_eq!(
+            routes[0].network_section(),
+            "\n[Route]\nDestination=192.168.50.0/24\nGateway=10.99.40.2\nMetric=100\n"
+        );
+        assert_eq!(
+            routes[1].network_section(),
+            "\n[Route]\nDestination=0.0.0.0/0\nGateway=10.99.40.254\nTable=200\n"
+        );
+        let policy = Policy::of(&config, &config.vlan["guest"]).unwrap();
+        assert_eq!(policy, Policy { table: 200, fwmark: 200 });
+        assert_eq!(policy.network_section(false), "\n[RoutingPolicyRule]\nFirewallMark=200\nTable=200\nPriority=900\n");
+        assert!(has_policies(&config));
+    }
+
+    #[test]
+    fn test_from_hcl_errors() {
+        let errors = from_hcl(&config(
+            r#"
+route "nowhere" {
+  destination = "192.168.50.0/24"
+  gateway     = "10.1.1.1"
+}
+route "mixed" {
+  destination = "2001:db8::/32"
+  gateway     = "10.99.40.2"
+}
+route "local" {
+  destination = "192.168.60.0/24"
+  gateway     = "10.99.40.2"
+  table       = 255
+}
+vlan "guest" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  routing_table = 300
+  fwmark        = 0
+}
+vlan "iot" {
+  id = 20
+  ipv4 { subnet = "10.99.20.1/24" }
+  fwmark = 7
+}
+route "via lab" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.254"
+  table       = 201
+}
+route "via-lab2" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.253"
+  table       = 202
+}
+vlan "lab2" {
+  id = 41
+  ipv4 { subnet = "10.99.41.1/24" }
+  routing_table = 201
+  fwmark        = 77
+}
+vlan "lab3" {
+  id = 42
+  ipv4 { subnet = "10.99.42.1/24" }
+  routing_table = 202
+  fwmark        = 77
+}
+"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "route \"local\".table: 255 is reserved; leave table unset for the main table.",
+                "route \"mixed\": gateway 10.99.40.2 and destination 2001:db8::/32 are different address families.",
+                "route \"nowhere\".gateway: 10.1.1.1 is not in the subnet of any VLAN.",
+                "route \"via lab\": names must start with a letter and contain only letters, digits, '_' and '-'.",
+                "vlan \"iot\".fwmark requires routing_table.",
+                "vlan \"guest\".routing_table: no route has table = 300.",
+                "vlan \"guest\".fwmark: must not be 0.",
+                "vlan \"lab3\".fwmark: 77 is already the mark of vlan \"lab2\", which routes to another table.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_egress_via() {
+        let config = config(
+            r#"
+route "via-lab" {
+  destination = "0.0.0.0/0"
+  gateway     = "10.99.40.254"
+  table       = 201
+}
+vlan "privacy" {
+  id = 50
+  ipv4 { subnet = "10.99.50.1/24" }
+  egress_via = "vpn-provider"
+}
+vlan "guest" {
+  id = 30
+  ipv4 { subnet = "10.99.30.1/24" }
+  egress_via    = "vpn"
+  routing_table = 201
+}
+wireguard_client "vpn-provider" {
+  private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+  address          = ["10.64.0.2/32"]
+  peer {
+    public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    endpoint   = "vpn.example.net:51820"
+  }
+}
+wireguard_client "backup" {
+  private_key_file = "/var/lib/nifty-filter/backup.key"
+  address          = ["10.65.0.2/32"]
+  table            = 201
+  peer {
+    public_key = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg="
+    endpoint   = "vpn.example.org:51820"
+  }
+}
+"#,
+        );
+        assert_eq!(Policy::of(&config, &config.vlan["privacy"]), Some(Policy { table: 201, fwmark: 201 }));
+        assert!(has_policies(&config));
+        assert_eq!(
+            from_hcl(&config).unwrap_err(),
+            vec![
+                "vlan \"guest\".egress_via: no wireguard_client \"vpn\".",
+                "vlan \"guest\": egress_via and routing_table/fwmark are mutually exclusive.",
+                "wireguard_client \"backup\".table: 201 is already used by route \"via-lab\".",
+                "wireguard_client \"vpn-provider\".table: 201 is already used by route \"via-lab\".",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1323 @@
+//! Builds the router's nftables ruleset from a resolved config.
+
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+use nifty_nft::{
+    tables, Chain, ChainType, Expr, Family, Field, Policy, Priority, RejectType, Rule, Ruleset, Set, Table, Value,
+};
+
+use crate::knock::Knock;
+use crate::limits::SET_SIZE;
+use crate::logging::LogMode;
+use crate::parsers::forward_route::{ForwardRoute, ForwardRouteList};
+use crate::parsers::inbound_rule::InboundRuleList;
+use crate::parsers::inter_vlan_rule::{InterVlanRule, InterVlanRuleList, PortSpec, RuleAddr};
+use crate::pd;
+use crate::upnp;
+use crate::vlan::{Vlan, DOMAIN_SET_TIMEOUT};
+use crate::Router;
+
+/// The `inet` tables nifty-filter owns. Loading the ruleset replaces all of
+/// them, so one that is no longer generated goes away; any other table on
+/// the box is left alone.
+pub const OWNED_TABLES: [&str; 4] = tables::OWNED;
+
+/// Deletes every owned table and every legacy unprefixed one, whether or
+/// not it exists.
+pub fn teardown() -> Ruleset {
+    Ruleset {
+        replace: OWNED_TABLES
+            .iter()
+            .chain(&tables::LEGACY)
+            .map(|name| (Family::Inet, name.to_string()))
+            .collect(),
+        tables: Vec::new(),
+    }
+}
+
+/// Replaces the owned tables with a filter that only lets loopback and
+/// established traffic through, for a router without a config.
+pub fn lockdown() -> Ruleset {
+    let mut table = Table::new(Family::Inet, tables::FILTER);
+    let mut input = Chain::base("input", ChainType::Filter, "input", Priority::Value(0), Policy::Drop);
+    input.push(established());
+    input.push(Rule::new().then(iifname("lo")).accept().describe("Allow localhost loopback"));
+    let forward = Chain::base("forward", ChainType::Filter, "forward", Priority::Value(0), Policy::Drop);
+    let mut output = Chain::base("output", ChainType::Filter, "output", Priority::Value(0), Policy::Drop);
+    output.push(Rule::new().then(oifname("lo")).accept().describe("Allow localhost loopback"));
+    table.chains.extend([input, forward, output]);
+    Ruleset { tables: vec![table], ..teardown() }
+}
+
+fn iifname(name: &str) -> Expr {
+    Expr::eq(Field::IIFNAME, Value::str(name))
+}
+
+fn oifname(name: &str) -> Expr {
+    Expr::eq(Field::OIFNAME, Value::str(name))
+}
+
+fn admin_prohibited(kind: RejectType) -> Expr {
+    Expr::Reject { kind, code: "admin-prohibited".to_string() }
+}
+
+/// A set filled from the packet path.
+fn dynamic_set(name: &str, kind: &str, timeout: Option<u32>) -> Set {
+    let mut set = Set::new(name, kind).flag("dynamic");
+    if timeout.is_some() {
+        set = set.flag("timeout");
+    }
+    set.size = Some(SET_SIZE);
+    set.timeout = timeout;
+    set
+}
+
+/// A forward rule's description, with the forward's own if it has one.
+fn forward_description(what: &str, route: &ForwardRoute) -> String {
+    match &route.description {
+        Some(description) => format!("{}: {}", what, description),
+        None => what.to_string(),
+    }
+}
+
+fn established() -> Rule {
+    Rule::new()
+        .matching(Field::CT_STATE, Value::flags(&["established", "related"]))
+        .accept()
+        .describe("Allow established/related connections")
+}
+
+fn invalid() -> Rule {
+    Rule::new()
+        .matching(Field::CT_STATE, Value::flags(&["invalid"]))
+        .drop()
+        .describe("Drop invalid conntrack state")
+}
+
+fn dhcpv6_replies() -> Rule {
+    Rule::new()
+        .matching(Field::IP6_SADDR, Value::literal("fe80::/10"))
+        .matching(Field::UDP_SPORT, Value::literal(547))
+        .matching(Field::UDP_DPORT, Value::literal(546))
+        .accept()
+}
+
+impl Vlan {
+    fn has_ipv4(&self, enable_ipv4: bool) -> bool {
+        enable_ipv4 && !self.subnet_ipv4.is_empty()
+    }
+
+    fn has_ipv6(&self, enable_ipv6: bool) -> bool {
+        enable_ipv6 && !self.subnet_ipv6.is_empty()
+    }
+
+    /// The zone's subnet for one family, a literal or a `@pd_vlan_<id>` set.
+    fn subnet(&self, ipv6: bool) -> Value {
+        Value::parse(if ipv6 { &self.subnet_ipv6 } else { &self.subnet_ipv4 })
+    }
+}
+
+impl Router {
+    /// The complete ruleset, loaded with `nft -f` as one transaction.
+    pub fn ruleset(&self) -> Ruleset {
+        let mut tables = vec![self.filter_table(), self.nat_table()];
+        if self.wan.is_multi() || self.vlans.iter().any(|vlan| vlan.routing.is_some()) {
+            tables.push(self.wan_routing_table());
+        }
+        if self.qos_enabled {
+            tables.push(self.mangle_table());
+        }
+        for table in &mut tables {
+            self.declare_pd_sets(table);
+        }
+        let mut ruleset = Ruleset { tables, ..teardown() };
+        if self.logging.counters {
+            ruleset.add_counters();
+        }
+        ruleset
+    }
+
+    /// The ruleset in `nft -f` syntax.
+    pub fn render(&self) -> String {
+        self.ruleset().to_string()
+    }
+
+    /// Names of the sets dnsmasq fills with resolved egress domains.
+    pub fn domain_sets(&self) -> Vec<String> {
+        self.vlans
+            .iter()
+            .filter(|vlan| !vlan.egress_domains.is_empty())
+            .flat_map(|vlan| [vlan.domain_set("v4"), vlan.domain_set("v6")])
+            .collect()
+    }
+
+    fn wan_ifaces(&self) -> Value {
+        self.wan.iface_match()
+    }
+
+    fn iif_wan(&self) -> Expr {
+        Expr::eq(Field::IIFNAME, self.wan_ifaces())
+    }
+
+    fn oif_wan(&self) -> Expr {
+        Expr::eq(Field::OIFNAME, self.wan_ifaces())
+    }
+
+    /// Where a zone's internet traffic leaves: its VPN tunnel or the WAN.
+    fn oif_egress(&self, vlan: &Vlan) -> Expr {
+        match &vlan.egress_via {
+            Some(tunnel) => oifname(tunnel),
+            None => self.oif_wan(),
+        }
+    }
+
+    /// Tunnels that zones egress through, each once.
+    fn egress_tunnels(&self) -> Vec<&str> {
+        let mut tunnels: Vec<&str> = self.vlans.iter().filter_map(|vlan| vlan.egress_via.as_deref()).collect();
+        tunnels.sort();
+        tunnels.dedup();
+        tunnels
+    }
+
+    fn not_mgmt(&self, field: Field) -> Expr {
+        Expr::ne(field, Value::str(&self.interface_mgmt))
+    }
+
+    fn to_mgmt_subnet(&self) -> Expr {
+        Expr::eq(Field::IP_DADDR, Value::literal(&self.subnet_mgmt_ipv4))
+    }
+
+    /// Declares the sets of the addresses delegated to each VLAN, kept up to
+    /// date by pd-sync, in the table if its rules use them.
+    fn declare_pd_sets(&self, table: &mut Table) {
+        let used: Vec<Set> = self
+            .vlans
+            .iter()
+            .filter(|v| v.ipv6_delegated)
+            .map(|v| Set::new(&pd::set_name(v.id), "ipv6_addr").flag("interval"))
+            .filter(|set| table.references(&set.name))
+            .collect();
+        table.sets.splice(0..0, used);
+    }
+
+    /// A drop rule, preceded by a rule logging the same packets unless
+    /// `mode` is off.
+    fn drop_logged(&self, matches: &[Expr], mode: &LogMode, prefix: &str, log: &str, drop: &str) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        if mode.enabled() {
+            rules.push(Rule::new().extend(matches.to_vec()).extend(self.logging.exprs(mode, prefix)).describe(log));
+        }
+        rules.push(Rule::new().extend(matches.to_vec()).drop().describe(drop));
+        rules
+    }
+
+    /// An accept of the packets `matched` selects, preceded by a rule
+    /// logging them unless `mode` is off.
+    fn accept_logged(&self, matched: Rule, mode: &LogMode, prefix: &str, description: &str) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        if mode.enabled() {
+            rules.push(matched.clone().extend(self.logging.exprs(mode, prefix)).describe(&format!("Log {}", description)));
+        }
+        rules.push(matched.accept().describe(description));
+        rules
+    }
+
+    /// Drops for the zones that override the default logging, so their
+    /// traffic never reaches the default log rule.
+    fn zone_drops(&self, chain: &mut Chain, kind: &str) {
+        for vlan in &self.vlans {
+            if let Some(mode) = &vlan.log {
+                chain.rules.extend(self.drop_logged(
+                    &[iifname(&vlan.interface_name)],
+                    mode,
+                    &vlan.log_prefix(kind),
+                    &format!("Log dropped {} {}", vlan.label, kind),
+                    &format!("Default drop {} {}", vlan.label, kind),
+                ));
+            }
+        }
+    }
+
+    fn filter_table(&self) -> Table {
+        let mut table = Table::new(Family::Inet, tables::FILTER);
+        for named in &self.object_sets {
+            let mut set = Set::new(&named.name, named.kind);
+            if named.interval {
+                set = set.flag("interval");
+            }
+            set.elements = named.elements.clone();
+            table.sets.push(set);
+        }
+        for list in &self.blocklists {
+            for (family, kind) in [("v4", "ipv4_addr"), ("v6", "ipv6_addr")] {
+                let mut set = Set::new(&list.set_name(family), kind).flag("interval");
+                set.auto_merge = true;
+                table.sets.push(set);
+            }
+        }
+        for vlan in self.vlans.iter().filter(|v| !v.egress_domains.is_empty()) {
+            for (family, kind) in [("v4", "ipv4_addr"), ("v6", "ipv6_addr")] {
+                table.sets.push(dynamic_set(&vlan.domain_set(family), kind, Some(DOMAIN_SET_TIMEOUT)));
+            }
+        }
+        for (family, kind) in [("v4", "ipv4_addr"), ("v6", "ipv6_addr")] {
+            for limit in &self.wan_limits.ports {
+                if let Some(rate) = &limit.rate {
+                    table.sets.push(dynamic_set(&limit.rate_set(family), kind, Some(rate.window().max(60))));
+                }
+                if limit.ct_limit.is_some() {
+                    table.sets.push(dynamic_set(&limit.conn_set(family), kind, None));
+                }
+            }
+            if let Some(knock) = &self.knock {
+                let timeout = Some(knock.timeout.as_secs() as u32);
+                for stage in 1..knock.sequence.len() {
+                    table.sets.push(dynamic_set(&Knock::stage_set(stage, family), kind, timeout));
+                }
+                table.sets.push(dynamic_set(&Knock::open_set(family), kind, timeout));
+            }
+            if let Some(guard) = &self.wan_limits.ssh_guard {
+                let window = guard.threshold.window().max(60);
+                table.sets.push(dynamic_set(&guard.meter_set(family), kind, Some(window)));
+                table.sets.push(dynamic_set(&guard.ban_set(family), kind, Some(guard.ban.as_secs() as u32)));
+            }
+        }
+
+        table.chains.push(self.input_chain());
+        table.chains.push(self.invalid_sources_chain("input"));
+        if !self.interface_mgmt.is_empty() {
+            table.chains.push(self.mgmt_isolation_chain("input"));
+        }
+        for vlan in &self.vlans {
+            table.chains.push(self.zone_input_chain(vlan));
+        }
+        table.chains.push(self.forward_chain());
+        table.chains.push(self.invalid_sources_chain("forward"));
+        if !self.interface_mgmt.is_empty() {
+            table.chains.push(self.mgmt_isolation_chain("forward"));
+        }
+        for vlan in &self.vlans {
+            table.chains.push(self.zone_forward_chain(vlan));
+        }
+        if self.upnp_enabled() {
+            // Filled at runtime by miniupnpd
+            table.chains.push(Chain::new(upnp::FORWARD_CHAIN));
+        }
+        table.chains.push(self.output_chain());
+        table
+    }
+
+    fn input_chain(&self) -> Chain {
+        let mut chain = Chain::base("input", ChainType::Filter, "input", Priority::Value(0), Policy::Drop);
+        chain.push(established());
+        chain.push(invalid());
+        chain.push(Rule::new().then(iifname("lo")).accept().describe("Allow localhost loopback"));
+        chain.push(Rule::new().jump("input_invalid_sources").describe("Check for bogon/spoofed sources"));
+        for vlan in &self.vlans {
+            chain.push(
+                Rule::new()
+                    .then(iifname(&vlan.interface_name))
+                    .jump(&format!("input_{}", vlan.chain_suffix))
+                    .describe(&format!("{} input rules", vlan.label)),
+            );
+        }
+        if !self.interface_mgmt.is_empty() {
+            chain.push(Rule::new().jump("input_mgmt_isolation").describe("Block non-mgmt access to mgmt subnet"));
+        }
+
+        if self.enable_ipv4 && !self.icmp_accept_wan.is_empty() {
+            chain.push(
+                Rule::new()
+                    .then(self.iif_wan())
+                    .matching(Field::ICMP_TYPE, Value::set(&self.icmp_accept_wan))
+                    .accept()
+                    .describe("Allow ICMP on WAN (IPv4)"),
+            );
+        }
+        if self.enable_ipv6 {
+            if !self.icmpv6_accept_wan.is_empty() {
+                chain.push(
+                    Rule::new()
+                        .then(self.iif_wan())
+                        .matching(Field::ICMPV6_TYPE, Value::set(&self.icmpv6_accept_wan))
+                        .accept()
+                        .describe("Allow ICMPv6 on WAN"),
+                );
+            }
+            let mut dhcpv6 = dhcpv6_replies().describe("Allow DHCPv6 replies on WAN");
+            dhcpv6.exprs.insert(0, self.iif_wan());
+            chain.push(dhcpv6);
+        }
+        let families: Vec<bool> =
+            [(self.enable_ipv4, false), (self.enable_ipv6, true)].into_iter().filter(|(on, _)| *on).map(|(_, v6)| v6).collect();
+        let to_port = |proto: &str, port: u16| Rule::new().then(self.iif_wan()).matching(Field::dport(proto), Value::literal(port));
+        if let Some(knock) = &self.knock {
+            for &ipv6 in &families {
+                chain.rules.extend(self.knock_rules(knock, ipv6));
+            }
+        }
+        let mut limited: Vec<(&str, u16)> = Vec::new();
+        for (proto, port, _) in self.wan_limits.limited_ports() {
+            let accepted = if proto == "tcp" { &self.tcp_accept_wan } else { &self.udp_accept_wan };
+            let open = accepted.contains(&port)
+                || self.knock.as_ref().is_some_and(|k| k.opens(proto, port));
+            if open && !limited.contains(&(proto, port)) {
+                limited.push((proto, port));
+            }
+        }
+        for (proto, port) in limited {
+            for &ipv6 in &families {
+                chain.rules.extend(self.wan_limit_rules(proto, port, ipv6, &to_port(proto, port)));
+            }
+        }
+        if let Some(knock) = &self.knock {
+            for p in &knock.opens {
+                for &ipv6 in &families {
+                    chain.push(
+                        to_port(p.proto, p.port)
+                            .matching(Field::saddr(ipv6), Value::SetRef(Knock::open_set(if ipv6 { "v6" } else { "v4" })))
+                            .accept()
+                            .describe(&format!("Allow {} {} after port knock", p.proto.to_uppercase(), p.port)),
+                    );
+                }
+            }
+        }
+        for (ports, field, description) in [
+            (&self.tcp_accept_wan, Field::TCP_DPORT, "Allow TCP ports on WAN"),
+            (&self.udp_accept_wan, Field::UDP_DPORT, "Allow UDP ports on WAN"),
+            (&self.wireguard_ports, Field::UDP_DPORT, "Allow WireGuard on WAN"),
+        ] {
+            if !ports.is_empty() {
+                chain.push(Rule::new().then(self.iif_wan()).matching(field, Value::set(ports)).accept().describe(description));
+            }
+        }
+
+        if !self.interface_mgmt.is_empty() {
+            chain.push(
+                Rule::new()
+                    .then(iifname(&self.interface_mgmt))
+                    .matching(Field::ICMP_TYPE, Value::set(&["echo-request", "echo-reply"]))
+                    .accept()
+                    .describe("Allow ICMP ping on mgmt"),
+            );
+            chain.push(
+                Rule::new()
+                    .then(iifname(&self.interface_mgmt))
+                    .matching(Field::TCP_DPORT, Value::set(&[22, 80, 443, self.dashboard_port]))
+                    .accept()
+                    .describe("Allow SSH and dashboard on mgmt"),
+            );
+        }
+
+        self.zone_drops(&mut chain, "input");
+        chain.rules.extend(self.drop_logged(&[], &self.logging.input, "Dropped input", "Log dropped input (rate-limited)", "Default drop"));
+        chain
+    }
+
+    /// Drops of bogon and blocklisted WAN sources, and of untagged trunk
+    /// traffic when the switch is VLAN aware.
+    fn invalid_sources_chain(&self, kind: &str) -> Chain {
+        let mut chain = Chain::new(&format!("{}_invalid_sources", kind));
+        for (bogons, ipv6, description) in [
+            (&self.wan_bogons_ipv4, false, "Drop bogon IPv4 sources on WAN"),
+            (&self.wan_bogons_ipv6, true, "Drop bogon IPv6 sources on WAN"),
+        ] {
+            if !bogons.is_empty() {
+                chain.push(
+                    Rule::new().then(self.iif_wan()).matching(Field::saddr(ipv6), Value::set(bogons)).drop().describe(description),
+                );
+            }
+        }
+        for blocklist in self.blocklists.iter().filter(|b| b.inbound()) {
+            for (family, ipv6) in [("v4", false), ("v6", true)] {
+                chain.push(
+                    Rule::new()
+                        .then(self.iif_wan())
+                        .matching(Field::saddr(ipv6), Value::SetRef(blocklist.set_name(family)))
+                        .drop()
+                        .describe(&format!("Drop sources on blocklist {}", blocklist.name)),
+                );
+            }
+        }
+        if self.vlan_aware_switch {
+            chain.rules.extend(self.drop_logged(
+                &[iifname(&self.interface_trunk.to_string())],
+                &self.logging.trunk,
+                &format!("Dropped untagged trunk {}", kind),
+                &format!("Log untagged trunk {}", kind),
+                &format!("Drop untagged trunk {}", kind),
+            ));
+        }
+        chain
+    }
+
+    fn mgmt_isolation_chain(&self, kind: &str) -> Chain {
+        let mut chain = Chain::new(&format!("{}_mgmt_isolation", kind));
+        for vlan in &self.vlans {
+            let description = if kind == "input" {
+                format!("Reject {} to mgmt subnet", vlan.interface_name)
+            } else {
+                format!("Reject {} forward to mgmt", vlan.interface_name)
+            };
+            chain.push(
+                Rule::new()
+                    .then(iifname(&vlan.interface_name))
+                    .then(self.to_mgmt_subnet())
+                    .then(admin_prohibited(RejectType::Icmp))
+                    .describe(&description),
+            );
+        }
+        let to_subnet = [self.not_mgmt(Field::IIFNAME), self.to_mgmt_subnet()];
+        if kind == "input" {
+            chain.rules.extend(self.drop_logged(
+                &to_subnet,
+                &self.logging.mgmt,
+                "Dropped non-mgmt input",
+                "Log non-mgmt to mgmt subnet",
+                "Drop non-mgmt to mgmt subnet",
+            ));
+        } else {
+            chain.rules.extend(self.drop_logged(
+                &to_subnet,
+                &self.logging.mgmt,
+                "Dropped non-mgmt forward",
+                "Log non-mgmt forward to mgmt",
+                "Drop non-mgmt forward to mgmt",
+            ));
+            chain.rules.extend(self.drop_logged(
+                &[self.not_mgmt(Field::IIFNAME), oifname(&self.interface_mgmt)],
+                &self.logging.mgmt,
+                "Dropped non-mgmt egress to mgmt",
+                "Log non-mgmt egress to mgmt",
+                "Drop non-mgmt egress to mgmt",
+            ));
+        }
+        chain
+    }
+
+    /// Services on the router reachable from one zone.
+    fn zone_input_chain(&self, vlan: &Vlan) -> Chain {
+        let mut chain = Chain::new(&format!("input_{}", vlan.chain_suffix));
+        let v4 = vlan.has_ipv4(self.enable_ipv4);
+        let v6 = vlan.has_ipv6(self.enable_ipv6);
+        let from = |ipv6: bool| Rule::new().matching(Field::saddr(ipv6), vlan.subnet(ipv6));
+
+        if v4 && !vlan.icmp_accept.is_empty() {
+            chain.push(from(false).matching(Field::ICMP_TYPE, Value::set(&vlan.icmp_accept)).accept().describe("Allow ICMP (IPv4)"));
+        }
+        if v6 {
+            if !vlan.icmpv6_accept.is_empty() {
+                chain.push(from(true).matching(Field::ICMPV6_TYPE, Value::set(&vlan.icmpv6_accept)).accept().describe("Allow ICMPv6"));
+            }
+            chain.push(dhcpv6_replies().describe("Allow DHCPv6 replies"));
+        }
+        if !vlan.tcp_accept.is_empty() {
+            if v4 {
+                chain.push(from(false).matching(Field::TCP_DPORT, Value::set(&vlan.tcp_accept)).accept().describe("Allow TCP ports (IPv4)"));
+            }
+            if v6 {
+                chain.push(from(true).matching(Field::TCP_DPORT, Value::set(&vlan.tcp_accept)).accept().describe("Allow TCP ports (IPv6)"));
+            }
+        }
+        if !vlan.udp_accept.is_empty() {
+            chain.push(
+                Rule::new()
+                    .matching(Field::UDP_DPORT, Value::set(&vlan.udp_accept))
+                    .accept()
+                    .describe("Allow UDP ports (no src filter for DHCP)"),
+            );
+        }
+        if vlan.iperf_enabled {
+            if v4 {
+                chain.push(from(false).matching(Field::TCP_DPORT, Value::literal(self.iperf_port)).accept().describe("Allow iperf3 (IPv4)"));
+            }
+            if v6 {
+                chain.push(from(true).matching(Field::TCP_DPORT, Value::literal(self.iperf_port)).accept().describe("Allow iperf3 (IPv6)"));
+            }
+        }
+        if vlan.upnp_ports.is_some() && v4 {
+            chain.push(
+                from(false)
+                    .matching(Field::UDP_DPORT, Value::set(&[upnp::SSDP_PORT, upnp::PCP_PORT]))
+                    .accept()
+                    .describe("Allow UPnP discovery and NAT-PMP/PCP"),
+            );
+            chain.push(
+                from(false)
+                    .matching(Field::TCP_DPORT, Value::literal(upnp::HTTP_PORT))
+                    .accept()
+                    .describe("Allow UPnP IGD control"),
+            );
+        }
+        if vlan.mdns_reflector {
+            let mdns = Rule::new().matching(Field::UDP_DPORT, Value::literal(5353));
+            if v4 {
+                chain.push(mdns.clone().matching(Field::IP_DADDR, Value::literal("224.0.0.251")).accept().describe("Allow mDNS (IPv4)"));
+            }
+            if v6 {
+                chain.push(mdns.matching(Field::IP6_DADDR, Value::literal("ff02::fb")).accept().describe("Allow mDNS (IPv6)"));
+            }
+        }
+        chain
+    }
+
+    fn forward_chain(&self) -> Chain {
+        let mut chain = Chain::base("forward", ChainType::Filter, "forward", Priority::Value(0), Policy::Drop);
+        if self.wan.has_pppoe() {
+            chain.push(
+                Rule::new()
+                    .matching(Field::OIFNAME, self.wan.pppoe_iface_match())
+                    .matching(Field::TCP_FLAGS, Value::flags(&["syn"]))
+                    .then(Expr::Mangle { field: Field::TCP_MAXSEG_SIZE, value: Value::Field(Field::RT_MTU) })
+                    .describe("Clamp TCP MSS to PPPoE path MTU"),
+            );
+        }
+        for vlan in &self.vlans {
+            // Ahead of the established rule, so connections that were
+            // routed through the tunnel do not fall back to the WAN either
+            if let Some(tunnel) = &vlan.egress_via {
+                chain.push(
+                    Rule::new()
+                        .then(iifname(&vlan.interface_name))
+                        .then(self.oif_wan())
+                        .matching(Field::CT_DIRECTION, Value::literal("original"))
+                        .drop()
+                        .describe(&format!("Drop {} egress to WAN outside {}", vlan.label, tunnel)),
+                );
+            }
+            for block in &vlan.egress_blocked {
+                chain.push(
+                    Rule::new()
+                        .then(iifname(&vlan.interface_name))
+                        .then(self.oif_egress(vlan))
+                        .extend(block.window.exprs())
+                        .then(admin_prohibited(RejectType::Icmpx))
+                        .describe(&format!("Block {} egress during {}", vlan.label, block.schedule)),
+                );
+            }
+        }
+        chain.push(established());
+        chain.push(invalid());
+        chain.push(Rule::new().jump("forward_invalid_sources").describe("Check for bogon/spoofed sources"));
+        for blocklist in self.blocklists.iter().filter(|b| b.outbound()) {
+            for (family, ipv6) in [("v4", false), ("v6", true)] {
+                chain.push(
+                    Rule::new()
+                        .then(self.oif_wan())
+                        .matching(Field::daddr(ipv6), Value::SetRef(blocklist.set_name(family)))
+                        .then(admin_prohibited(RejectType::Icmpx))
+                        .describe(&format!("Reject destinations on blocklist {}", blocklist.name)),
+                );
+            }
+        }
+        for vlan in &self.vlans {
+            chain.push(
+                Rule::new()
+                    .then(iifname(&vlan.interface_name))
+                    .jump(&format!("forward_{}", vlan.chain_suffix))
+                    .describe(&format!("{} forward rules", vlan.label)),
+            );
+        }
+        if !self.interface_mgmt.is_empty() {
+            chain.push(Rule::new().jump("forward_mgmt_isolation").describe("Block non-mgmt access to mgmt subnet"));
+        }
+
+        for vlan in &self.vlans {
+            self.inter_vlan_rules(&mut chain, vlan, &vlan.tcp_allow_inter_vlan, "tcp");
+            self.inter_vlan_rules(&mut chain, vlan, &vlan.udp_allow_inter_vlan, "udp");
+        }
+        for route in self.routes.iter().filter(|route| route.table.is_none()) {
+            let ipv6 = route.destination.is_ipv6();
+            let Some(vlan) = self.vlans.iter().find(|vlan| vlan.name == route.vlan) else {
+                continue;
+            };
+            if (ipv6 && self.enable_ipv6) || (!ipv6 && self.enable_ipv4) {
+                // Hosts on the gateway's VLAN that send to the router instead
+                chain.push(
+                    Rule::new()
+                        .then(iifname(&vlan.interface_name))
+                        .then(oifname(&vlan.interface_name))
+                        .matching(Field::daddr(ipv6), Value::literal(route.destination))
+                        .accept()
+                        .describe(&format!("Allow {} to routed network {}", vlan.label, route.name)),
+                );
+            }
+        }
+        for vlan in &self.vlans {
+            self.inbound_rules(&mut chain, vlan, &vlan.tcp_allow_inbound, "tcp");
+            self.inbound_rules(&mut chain, vlan, &vlan.udp_allow_inbound, "udp");
+        }
+        for (routes, proto) in [(&self.tcp_forward_wan, "tcp"), (&self.udp_forward_wan, "udp")] {
+            for route in &routes.routes {
+                let ipv6 = !route.is_ipv4();
+                let to_route = |dport: Value| {
+                    let mut rule = Rule::new().matching(Field::CT_STATUS, Value::flags(&["dnat"])).then(self.iif_wan());
+                    if !route.sources.is_empty() {
+                        rule = rule.matching(Field::saddr(ipv6), Value::set(&route.sources));
+                    }
+                    rule.matching(Field::daddr(ipv6), Value::literal(route.destination_ip))
+                        .matching(Field::dport(proto), dport)
+                        .extend(route.time())
+                };
+                // Limits are per WAN-side port, so a range is limited port by port
+                let mut limited: Vec<u16> = self
+                    .wan_limits
+                    .limited_ports()
+                    .into_iter()
+                    .filter(|(p, port, _)| *p == proto && route.covers(*port))
+                    .map(|(_, port, _)| port)
+                    .collect();
+                limited.sort_unstable();
+                limited.dedup();
+                for port in limited {
+                    let to_port = to_route(Value::literal(route.destination_for(port)));
+                    chain.rules.extend(self.wan_limit_rules(proto, port, ipv6, &to_port));
+                }
+                let description = forward_description(&format!("DNAT forward {} from WAN", proto.to_uppercase()), route);
+                let prefix = match &route.description {
+                    Some(label) => format!("Forwarded {}", label),
+                    None => format!("Forwarded {} {}", proto.to_uppercase(), route.incoming()),
+                };
+                chain.rules.extend(self.accept_logged(to_route(route.destination()), &route.log, &prefix, &description));
+            }
+        }
+        if self.upnp_enabled() {
+            chain.push(Rule::new().then(self.iif_wan()).jump(upnp::FORWARD_CHAIN).describe("UPnP port mappings"));
+        }
+        // Reflected connections between zones are accepted by the inter-VLAN
+        // rules; only the destination's own zone needs its own accept.
+        for (route, proto, zones) in self.hairpin_routes() {
+            let home = zones[0];
+            chain.push(
+                Rule::new()
+                    .matching(Field::CT_STATUS, Value::flags(&["dnat"]))
+                    .then(iifname(&home.interface_name))
+                    .then(oifname(&home.interface_name))
+                    .matching(Field::IP_DADDR, Value::literal(route.destination_ip))
+                    .matching(Field::dport(proto), route.destination())
+                    .extend(route.time())
+                    .accept()
+                    .describe(&format!("Hairpin forward {} within {}", proto.to_uppercase(), home.label)),
+            );
+        }
+
+        self.zone_drops(&mut chain, "forward");
+        chain.rules.extend(self.drop_logged(
+            &[],
+            &self.logging.forward,
+            "Dropped forward",
+            "Log dropped forward (rate-limited)",
+            "Default drop",
+        ));
+        chain
+    }
+
+    /// Knock rules for one family, last stage first so a source that has
+    /// reached a stage advances rather than starting over when the same
+    /// port appears twice. Knocks are dropped like any closed port.
+    fn knock_rules(&self, knock: &Knock, ipv6: bool) -> Vec<Rule> {
+        let family = if ipv6 { "v6" } else { "v4" };
+        let saddr = Field::saddr(ipv6);
+        let total = knock.sequence.len();
+        // A source is in at most one stage set: each knock moves it on
+        let forget = |keep: usize| {
+            (1..total)
+                .filter(move |stage| *stage != keep)
+                .map(move |stage| Expr::DeleteFromSet { set: Knock::stage_set(stage, family), key: saddr })
+        };
+        let mut rules = Vec::new();
+        for (i, p) in knock.sequence.iter().enumerate().rev() {
+            let mut rule = Rule::new().then(self.iif_wan()).matching(Field::dport(p.proto), Value::literal(p.port));
+            if i > 0 {
+                rule = rule.matching(saddr, Value::SetRef(Knock::stage_set(i, family)));
+            }
+            let next = if i + 1 == total { Knock::open_set(family) } else { Knock::stage_set(i + 1, family) };
+            rule.exprs.extend(forget(i + 1));
+            rules.push(
+                rule.then(Expr::UpdateSet { set: next, key: saddr })
+                    .drop()
+                    .describe(&format!("Port knock {} of {}", i + 1, total)),
+            );
+        }
+        // Any other packet to the ports the sequence spans starts it over
+        for proto in ["tcp", "udp"].into_iter().filter(|_| total > 1) {
+            let ports: Vec<u16> = knock.sequence.iter().filter(|p| p.proto == proto).map(|p| p.port).collect();
+            let (Some(low), Some(high)) = (ports.iter().min(), ports.iter().max()) else {
+                continue;
+            };
+            let range = if low == high { low.to_string() } else { format!("{}-{}", low, high) };
+            let mut rule = Rule::new().then(self.iif_wan()).matching(Field::dport(proto), Value::literal(range));
+            rule.exprs.extend(forget(0));
+            rules.push(rule.describe("Reset port knocks on a wrong port"));
+        }
+        rules
+    }
+
+    /// Drops of new connections to a WAN-side port that break its limits
+    /// or the SSH guard. `to_port` matches the traffic to the port.
+    fn wan_limit_rules(&self, proto: &str, port: u16, ipv6: bool, to_port: &Rule) -> Vec<Rule> {
+        let family = if ipv6 { "v6" } else { "v4" };
+        let saddr = Field::saddr(ipv6);
+        let new = to_port.clone().matching(Field::CT_STATE, Value::literal("new"));
+        let add = |set: String, stmt: Option<Expr>| Expr::AddToSet { set, key: saddr, stmt: stmt.map(Box::new) };
+        let label = format!("{} {}", proto.to_uppercase(), port);
+        let mut rules = Vec::new();
+        if let Some(guard) = self.wan_limits.guard(proto, port) {
+            rules.push(
+                to_port
+                    .clone()
+                    .matching(saddr, Value::SetRef(guard.ban_set(family)))
+                    .drop()
+                    .describe("Drop sources banned by the SSH guard"),
+            );
+            rules.push(
+                new.clone()
+                    .then(add(guard.meter_set(family), Some(guard.threshold.over())))
+                    .then(add(guard.ban_set(family), None))
+                    .drop()
+                    .describe("Ban sources exceeding the SSH guard rate"),
+            );
+        }
+        if let Some(limit) = self.wan_limits.port(proto, port) {
+            if let Some(rate) = &limit.rate {
+                rules.push(
+                    new.clone()
+                        .then(add(limit.rate_set(family), Some(rate.over())))
+                        .drop()
+                        .describe(&format!("Rate limit {} per source", label)),
+                );
+            }
+            if let Some(max) = limit.ct_limit {
+                rules.push(
+                    new.clone()
+                        .then(add(limit.conn_set(family), Some(Expr::CtCount { over: max })))
+                        .drop()
+                        .describe(&format!("Limit {} connections per source", label)),
+                );
+            }
+        }
+        rules
+    }
+
+    fn inter_vlan_rules(&self, chain: &mut Chain, vlan: &Vlan, rules: &InterVlanRuleList, proto: &str) {
+        for entry in &rules.entries {
+            for rule in &entry.rules {
+                let ipv6 = !rule.dest_is_ipv4();
+                let mut nft_rule = Rule::new().then(iifname(&entry.source_interface)).then(oifname(&vlan.interface_name));
+                if let Some(src) = &rule.src {
+                    nft_rule = nft_rule.matching(Field::saddr(ipv6), src.value());
+                }
+                let matched = nft_rule
+                    .matching(Field::daddr(ipv6), rule.dest.value())
+                    .matching(Field::dport(proto), rule.port.value())
+                    .extend(rule.time());
+                chain.rules.extend(self.accept_logged(
+                    matched,
+                    &entry.log,
+                    &format!("Allowed {} to {}", entry.source_label, vlan.label),
+                    &format!("Allow inter-VLAN {} from {} to {}", proto.to_uppercase(), entry.source_label, vlan.label),
+                ));
+            }
+        }
+    }
+
+    fn inbound_rules(&self, chain: &mut Chain, vlan: &Vlan, rules: &InboundRuleList, proto: &str) {
+        for rule in &rules.rules {
+            chain.push(
+                Rule::new()
+                    .then(self.iif_wan())
+                    .then(oifname(&vlan.interface_name))
+                    .then(rule.daddr())
+                    .matching(Field::dport(proto), rule.port.value())
+                    .extend(rule.time())
+                    .accept()
+                    .describe(&format!("Allow inbound {} to {}", proto.to_uppercase(), vlan.label)),
+            );
+        }
+    }
+
+    /// Egress to the WAN and DNAT forwards for one zone.
+    fn zone_forward_chain(&self, vlan: &Vlan) -> Chain {
+        let mut chain = Chain::new(&format!("forward_{}", vlan.chain_suffix));
+        let v4 = vlan.has_ipv4(self.enable_ipv4);
+        let v6 = vlan.has_ipv6(self.enable_ipv6);
+        // Where the zone's internet traffic leaves, for rule comments
+        let exit = match &vlan.egress_via {
+            Some(tunnel) => format!("via {}", tunnel),
+            None => "to WAN".to_string(),
+        };
+
+        for client in &vlan.clients {
+            let from = Rule::new().matching(Field::ETHER_SADDR, Value::literal(&client.mac));
+            let allow = format!("Allow {} egress {}", client.name, exit);
+            if self.enable_ipv4 && !client.egress_ipv4.is_empty() {
+                chain.push(
+                    from.clone()
+                        .matching(Field::IP_DADDR, Value::set(&client.egress_ipv4))
+                        .then(self.oif_egress(vlan))
+                        .accept()
+                        .describe(&allow),
+                );
+            }
+            if v6 && !client.egress_ipv6.is_empty() {
+                chain.push(
+                    from.clone()
+                        .matching(Field::IP6_DADDR, Value::set(&client.egress_ipv6))
+                        .then(self.oif_egress(vlan))
+                        .accept()
+                        .describe(&allow),
+                );
+            }
+            chain.push(
+                from.then(self.oif_egress(vlan))
+                    .then(admin_prohibited(RejectType::Icmpx))
+                    .describe(&format!("Reject other {} egress {}", client.name, exit)),
+            );
+        }
+
+        let from = |ipv6: bool| Rule::new().matching(Field::saddr(ipv6), vlan.subnet(ipv6));
+        if v4 && !vlan.egress_allowed_ipv4.is_empty() {
+            chain.push(
+                from(false)
+                    .matching(Field::IP_DADDR, Value::set(&vlan.egress_allowed_ipv4))
+                    .then(self.oif_egress(vlan))
+                    .accept()
+                    .describe(&format!("Allow IPv4 egress {}", exit)),
+            );
+        }
+        if v6 && !vlan.egress_allowed_ipv6.is_empty() {
+            chain.push(
+                from(true)
+                    .matching(Field::IP6_DADDR, Value::set(&vlan.egress_allowed_ipv6))
+                    .then(self.oif_egress(vlan))
+                    .accept()
+                    .describe(&format!("Allow IPv6 egress {}", exit)),
+            );
+        }
+        if let Some(policy) = vlan.routing {
+            for route in self.routes.iter().filter(|route| route.table == Some(policy.table)) {
+                let ipv6 = route.destination.is_ipv6();
+                let (enabled, egress) =
+                    if ipv6 { (v6, &vlan.egress_allowed_ipv6) } else { (v4, &vlan.egress_allowed_ipv4) };
+                let Some(gateway) = self.vlans.iter().find(|zone| zone.name == route.vlan) else {
+                    continue;
+                };
+                if !enabled || egress.is_empty() {
+                    continue;
+                }
+                // The gateway's own VLAN stays subject to inter-VLAN rules
+                let mut rule = from(ipv6)
+                    .matching(Field::daddr(ipv6), Value::set(egress))
+                    .not_matching(Field::daddr(ipv6), gateway.subnet(ipv6));
+                if route.destination.prefix() > 0 {
+                    rule = rule.matching(Field::daddr(ipv6), Value::literal(route.destination));
+                }
+                chain.push(
+                    rule.then(oifname(&gateway.interface_name))
+                        .accept()
+                        .describe(&format!("Allow egress via route {}", route.name)),
+                );
+            }
+        }
+        if !vlan.egress_domains.is_empty() {
+            for (enabled, ipv6, family, description) in [
+                (v4, false, "v4", "Allow IPv4 egress to resolved domains"),
+                (v6, true, "v6", "Allow IPv6 egress to resolved domains"),
+            ] {
+                if enabled {
+                    chain.push(
+                        from(ipv6)
+                            .matching(Field::daddr(ipv6), Value::SetRef(vlan.domain_set(family)))
+                            .then(self.oif_egress(vlan))
+                            .then(Expr::UpdateSet { set: vlan.domain_set(family), key: Field::daddr(ipv6) })
+                            .accept()
+                            .describe(description),
+                    );
+                }
+            }
+        }
+
+        for (routes, proto) in [(&vlan.tcp_forward, "tcp"), (&vlan.udp_forward, "udp")] {
+            for route in &routes.routes {
+                let ipv6 = !route.is_ipv4();
+                chain.push(
+                    Rule::new()
+                        .matching(Field::CT_STATUS, Value::flags(&["dnat"]))
+                        .matching(Field::saddr(ipv6), vlan.subnet(ipv6))
+                        .matching(Field::daddr(ipv6), Value::literal(route.destination_ip))
+                        .matching(Field::dport(proto), route.destination())
+                        .extend(route.time())
+                        .accept()
+                        .describe(&format!("DNAT forward {}", proto.to_uppercase())),
+                );
+            }
+        }
+        chain
+    }
+
+    fn output_chain(&self) -> Chain {
+        let mut chain = Chain::base("output", ChainType::Filter, "output", Priority::Value(0), Policy::Drop);
+        chain.push(Rule::new().then(oifname("lo")).accept().describe("Allow loopback"));
+        if !self.interface_mgmt.is_empty() {
+            chain.rules.extend(self.drop_logged(
+                &[self.not_mgmt(Field::OIFNAME), self.to_mgmt_subnet()],
+                &self.logging.mgmt,
+                "Dropped output to mgmt subnet",
+                "Log output to mgmt from non-mgmt",
+                "Drop output to mgmt from non-mgmt",
+            ));
+        }
+        chain.push(Rule::new().then(self.oif_wan()).accept().describe("Allow outgoing WAN"));
+        for vlan in &self.vlans {
+            chain.push(
+                Rule::new()
+                    .then(oifname(&vlan.interface_name))
+                    .accept()
+                    .describe(&format!("Allow outgoing to {}", vlan.label)),
+            );
+        }
+        if self.vlan_aware_switch {
+            chain.push(
+                Rule::new()
+                    .then(oifname(&self.interface_trunk.to_string()))
+                    .accept()
+                    .describe("Allow outgoing on bare trunk"),
+            );
+        }
+        if !self.interface_mgmt.is_empty() {
+            chain.push(Rule::new().then(oifname(&self.interface_mgmt)).accept().describe("Allow outgoing mgmt"));
+        }
+        chain
+    }
+
+    fn nat_table(&self) -> Table {
+        let mut table = Table::new(Family::Inet, tables::NAT);
+
+        let mut prerouting = Chain::base("prerouting", ChainType::Nat, "prerouting", Priority::Value(0), Policy::Accept);
+        // Ahead of the dashboard redirect, which would otherwise catch
+        // reflected forwards of ports 80 and 443.
+        let lan = self.lan_subnets_ipv4();
+        for (route, proto, zones) in self.hairpin_routes() {
+            for zone in zones {
+                let mut rule = Rule::new()
+                    .then(iifname(&zone.interface_name))
+                    .matching(Field::FIB_DADDR_TYPE, Value::literal("local"))
+                    .then(Expr::ne(Field::IP_DADDR, lan.clone()));
+                if !route.sources.is_empty() {
+                    rule = rule.matching(Field::IP_SADDR, Value::set(&route.sources));
+                }
+                prerouting.push(
+                    rule.matching(Field::dport(proto), route.incoming())
+                        .extend(route.time())
+                        .then(Expr::Dnat {
+                            ipv6: false,
+                            addr: route.destination_ip.to_string(),
+                            port: route.dnat_port(),
+                        })
+                        .describe(&format!("Hairpin DNAT {} from {}", proto.to_uppercase(), zone.label)),
+                );
+            }
+        }
+        prerouting.push(
+            Rule::new()
+                .matching(Field::FIB_DADDR_TYPE, Value::literal("local"))
+                .matching(Field::TCP_DPORT, Value::set(&[80, 443]))
+                .then(Expr::Redirect { port: self.dashboard_port })
+                .describe("Redirect HTTP/HTTPS to dashboard"),
+        );
+        for vlan in &self.vlans {
+            for (routes, proto) in [(&vlan.tcp_forward, "tcp"), (&vlan.udp_forward, "udp")] {
+                let matches = |ipv6: bool| {
+                    vec![iifname(&vlan.interface_name), Expr::eq(Field::saddr(ipv6), vlan.subnet(ipv6))]
+                };
+                self.dnat_rules(&mut prerouting, routes, proto, matches, &format!("from {}", vlan.label));
+            }
+        }
+        for (routes, proto) in [(&self.tcp_forward_wan, "tcp"), (&self.udp_forward_wan, "udp")] {
+            self.dnat_rules(&mut prerouting, routes, proto, |_| vec![self.iif_wan()], "from WAN");
+        }
+        if self.upnp_enabled() {
+            prerouting.push(Rule::new().then(self.iif_wan()).jump(upnp::NAT_CHAIN).describe("UPnP port mappings"));
+            table.chains.push(Chain::new(upnp::NAT_CHAIN));
+        }
+        table.chains.push(prerouting);
+
+        let mut postrouting =
+            Chain::base("postrouting", ChainType::Nat, "postrouting", Priority::Value(100), Policy::Accept);
+        // A client on the destination's own subnet would get the reply
+        // straight from the server, bypassing the DNAT; make it come back
+        // through the router instead.
+        for (route, proto, zones) in self.hairpin_routes() {
+            let home = zones[0];
+            postrouting.push(
+                Rule::new()
+                    .then(iifname(&home.interface_name))
+                    .then(oifname(&home.interface_name))
+                    .matching(Field::CT_STATUS, Value::flags(&["dnat"]))
+                    .matching(Field::IP_DADDR, Value::literal(route.destination_ip))
+                    .matching(Field::dport(proto), route.destination())
+                    .then(Expr::Masquerade)
+                    .describe(&format!("Hairpin SNAT {} within {}", proto.to_uppercase(), home.label)),
+            );
+        }
+        if self.upnp_enabled() {
+            postrouting.push(
+                Rule::new().then(self.oif_wan()).jump(upnp::POSTROUTING_CHAIN).describe("UPnP port mappings"),
+            );
+            table.chains.push(Chain::new(upnp::POSTROUTING_CHAIN));
+        }
+        if self.enable_ipv4 {
+            let ipv4 = Rule::new().matching(Field::NFPROTO, Value::literal("ipv4"));
+            if self.wan.is_multi() {
+                for uplink in &self.wan.uplinks {
+                    postrouting.push(
+                        ipv4.clone()
+                            .then(oifname(&uplink.interface_name))
+                            .then(Expr::Masquerade)
+                            .describe(&format!("Masquerade IPv4 LAN-to-WAN via {} (NAT)", uplink.name)),
+                    );
+                }
+            } else {
+                postrouting.push(
+                    ipv4.then(self.oif_wan()).then(Expr::Masquerade).describe("Masquerade IPv4 LAN-to-WAN (NAT)"),
+                );
+            }
+        }
+        // The provider routes only the tunnel addresses back to us
+        for tunnel in self.egress_tunnels() {
+            postrouting.push(
+                Rule::new()
+                    .then(oifname(tunnel))
+                    .then(Expr::Masquerade)
+                    .describe(&format!("Masquerade egress via {} (NAT)", tunnel)),
+            );
+        }
+        table.chains.push(postrouting);
+        table
+    }
+
+    /// Literal IPv4 subnets of every zone and the mgmt network: addresses
+    /// that belong to the router on the LAN side rather than the WAN.
+    fn lan_subnets_ipv4(&self) -> Value {
+        let subnets: Vec<String> = self
+            .vlans
+            .iter()
+            .map(|vlan| vlan.subnet_ipv4.as_str())
+            .chain([self.subnet_mgmt_ipv4.as_str()])
+            .filter_map(|subnet| subnet.parse::<IpNetwork>().ok())
+            .map(|net| format!("{}/{}", net.network(), net.prefix()))
+            .collect();
+        Value::set(&subnets)
+    }
+
+    /// The zone whose literal subnet holds `ip`.
+    fn zone_of(&self, ip: IpAddr) -> Option<&Vlan> {
+        self.vlans.iter().find(|vlan| {
+            let subnet = if ip.is_ipv4() { &vlan.subnet_ipv4 } else { &vlan.subnet_ipv6 };
+            subnet.parse::<IpNetwork>().is_ok_and(|net| net.contains(ip))
+        })
+    }
+
+    /// Whether a named object set lists an element matching `has`.
+    fn object_set_has(&self, name: &str, has: impl Fn(&str) -> bool) -> bool {
+        self.object_sets.iter().filter(|set| set.name == name).flat_map(|set| &set.elements).any(|e| has(e))
+    }
+
+    /// Whether an inter-VLAN rule lets its source zone reach a forward's
+    /// destination.
+    fn reaches(&self, rule: &InterVlanRule, route: &ForwardRoute) -> bool {
+        let ip = route.destination_ip;
+        let to_addr = match &rule.dest {
+            RuleAddr::Ip(dest) => *dest == ip,
+            RuleAddr::Set(name, _) => {
+                self.object_set_has(name, |e| e.parse::<IpNetwork>().is_ok_and(|net| net.contains(ip)))
+            }
+        };
+        let to_port = |port: u16| match &rule.port {
+            PortSpec::Single(p) => *p == port,
+            PortSpec::Range(start, end) => (*start..=*end).contains(&port),
+            PortSpec::Set(name) => self.object_set_has(name, |e| e == port.to_string()),
+        };
+        to_addr && route.destination_ports().all(to_port)
+    }
+
+    /// IPv4 WAN forwards to reflect with `wan.hairpin`, each with the zones
+    /// whose clients may use it: the destination's own zone first, then any
+    /// zone with an inter-VLAN rule reaching the destination. Forwards to
+    /// addresses outside every zone are not reflected, and a forward's
+    /// `from` sources also limit which clients it is reflected for.
+    fn hairpin_routes(&self) -> Vec<(&ForwardRoute, &'static str, Vec<&Vlan>)> {
+        if !self.hairpin || !self.enable_ipv4 {
+            return Vec::new();
+        }
+        let mut routes = Vec::new();
+        for (list, proto) in [(&self.tcp_forward_wan, "tcp"), (&self.udp_forward_wan, "udp")] {
+            for route in list.routes.iter().filter(|r| r.is_ipv4()) {
+                let Some(home) = self.zone_of(route.destination_ip) else {
+                    continue;
+                };
+                let rules = if proto == "tcp" { &home.tcp_allow_inter_vlan } else { &home.udp_allow_inter_vlan };
+                let mut zones = vec![home];
+                for entry in rules.entries.iter().filter(|e| e.rules.iter().any(|r| self.reaches(r, route))) {
+                    if let Some(zone) = self.vlans.iter().find(|v| v.interface_name == entry.source_interface) {
+                        if !zones.iter().any(|z| z.interface_name == zone.interface_name) {
+                            zones.push(zone);
+                        }
+                    }
+                }
+                routes.push((route, proto, zones));
+            }
+        }
+        routes
+    }
+
+    /// DNAT rules for port forwards; `matches` gives the leading matches for
+    /// the destination's family.
+    fn dnat_rules(
+        &self,
+        chain: &mut Chain,
+        routes: &ForwardRouteList,
+        proto: &str,
+        matches: impl Fn(bool) -> Vec<Expr>,
+        from: &str,
+    ) {
+        for route in &routes.routes {
+            let ipv6 = !route.is_ipv4();
+            let mut rule = Rule::new().extend(matches(ipv6));
+            if !route.sources.is_empty() {
+                rule = rule.matching(Field::saddr(ipv6), Value::set(&route.sources));
+            }
+            chain.push(
+                rule.matching(Field::dport(proto), route.incoming())
+                    .extend(route.time())
+                    .then(Expr::Dnat { ipv6, addr: route.destination_ip.to_string(), port: route.dnat_port() })
+                    .describe(&forward_description(&format!("DNAT {} {}", proto.to_uppercase(), from), route)),
+            );
+        }
+    }
+
+    /// Connection pinning and balancing across multiple WAN uplinks, and
+    /// the connection marks of VLANs routed by their own table.
+    fn wan_routing_table(&self) -> Table {
+        let mut table = Table::new(Family::Inet, tables::WAN_ROUTING);
+        let route_via_ct_mark = Expr::Mangle { field: Field::MARK, value: Value::Field(Field::CT_MARK) };
+        let multi = self.wan.is_multi();
+
+        let mut prerouting =
+            Chain::base("prerouting", ChainType::Filter, "prerouting", Priority::Mangle, Policy::Accept);
+        for uplink in self.wan.uplinks.iter().filter(|_| multi) {
+            prerouting.push(
+                Rule::new()
+                    .then(iifname(&uplink.interface_name))
+                    .matching(Field::CT_STATE, Value::flags(&["new"]))
+                    .then(Expr::Mangle { field: Field::CT_MARK, value: Value::literal(uplink.table) })
+                    .describe(&format!("Pin inbound connections to uplink {}", uplink.name)),
+            );
+        }
+        for vlan in &self.vlans {
+            let Some(policy) = vlan.routing else {
+                continue;
+            };
+            prerouting.push(
+                Rule::new()
+                    .then(iifname(&vlan.interface_name))
+                    .matching(Field::CT_STATE, Value::flags(&["new"]))
+                    .then(Expr::Mangle { field: Field::CT_MARK, value: Value::literal(policy.fwmark) })
+                    .describe(&format!("Route {} by table {}", vlan.label, policy.table)),
+            );
+        }
+        if self.wan.is_balance() {
+            prerouting.push(
+                Rule::new()
+                    .not_matching(Field::IIFNAME, self.wan_ifaces())
+                    .matching(Field::CT_STATE, Value::flags(&["new"]))
+                    .matching(Field::CT_MARK, Value::literal(0))
+                    .then(Expr::Mangle { field: Field::CT_MARK, value: self.wan.balance_map() })
+                    .describe("Balance new connections across uplinks by weight"),
+            );
+        }
+        prerouting.push(
+            Rule::new()
+                .not_matching(Field::IIFNAME, self.wan_ifaces())
+                .not_matching(Field::CT_MARK, Value::literal(0))
+                .then(route_via_ct_mark.clone())
+                .describe(if multi { "Route connection via its pinned uplink" } else { "Route connection by its mark" }),
+        );
+        table.chains.push(prerouting);
+        if !multi {
+            return table;
+        }
+
+        let mut output = Chain::base("output", ChainType::Route, "output", Priority::Mangle, Policy::Accept);
+        output.push(
+            Rule::new()
+                .not_matching(Field::CT_MARK, Value::literal(0))
+                .then(route_via_ct_mark)
+                .describe("Route replies via the uplink they arrived on"),
+        );
+        table.chains.push(output);
+        table
+    }
+
+    /// DSCP marking and bandwidth marks for QoS.
+    fn mangle_table(&self) -> Table {
+        let mut table = Table::new(Family::Inet, tables::MANGLE);
+        let mut chain =
+            Chain::base("postrouting", ChainType::Filter, "postrouting", Priority::Mangle, Policy::Accept);
+        let dscp = |ipv6: bool| if ipv6 { Field::IP6_DSCP } else { Field::IP_DSCP };
+        let suffix = |ipv6: bool| if ipv6 { " (IPv6)" } else { "" };
+
+        for vlan in &self.vlans {
+            if let Some(class) = &vlan.qos_class {
+                for (enabled, ipv6) in [(vlan.has_ipv4(self.enable_ipv4), false), (vlan.has_ipv6(self.enable_ipv6), true)] {
+                    if enabled {
+                        chain.push(
+                            Rule::new()
+                                .then(self.oif_wan())
+                                .matching(Field::saddr(ipv6), vlan.subnet(ipv6))
+                                .then(Expr::Mangle { field: dscp(ipv6), value: Value::literal(class.dscp_name()) })
+                                .describe(&format!("QoS VLAN {} {}{}", vlan.id, class, suffix(ipv6))),
+                        );
+                    }
+                }
+            }
+        }
+        for ovr in &self.qos_overrides {
+            for (cidrs, ipv6) in [(&ovr.cidrs_ipv4, false), (&ovr.cidrs_ipv6, true)] {
+                if !cidrs.is_empty() {
+                    chain.push(
+                        Rule::new()
+                            .then(self.oif_wan())
+                            .matching(Field::saddr(ipv6), Value::set(cidrs))
+                            .then(Expr::Mangle { field: dscp(ipv6), value: Value::literal(ovr.class.dscp_name()) })
+                            .describe(&format!("QoS override {}{}", ovr.class, suffix(ipv6))),
+                    );
+                }
+            }
+        }
+        for vlan in self.vlans.iter().filter(|v| v.bandwidth_upload_kbit.is_some()) {
+            for (enabled, ipv6) in [(vlan.has_ipv4(self.enable_ipv4), false), (vlan.has_ipv6(self.enable_ipv6), true)] {
+                if enabled {
+                    chain.push(
+                        Rule::new()
+                            .then(self.oif_wan())
+                            .matching(Field::saddr(ipv6), vlan.subnet(ipv6))
+                            .then(Expr::Mangle { field: Field::MARK, value: Value::literal(vlan.id) })
+                            .describe(&format!("Upload cap VLAN {}{}", vlan.id, suffix(ipv6))),
+                    );
+                }
+            }
+        }
+        if self.has_download_bandwidth {
+            chain.push(
+                Rule::new()
+                    .then(self.iif_wan())
+                    .then(Expr::Mangle { field: Field::MARK, value: Value::literal("0x10000") })
+                    .describe("Mark WAN downloads for per-VLAN shaping"),
+            );
+        }
+        table.chains.push(chain);
+        table
+    }
+}
@@ -0,0 +1,120 @@
+//! Automatic port mapping (UPnP IGD, NAT-PMP and PCP) by miniupnpd, for
+//! VLANs with an enabled `upnp` block.
+//!
+//! miniupnpd adds its mappings to dedicated chains in our `inet nifty_filter`
+//! and `inet nifty_nat` tables. Loading the ruleset replaces those tables and empties
+//! the chains, so the service restarts with the firewall and restores its
+//! mappings from the lease file.
+
+use ipnetwork::IpNetwork;
+use nifty_nft::tables;
+
+use crate::hcl_config::UpnpConfig;
+use crate::vlan::Vlan;
+use crate::Router;
+
+/// Chain in `inet nifty_filter` holding the mappings' forward accepts.
+pub const FORWARD_CHAIN: &str = "miniupnpd";
+/// Chain in `inet nifty_nat` holding the mappings' DNAT rules.
+pub const NAT_CHAIN: &str = "prerouting_miniupnpd";
+/// Chain in `inet nifty_nat` holding the mappings' SNAT rules.
+pub const POSTROUTING_CHAIN: &str = "postrouting_miniupnpd";
+
+pub const LEASE_FILE: &str = "/var/lib/miniupnpd/upnp.leases";
+
+/// UPnP IGD control (HTTP/SOAP)
+pub const HTTP_PORT: u16 = 5000;
+/// UPnP discovery
+pub const SSDP_PORT: u16 = 1900;
+/// NAT-PMP and PCP
+pub const PCP_PORT: u16 = 5351;
+
+/// Ports a zone may map when `allowed_ports` is unset
+pub const DEFAULT_PORTS: &str = "1024-65535";
+
+/// Resolve a VLAN's `upnp` block into the ports its clients may map, or
+/// None when it is absent or disabled.
+pub fn ports(upnp: Option<&UpnpConfig>) -> Result<Option<(u16, u16)>, String> {
+    let Some(upnp) = upnp.filter(|u| u.enabled) else {
+        return Ok(None);
+    };
+    let ports = upnp.allowed_ports.as_deref().unwrap_or(DEFAULT_PORTS);
+    let port = |p: &str| p.trim().parse::<u16>().ok().filter(|p| *p > 0);
+    let range = match ports.split_once('-') {
+        Some((first, last)) => port(first).zip(port(last)),
+        None => port(ports).map(|p| (p, p)),
+    };
+    match range {
+        Some((first, last)) if first <= last => Ok(Some((first, last))),
+        _ => Err(format!("allowed_ports: invalid port range '{}'. Expected e.g. \"1024-65535\".", ports)),
+    }
+}
+
+impl Router {
+    /// Zones whose clients may map ports, with the allowed range.
+    pub fn upnp_zones(&self) -> impl Iterator<Item = (&Vlan, (u16, u16))> {
+        self.vlans.iter().filter_map(|vlan| vlan.upnp_ports.map(|ports| (vlan, ports)))
+    }
+
+    pub fn upnp_enabled(&self) -> bool {
+        self.upnp_zones().next().is_some()
+    }
+
+    /// miniupnpd.conf: listen on the UPnP zones only and let each client map
+    /// the zone's allowed ports to itself. IPv4 only; mappings go out the
+    /// primary WAN uplink.
+    pub fn miniupnpd_conf(&self) -> Option<String> {
+        if !self.upnp_enabled() {
+            return None;
+        }
+        let mut out = String::from("# Generated from nifty-filter HCL config\n");
+        out.push_str(&format!("ext_ifname={}\n", self.wan.primary().interface_name));
+        for (vlan, _) in self.upnp_zones() {
+            out.push_str(&format!("listening_ip={}\n", vlan.interface_name));
+        }
+        out.push_str(&format!("http_port={}\n", HTTP_PORT));
+        out.push_str("enable_upnp=yes\nenable_natpmp=yes\nenable_pcp_pmp=yes\nipv6_disable=yes\n");
+        out.push_str("secure_mode=yes\nsystem_uptime=yes\n");
+        out.push_str(&format!("lease_file={}\n", LEASE_FILE));
+        out.push_str("upnp_nftables_family_split=no\n");
+        out.push_str(&format!(
+            "upnp_table_name={}\nupnp_nat_table_name={}\nupnp_forward_chain={}\nupnp_nat_chain={}\nupnp_nat_postrouting_chain={}\n",
+            tables::FILTER, tables::NAT, FORWARD_CHAIN, NAT_CHAIN, POSTROUTING_CHAIN
+        ));
+        // Permission rules: first match wins
+        for (vlan, (first, last)) in self.upnp_zones() {
+            let Ok(subnet) = vlan.subnet_ipv4.parse::<IpNetwork>() else {
+                continue;
+            };
+            out.push_str(&format!(
+                "allow {first}-{last} {}/{} {first}-{last}\n",
+                subnet.network(),
+                subnet.prefix()
+            ));
+        }
+        out.push_str("deny 0-65535 0.0.0.0/0 0-65535\n");
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ports() {
+        let config = |enabled: bool, ports: Option<&str>| UpnpConfig {
+            enabled,
+            allowed_ports: ports.map(str::to_string),
+        };
+        assert_eq!(ports(None), Ok(None));
+        assert_eq!(ports(Some(&config(false, None))), Ok(None));
+        assert_eq!(ports(Some(&config(true, None))), Ok(Some((1024, 65535))));
+        assert_eq!(ports(Some(&config(true, Some("3074")))), Ok(Some((3074, 3074))));
+        assert_eq!(
+            ports(Some(&config(true, Some("2000-1000")))),
+            Err("allowed_ports: invalid port range '2000-1000'. Expected e.g. \"1024-65535\".".to_string())
+        );
+        assert!(ports(Some(&config(true, Some("0-100")))).is_err());
+    }
+}
@@ -0,0 +1,812 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::str::FromStr;
+
+use hcl::edit::structure::Body;
+use hcl::edit::Span as _;
+use ipnetwork::IpNetwork;
+use serde::Serialize;
+
+use crate::hcl_config::{parse_hcl, HclConfig, VlanHclConfig};
+use crate::objects::Objects;
+use crate::parsers::forward_route::ForwardRoute;
+
+// Stable diagnostic codes. Never renumber these; tooling matches on them.
+pub const PARSE_ERROR: &str = "NF001";
+pub const CONFIG_ERROR: &str = "NF002";
+pub const DUPLICATE_VLAN_ID: &str = "NF101";
+pub const OVERLAPPING_SUBNETS: &str = "NF102";
+pub const DHCP_POOL_OUTSIDE_SUBNET: &str = "NF103";
+pub const FORWARD_OUTSIDE_VLANS: &str = "NF104";
+pub const SWITCH_PORT_UNDEFINED_VLAN: &str = "NF105";
+pub const INVALID_ADDRESS: &str = "NF106";
+pub const DHCP_POOL_REVERSED: &str = "NF107";
+pub const DHCP_OPTION_OUTSIDE_SUBNET: &str = "NF108";
+pub const DHCP_HOST_OUTSIDE_SUBNET: &str = "NF109";
+pub const DHCP_HOST_IN_POOL: &str = "NF110";
+pub const DUPLICATE_DHCP_HOST: &str = "NF111";
+pub const DHCPV6_POOL_OUTSIDE_PREFIX: &str = "NF112";
+
+/// A semantic problem in a config, tied to the HCL element it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    /// Location as block/label/attribute segments, e.g.
+    /// `["vlan", "trusted", "dhcp", "pool_start"]`. A numeric segment after
+    /// an attribute selects an array element, and after an unlabeled block
+    /// name selects one of the repeated blocks.
+    pub path: Vec<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(code: &'static str, path: &[&str], message: String) -> Self {
+        Diagnostic { code, path: owned(path), message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Run the semantic checks that need a view of the whole config.
+pub fn check(config: &HclConfig) -> Vec<Diagnostic> {
+    let mut diags = check_addressing(config);
+    let mut vlans: Vec<_> = config.vlan.iter().collect();
+    vlans.sort_by_key(|(name, v)| (v.id, name.as_str()));
+    let zones = zone_subnets(config);
+    let objects = Objects::from_hcl(config).unwrap_or_default();
+
+    // Port forwards must target a host inside a VLAN or WireGuard zone, or a
+    // network routed behind one from the main table (default routes aside).
+    // IPv6 is skipped when a delegated prefix makes the subnets unknown.
+    let any_delegated = config.vlan.values().any(|v| v.ipv6.as_ref().is_some_and(|i| i.delegated));
+    let routed: Vec<IpNetwork> = config
+        .route
+        .values()
+        .filter(|r| r.table.is_none())
+        .filter_map(|r| r.destination.parse::<IpNetwork>().ok())
+        .filter(|net| net.prefix() > 0)
+        .collect();
+    let inside_zone = |ip: IpAddr| {
+        (ip.is_ipv6() && any_delegated)
+            || zones.iter().any(|(path, net)| path[0] != "interfaces" && net.contains(ip))
+            || routed.iter().any(|net| net.contains(ip))
+    };
+    let mut forwards: Vec<(Vec<&str>, &Vec<String>)> = vec![
+        (vec!["wan", "tcp_forward"], &config.wan.tcp_forward),
+        (vec!["wan", "udp_forward"], &config.wan.udp_forward),
+    ];
+    for (name, v) in &vlans {
+        forwards.push((vec!["vlan", name, "tcp_forward"], &v.tcp_forward));
+        forwards.push((vec!["vlan", name, "udp_forward"], &v.udp_forward));
+    }
+    for (path, entries) in forwards {
+        for (i, entry) in entries.iter().enumerate() {
+            let resolved = objects.split_schedule(entry)
+                .and_then(|(e, _)| objects.resolve_forward(e))
+                .and_then(|e| ForwardRoute::from_str(&e));
+            let Ok(route) = resolved else { continue };
+            if !inside_zone(route.destination_ip) {
+                let index = i.to_string();
+                let mut path = path.clone();
+                path.push(&index);
+                diags.push(Diagnostic::new(
+                    FORWARD_OUTSIDE_VLANS,
+                    &path,
+                    format!(
+                        "{} \"{}\": destination {} is not inside any VLAN subnet or routed network.",
+                        display_path(&owned(&path[..path.len() - 1])),
+                        entry, route.destination_ip
+                    ),
+                ));
+            }
+        }
+    }
+    for (description, forward) in &config.wan.forward {
+        let to = if forward.to.starts_with('@') {
+            objects.host_address(&forward.to).ok()
+        } else {
+            forward.to.parse::<IpAddr>().ok()
+        };
+        if let Some(ip) = to.filter(|ip| !inside_zone(*ip)) {
+            diags.push(Diagnostic::new(
+                FORWARD_OUTSIDE_VLANS,
+                &["wan", "forward", description, "to"],
+                format!("wan.forward \"{}\": destination {} is not inside any VLAN subnet or routed network.", description, ip),
+            ));
+        }
+    }
+
+    // Switch ports may only reference configured VLANs (1 is the switch default)
+    if let Some(sw) = &config.switch {
+        let mut ports: Vec<_> = sw.port.iter().collect();
+        ports.sort_by(|(a, _), (b, _)| {
+            (a.parse::<u32>().ok(), a.as_str()).cmp(&(b.parse::<u32>().ok(), b.as_str()))
+        });
+        let defined = |id: u16| id == 1 || config.vlan.values().any(|v| v.id == id);
+        for (port_id, port) in ports {
+            let mut refs = vec![(vec!["switch", "port", port_id, "pvid"], port.pvid)];
+            if let Some(vlans) = &port.vlans {
+                refs.extend(vlans.untagged.iter().map(|&id| (vec!["switch", "port", port_id, "vlans", "untagged"], id)));
+                refs.extend(vlans.tagged.iter().map(|&id| (vec!["switch", "port", port_id, "vlans", "tagged"], id)));
+            }
+            for (path, id) in refs {
+                if !defined(id) {
+                    diags.push(Diagnostic::new(
+                        SWITCH_PORT_UNDEFINED_VLAN,
+                        &path,
+                        format!(
+                            "switch port \"{}\".{}: VLAN {} is not defined by any vlan block.",
+                            port_id, path[3..].join("."), id
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    diags
+}
+
+/// Checks on VLAN addressing and DHCP: duplicate IDs, overlapping subnets,
+/// and DHCP/DHCPv6 pools and reservations that don't fit their subnet.
+/// These are the checks `generate dnsmasq` and the config menu rely on.
+pub fn check_addressing(config: &HclConfig) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let mut vlans: Vec<_> = config.vlan.iter().collect();
+    vlans.sort_by_key(|(name, v)| (v.id, name.as_str()));
+
+    // Duplicate VLAN IDs
+    let mut seen_ids = HashSet::new();
+    for (name, v) in &vlans {
+        if !seen_ids.insert(v.id) {
+            diags.push(Diagnostic::new(
+                DUPLICATE_VLAN_ID,
+                &["vlan", name, "id"],
+                format!("vlan \"{}\": duplicate VLAN ID {}.", name, v.id),
+            ));
+        }
+    }
+
+    // Overlapping subnets across VLANs, WireGuard zones and mgmt
+    let zones = zone_subnets(config);
+    for (i, (a_path, a_net)) in zones.iter().enumerate() {
+        for (b_path, b_net) in &zones[..i] {
+            if a_net.contains(b_net.network()) || b_net.contains(a_net.network()) {
+                diags.push(Diagnostic {
+                    code: OVERLAPPING_SUBNETS,
+                    path: a_path.clone(),
+                    message: format!(
+                        "{} {} overlaps {} {}.",
+                        display_path(a_path), a_net, display_path(b_path), b_net
+                    ),
+                });
+            }
+        }
+    }
+
+    for (name, v) in &vlans {
+        check_dhcp(name, v, &mut diags);
+        check_dhcpv6(name, v, &mut diags);
+    }
+
+    diags
+}
+
+/// Parse an address field, reporting it if it isn't an address of the
+/// expected family.
+fn parse_addr<T: FromStr>(
+    value: &str,
+    family: &str,
+    path: &[&str],
+    diags: &mut Vec<Diagnostic>,
+) -> Option<T> {
+    let parsed = value.parse().ok();
+    if parsed.is_none() {
+        diags.push(Diagnostic::new(
+            INVALID_ADDRESS,
+            path,
+            format!("{} \"{}\" is not a valid {} address.", display_path(&owned(path)), value, family),
+        ));
+    }
+    parsed
+}
+
+fn owned(path: &[&str]) -> Vec<String> {
+    path.iter().map(|s| s.to_string()).collect()
+}
+
+fn check_dhcp(name: &str, v: &VlanHclConfig, diags: &mut Vec<Diagnostic>) {
+    let (Some(dhcp), Some(ipv4)) = (&v.dhcp, &v.ipv4) else { return };
+    let Ok(IpNetwork::V4(subnet)) = ipv4.subnet.parse::<IpNetwork>() else { return };
+
+    // Pool bounds
+    let mut bounds = Vec::new();
+    for (field, value) in [("pool_start", &dhcp.pool_start), ("pool_end", &dhcp.pool_end)] {
+        let path = ["vlan", name, "dhcp", field];
+        let Some(ip) = parse_addr::<Ipv4Addr>(value, "IPv4", &path, diags) else { continue };
+        if !subnet.contains(ip) {
+            diags.push(Diagnostic::new(
+                DHCP_POOL_OUTSIDE_SUBNET,
+                &path,
+                format!("vlan \"{}\".dhcp.{} {} is outside subnet {}.", name, field, value, ipv4.subnet),
+            ));
+        }
+        bounds.push(ip);
+    }
+    let pool = match bounds[..] {
+        [start, end] if start > end => {
+            diags.push(Diagnostic::new(
+                DHCP_POOL_REVERSED,
+                &["vlan", name, "dhcp", "pool_start"],
+                format!("vlan \"{}\".dhcp: pool_start {} is after pool_end {}.", name, start, end),
+            ));
+            None
+        }
+        [start, end] => Some(start..=end),
+        _ => None,
+    };
+
+    // Options handed to clients
+    for (field, value) in [("router", &dhcp.router), ("dns", &dhcp.dns)] {
+        let path = ["vlan", name, "dhcp", field];
+        let Some(ip) = parse_addr::<Ipv4Addr>(value, "IPv4", &path, diags) else { continue };
+        if !subnet.contains(ip) {
+            diags.push(Diagnostic::new(
+                DHCP_OPTION_OUTSIDE_SUBNET,
+                &path,
+                format!("vlan \"{}\".dhcp.{} {} is outside subnet {}.", name, field, value, ipv4.subnet),
+            ));
+        }
+    }
+
+    // Static reservations
+    let mut seen_ips = HashSet::new();
+    let mut seen_macs = HashSet::new();
+    for (i, host) in dhcp.host.iter().enumerate() {
+        let index = i.to_string();
+        let label = format!("vlan \"{}\".dhcp.host \"{}\"", name, host.mac);
+        if !seen_macs.insert(host.mac.to_ascii_lowercase()) {
+            diags.push(Diagnostic::new(
+                DUPLICATE_DHCP_HOST,
+                &["vlan", name, "dhcp", "host", &index, "mac"],
+                format!("{}: MAC address is reserved more than once.", label),
+            ));
+        }
+        let path = ["vlan", name, "dhcp", "host", &index, "ip"];
+        let Some(ip) = parse_addr::<Ipv4Addr>(&host.ip, "IPv4", &path, diags) else { continue };
+        if !subnet.contains(ip) {
+            diags.push(Diagnostic::new(
+                DHCP_HOST_OUTSIDE_SUBNET,
+                &path,
+                format!("{}: ip {} is outside subnet {}.", label, ip, ipv4.subnet),
+            ));
+        } else if pool.as_ref().is_some_and(|p| p.contains(&ip)) {
+            diags.push(Diagnostic::new(
+                DHCP_HOST_IN_POOL,
+                &path,
+                format!(
+                    "{}: ip {} is inside the dynamic pool {}-{}.",
+                    label, ip, dhcp.pool_start, dhcp.pool_end
+                ),
+            ));
+        }
+        if !seen_ips.insert(ip) {
+            diags.push(Diagnostic::new(
+                DUPLICATE_DHCP_HOST,
+                &path,
+                format!("{}: ip {} is reserved more than once.", label, ip),
+            ));
+        }
+    }
+}
+
+fn check_dhcpv6(name: &str, v: &VlanHclConfig, diags: &mut Vec<Diagnostic>) {
+    let (Some(dhcpv6), Some(ipv6)) = (&v.dhcpv6, &v.ipv6) else { return };
+    // A delegated VLAN gets a /64, so pools are suffixes within it
+    let subnet = if ipv6.delegated {
+        None
+    } else {
+        match ipv6.subnet.parse::<IpNetwork>() {
+            Ok(IpNetwork::V6(subnet)) => Some(subnet),
+            _ => return,
+        }
+    };
+    let mut bounds = Vec::new();
+    for (field, value) in [("pool_start", &dhcpv6.pool_start), ("pool_end", &dhcpv6.pool_end)] {
+        let path = ["vlan", name, "dhcpv6", field];
+        let Some(ip) = parse_addr::<Ipv6Addr>(value, "IPv6", &path, diags) else { continue };
+        let (fits, prefix) = match subnet {
+            Some(subnet) => (subnet.contains(ip), subnet.to_string()),
+            None => (u128::from(ip) >> 64 == 0, "the delegated /64".to_string()),
+        };
+        if !fits {
+            diags.push(Diagnostic::new(
+                DHCPV6_POOL_OUTSIDE_PREFIX,
+                &path,
+                format!("vlan \"{}\".dhcpv6.{} {} does not fit in {}.", name, field, value, prefix),
+            ));
+        }
+        bounds.push(ip);
+    }
+    if let [start, end] = bounds[..] {
+        if start > end {
+            diags.push(Diagnostic::new(
+                DHCP_POOL_REVERSED,
+                &["vlan", name, "dhcpv6", "pool_start"],
+                format!("vlan \"{}\".dhcpv6: pool_start {} is after pool_end {}.", name, start, end),
+            ));
+        }
+    }
+}
+
+/// Every statically addressed subnet with the path of its `subnet` attribute.
+fn zone_subnets(config: &HclConfig) -> Vec<(Vec<String>, IpNetwork)> {
+    let mut zones = Vec::new();
+    let mut push = |path: &[&str], subnet: &str| {
+        if let Ok(net) = subnet.parse::<IpNetwork>() {
+            zones.push((path.iter().map(|s| s.to_string()).collect(), net));
+        }
+    };
+    if let Some(subnet) = config.interfaces.mgmt_subnet() {
+        push(&["interfaces", "mgmt", "subnet"], subnet);
+    }
+    let mut vlans: Vec<_> = config.vlan.iter().collect();
+    vlans.sort_by_key(|(name, v)| (v.id, name.as_str()));
+    for (name, v) in vlans {
+        if let Some(ipv4) = &v.ipv4 {
+            push(&["vlan", name, "ipv4", "subnet"], &ipv4.subnet);
+        }
+        if let Some(ipv6) = v.ipv6.as_ref().filter(|i| !i.delegated) {
+            push(&["vlan", name, "ipv6", "subnet"], &ipv6.subnet);
+        }
+    }
+    let mut wgs: Vec<_> = config.wireguard.iter().collect();
+    wgs.sort_by_key(|(name, _)| name.as_str());
+    for (name, wg) in wgs {
+        if let Some(ipv4) = &wg.ipv4 {
+            push(&["wireguard", name, "ipv4", "subnet"], &ipv4.subnet);
+        }
+        if let Some(ipv6) = &wg.ipv6 {
+            push(&["wireguard", name, "ipv6", "subnet"], &ipv6.subnet);
+        }
+    }
+    zones
+}
+
+/// Render a path the way error messages name config elements:
+/// `vlan "trusted".ipv4.subnet`.
+fn display_path(path: &[String]) -> String {
+    let mut out = String::new();
+    let mut iter = path.iter();
+    while let Some(seg) = iter.next() {
+        if !out.is_empty() {
+            out.push('.');
+        }
+        out.push_str(seg);
+        if matches!(seg.as_str(), "vlan" | "wireguard" | "wireguard_client" | "uplink" | "port" | "allow_from" | "peer" | "host" | "group" | "service" | "schedule" | "client" | "blocklist" | "forward" | "route") {
+            if let Some(label) = iter.next() {
+                out.push_str(&format!(" \"{}\"", label));
+            }
+        }
+    }
+    out
+}
+
+/// Recover a path from an error message that starts with a config
+/// reference such as `vlan "lab".allow_from "trusted".tcp: ...`. Config
+/// keys are lowercase, so a message starting with a capitalized word has
+/// no path.
+fn path_from_message(message: &str) -> Vec<String> {
+    let mut path = Vec::new();
+    let mut rest = message;
+    loop {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end == 0 || !rest.starts_with(|c: char| c.is_ascii_lowercase()) {
+            break;
+        }
+        path.push(rest[..end].to_string());
+        rest = &rest[end..];
+        if let Some(labeled) = rest.strip_prefix(" \"") {
+            let Some(close) = labeled.find('"') else { break };
+            path.push(labeled[..close].to_string());
+            rest = &labeled[close + 1..];
+        }
+        match rest.strip_prefix('.') {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    path
+}
+
+/// Byte range of the deepest element of `path` present in the source.
+fn locate(body: &Body, path: &[String]) -> Option<Range<usize>> {
+    let (first, rest) = path.split_first()?;
+    if let Some(label) = rest.first() {
+        if let Some(block) = body
+            .get_blocks(first)
+            .find(|b| b.labels.first().is_some_and(|l| l.as_str() == label))
+        {
+            return locate(&block.body, &rest[1..]).or_else(|| block.span());
+        }
+    }
+    // Repeated unlabeled blocks such as dhcp `host` are selected by index
+    let mut unlabeled = body.get_blocks(first).filter(|b| b.labels.is_empty());
+    let index = rest.first().and_then(|i| i.parse::<usize>().ok());
+    let (block, inner) = match index {
+        Some(i) => (unlabeled.nth(i), &rest[1..]),
+        None => (unlabeled.next(), rest),
+    };
+    if let Some(block) = block {
+        return locate(&block.body, inner).or_else(|| block.span());
+    }
+    let attr = body.get_attribute(first)?;
+    rest.first()
+        .and_then(|index| index.parse::<usize>().ok())
+        .and_then(|index| attr.value.as_array()?.get(index)?.span())
+        .or_else(|| attr.span())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SourceSpan {
+    pub start: Position,
+    pub end: Position,
+}
+
+fn position(source: &str, offset: usize) -> Position {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+fn source_span(source: &str, range: Range<usize>) -> SourceSpan {
+    SourceSpan {
+        start: position(source, range.start),
+        end: position(source, range.end),
+    }
+}
+
+/// A diagnostic resolved against the config source, ready for output.
+#[derive(Debug, Serialize)]
+pub struct Located {
+    pub code: &'static str,
+    pub message: String,
+    pub path: String,
+    pub span: Option<SourceSpan>,
+}
+
+/// Parse and fully validate an HCL config source, locating every problem.
+pub fn validate_source(source: &str) -> Vec<Located> {
+    let config = match parse_hcl(source) {
+        Ok(config) => config,
+        Err(e) => {
+            // Syntax errors carry a position; schema errors don't
+            let span = hcl::edit::parser::parse_body(source).err().map(|err| {
+                let loc = err.location();
+                let start = Position { line: loc.line(), column: loc.column() };
+                SourceSpan { start, end: start }
+            });
+            return vec![Located { code: PARSE_ERROR, message: e, path: String::new(), span }];
+        }
+    };
+    let body = hcl::edit::parser::parse_body(source).ok();
+
+    let mut diags = check(&config);
+    let known: HashSet<String> = diags.iter().map(|d| d.message.clone()).collect();
+    if let Err(errors) = crate::Router::from_hcl(&config) {
+        for message in errors {
+            if !known.contains(&message) {
+                let path = path_from_message(&message);
+                diags.push(Diagnostic { code: CONFIG_ERROR, path, message });
+            }
+        }
+    }
+
+    diags
+        .into_iter()
+        .map(|d| {
+            let range = body.as_ref().and_then(|b| locate(b, &d.path));
+            // Only report the path when it names something in the file
+            let path = if range.is_some() { d.path.join(".") } else { String::new() };
+            Located {
+                code: d.code,
+                message: d.message,
+                path,
+                span: range.map(|r| source_span(source, r)),
+            }
+        })
+        .collect()
+}
+
+/// JSON report for `validate --format json`.
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub file: &'a str,
+    pub valid: bool,
+    pub diagnostics: &'a [Located],
+}
+
+/// Human-readable report, one `error[CODE]` entry per diagnostic.
+pub fn format_text(file: &str, diags: &[Located]) -> String {
+    let mut out = String::new();
+    for d in diags {
+        out.push_str(&format!("error[{}]: {}\n", d.code, d.message));
+        match &d.span {
+            Some(span) => out.push_str(&format!("  --> {}:{}:{}\n", file, span.start.line, span.start.column)),
+            None => out.push_str(&format!("  --> {}\n", file)),
+        }
+    }
+    if diags.is_empty() {
+        out.push_str(&format!("{}: OK\n", file));
+    } else {
+        out.push_str(&format!(
+            "{}: {} error{}\n",
+            file,
+            diags.len(),
+            if diags.len() == 1 { "" } else { "s" }
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &str = r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan {
+  enable_ipv4 = true
+  tcp_forward = ["443:10.99.10.50:443", "80:192.168.77.5:80"]
+}
+vlan_aware_switch = true
+"#;
+
+    fn source(vlans: &str) -> String {
+        format!("{}{}", PREFIX, vlans)
+    }
+
+    #[test]
+    fn test_semantic_checks() {
+        let src = source(r#"
+vlan "trusted" {
+  id = 10
+  ipv4 { subnet = "10.99.10.1/24" }
+  dhcp {
+    pool_start = "10.99.10.100"
+    pool_end   = "10.99.11.250"
+    router     = "10.99.10.1"
+    dns        = "10.99.10.1"
+  }
+}
+vlan "iot" {
+  id = 10
+  ipv4 { subnet = "10.99.10.129/25" }
+}
+switch {
+  url = "http://192.168.2.1"
+  port "1" {
+    pvid   = 30
+    accept = "all"
+    vlans { tagged = [10, 40] }
+  }
+}
+"#);
+        let diags = check(&parse_hcl(&src).unwrap());
+        let codes: Vec<_> = diags.iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                DUPLICATE_VLAN_ID,
+                OVERLAPPING_SUBNETS,
+                DHCP_POOL_OUTSIDE_SUBNET,
+                FORWARD_OUTSIDE_VLANS,
+                SWITCH_PORT_UNDEFINED_VLAN,
+                SWITCH_PORT_UNDEFINED_VLAN,
+            ],
+            "{:#?}",
+            diags
+        );
+        assert_eq!(diags[1].message, "vlan \"trusted\".ipv4.subnet 10.99.10.1/24 overlaps vlan \"iot\".ipv4.subnet 10.99.10.129/25.");
+        assert_eq!(diags[2].message, "vlan \"trusted\".dhcp.pool_end 10.99.11.250 is outside subnet 10.99.10.1/24.");
+        assert_eq!(diags[3].path, vec!["wan", "tcp_forward", "1"]);
+        assert_eq!(diags[3].message, "wan.tcp_forward \"80:192.168.77.5:80\": destination 192.168.77.5 is not inside any VLAN subnet or routed network.");
+        assert_eq!(diags[4].message, "switch port \"1\".pvid: VLAN 30 is not defined by any vlan block.");
+        assert_eq!(diags[5].message, "switch port \"1\".vlans.tagged: VLAN 40 is not defined by any vlan block.");
+    }
+
+    #[test]
+    fn test_forward_to_routed_network() {
+        let src = source(r#"
+vlan "lab" {
+  id = 10
+  ipv4 { subnet = "10.99.10.1/24" }
+}
+route "behind-r2" {
+  destination = "192.168.77.0/24"
+  gateway     = "10.99.10.2"
+}
+"#);
+        let outside = |src: &str| check(&parse_hcl(src).unwrap()).iter().filter(|d| d.code == FORWARD_OUTSIDE_VLANS).count();
+        assert_eq!(outside(&src), 0);
+        // Only the main table routes forwarded traffic
+        let policy_routed = src.replace("gateway     = \"10.99.10.2\"", "gateway = \"10.99.10.2\"\n  table = 200");
+        assert_eq!(outside(&policy_routed), 1);
+        // A diagnostic, not an error: the ruleset is still generated
+        assert!(crate::Router::from_hcl(&parse_hcl(&policy_routed).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_source_spans() {
+        let src = source(r#"
+vlan "trusted" {
+  id = 10
+  ipv4 {
+    subnet = "10.99.10.1/24"
+    egress = ["not-a-cidr"]
+  }
+}
+"#);
+        let diags = validate_source(&src);
+        assert_eq!(diags.len(), 2, "{:#?}", diags);
+
+        // Array element of wan.tcp_forward
+        assert_eq!(diags[0].code, FORWARD_OUTSIDE_VLANS);
+        assert_eq!(diags[0].path, "wan.tcp_forward.1");
+        assert_eq!(diags[0].span.unwrap().start, Position { line: 8, column: 41 });
+
+        // Unclassified template error, located from its message
+        assert_eq!(diags[1].code, CONFIG_ERROR);
+        assert_eq!(diags[1].path, "vlan.trusted.ipv4.egress");
+        assert_eq!(diags[1].span.unwrap().start, Position { line: 16, column: 5 });
+    }
+
+    #[test]
+    fn test_dhcp_checks() {
+        let src = source(r#"
+vlan "trusted" {
+  id = 10
+  ipv4 { subnet = "10.99.10.1/24" }
+  dhcp {
+    pool_start = "10.99.10.250"
+    pool_end   = "10.99.10.100"
+    router     = "10.99.10.1"
+    dns        = "10.99.20.1"
+    host {
+      mac = "aa:bb:cc:dd:ee:01"
+      ip  = "10.99.10.20"
+    }
+    host {
+      mac = "aa:bb:cc:dd:ee:02"
+      ip  = "10.99.10.20"
+    }
+    host {
+      mac = "AA:BB:CC:DD:EE:01"
+      ip  = "10.99.30.5"
+    }
+  }
+}
+vlan "lab" {
+  id = 20
+  ipv4 { subnet = "10.99.20.1/24" }
+  dhcp {
+    pool_start = "10.99.20.100"
+    pool_end   = "10.99.20.200"
+    router     = "10.99.20.one"
+    dns        = "10.99.20.1"
+    host {
+      mac = "aa:bb:cc:dd:ee:03"
+      ip  = "10.99.20.150"
+    }
+  }
+}
+"#);
+        let diags = check_addressing(&parse_hcl(&src).unwrap());
+        let found: Vec<_> = diags.iter().map(|d| (d.code, d.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (DHCP_POOL_REVERSED, "vlan \"trusted\".dhcp: pool_start 10.99.10.250 is after pool_end 10.99.10.100."),
+                (DHCP_OPTION_OUTSIDE_SUBNET, "vlan \"trusted\".dhcp.dns 10.99.20.1 is outside subnet 10.99.10.1/24."),
+                (DUPLICATE_DHCP_HOST, "vlan \"trusted\".dhcp.host \"aa:bb:cc:dd:ee:02\": ip 10.99.10.20 is reserved more than once."),
+                (DUPLICATE_DHCP_HOST, "vlan \"trusted\".dhcp.host \"AA:BB:CC:DD:EE:01\": MAC address is reserved more than once."),
+                (DHCP_HOST_OUTSIDE_SUBNET, "vlan \"trusted\".dhcp.host \"AA:BB:CC:DD:EE:01\": ip 10.99.30.5 is outside subnet 10.99.10.1/24."),
+                (INVALID_ADDRESS, "vlan \"lab\".dhcp.router \"10.99.20.one\" is not a valid IPv4 address."),
+                (DHCP_HOST_IN_POOL, "vlan \"lab\".dhcp.host \"aa:bb:cc:dd:ee:03\": ip 10.99.20.150 is inside the dynamic pool 10.99.20.100-10.99.20.200."),
+            ]
+        );
+
+        // Repeated host blocks are located by index
+        let located = validate_source(&src);
+        let outside = located.iter().find(|d| d.code == DHCP_HOST_OUTSIDE_SUBNET).unwrap();
+        assert_eq!(outside.path, "vlan.trusted.dhcp.host.2.ip");
+        assert_eq!(outside.span.unwrap().start, Position { line: 30, column: 7 });
+    }
+
+    #[test]
+    fn test_dhcpv6_checks() {
+        let src = source(r#"
+vlan "trusted" {
+  id = 10
+  ipv6 { subnet = "fd00:10::1/64" }
+  dhcpv6 {
+    pool_start = "fd00:10::1ff"
+    pool_end   = "fd00:11::100"
+  }
+}
+vlan "lab" {
+  id = 20
+  ipv6 { delegated = true }
+  dhcpv6 {
+    pool_start = "::200"
+    pool_end   = "::1:0:0:0:100"
+  }
+}
+"#);
+        let diags = check_addressing(&parse_hcl(&src).unwrap());
+        let found: Vec<_> = diags.iter().map(|d| (d.code, d.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (DHCPV6_POOL_OUTSIDE_PREFIX, "vlan \"trusted\".dhcpv6.pool_end fd00:11::100 does not fit in fd00:10::1/64."),
+                (DHCPV6_POOL_OUTSIDE_PREFIX, "vlan \"lab\".dhcpv6.pool_end ::1:0:0:0:100 does not fit in the delegated /64."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_source_parse_error() {
+        let diags = validate_source("interfaces {\n  trunk = \n}\n");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].code, PARSE_ERROR);
+        assert_eq!(diags[0].span.unwrap().start.line, 2);
+    }
+
+    #[test]
+    fn test_path_from_message() {
+        assert_eq!(
+            path_from_message("vlan \"lab\".allow_from \"trusted\".tcp: bad rule"),
+            vec!["vlan", "lab", "allow_from", "trusted", "tcp"]
+        );
+        assert_eq!(path_from_message("qos.upload_mbps must be > 0."), vec!["qos", "upload_mbps"]);
+        assert!(path_from_message("At least one vlan").is_empty());
+    }
+
+    #[test]
+    fn test_format_text() {
+        let diags = vec![Located {
+            code: DUPLICATE_VLAN_ID,
+            message: "vlan \"iot\": duplicate VLAN ID 10.".to_string(),
+            path: "vlan.iot.id".to_string(),
+            span: Some(SourceSpan {
+                start: Position { line: 3, column: 3 },
+                end: Position { line: 3, column: 10 },
+            }),
+        }];
+        assert_eq!(
+            format_text("router.hcl", &diags),
+            "error[NF101]: vlan \"iot\": duplicate VLAN ID 10.\n  --> router.hcl:3:3\nrouter.hcl: 1 error\n"
+        );
+        assert_eq!(format_text("router.hcl", &[]), "router.hcl: OK\n");
+    }
+}
@@ -1,23 +1,63 @@
 use std::collections::HashSet;
+use std::process::Command;
+
+use ipnetwork::IpNetwork;
+use nifty_nft::listing::{self, ListedElement};
+use nifty_nft::tables;
 
 use crate::parsers::forward_route::ForwardRouteList;
+use crate::parsers::icmp_type::IcmpType;
+use crate::parsers::icmpv6_type::Icmpv6Type;
 use crate::parsers::inbound_rule::InboundRuleList;
 use crate::parsers::inter_vlan_rule::InterVlanRuleList;
 use crate::parsers::qos_class::QosClass;
+use crate::logging::LogMode;
+use crate::routing::Policy;
+use crate::parsers::schedule::TimeWindow;
+
+/// Egress rules for one device, matched by its MAC address.
+pub struct ClientRule {
+    /// Reservation hostname, or the MAC when the block is keyed by MAC
+    pub name: String,
+    pub mac: String,
+    pub egress_ipv4: Vec<IpNetwork>,
+    pub egress_ipv6: Vec<IpNetwork>,
+}
+
+/// A time window during which a zone's WAN access is cut off.
+pub struct EgressBlock {
+    pub schedule: String,
+    pub window: TimeWindow,
+}
 
 /// A single LAN segment — either VLAN 1 (bare trunk) or a tagged sub-interface.
 pub struct Vlan {
+    /// VLAN ID, or 0 for a WireGuard zone.
     pub id: u16,
     pub name: String,
+    /// Zone name used in rule comments, e.g. "VLAN 10" or "WireGuard wg0".
+    pub label: String,
+    /// Suffix of the zone's `input_`/`forward_` chains, e.g. "vlan_10".
+    pub chain_suffix: String,
     pub interface_name: String,
     pub subnet_ipv4: String,
+    /// Literal IPv6 subnet, or a set reference (`@pd_vlan_<id>`) when the
+    /// subnet comes from a delegated prefix.
     pub subnet_ipv6: String,
-    pub egress_allowed_ipv4: String,
-    pub egress_allowed_ipv6: String,
-    pub icmp_accept: String,
-    pub icmpv6_accept: String,
-    pub tcp_accept: String,
-    pub udp_accept: String,
+    pub ipv6_delegated: bool,
+    pub egress_allowed_ipv4: Vec<IpNetwork>,
+    pub egress_allowed_ipv6: Vec<IpNetwork>,
+    /// Scheduled WAN cutoffs, including already-established connections.
+    pub egress_blocked: Vec<EgressBlock>,
+    pub clients: Vec<ClientRule>,
+    /// Domains whose resolved addresses are allowed as egress destinations.
+    pub egress_domains: Vec<String>,
+    /// Logging of this zone's dropped traffic, when it overrides the default
+    pub log: Option<LogMode>,
+    pub icmp_accept: Vec<IcmpType>,
+    pub icmpv6_accept: Vec<Icmpv6Type>,
+    pub tcp_accept: Vec<u16>,
+    pub udp_accept: Vec<u16>,
     pub tcp_forward: ForwardRouteList,
     pub udp_forward: ForwardRouteList,
     pub tcp_allow_inbound: InboundRuleList,
@@ -29,6 +69,13 @@ pub struct Vlan {
     pub bandwidth_download_kbit: Option<u32>,
     pub iperf_enabled: bool,
     pub mdns_reflector: bool,
+    /// Ports the zone's clients may map with UPnP/NAT-PMP/PCP, when enabled
+    pub upnp_ports: Option<(u16, u16)>,
+    /// Routing table (and connection mark) for the zone's traffic
+    pub routing: Option<Policy>,
+    /// WireGuard client tunnel carrying the zone's internet traffic
+    /// instead of the WAN
+    pub egress_via: Option<String>,
     pub dhcp_enabled: bool,
     pub dhcp_pool_start: String,
     pub dhcp_pool_end: String,
@@ -39,6 +86,92 @@ pub struct Vlan {
     pub dhcpv6_pool_end: String,
 }
 
+impl Vlan {
+    /// Log prefix for drops of this zone's traffic in `chain`.
+    pub fn log_prefix(&self, chain: &str) -> String {
+        format!("Dropped {} {}", self.label, chain)
+    }
+
+    /// Name of the set dnsmasq fills with this zone's resolved egress
+    /// domain addresses for `family` ("v4" or "v6").
+    pub fn domain_set(&self, family: &str) -> String {
+        domain_set_name(&self.chain_suffix, family)
+    }
+}
+
+/// Seconds a resolved egress domain address stays allowed without new
+/// connections to it.
+pub const DOMAIN_SET_TIMEOUT: u32 = 3600;
+
+pub fn domain_set_name(chain_suffix: &str, family: &str) -> String {
+    format!("domains_{}_{}", chain_suffix, family)
+}
+
+/// An `add element` command putting a domain set's elements back with the
+/// time they had left, or nothing if the set was empty.
+fn carry_elements(set: &str, elements: &[ListedElement]) -> String {
+    let elements: Vec<String> = elements
+        .iter()
+        .filter(|e| e.expires != Some(0))
+        .map(|e| match e.expires {
+            Some(expires) => format!("{} timeout {}s", e.value, expires),
+            None => e.value.clone(),
+        })
+        .collect();
+    if elements.is_empty() {
+        return String::new();
+    }
+    format!("add element inet {} {} {{ {} }}\n", tables::FILTER, set, elements.join(", "))
+}
+
+/// Commands that copy the loaded domain sets' elements into a reloaded
+/// ruleset. Replacing the filter table empties the sets, and clients keep
+/// using their cached DNS answers, so dnsmasq would not refill them until
+/// the names are looked up again. Sets that are not loaded are skipped.
+pub fn carry_domain_sets(sets: &[String]) -> String {
+    let mut script = String::new();
+    for set in sets {
+        let output = Command::new("nft").args(["-j", "list", "set", "inet", tables::FILTER, set]).output();
+        let Ok(output) = output else { continue };
+        if !output.status.success() {
+            continue;
+        }
+        let Ok(json) = serde_json::from_slice(&output.stdout) else { continue };
+        script.push_str(&carry_elements(set, &listing::set_elements(&json)));
+    }
+    script
+}
+
+/// Parse an egress domain. A leading "*." is accepted and dropped, since a
+/// domain always covers its subdomains.
+pub fn parse_domain(input: &str) -> Result<String, String> {
+    let domain = input.trim().trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+    let valid = !domain.is_empty()
+        && domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if valid {
+        Ok(domain)
+    } else {
+        Err(format!("Invalid domain: '{}'", input))
+    }
+}
+
+/// Parse a colon-separated MAC address, returning it in lowercase.
+pub fn parse_mac(input: &str) -> Result<String, String> {
+    let parts: Vec<&str> = input.split(':').collect();
+    if parts.len() == 6 && parts.iter().all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit())) {
+        Ok(input.to_ascii_lowercase())
+    } else {
+        Err(format!("Invalid MAC address: '{}'", input))
+    }
+}
+
 /// Parse comma-separated VLAN IDs, validating uniqueness and range.
 pub fn parse_vlan_ids(input: &str, errors: &mut Vec<String>) -> Vec<u16> {
     if input.trim().is_empty() {
@@ -120,4 +253,36 @@ mod tests {
         assert_eq!(errors.len(), 1);
         assert!(errors[0].contains("not a number"));
     }
+
+    #[test]
+    fn test_parse_mac() {
+        assert_eq!(parse_mac("AA:bb:cc:dd:ee:01").unwrap(), "aa:bb:cc:dd:ee:01");
+        assert!(parse_mac("aa:bb:cc:dd:ee").is_err());
+        assert!(parse_mac("aa-bb-cc-dd-ee-01").is_err());
+        assert!(parse_mac("thermostat").is_err());
+    }
+
+    #[test]
+    fn test_parse_domain() {
+        assert_eq!(parse_domain("*.Vendor-Cloud.com").unwrap(), "vendor-cloud.com");
+        assert_eq!(parse_domain("api.example.org.").unwrap(), "api.example.org");
+        assert!(parse_domain("").is_err());
+        assert!(parse_domain("bad..domain").is_err());
+        assert!(parse_domain("-bad.com").is_err());
+        assert!(parse_domain("evil.com/#inet#filter#x").is_err());
+    }
+
+    #[test]
+    fn test_carry_elements() {
+        let elements = [
+            ListedElement { value: "192.0.2.7".to_string(), expires: Some(3412) },
+            ListedElement { value: "192.0.2.8".to_string(), expires: Some(0) },
+            ListedElement { value: "198.51.100.1".to_string(), expires: None },
+        ];
+        assert_eq!(
+            carry_elements("domains_vlan_20_v4", &elements),
+            "add element inet nifty_filter domains_vlan_20_v4 { 192.0.2.7 timeout 3412s, 198.51.100.1 }\n"
+        );
+        assert_eq!(carry_elements("domains_vlan_20_v4", &elements[1..2]), "");
+    }
 }
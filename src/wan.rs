@@ -0,0 +1,689 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use ipnetwork::IpNetwork;
+use nifty_nft::Value;
+
+use crate::hcl_config::{HclConfig, PppoeConfig, WanConfig, WanUplinkConfig};
+use crate::parsers::Interface;
+use crate::parsers::{WanMode, WanPolicy};
+
+/// Routing tables (and fwmarks) for uplinks without an explicit `table`
+/// are numbered from here, in declaration order.
+pub const TABLE_BASE: u32 = 100;
+/// `ip rule` priority of the main-table lookup that ignores default routes,
+/// so LAN and directly-connected routes win over the per-uplink tables.
+pub const MAIN_RULE_PRIORITY: u32 = 500;
+/// `ip rule` priority of the first per-uplink fwmark rule.
+const MARK_RULE_PRIORITY: u32 = 1000;
+/// `ip rule` priority of the first per-uplink fallback rule. These are
+/// ordered by uplink priority, so an uplink without a default route is
+/// skipped and the next one is used (failover).
+const FALLBACK_RULE_PRIORITY: u32 = 2000;
+/// Consecutive failed health checks before an uplink is taken out of service.
+const FAIL_THRESHOLD: u32 = 3;
+/// PPP interface name when `pppoe.ifname` is not set.
+const DEFAULT_PPP_IFNAME: &str = "ppp0";
+/// PPPoE adds 8 bytes of header to each Ethernet frame.
+const DEFAULT_PPPOE_MTU: u16 = 1492;
+
+/// PPPoE session settings for an uplink.
+#[derive(Debug, Clone)]
+pub struct Pppoe {
+    pub username: String,
+    pub password: String,
+    pub service_name: Option<String>,
+    pub mtu: u16,
+}
+
+/// A single WAN uplink, resolved from the HCL config.
+#[derive(Debug, Clone)]
+pub struct WanUplink {
+    pub name: String,
+    /// Interface carrying the uplink's IP traffic (the PPP interface for PPPoE).
+    pub interface_name: String,
+    /// Physical interface the uplink runs on. Same as `interface_name`
+    /// except for PPPoE.
+    pub device: String,
+    pub mode: WanMode,
+    /// Static addresses (static mode).
+    pub addresses: Vec<IpNetwork>,
+    pub gateway: Option<Ipv4Addr>,
+    pub gateway6: Option<Ipv6Addr>,
+    pub dns: Vec<IpAddr>,
+    pub pppoe: Option<Pppoe>,
+    pub priority: u32,
+    pub weight: u32,
+    /// Routing table ID, also used as the connection mark.
+    pub table: u32,
+    pub mark_rule_priority: u32,
+    pub fallback_rule_priority: u32,
+    pub health_check: Vec<IpAddr>,
+}
+
+/// All WAN uplinks, sorted by priority, and the policy used to spread
+/// traffic across them.
+#[derive(Debug)]
+pub struct WanUplinks {
+    pub policy: WanPolicy,
+    pub uplinks: Vec<WanUplink>,
+}
+
+/// Addressing settings, which appear both on `wan {}` (single uplink)
+/// and on each `wan "name" {}` uplink block.
+struct AddressingFields<'a> {
+    mode: Option<&'a str>,
+    address: &'a [String],
+    gateway: Option<&'a str>,
+    gateway6: Option<&'a str>,
+    dns: &'a [String],
+    pppoe: Option<&'a PppoeConfig>,
+}
+
+impl<'a> From<&'a WanConfig> for AddressingFields<'a> {
+    fn from(wan: &'a WanConfig) -> Self {
+        AddressingFields {
+            mode: wan.mode.as_deref(),
+            address: &wan.address,
+            gateway: wan.gateway.as_deref(),
+            gateway6: wan.gateway6.as_deref(),
+            dns: &wan.dns,
+            pppoe: wan.pppoe.as_ref(),
+        }
+    }
+}
+
+impl<'a> From<&'a WanUplinkConfig> for AddressingFields<'a> {
+    fn from(up: &'a WanUplinkConfig) -> Self {
+        AddressingFields {
+            mode: up.mode.as_deref(),
+            address: &up.address,
+            gateway: up.gateway.as_deref(),
+            gateway6: up.gateway6.as_deref(),
+            dns: &up.dns,
+            pppoe: up.pppoe.as_ref(),
+        }
+    }
+}
+
+impl AddressingFields<'_> {
+    fn is_set(&self) -> bool {
+        self.mode.is_some()
+            || !self.address.is_empty()
+            || self.gateway.is_some()
+            || self.gateway6.is_some()
+            || !self.dns.is_empty()
+            || self.pppoe.is_some()
+    }
+}
+
+impl WanUplink {
+    /// A DHCP uplink on `device`; the caller fills in routing details.
+    fn new(name: &str, device: &str) -> Self {
+        WanUplink {
+            name: name.to_string(),
+            interface_name: device.to_string(),
+            device: device.to_string(),
+            mode: WanMode::Dhcp,
+            addresses: Vec::new(),
+            gateway: None,
+            gateway6: None,
+            dns: Vec::new(),
+            pppoe: None,
+            priority: 1,
+            weight: 1,
+            table: 0,
+            mark_rule_priority: 0,
+            fallback_rule_priority: 0,
+            health_check: Vec::new(),
+        }
+    }
+
+    /// Validate and apply the addressing mode. `ctx` prefixes error messages.
+    fn resolve_addressing(&mut self, fields: &AddressingFields, ctx: &str, errors: &mut Vec<String>) {
+        self.mode = match fields.mode {
+            Some(m) => WanMode::new(m).unwrap_or_else(|e| {
+                errors.push(format!("{}.mode: {}", ctx, e));
+                WanMode::Dhcp
+            }),
+            None => WanMode::Dhcp,
+        };
+
+        if self.mode == WanMode::Static {
+            if fields.address.is_empty() {
+                errors.push(format!("{}: static mode requires at least one address.", ctx));
+            }
+            for addr in fields.address {
+                match IpNetwork::from_str(addr) {
+                    Ok(net) if addr.contains('/') => self.addresses.push(net),
+                    _ => errors.push(format!(
+                        "{}.address: invalid address '{}' (expected address/prefix)",
+                        ctx, addr
+                    )),
+                }
+            }
+            if let Some(gw) = fields.gateway {
+                match gw.parse::<Ipv4Addr>() {
+                    Ok(ip) => self.gateway = Some(ip),
+                    Err(_) => errors.push(format!("{}.gateway: invalid IPv4 address '{}'", ctx, gw)),
+                }
+            }
+            if let Some(gw) = fields.gateway6 {
+                match gw.parse::<Ipv6Addr>() {
+                    Ok(ip) => self.gateway6 = Some(ip),
+                    Err(_) => errors.push(format!("{}.gateway6: invalid IPv6 address '{}'", ctx, gw)),
+                }
+            }
+        } else if !fields.address.is_empty() || fields.gateway.is_some() || fields.gateway6.is_some() {
+            errors.push(format!(
+                "{}: address, gateway and gateway6 are only valid in static mode.",
+                ctx
+            ));
+        }
+
+        for server in fields.dns {
+            match server.parse::<IpAddr>() {
+                Ok(ip) => self.dns.push(ip),
+                Err(_) => errors.push(format!("{}.dns: invalid address '{}'", ctx, server)),
+            }
+        }
+
+        match (self.mode, fields.pppoe) {
+            (WanMode::Pppoe, Some(p)) => {
+                let ifname = p.ifname.as_deref().unwrap_or(DEFAULT_PPP_IFNAME);
+                if let Err(e) = Interface::new(ifname) {
+                    errors.push(format!("{}.pppoe.ifname: {}", ctx, e));
+                }
+                for (field, value) in [("username", &p.username), ("password", &p.password)] {
+                    if value.is_empty() || value.contains(['\n', '\r']) {
+                        errors.push(format!("{}.pppoe.{} must be a non-empty single line.", ctx, field));
+                    }
+                }
+                let mtu = p.mtu.unwrap_or(DEFAULT_PPPOE_MTU);
+                if !(576..=1492).contains(&mtu) {
+                    errors.push(format!("{}.pppoe.mtu: {} is out of range (576-1492).", ctx, mtu));
+                }
+                self.interface_name = ifname.to_string();
+                self.pppoe = Some(Pppoe {
+                    username: p.username.clone(),
+                    password: p.password.clone(),
+                    service_name: p.service_name.clone(),
+                    mtu,
+                });
+            }
+            (WanMode::Pppoe, None) => {
+                errors.push(format!("{}: pppoe mode requires a pppoe block.", ctx));
+            }
+            (_, Some(_)) => {
+                errors.push(format!("{}: the pppoe block is only valid in pppoe mode.", ctx));
+            }
+            (_, None) => {}
+        }
+    }
+}
+
+impl WanUplinks {
+    /// Resolve uplinks from `wan "name"` blocks. Without any, the single
+    /// `interfaces.wan` interface is the only uplink.
+    pub fn from_hcl(config: &HclConfig) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let policy = match &config.wan.policy {
+            Some(m) => WanPolicy::new(m).unwrap_or_else(|e| {
+                errors.push(format!("wan.policy: {}", e));
+                WanPolicy::Failover
+            }),
+            None => WanPolicy::Failover,
+        };
+
+        let mut uplinks = Vec::new();
+        if config.wan.uplink.is_empty() {
+            let mut uplink = WanUplink::new("wan", config.interfaces.wan_name());
+            uplink.table = TABLE_BASE + 1;
+            uplink.mark_rule_priority = MARK_RULE_PRIORITY;
+            uplink.fallback_rule_priority = FALLBACK_RULE_PRIORITY;
+            uplink.resolve_addressing(&AddressingFields::from(&config.wan), "wan", &mut errors);
+            uplinks.push(uplink);
+        } else if AddressingFields::from(&config.wan).is_set() {
+            errors.push(
+                "wan: mode, address, gateway, gateway6, dns and pppoe must be set on each uplink when uplink blocks are used."
+                    .to_string(),
+            );
+        }
+
+        let mut seen_ifaces = HashSet::new();
+        let mut seen_tables = HashSet::new();
+        let mut seen_ppp = HashSet::new();
+        for (idx, (name, up)) in config.wan.uplink.iter().enumerate() {
+            if let Err(e) = Interface::new(&up.interface) {
+                errors.push(format!("wan \"{}\".interface: {}", name, e));
+            } else if !config.interfaces.has_name(&up.interface) {
+                errors.push(format!(
+                    "wan \"{}\".interface: \"{}\" is not declared in the interfaces block.",
+                    name, up.interface
+                ));
+            }
+            if !seen_ifaces.insert(up.interface.as_str()) {
+                errors.push(format!(
+                    "wan \"{}\": interface \"{}\" is already used by another uplink.",
+                    name, up.interface
+                ));
+            }
+            if policy == WanPolicy::Balance && up.weight == 0 {
+                errors.push(format!("wan \"{}\".weight must be greater than 0.", name));
+            }
+
+            let table = up.table.unwrap_or(TABLE_BASE + 1 + idx as u32);
+            if table == 0 || (253..=255).contains(&table) {
+                errors.push(format!(
+                    "wan \"{}\".table: {} is reserved (0, 253-255).",
+                    name, table
+                ));
+            }
+            if !seen_tables.insert(table) {
+                errors.push(format!("wan \"{}\": duplicate routing table {}.", name, table));
+            }
+
+            let health_check = up.health_check.iter()
+                .filter_map(|t| t.parse::<IpAddr>().map_err(|_| {
+                    errors.push(format!("wan \"{}\".health_check: invalid address '{}'", name, t));
+                }).ok())
+                .collect();
+
+            let mut uplink = WanUplink::new(name, &up.interface);
+            uplink.priority = up.priority;
+            uplink.weight = up.weight;
+            uplink.table = table;
+            uplink.health_check = health_check;
+            uplink.resolve_addressing(
+                &AddressingFields::from(up),
+                &format!("wan \"{}\"", name),
+                &mut errors,
+            );
+            if uplink.pppoe.is_some() && !seen_ppp.insert(uplink.interface_name.clone()) {
+                errors.push(format!(
+                    "wan \"{}\".pppoe.ifname: \"{}\" is already used by another uplink.",
+                    name, uplink.interface_name
+                ));
+            }
+            uplinks.push(uplink);
+        }
+
+        // Stable sort keeps declaration order for equal priorities
+        uplinks.sort_by_key(|u| u.priority);
+        for (i, u) in uplinks.iter_mut().enumerate() {
+            u.mark_rule_priority = MARK_RULE_PRIORITY + i as u32;
+            u.fallback_rule_priority = FALLBACK_RULE_PRIORITY + i as u32;
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(WanUplinks { policy, uplinks })
+    }
+
+    /// True when traffic has to be policy-routed across several uplinks.
+    pub fn is_multi(&self) -> bool {
+        self.uplinks.len() > 1
+    }
+
+    /// True when new connections are spread across uplinks by weight.
+    pub fn is_balance(&self) -> bool {
+        self.is_multi() && self.policy == WanPolicy::Balance
+    }
+
+    /// The preferred uplink (lowest priority value).
+    pub fn primary(&self) -> &WanUplink {
+        &self.uplinks[0]
+    }
+
+    /// nftables interface match for all uplinks: `"wan"` or `{ "wan", "wan2" }`.
+    pub fn iface_match(&self) -> Value {
+        iface_value(self.uplinks.iter())
+    }
+
+    /// Sum of all uplink weights (the `numgen` modulus).
+    pub fn weight_total(&self) -> u32 {
+        self.uplinks.iter().map(|u| u.weight).sum()
+    }
+
+    /// True when any uplink runs over PPPoE (and needs MSS clamping).
+    pub fn has_pppoe(&self) -> bool {
+        self.uplinks.iter().any(|u| u.pppoe.is_some())
+    }
+
+    /// nftables interface match for PPPoE uplinks, like `iface_match`.
+    pub fn pppoe_iface_match(&self) -> Value {
+        iface_value(self.uplinks.iter().filter(|u| u.pppoe.is_some()))
+    }
+
+    /// Weighted `numgen` map for balance mode, e.g.
+    /// `numgen random mod 4 map { 0-2 : 101, 3 : 102 }`.
+    pub fn balance_map(&self) -> Value {
+        let mut start = 0;
+        let mut map = Vec::new();
+        for u in &self.uplinks {
+            let end = start + u.weight - 1;
+            let key = if start == end { start.to_string() } else { format!("{}-{}", start, end) };
+            map.push((Value::literal(key), Value::literal(u.table)));
+            start = end + 1;
+        }
+        Value::NumgenMap { modulus: self.weight_total(), map }
+    }
+}
+
+/// A single quoted interface name, or a set of them.
+fn iface_value<'a>(uplinks: impl Iterator<Item = &'a WanUplink>) -> Value {
+    let mut names: Vec<Value> = uplinks.map(|u| Value::str(&u.interface_name)).collect();
+    if names.len() == 1 {
+        names.remove(0)
+    } else {
+        Value::Set(names)
+    }
+}
+
+/// Ping each health-check target through the uplink; healthy if any answers.
+fn probe(uplink: &WanUplink) -> bool {
+    uplink.health_check.iter().any(|target| {
+        Command::new("ping")
+            .args(["-c", "1", "-W", "2", "-I", &uplink.interface_name, &target.to_string()])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    })
+}
+
+/// Run `ip <family> rule add|del <selector> table <table> priority <priority>`.
+fn ip_rule(family: &str, add: bool, selector: &[&str], table: u32, priority: u32) {
+    let (table, priority) = (table.to_string(), priority.to_string());
+    let _ = Command::new("ip")
+        .args([family, "rule", if add { "add" } else { "del" }])
+        .args(selector)
+        .args(["table", &table, "priority", &priority])
+        .output();
+}
+
+/// Add or remove an uplink's policy routing rules. While they are out, a
+/// rule for sockets bound to the uplink's interface keeps its table
+/// reachable, so the `ping -I` probe can see the uplink recover.
+fn set_rules(uplink: &WanUplink, present: bool, ipv6: bool) {
+    let table = uplink.table.to_string();
+    let families: &[&str] = if ipv6 { &["-4", "-6"] } else { &["-4"] };
+    for &family in families {
+        ip_rule(family, present, &["fwmark", &table], uplink.table, uplink.mark_rule_priority);
+        ip_rule(family, present, &[], uplink.table, uplink.fallback_rule_priority);
+        ip_rule(family, !present, &["oif", &uplink.interface_name], uplink.table, uplink.mark_rule_priority);
+    }
+}
+
+/// Watch uplink health forever, taking uplinks out of the routing policy
+/// after repeated failures and restoring them once they answer again.
+/// Uplinks without health-check targets are always considered up.
+pub fn run_monitor(wan: &WanUplinks, interval: Duration, ipv6: bool) -> ! {
+    let mut failures = vec![0u32; wan.uplinks.len()];
+    let mut up = vec![true; wan.uplinks.len()];
+    loop {
+        for (i, uplink) in wan.uplinks.iter().enumerate() {
+            if uplink.health_check.is_empty() {
+                continue;
+            }
+            if probe(uplink) {
+                failures[i] = 0;
+                if !up[i] {
+                    eprintln!("wan-monitor: uplink \"{}\" ({}) is back up", uplink.name, uplink.interface_name);
+                    set_rules(uplink, true, ipv6);
+                    up[i] = true;
+                }
+            } else {
+                failures[i] += 1;
+                if up[i] && failures[i] >= FAIL_THRESHOLD {
+                    eprintln!("wan-monitor: uplink \"{}\" ({}) is down", uplink.name, uplink.interface_name);
+                    set_rules(uplink, false, ipv6);
+                    up[i] = false;
+                }
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    fn parse_wan(body: &str) -> Result<WanUplinks, Vec<String>> {
+        let input = format!(
+            r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+  wan2  {{ name = "wan2" }}
+}}
+{}
+"#,
+            body
+        );
+        WanUplinks::from_hcl(&parse_hcl(&input).unwrap())
+    }
+
+    #[test]
+    fn test_single_wan_implicit() {
+        let wan = parse_wan("wan {}").unwrap();
+        assert!(!wan.is_multi());
+        assert_eq!(wan.policy, WanPolicy::Failover);
+        assert_eq!(wan.primary().interface_name, "wan");
+        assert_eq!(wan.iface_match().to_string(), "\"wan\"");
+    }
+
+    #[test]
+    fn test_uplinks_sorted_by_priority() {
+        let wan = parse_wan(r#"
+wan {}
+
+wan "backup" {
+  interface = "wan2"
+  priority  = 2
+}
+
+wan "fiber" {
+  interface = "wan"
+  priority  = 1
+}
+"#).unwrap();
+        assert!(wan.is_multi());
+        assert!(!wan.is_balance());
+        assert_eq!(wan.primary().name, "fiber");
+        // Tables follow declaration order, rule priorities follow uplink priority
+        assert_eq!(wan.uplinks[0].table, 102);
+        assert_eq!(wan.uplinks[0].fallback_rule_priority, 2000);
+        assert_eq!(wan.uplinks[1].table, 101);
+        assert_eq!(wan.uplinks[1].fallback_rule_priority, 2001);
+        assert_eq!(wan.iface_match().to_string(), r#"{ "wan", "wan2" }"#);
+    }
+
+    #[test]
+    fn test_balance_map() {
+        let wan = parse_wan(r#"
+wan {
+  policy = "balance"
+}
+
+wan "isp1" {
+  interface = "wan"
+  weight    = 3
+}
+
+wan "isp2" {
+  interface = "wan2"
+}
+"#).unwrap();
+        assert!(wan.is_balance());
+        assert_eq!(wan.weight_total(), 4);
+        assert_eq!(wan.balance_map().to_string(), "numgen random mod 4 map { 0-2 : 101, 3 : 102 }");
+    }
+
+    #[test]
+    fn test_uplink_unknown_interface() {
+        let err = parse_wan(r#"
+wan {}
+wan "isp1" { interface = "wan" }
+wan "isp2" { interface = "wan3" }
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("\"wan3\" is not declared")));
+    }
+
+    #[test]
+    fn test_uplink_duplicate_interface_and_table() {
+        let err = parse_wan(r#"
+wan {}
+
+wan "isp1" {
+  interface = "wan"
+  table     = 200
+}
+
+wan "isp2" {
+  interface = "wan"
+  table     = 200
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("already used by another uplink")));
+        assert!(err.iter().any(|e| e.contains("duplicate routing table 200")));
+    }
+
+    #[test]
+    fn test_uplink_invalid_policy_and_health_check() {
+        let err = parse_wan(r#"
+wan {
+  policy = "roundrobin"
+}
+
+wan "isp1" {
+  interface    = "wan"
+  health_check = ["not-an-ip"]
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("wan.policy")));
+        assert!(err.iter().any(|e| e.contains("invalid address 'not-an-ip'")));
+    }
+
+    #[test]
+    fn test_uplink_reserved_table() {
+        let err = parse_wan(r#"
+wan {}
+
+wan "isp1" {
+  interface = "wan"
+  table     = 254
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("reserved")));
+    }
+
+    #[test]
+    fn test_static_addressing() {
+        let wan = parse_wan(r#"
+wan {
+  mode     = "static"
+  address  = ["203.0.113.10/29", "2001:db8::10/64"]
+  gateway  = "203.0.113.9"
+  gateway6 = "2001:db8::1"
+  dns      = ["9.9.9.9"]
+}
+"#).unwrap();
+        let up = wan.primary();
+        assert_eq!(up.mode, WanMode::Static);
+        assert_eq!(up.addresses.len(), 2);
+        assert_eq!(up.gateway, Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(up.gateway6, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(up.dns, vec!["9.9.9.9".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_static_addressing_errors() {
+        let err = parse_wan(r#"
+wan {
+  mode    = "static"
+  address = ["203.0.113.10"]
+  gateway = "2001:db8::1"
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("invalid address '203.0.113.10'")));
+        assert!(err.iter().any(|e| e.contains("wan.gateway: invalid IPv4 address")));
+
+        let err = parse_wan(r#"
+wan {
+  gateway = "203.0.113.9"
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("only valid in static mode")));
+    }
+
+    #[test]
+    fn test_pppoe_uplink() {
+        let wan = parse_wan(r#"
+wan {
+  mode = "pppoe"
+  pppoe {
+    username = "user@isp"
+    password = "secret"
+  }
+}
+"#).unwrap();
+        let up = wan.primary();
+        assert_eq!(up.mode, WanMode::Pppoe);
+        assert_eq!(up.device, "wan");
+        assert_eq!(up.interface_name, "ppp0");
+        assert_eq!(up.pppoe.as_ref().unwrap().mtu, 1492);
+        assert!(wan.has_pppoe());
+        assert_eq!(wan.iface_match().to_string(), "\"ppp0\"");
+        assert_eq!(wan.pppoe_iface_match().to_string(), "\"ppp0\"");
+    }
+
+    #[test]
+    fn test_pppoe_errors() {
+        let err = parse_wan(r#"wan { mode = "pppoe" }"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("pppoe mode requires a pppoe block")));
+
+        let err = parse_wan(r#"
+wan {}
+
+wan "dsl1" {
+  interface = "wan"
+  mode      = "pppoe"
+  pppoe {
+    username = "a"
+    password = "b"
+  }
+}
+
+wan "dsl2" {
+  interface = "wan2"
+  mode      = "pppoe"
+  pppoe {
+    username = "c"
+    password = "d"
+    mtu      = 1500
+  }
+}
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("\"ppp0\" is already used by another uplink")));
+        assert!(err.iter().any(|e| e.contains("pppoe.mtu: 1500 is out of range")));
+    }
+
+    #[test]
+    fn test_addressing_on_wan_with_uplinks() {
+        let err = parse_wan(r#"
+wan {
+  mode = "static"
+}
+wan "isp1" { interface = "wan" }
+"#).unwrap_err();
+        assert!(err.iter().any(|e| e.contains("must be set on each uplink")));
+    }
+}
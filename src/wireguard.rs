@@ -0,0 +1,584 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use ipnetwork::IpNetwork;
+
+use crate::hcl_config::{HclConfig, WireguardClientHclConfig, WireguardHclConfig, WireguardPeerConfig};
+use crate::parsers::interface::Interface;
+
+/// Keepalive written into exported peer configs so NAT mappings on the
+/// client side stay open.
+const PERSISTENT_KEEPALIVE: u16 = 25;
+
+/// Routing table of the first `wireguard_client` tunnel without a `table`.
+const CLIENT_TABLE_BASE: u32 = 200;
+
+/// Check that a WireGuard key is 32 bytes of base64 (44 characters
+/// ending in `=`).
+fn is_valid_key(key: &str) -> bool {
+    key.len() == 44
+        && key.ends_with('=')
+        && key[..43]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+/// Validate the WireGuard-specific settings of a zone.
+pub fn validate(name: &str, wg: &WireguardHclConfig) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+    if wg.listen_port == 0 {
+        errors.push(format!("wireguard \"{}\".listen_port must be greater than 0.", name));
+    }
+    if !wg.private_key_file.starts_with('/') {
+        errors.push(format!(
+            "wireguard \"{}\".private_key_file must be an absolute path.",
+            name
+        ));
+    }
+    if let Some(mtu) = wg.mtu {
+        if !(1280..=1500).contains(&mtu) {
+            errors.push(format!(
+                "wireguard \"{}\".mtu {} is out of range (1280-1500).",
+                name, mtu
+            ));
+        }
+    }
+    let mut seen_keys = HashSet::new();
+    for (peer_name, peer) in &wg.peer {
+        let ctx = format!("wireguard \"{}\".peer \"{}\"", name, peer_name);
+        if !is_valid_key(&peer.public_key) {
+            errors.push(format!("{}.public_key is not a valid WireGuard key.", ctx));
+        } else if !seen_keys.insert(peer.public_key.as_str()) {
+            errors.push(format!("{}: duplicate public_key.", ctx));
+        }
+        if peer.allowed_ips.is_empty() {
+            errors.push(format!("{}.allowed_ips must not be empty.", ctx));
+        }
+        for ip in &peer.allowed_ips {
+            if ip.parse::<IpNetwork>().is_err() {
+                errors.push(format!("{}.allowed_ips: invalid CIDR '{}'.", ctx, ip));
+            }
+        }
+        if let Some(psk) = &peer.preshared_key_file {
+            if !psk.starts_with('/') {
+                errors.push(format!("{}.preshared_key_file must be an absolute path.", ctx));
+            }
+        }
+    }
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Validate listen ports across all zones.
+pub fn validate_ports(config: &HclConfig) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut zones: Vec<_> = config.wireguard.iter().collect();
+    zones.sort_by_key(|(name, _)| name.as_str());
+    let mut seen = HashSet::new();
+    for (name, wg) in zones {
+        if !seen.insert(wg.listen_port) {
+            errors.push(format!(
+                "wireguard \"{}\": listen_port {} is used by another WireGuard zone.",
+                name, wg.listen_port
+            ));
+        }
+        if config.wan.udp_accept.contains(&wg.listen_port) {
+            errors.push(format!(
+                "wireguard \"{}\": listen_port {} is already opened by wan.udp_accept.",
+                name, wg.listen_port
+            ));
+        }
+    }
+    errors
+}
+
+/// systemd-networkd .netdev contents for a WireGuard zone.
+pub fn netdev(name: &str, wg: &WireguardHclConfig) -> String {
+    let mut out = format!("[NetDev]\nName={}\nKind=wireguard\n", name);
+    if let Some(mtu) = wg.mtu {
+        writeln!(out, "MTUBytes={}", mtu).ok();
+    }
+    write!(
+        out,
+        "\n[WireGuard]\nPrivateKeyFile={}\nListenPort={}\n",
+        wg.private_key_file, wg.listen_port
+    )
+    .ok();
+    for (peer_name, peer) in &wg.peer {
+        write!(
+            out,
+            "\n# {}\n[WireGuardPeer]\nPublicKey={}\nAllowedIPs={}\n",
+            peer_name,
+            peer.public_key,
+            peer.allowed_ips.join(",")
+        )
+        .ok();
+        if let Some(psk) = &peer.preshared_key_file {
+            writeln!(out, "PresharedKeyFile={}", psk).ok();
+        }
+    }
+    out
+}
+
+/// systemd-networkd .network contents for a WireGuard zone.
+pub fn network(name: &str, wg: &WireguardHclConfig, enable_ipv4: bool) -> String {
+    let mut out = format!("[Match]\nName={}\n\n[Network]\n", name);
+    if enable_ipv4 {
+        if let Some(ipv4) = &wg.ipv4 {
+            writeln!(out, "Address={}", ipv4.subnet).ok();
+        }
+    }
+    if let Some(ipv6) = &wg.ipv6 {
+        writeln!(out, "Address={}", ipv6.subnet).ok();
+    }
+    out.push_str("LinkLocalAddressing=no\nIPv6AcceptRA=no\n");
+    out
+}
+
+/// Validate the `wireguard_client` tunnels: settings, and interface names
+/// that no VLAN or WireGuard zone uses.
+pub fn validate_clients(config: &HclConfig) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut clients: Vec<_> = config.wireguard_client.iter().collect();
+    clients.sort_by_key(|(name, _)| name.as_str());
+    for (name, client) in clients {
+        let ctx = format!("wireguard_client \"{}\"", name);
+        if let Err(e) = Interface::new(name) {
+            errors.push(format!("{}: {}", ctx, e));
+        }
+        if config.vlan.contains_key(name) || config.wireguard.contains_key(name) {
+            errors.push(format!("{}: name is already used by a VLAN or WireGuard zone.", ctx));
+        }
+        if config.interfaces.has_name(name) {
+            errors.push(format!("{}: name is already used by an interface in the interfaces block.", ctx));
+        }
+        if !client.private_key_file.starts_with('/') {
+            errors.push(format!("{}.private_key_file must be an absolute path.", ctx));
+        }
+        if client.address.is_empty() {
+            errors.push(format!("{}.address must not be empty.", ctx));
+        }
+        for address in &client.address {
+            if address.parse::<IpNetwork>().is_err() {
+                errors.push(format!("{}.address: invalid CIDR '{}'.", ctx, address));
+            }
+        }
+        if let Some(mtu) = client.mtu {
+            if !(1280..=1500).contains(&mtu) {
+                errors.push(format!("{}.mtu {} is out of range (1280-1500).", ctx, mtu));
+            }
+        }
+        let peer = &client.peer;
+        if !is_valid_key(&peer.public_key) {
+            errors.push(format!("{}.peer.public_key is not a valid WireGuard key.", ctx));
+        }
+        let port = peer.endpoint.rsplit_once(':').and_then(|(host, port)| {
+            port.parse::<u16>().ok().filter(|p| *p > 0 && !host.is_empty())
+        });
+        if port.is_none() {
+            errors.push(format!(
+                "{}.peer.endpoint: expected \"host:port\", got '{}'.",
+                ctx, peer.endpoint
+            ));
+        }
+        if let Some(psk) = &peer.preshared_key_file {
+            if !psk.starts_with('/') {
+                errors.push(format!("{}.peer.preshared_key_file must be an absolute path.", ctx));
+            }
+        }
+    }
+    errors
+}
+
+/// Routing table (and connection mark) of a `wireguard_client` tunnel:
+/// its `table`, or CLIENT_TABLE_BASE + its position by name.
+pub fn client_table(config: &HclConfig, name: &str) -> Option<u32> {
+    let client = config.wireguard_client.get(name)?;
+    let mut names: Vec<_> = config.wireguard_client.keys().collect();
+    names.sort();
+    let position = names.iter().position(|n| *n == name)? as u32;
+    Some(client.table.unwrap_or(CLIENT_TABLE_BASE + position))
+}
+
+/// systemd-networkd .netdev contents for a `wireguard_client` tunnel. The
+/// peer accepts any source; the routes in `client_network` pick what
+/// goes through it.
+pub fn client_netdev(name: &str, client: &WireguardClientHclConfig) -> String {
+    let mut out = format!("[NetDev]\nName={}\nKind=wireguard\n", name);
+    if let Some(mtu) = client.mtu {
+        writeln!(out, "MTUBytes={}", mtu).ok();
+    }
+    write!(
+        out,
+        "\n[WireGuard]\nPrivateKeyFile={}\n\n[WireGuardPeer]\nPublicKey={}\nEndpoint={}\nAllowedIPs=0.0.0.0/0,::/0\n",
+        client.private_key_file, client.peer.public_key, client.peer.endpoint
+    )
+    .ok();
+    if let Some(psk) = &client.peer.preshared_key_file {
+        writeln!(out, "PresharedKeyFile={}", psk).ok();
+    }
+    writeln!(
+        out,
+        "PersistentKeepalive={}",
+        client.peer.persistent_keepalive.unwrap_or(PERSISTENT_KEEPALIVE)
+    )
+    .ok();
+    out
+}
+
+/// systemd-networkd .network contents for a `wireguard_client` tunnel: its
+/// addresses, and default routes through it in `table` for each family
+/// it has an address in.
+pub fn client_network(name: &str, client: &WireguardClientHclConfig, table: u32) -> String {
+    let mut out = format!("[Match]\nName={}\n\n[Network]\n", name);
+    for address in &client.address {
+        writeln!(out, "Address={}", address).ok();
+    }
+    out.push_str("LinkLocalAddressing=no\nIPv6AcceptRA=no\n");
+    let addresses: Vec<IpNetwork> = client.address.iter().filter_map(|a| a.parse().ok()).collect();
+    for (ipv6, destination) in [(false, "0.0.0.0/0"), (true, "::/0")] {
+        if addresses.iter().any(|a| a.is_ipv6() == ipv6) {
+            write!(out, "\n[Route]\nDestination={}\nTable={}\n", destination, table).ok();
+        }
+    }
+    out
+}
+
+/// Derive the server's public key from its private key file via `wg pubkey`.
+fn server_public_key(private_key_file: &str) -> Result<String, String> {
+    let private_key = fs::read_to_string(private_key_file)
+        .map_err(|e| format!("Cannot read {}: {}", private_key_file, e))?;
+    let mut child = Command::new("wg")
+        .arg("pubkey")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run wg: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(private_key.trim().as_bytes())
+            .map_err(|e| format!("Cannot write to wg: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("wg pubkey failed: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("wg pubkey failed for {}", private_key_file));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Router address and network of a zone subnet such as "10.99.100.1/24".
+fn split_subnet(subnet: &str) -> Option<(String, String)> {
+    let net: IpNetwork = subnet.parse().ok()?;
+    Some((
+        net.ip().to_string(),
+        format!("{}/{}", net.network(), net.prefix()),
+    ))
+}
+
+/// Inputs for a client config that don't come from the HCL file.
+pub struct PeerKeys {
+    pub server_public_key: String,
+    pub client_private_key: Option<String>,
+    pub preshared_key: Option<String>,
+}
+
+/// Render a wg-quick style client config for one peer of a zone.
+///
+/// The client routes the zone subnets plus the zone's egress CIDRs through
+/// the tunnel and uses the router's tunnel address for DNS.
+pub fn render_peer_config(
+    wg: &WireguardHclConfig,
+    peer: &WireguardPeerConfig,
+    keys: &PeerKeys,
+) -> Result<String, String> {
+    let endpoint = wg
+        .endpoint
+        .as_ref()
+        .ok_or("wireguard zone has no endpoint configured.")?;
+
+    let mut dns = Vec::new();
+    let mut allowed_ips = Vec::new();
+    for (subnet, egress) in [
+        wg.ipv4.as_ref().map(|v| (&v.subnet, &v.egress)),
+        wg.ipv6.as_ref().map(|v| (&v.subnet, &v.egress)),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if let Some((addr, net)) = split_subnet(subnet) {
+            dns.push(addr);
+            allowed_ips.push(net);
+        }
+        allowed_ips.extend(egress.iter().cloned());
+    }
+    allowed_ips.dedup();
+
+    let mut out = String::from("[Interface]\n");
+    writeln!(
+        out,
+        "PrivateKey = {}",
+        keys.client_private_key.as_deref().unwrap_or("<client private key>")
+    )
+    .ok();
+    writeln!(out, "Address = {}", peer.allowed_ips.join(", ")).ok();
+    if !dns.is_empty() {
+        writeln!(out, "DNS = {}", dns.join(", ")).ok();
+    }
+    out.push_str("\n[Peer]\n");
+    writeln!(out, "PublicKey = {}", keys.server_public_key).ok();
+    if let Some(psk) = &keys.preshared_key {
+        writeln!(out, "PresharedKey = {}", psk).ok();
+    }
+    writeln!(out, "Endpoint = {}:{}", endpoint, wg.listen_port).ok();
+    writeln!(out, "AllowedIPs = {}", allowed_ips.join(", ")).ok();
+    writeln!(out, "PersistentKeepalive = {}", PERSISTENT_KEEPALIVE).ok();
+    Ok(out)
+}
+
+/// Build the client config for `zone`/`peer`, reading the server and
+/// preshared keys from disk.
+pub fn peer_config(
+    config: &HclConfig,
+    zone: &str,
+    peer_name: &str,
+    client_private_key_file: Option<&str>,
+) -> Result<String, String> {
+    let wg = config
+        .wireguard
+        .get(zone)
+        .ok_or_else(|| format!("Unknown wireguard zone: {}", zone))?;
+    let peer = wg
+        .peer
+        .get(peer_name)
+        .ok_or_else(|| format!("wireguard \"{}\": unknown peer {}", zone, peer_name))?;
+    let read_key = |path: &str| {
+        fs::read_to_string(path)
+            .map(|s| s.trim().to_string())
+            .map_err(|e| format!("Cannot read {}: {}", path, e))
+    };
+    let keys = PeerKeys {
+        server_public_key: server_public_key(&wg.private_key_file)?,
+        client_private_key: client_private_key_file.map(read_key).transpose()?,
+        preshared_key: peer.preshared_key_file.as_deref().map(read_key).transpose()?,
+    };
+    render_peer_config(wg, peer, &keys)
+        .map_err(|e| format!("wireguard \"{}\": {}", zone, e))
+}
+
+/// Render text as a terminal QR code via `qrencode`.
+pub fn qr_code(text: &str) -> Result<String, String> {
+    let mut child = Command::new("qrencode")
+        .args(["-t", "ansiutf8"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Cannot run qrencode: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| format!("Cannot write to qrencode: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("qrencode failed: {}", e))?;
+    if !output.status.success() {
+        return Err("qrencode failed".to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hcl_config::parse_hcl;
+
+    const SERVER_KEY: &str = "gN65BkIKy1eCE9pP1wdc8ROUtkHLF2PfAqYdyYBz6EA=";
+    const PEER_KEY: &str = "xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=";
+
+    fn config() -> HclConfig {
+        parse_hcl(&format!(r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{ enable_ipv6 = true }}
+vlan "lan" {{
+  id = 10
+  ipv4 {{ subnet = "10.99.10.1/24" }}
+}}
+wireguard "wg0" {{
+  listen_port      = 51820
+  private_key_file = "/var/lib/nifty-filter/wg0.key"
+  endpoint         = "vpn.example.com"
+  mtu              = 1420
+  ipv4 {{
+    subnet = "10.99.100.1/24"
+    egress = ["0.0.0.0/0"]
+  }}
+  ipv6 {{ subnet = "fd00:100::1/64" }}
+  peer "phone" {{
+    public_key         = "{}"
+    allowed_ips        = ["10.99.100.10/32", "fd00:100::10/128"]
+    preshared_key_file = "/var/lib/nifty-filter/phone.psk"
+  }}
+}}
+"#, PEER_KEY)).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = config();
+        let wg = &config.wireguard["wg0"];
+        assert!(validate("wg0", wg).is_ok());
+        assert!(validate_ports(&config).is_empty());
+
+        let bad = parse_hcl(r#"
+interfaces {
+  trunk { name = "trunk" }
+  wan   { name = "wan" }
+}
+wan { udp_accept = [51820] }
+wireguard "wg0" {
+  listen_port      = 51820
+  private_key_file = "wg0.key"
+  peer "phone" {
+    public_key  = "not-a-key"
+    allowed_ips = ["10.99.100.300/32"]
+  }
+}
+"#).unwrap();
+        let errors = validate("wg0", &bad.wireguard["wg0"]).unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors[0].contains("private_key_file must be an absolute path"));
+        assert!(errors[1].contains("public_key is not a valid WireGuard key"));
+        assert!(errors[2].contains("invalid CIDR '10.99.100.300/32'"));
+        assert_eq!(validate_ports(&bad).len(), 1);
+    }
+
+    #[test]
+    fn test_netdev_and_network() {
+        let config = config();
+        let wg = &config.wireguard["wg0"];
+        let netdev = netdev("wg0", wg);
+        assert!(netdev.contains("[NetDev]\nName=wg0\nKind=wireguard\nMTUBytes=1420\n"));
+        assert!(netdev.contains("PrivateKeyFile=/var/lib/nifty-filter/wg0.key\nListenPort=51820\n"));
+        assert!(netdev.contains(&format!("# phone\n[WireGuardPeer]\nPublicKey={}\n", PEER_KEY)));
+        assert!(netdev.contains("AllowedIPs=10.99.100.10/32,fd00:100::10/128\n"));
+        assert!(netdev.contains("PresharedKeyFile=/var/lib/nifty-filter/phone.psk\n"));
+
+        let network = network("wg0", wg, true);
+        assert!(network.contains("Address=10.99.100.1/24\nAddress=fd00:100::1/64\n"));
+        assert!(!super::network("wg0", wg, false).contains("10.99.100.1"));
+    }
+
+    #[test]
+    fn test_client() {
+        let config = parse_hcl(&format!(r#"
+interfaces {{
+  trunk {{ name = "trunk" }}
+  wan   {{ name = "wan" }}
+}}
+wan {{}}
+wireguard_client "vpn-provider" {{
+  private_key_file = "/var/lib/nifty-filter/vpn-provider.key"
+  address          = ["10.64.0.2/32"]
+  mtu              = 1380
+  peer {{
+    public_key         = "{}"
+    endpoint           = "vpn.example.net:51820"
+    preshared_key_file = "/var/lib/nifty-filter/vpn-provider.psk"
+  }}
+}}
+wireguard_client "backup" {{
+  private_key_file = "backup.key"
+  address          = ["10.65.0.300/32"]
+  table            = 220
+  peer {{
+    public_key = "not-a-key"
+    endpoint   = "vpn.example.org"
+  }}
+}}
+wireguard_client "wan" {{
+  private_key_file = "/var/lib/nifty-filter/wan.key"
+  address          = ["10.66.0.2/32"]
+  peer {{
+    public_key = "{}"
+    endpoint   = "vpn.example.com:51820"
+  }}
+}}
+"#, PEER_KEY, PEER_KEY)).unwrap();
+        assert_eq!(
+            validate_clients(&config),
+            vec![
+                "wireguard_client \"backup\".private_key_file must be an absolute path.",
+                "wireguard_client \"backup\".address: invalid CIDR '10.65.0.300/32'.",
+                "wireguard_client \"backup\".peer.public_key is not a valid WireGuard key.",
+                "wireguard_client \"backup\".peer.endpoint: expected \"host:port\", got 'vpn.example.org'.",
+                "wireguard_client \"wan\": name is already used by an interface in the interfaces block.",
+            ]
+        );
+        assert_eq!(client_table(&config, "backup"), Some(220));
+        assert_eq!(client_table(&config, "vpn-provider"), Some(201));
+        assert_eq!(client_table(&config, "other"), None);
+
+        let client = &config.wireguard_client["vpn-provider"];
+        assert_eq!(client_netdev("vpn-provider", client), format!("\
+[NetDev]
+Name=vpn-provider
+Kind=wireguard
+MTUBytes=1380
+
+[WireGuard]
+PrivateKeyFile=/var/lib/nifty-filter/vpn-provider.key
+
+[WireGuardPeer]
+PublicKey={}
+Endpoint=vpn.example.net:51820
+AllowedIPs=0.0.0.0/0,::/0
+PresharedKeyFile=/var/lib/nifty-filter/vpn-provider.psk
+PersistentKeepalive=25
+", PEER_KEY));
+        assert_eq!(client_network("vpn-provider", client, 201), "\
+[Match]
+Name=vpn-provider
+
+[Network]
+Address=10.64.0.2/32
+LinkLocalAddressing=no
+IPv6AcceptRA=no
+
+[Route]
+Destination=0.0.0.0/0
+Table=201
+");
+    }
+
+    #[test]
+    fn test_render_peer_config() {
+        let config = config();
+        let wg = &config.wireguard["wg0"];
+        let keys = PeerKeys {
+            server_public_key: SERVER_KEY.to_string(),
+            client_private_key: None,
+            preshared_key: Some("psk".to_string()),
+        };
+        let out = render_peer_config(wg, &wg.peer["phone"], &keys).unwrap();
+        assert_eq!(out, format!("\
+[Interface]
+PrivateKey = <client private key>
+Address = 10.99.100.10/32, fd00:100::10/128
+DNS = 10.99.100.1, fd00:100::1
+
+[Peer]
+PublicKey = {}
+PresharedKey = psk
+Endpoint = vpn.example.com:51820
+AllowedIPs = 10.99.100.0/24, 0.0.0.0/0, fd00:100::/64
+PersistentKeepalive = 25
+", SERVER_KEY));
+    }
+}